@@ -0,0 +1,222 @@
+use crate::{config::AbuseConfig, metrics::Metrics, ratelimit::TokenBucket, Rejection};
+use anyhow::Result;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Status,
+    serde::Serialize,
+    Request, Response,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// Stats kept per client are dropped once this many are tracked and their
+/// windows have expired.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct ClientStats {
+    window_start: Instant,
+    requests: u32,
+    client_errors: u32,
+    last_id: Option<u64>,
+    sequential: u32,
+}
+
+struct Penalty {
+    reason: &'static str,
+    until: Instant,
+    /// `None` bans the client outright.
+    limiter: Option<TokenBucket>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PenaltyStatus {
+    client: String,
+    reason: &'static str,
+    remaining_secs: u64,
+    banned: bool,
+}
+
+/// Flags clients whose traffic looks like abuse (request floods, walking
+/// sequential IDs, mostly failing requests) and throttles or bans them for a
+/// while. Penalties are listed and can be lifted through the admin API.
+pub struct AbuseDetector {
+    config: AbuseConfig,
+    clients: Mutex<HashMap<IpAddr, ClientStats>>,
+    penalties: Mutex<HashMap<IpAddr, Penalty>>,
+    metrics: Arc<Metrics>,
+}
+
+impl AbuseDetector {
+    pub fn new(config: AbuseConfig, metrics: Arc<Metrics>) -> Arc<Self> {
+        Arc::new(AbuseDetector {
+            config,
+            clients: Mutex::default(),
+            penalties: Mutex::default(),
+            metrics,
+        })
+    }
+
+    /// Rejects requests from a client serving a penalty.
+    pub fn check(&self, ip: Option<IpAddr>) -> Result<()> {
+        let Some(ip) = ip else {
+            return Ok(());
+        };
+        let mut penalties = self.penalties.lock().unwrap();
+        let Some(penalty) = penalties.get(&ip) else {
+            return Ok(());
+        };
+        let remaining = penalty.until.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            penalties.remove(&ip);
+            return Ok(());
+        }
+
+        let retry_after = match &penalty.limiter {
+            None => remaining,
+            Some(limiter) => match limiter.take(Duration::ZERO) {
+                Ok(_) => return Ok(()),
+                Err(retry_after) => retry_after,
+            },
+        };
+        self.metrics
+            .incr("roproxy_abuse_rejections_total", &[("reason", penalty.reason)]);
+        let status = if penalty.limiter.is_some() {
+            Status::TooManyRequests
+        } else {
+            Status::Forbidden
+        };
+        Err(Rejection::new(status, "Client is temporarily restricted for unusual traffic")
+            .with_header("Retry-After", retry_after.as_secs_f64().ceil() as u64)
+            .with_field("reason", penalty.reason)
+            .into())
+    }
+
+    pub fn record(&self, ip: IpAddr, path: &str, status: u16) {
+        if !self.config.enabled {
+            return;
+        }
+        let window = Duration::from_secs(self.config.window_secs);
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&ip) {
+            clients.retain(|_, stats| stats.window_start.elapsed() < window);
+        }
+        let stats = clients.entry(ip).or_insert_with(|| ClientStats {
+            window_start: Instant::now(),
+            requests: 0,
+            client_errors: 0,
+            last_id: None,
+            sequential: 0,
+        });
+        if stats.window_start.elapsed() >= window {
+            stats.window_start = Instant::now();
+            stats.requests = 0;
+            stats.client_errors = 0;
+        }
+
+        stats.requests += 1;
+        if (400..500).contains(&status) {
+            stats.client_errors += 1;
+        }
+        if let Some(id) = last_id(path) {
+            match stats.last_id {
+                Some(last) if id.abs_diff(last) == 1 => stats.sequential += 1,
+                Some(last) if id == last => {}
+                _ => stats.sequential = 0,
+            }
+            stats.last_id = Some(id);
+        }
+
+        let config = &self.config;
+        let reason = if stats.requests > config.max_requests {
+            "burst"
+        } else if stats.sequential >= config.sequential_ids {
+            "enumeration"
+        } else if stats.requests >= config.error_ratio_min_requests
+            && stats.client_errors as f64 / stats.requests as f64 > config.max_error_ratio
+        {
+            "errors"
+        } else {
+            return;
+        };
+        clients.remove(&ip);
+        drop(clients);
+        self.penalize(ip, reason);
+    }
+
+    fn penalize(&self, ip: IpAddr, reason: &'static str) {
+        let mut penalties = self.penalties.lock().unwrap();
+        if penalties.get(&ip).is_some_and(|penalty| penalty.until > Instant::now()) {
+            return;
+        }
+        warn!("Restricting {} for {}s: {}", ip, self.config.penalty_secs, reason);
+        self.metrics.incr("roproxy_abuse_flags_total", &[("reason", reason)]);
+        penalties.insert(
+            ip,
+            Penalty {
+                reason,
+                until: Instant::now() + Duration::from_secs(self.config.penalty_secs),
+                limiter: self
+                    .config
+                    .penalty_rate
+                    .map(|rate| TokenBucket::new(rate.limit, Duration::from_secs(rate.window_secs))),
+            },
+        );
+    }
+
+    pub fn list(&self) -> Vec<PenaltyStatus> {
+        let now = Instant::now();
+        self.penalties
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, penalty)| penalty.until > now)
+            .map(|(ip, penalty)| PenaltyStatus {
+                client: ip.to_string(),
+                reason: penalty.reason,
+                remaining_secs: penalty.until.duration_since(now).as_secs(),
+                banned: penalty.limiter.is_none(),
+            })
+            .collect()
+    }
+
+    pub fn lift(&self, ip: IpAddr) -> bool {
+        self.penalties.lock().unwrap().remove(&ip).is_some()
+    }
+}
+
+// The last all-digit path segment, which is where Roblox puts user, asset
+// and group IDs.
+fn last_id(path: &str) -> Option<u64> {
+    path.split('/')
+        .rev()
+        .find(|segment| !segment.is_empty() && segment.bytes().all(|byte| byte.is_ascii_digit()))
+        .and_then(|segment| segment.parse().ok())
+}
+
+pub struct AbuseMonitor(pub Arc<AbuseDetector>);
+
+#[rocket::async_trait]
+impl Fairing for AbuseMonitor {
+    fn info(&self) -> Info {
+        Info {
+            name: "Abuse detection",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let path = req.uri().path();
+        if path.starts_with("/admin") {
+            return;
+        }
+        if let Some(ip) = req.client_ip() {
+            self.0.record(ip, path.as_str(), res.status().code);
+        }
+    }
+}
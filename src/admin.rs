@@ -0,0 +1,419 @@
+use crate::{
+    abuse::PenaltyStatus, async_jobs::JobStatus, credentials::Credentials, csv_export, inflight::InFlightStatus, request_log::RequestLog,
+    tenants::same_secret, usage, AppState, ErrorResponse, Rejection,
+};
+use anyhow::anyhow;
+use chrono::{Duration as ChronoDuration, NaiveDate, Utc};
+use rocket::{
+    http::{ContentType, Method, Status},
+    request::{FromRequest, Outcome},
+    response::stream::{Event, EventStream},
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    Request, Route, Shutdown, State,
+};
+use tokio::sync::broadcast::error::RecvError;
+use std::{collections::HashMap, convert::Infallible, str::FromStr, sync::Arc, time::Duration};
+use tracing::info;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        put_credential,
+        delete_credential,
+        rotate_key,
+        sign_url,
+        list_inflight,
+        cancel_inflight,
+        stream_logs,
+        list_penalties,
+        lift_penalty,
+        purge_cache_key,
+        list_bandwidth,
+        export_usage,
+        list_async_jobs,
+        retry_async_job,
+        delete_async_job
+    ]
+}
+
+/// The bearer token presented on an admin request, checked by each handler
+/// so failures get the same JSON errors as everything else.
+pub struct AdminToken<'r>(Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken<'r> {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        Outcome::Success(AdminToken(token))
+    }
+}
+
+impl AdminToken<'_> {
+    pub fn check(&self, state: &AppState) -> Result<(), ErrorResponse> {
+        match (&state.admin_token, self.0) {
+            (Some(expected), Some(token)) if same_secret(expected, token) => Ok(()),
+            _ => Err(ErrorResponse(
+                Rejection::new(Status::Unauthorized, "Missing or invalid admin token").into(),
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AccountSecrets {
+    roblosecurity: Option<String>,
+    open_cloud_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct NewKey {
+    key: String,
+}
+
+fn no_store() -> ErrorResponse {
+    ErrorResponse(Rejection::new(Status::Conflict, "No credential store is configured").into())
+}
+
+fn unknown_pool(pool: &str) -> ErrorResponse {
+    ErrorResponse(Rejection::new(Status::NotFound, format!("Unknown credential pool {}", pool)).into())
+}
+
+#[put("/admin/credentials/<pool>/<name>", data = "<secrets>")]
+fn put_credential(
+    pool: &str,
+    name: &str,
+    secrets: Json<AccountSecrets>,
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    token.check(state)?;
+    let store = state.credential_store.as_ref().ok_or_else(no_store)?;
+    let target = state.credential_pool(pool).ok_or_else(|| unknown_pool(pool))?;
+    let secrets = secrets.into_inner();
+    if secrets.roblosecurity.is_none() && secrets.open_cloud_key.is_none() {
+        return Err(ErrorResponse(
+            Rejection::new(Status::BadRequest, "Expected roblosecurity or open_cloud_key").into(),
+        ));
+    }
+    let credentials = Credentials {
+        roblosecurity: secrets.roblosecurity,
+        open_cloud_key: secrets.open_cloud_key,
+    };
+
+    store.put(pool, name, credentials.clone())?;
+    target.upsert(name, credentials);
+    info!("Stored credential {} in pool {}", name, pool);
+    Ok(Json(json!({ "pool": pool, "name": name })))
+}
+
+#[delete("/admin/credentials/<pool>/<name>")]
+fn delete_credential(
+    pool: &str,
+    name: &str,
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<Status, ErrorResponse> {
+    token.check(state)?;
+    let store = state.credential_store.as_ref().ok_or_else(no_store)?;
+    let target = state.credential_pool(pool).ok_or_else(|| unknown_pool(pool))?;
+    // Accounts from the config file aren't in the store and come back on
+    // restart, so only stored ones can be deleted.
+    if !store.remove(pool, name)? {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, format!("No stored credential {} in pool {}", name, pool)).into(),
+        ));
+    }
+    target.remove(name);
+    info!("Removed credential {} from pool {}", name, pool);
+    Ok(Status::NoContent)
+}
+
+#[post("/admin/credentials/rotate-key", data = "<new_key>")]
+fn rotate_key(
+    new_key: Json<NewKey>,
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    token.check(state)?;
+    let store = state.credential_store.as_ref().ok_or_else(no_store)?;
+    let (key_id, rewrapped) = store.rotate_key(&new_key.key).map_err(|err| {
+        ErrorResponse(anyhow!(Rejection::new(Status::BadRequest, format!("{:#}", err))))
+    })?;
+    info!("Re-encrypted {} stored credentials under key {}", rewrapped, key_id);
+    Ok(Json(json!({ "key_id": key_id, "rewrapped": rewrapped })))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct SignRequest {
+    #[serde(default = "default_method")]
+    method: String,
+    path: String,
+    #[serde(default)]
+    query: HashMap<String, String>,
+    ttl_secs: u64,
+    tenant: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+#[post("/admin/signed-urls", data = "<request>")]
+fn sign_url(
+    request: Json<SignRequest>,
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    token.check(state)?;
+    let Some(signer) = &state.signer else {
+        return Err(ErrorResponse(
+            Rejection::new(Status::Conflict, "No signed_urls.secret is configured").into(),
+        ));
+    };
+    let request = request.into_inner();
+    let Ok(method) = Method::from_str(&request.method) else {
+        return Err(ErrorResponse(
+            Rejection::new(Status::BadRequest, format!("Unknown method {}", request.method)).into(),
+        ));
+    };
+    if let Some(tenant) = &request.tenant {
+        if state.tenants.get(tenant).is_none() {
+            return Err(ErrorResponse(
+                Rejection::new(Status::NotFound, format!("Unknown tenant {}", tenant)).into(),
+            ));
+        }
+    }
+
+    let (url, expires) = signer.sign(
+        method,
+        &request.path,
+        request.query,
+        Duration::from_secs(request.ttl_secs),
+        request.tenant.as_deref(),
+    )?;
+    Ok(Json(json!({ "url": url, "expires": expires })))
+}
+
+#[get("/admin/inflight")]
+fn list_inflight(
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<Json<Vec<InFlightStatus>>, ErrorResponse> {
+    token.check(state)?;
+    Ok(Json(state.inflight.list()))
+}
+
+#[delete("/admin/inflight/<id>")]
+fn cancel_inflight(
+    id: u64,
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<Status, ErrorResponse> {
+    token.check(state)?;
+    if !state.inflight.cancel(id) {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, format!("No request {} is in flight", id)).into(),
+        ));
+    }
+    info!("Cancelled in-flight request {}", id);
+    Ok(Status::NoContent)
+}
+
+struct LogFilter {
+    route: Option<String>,
+    status: Option<String>,
+    key: Option<String>,
+}
+
+impl LogFilter {
+    // `status` takes a code like `404` or a class like `5xx`; `key` matches
+    // a key label or tenant name.
+    fn matches(&self, log: &RequestLog) -> bool {
+        let route = self.route.as_ref().is_none_or(|route| log.path.starts_with(route.as_str()));
+        let status = self.status.as_ref().is_none_or(|status| {
+            let code = log.status.to_string();
+            match status.strip_suffix("xx") {
+                Some(class) => code.starts_with(class),
+                None => code == *status,
+            }
+        });
+        let key = self.key.as_ref().is_none_or(|key| {
+            log.key.as_ref() == Some(key) || log.tenant.as_ref() == Some(key)
+        });
+        route && status && key
+    }
+}
+
+#[get("/admin/logs?<route>&<status>&<key>")]
+fn stream_logs(
+    route: Option<String>,
+    status: Option<String>,
+    key: Option<String>,
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], ErrorResponse> {
+    token.check(state)?;
+    let filter = LogFilter { route, status, key };
+    let mut logs = state.request_log.subscribe();
+    Ok(EventStream! {
+        loop {
+            let log = tokio::select! {
+                log = logs.recv() => log,
+                _ = &mut shutdown => break,
+            };
+            match log {
+                Ok(log) if filter.matches(&log) => yield Event::json(&log),
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    yield Event::comment(format!("{} entries skipped", skipped));
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[get("/admin/abuse")]
+fn list_penalties(
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<Json<Vec<PenaltyStatus>>, ErrorResponse> {
+    token.check(state)?;
+    Ok(Json(state.abuse.list()))
+}
+
+#[delete("/admin/abuse/<client>")]
+fn lift_penalty(
+    client: &str,
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<Status, ErrorResponse> {
+    token.check(state)?;
+    let lifted = client.parse().is_ok_and(|ip| state.abuse.lift(ip));
+    if !lifted {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, format!("{} has no active penalty", client)).into(),
+        ));
+    }
+    info!("Lifted abuse penalty on {}", client);
+    Ok(Status::NoContent)
+}
+
+/// Bytes each proxy key has moved today and this month, against its quotas.
+#[get("/admin/bandwidth")]
+fn list_bandwidth(state: &State<Arc<AppState>>, token: AdminToken<'_>) -> Result<Json<Vec<Value>>, ErrorResponse> {
+    token.check(state)?;
+    let keys = state
+        .tenants
+        .iter()
+        .flat_map(|tenant| {
+            tenant.api_keys().iter().map(|key| {
+                let mut entry = json!(key.bandwidth().status());
+                entry["tenant"] = json!(tenant.name);
+                entry["key"] = json!(key.label());
+                entry
+            })
+        })
+        .collect();
+    Ok(Json(keys))
+}
+
+/// Requests, errors and bytes per proxy key per day, from `from` to `to`
+/// (`YYYY-MM-DD`, both inclusive, the last 30 days by default), as JSON or
+/// `format=csv`.
+#[get("/admin/usage/export?<from>&<to>&<format>")]
+fn export_usage(
+    from: Option<&str>,
+    to: Option<&str>,
+    format: Option<&str>,
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<(ContentType, String), ErrorResponse> {
+    token.check(state)?;
+    let csv = csv_export::wants_csv(format)?;
+    let date = |value: Option<&str>, default: NaiveDate| match value {
+        Some(value) => NaiveDate::parse_from_str(value, usage::DATE_FORMAT).map_err(|_| {
+            ErrorResponse(Rejection::new(Status::BadRequest, format!("Invalid date {}, expected YYYY-MM-DD", value)).into())
+        }),
+        None => Ok(default),
+    };
+    let today = Utc::now().date_naive();
+    let to = date(to, today)?;
+    let from = date(from, to - ChronoDuration::days(29))?;
+
+    let rows: Vec<Value> = state
+        .usage
+        .rows(from, to)
+        .into_iter()
+        .map(|row| {
+            let error_rate = if row.requests == 0 { 0.0 } else { row.errors as f64 / row.requests as f64 };
+            let mut row = json!(row);
+            row["error_rate"] = json!(error_rate);
+            row
+        })
+        .collect();
+    if csv {
+        let columns = "date,tenant,key,requests,errors,error_rate,bytes_in,bytes_out";
+        let content_type = ContentType::parse_flexible(csv_export::CONTENT_TYPE).unwrap_or(ContentType::CSV);
+        return Ok((content_type, csv_export::to_csv(&rows, Some(columns))));
+    }
+    Ok((ContentType::JSON, Value::Array(rows).to_string()))
+}
+
+/// Drops every cached response tagged with a surrogate key such as
+/// `group:456`, whichever tenant it was cached for.
+#[delete("/admin/cache/keys/<key>")]
+fn purge_cache_key(
+    key: &str,
+    state: &State<Arc<AppState>>,
+    token: AdminToken<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    token.check(state)?;
+    let purged = state.engine.cache.purge(key);
+    info!("Purged {} cached responses tagged {}", purged, key);
+    Ok(Json(json!({ "key": key, "purged": purged })))
+}
+
+/// Every async job still held: queued, running, dead-lettered, or finished
+/// with a result not yet expired.
+#[get("/admin/async")]
+fn list_async_jobs(state: &State<Arc<AppState>>, token: AdminToken<'_>) -> Result<Json<Vec<JobStatus>>, ErrorResponse> {
+    token.check(state)?;
+    Ok(Json(state.async_jobs.list()))
+}
+
+/// Queues a dead-lettered job again, with a fresh set of attempts.
+#[post("/admin/async/<id>/retry")]
+fn retry_async_job(id: &str, state: &State<Arc<AppState>>, token: AdminToken<'_>) -> Result<Status, ErrorResponse> {
+    token.check(state)?;
+    if !state.async_jobs.retry(id) {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, format!("No dead-lettered job {} that can be retried", id)).into(),
+        ));
+    }
+    info!("Requeued async job {}", id);
+    Ok(Status::Accepted)
+}
+
+#[delete("/admin/async/<id>")]
+fn delete_async_job(id: &str, state: &State<Arc<AppState>>, token: AdminToken<'_>) -> Result<Status, ErrorResponse> {
+    token.check(state)?;
+    if !state.async_jobs.remove(id) {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, format!("No job {} that isn't running", id)).into(),
+        ));
+    }
+    info!("Deleted async job {}", id);
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,595 @@
+use crate::{
+    client_cert,
+    config::AsyncJobsConfig,
+    envelope::{self, Envelope, Prepared},
+    trace::SECRET_HEADERS,
+    AppState, ErrorResponse, MyRequestGuard, ProxyResponse, Rejection,
+};
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use rocket::{
+    http::{Method, Status, StatusClass},
+    serde::{
+        json::{self, json, Json, Value},
+        Deserialize, Serialize,
+    },
+    Request, Route, State,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, info, warn};
+
+pub fn routes() -> Vec<Route> {
+    routes![submit, result]
+}
+
+/// What's kept of a queued request, on disk too. Pool credentials aren't
+/// part of it: the account is picked from `pool` when the job runs. The
+/// client's own credential headers are, in memory, but are left out of the
+/// file, and a job saved without them is never run again: it would go out
+/// as a pool account instead.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct QueuedRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    /// Base64.
+    body: Option<String>,
+    identity: Option<String>,
+    #[serde(default)]
+    affinity: Option<String>,
+    pool: String,
+    tenant: Option<String>,
+    #[serde(default)]
+    credentials_dropped: bool,
+}
+
+impl QueuedRequest {
+    fn new(prepared: Prepared) -> Self {
+        QueuedRequest {
+            method: prepared.method.as_str().to_string(),
+            url: prepared.url,
+            headers: prepared.headers,
+            body: prepared.body.map(|body| STANDARD.encode(body)),
+            identity: prepared.identity,
+            affinity: prepared.affinity,
+            pool: prepared.pool,
+            tenant: prepared.tenant,
+            credentials_dropped: false,
+        }
+    }
+
+    fn prepared(&self) -> Result<Prepared> {
+        Ok(Prepared {
+            method: Method::from_str(&self.method).map_err(|_| anyhow!("Invalid method {}", self.method))?,
+            url: self.url.clone(),
+            headers: self.headers.clone(),
+            body: self.body.as_deref().map(|body| STANDARD.decode(body)).transpose()?,
+            identity: self.identity.clone(),
+            affinity: self.affinity.clone(),
+            pool: self.pool.clone(),
+            tenant: self.tenant.clone(),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct StoredJob {
+    id: String,
+    request: QueuedRequest,
+    attempts: u32,
+    dead: bool,
+    last_error: Option<String>,
+    created: u64,
+    #[serde(default)]
+    callback: Option<String>,
+}
+
+enum JobState {
+    Queued,
+    Running,
+    Done(Result<ProxyResponse, Rejection>),
+    /// Out of attempts; kept until an admin retries or deletes it.
+    Dead,
+}
+
+struct Job {
+    request: QueuedRequest,
+    state: JobState,
+    attempts: u32,
+    last_error: Option<String>,
+    created: u64,
+    next_attempt: Instant,
+    finished: Option<Instant>,
+    callback: Option<String>,
+}
+
+impl Job {
+    fn status(&self) -> &'static str {
+        match self.state {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done(_) => "done",
+            JobState::Dead => "dead",
+        }
+    }
+
+    fn dead_letter(&self) -> Rejection {
+        Rejection::new(
+            Status::BadGateway,
+            format!(
+                "Gave up after {} attempts: {}",
+                self.attempts,
+                self.last_error.as_deref().unwrap_or("unknown error")
+            ),
+        )
+        .with_field("code", "dead_letter")
+    }
+
+    /// What the job's callback is sent once it's done or dead.
+    fn report(&self, id: &str) -> Value {
+        let mut report = json!({ "type": "async", "id": id, "status": self.status(), "attempts": self.attempts });
+        match &self.state {
+            JobState::Done(Ok(response)) => {
+                report["response"] = json!({
+                    "status": response.status.code,
+                    "content_type": response.content_type,
+                    "body": response
+                        .json()
+                        .unwrap_or_else(|| Value::String(String::from_utf8_lossy(&response.body).into_owned())),
+                });
+            }
+            JobState::Done(Err(rejection)) => report["error"] = json!(rejection.to_string()),
+            JobState::Dead => report["error"] = json!(self.dead_letter().to_string()),
+            JobState::Queued | JobState::Running => {}
+        }
+        report
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct JobStatus {
+    id: String,
+    tenant: Option<String>,
+    method: String,
+    url: String,
+    status: &'static str,
+    attempts: u32,
+    last_error: Option<String>,
+    created: String,
+}
+
+const CREDENTIALS_DROPPED: &str = "Client credentials aren't persisted across restarts; submit the job again";
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// A job ID nobody can guess, since knowing one is what lets a caller read
+/// or cancel the job.
+pub fn new_id() -> String {
+    let mut bytes = [0; 16];
+    getrandom::fill(&mut bytes).expect("OS random number generator failed");
+    hex::encode(bytes)
+}
+
+/// Requests run in the background by a few workers, for bulk work that
+/// shouldn't hold up interactive traffic: `POST /async` takes the same body
+/// as `POST /proxy` and answers with a job ID right away, and
+/// `GET /async/<id>` returns the upstream response once there is one.
+///
+/// Jobs that fail upstream (a 5xx, a 429, or no answer) are retried with
+/// backoff up to `max_attempts` times, then dead-lettered for an admin to
+/// look at. Writes are only retried on a 429 or when turned away before
+/// being sent, unless the client marked them safe to repeat with an
+/// `Idempotency-Key` header: after a 5xx or a lost answer, Roblox may
+/// already have done the write. With `path` set, queued and dead jobs are saved there in the
+/// background after every change and picked up again after a restart, except
+/// that jobs sent with the client's own credentials come back dead; results
+/// are only kept in memory, for `result_ttl_secs`.
+pub struct AsyncJobs {
+    config: AsyncJobsConfig,
+    jobs: Mutex<HashMap<String, Job>>,
+    workers: Arc<Semaphore>,
+    wake: Notify,
+    path: Option<PathBuf>,
+    changed: Notify,
+}
+
+impl AsyncJobs {
+    pub fn new(config: &AsyncJobsConfig) -> Result<Self> {
+        let path = config.path.as_ref().map(PathBuf::from);
+        let stored = match &path {
+            Some(path) if path.exists() => {
+                let contents = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+                json::from_slice::<Vec<StoredJob>>(&contents)
+                    .with_context(|| format!("{} isn't a job queue file", path.display()))?
+            }
+            _ => Vec::new(),
+        };
+        if !stored.is_empty() {
+            info!("Loaded {} queued jobs", stored.len());
+        }
+        let jobs = stored
+            .into_iter()
+            .map(|job| {
+                let (state, last_error) = match job.request.credentials_dropped {
+                    true => (JobState::Dead, Some(CREDENTIALS_DROPPED.to_string())),
+                    false if job.dead => (JobState::Dead, job.last_error),
+                    false => (JobState::Queued, job.last_error),
+                };
+                let job_state = Job {
+                    request: job.request,
+                    state,
+                    attempts: job.attempts,
+                    last_error,
+                    created: job.created,
+                    next_attempt: Instant::now(),
+                    finished: None,
+                    callback: job.callback,
+                };
+                (job.id, job_state)
+            })
+            .collect();
+        Ok(AsyncJobs {
+            config: config.clone(),
+            jobs: Mutex::new(jobs),
+            workers: Arc::new(Semaphore::new(config.workers.max(1))),
+            wake: Notify::new(),
+            path,
+            changed: Notify::new(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    // Drops results nobody collected in time.
+    fn prune(&self, jobs: &mut HashMap<String, Job>) {
+        let ttl = Duration::from_secs(self.config.result_ttl_secs);
+        jobs.retain(|_, job| job.finished.is_none_or(|finished| finished.elapsed() < ttl));
+    }
+
+    // Has the writer save the queue; changes made while it's writing are
+    // picked up by another write straight after.
+    fn save(&self) {
+        if self.path.is_some() {
+            self.changed.notify_one();
+        }
+    }
+
+    // Every job that isn't finished, or is dead, as it's saved.
+    fn stored(&self) -> Vec<StoredJob> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter()
+            .filter(|(_, job)| !matches!(job.state, JobState::Done(_)))
+            .map(|(id, job)| {
+                let mut request = job.request.clone();
+                request
+                    .headers
+                    .retain(|(name, _)| !SECRET_HEADERS.contains(&name.to_lowercase().as_str()));
+                request.credentials_dropped |= request.headers.len() < job.request.headers.len();
+                StoredJob {
+                    id: id.clone(),
+                    request,
+                    attempts: job.attempts,
+                    dead: matches!(job.state, JobState::Dead),
+                    last_error: job.last_error.clone(),
+                    created: job.created,
+                    callback: job.callback.clone(),
+                }
+            })
+            .collect()
+    }
+
+    // The oldest job that's due, marked as running.
+    fn next(&self) -> Option<(String, QueuedRequest)> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let now = Instant::now();
+        let (id, job) = jobs
+            .iter_mut()
+            .filter(|(_, job)| matches!(job.state, JobState::Queued) && job.next_attempt <= now)
+            .min_by_key(|(_, job)| job.created)?;
+        job.state = JobState::Running;
+        job.attempts += 1;
+        Some((id.clone(), job.request.clone()))
+    }
+
+    // Settles a run: done, queued again after a backoff if `retry` says why
+    // it should be, or dead once out of attempts. Returns the callback to
+    // send, if the job has one and is settled for good.
+    fn finish(
+        &self,
+        id: &str,
+        outcome: Result<ProxyResponse, Rejection>,
+        retry: Option<String>,
+    ) -> (&'static str, Option<(String, Value)>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(id) else {
+            return ("removed", None);
+        };
+        let result = match retry {
+            Some(error) if job.attempts < self.config.max_attempts => {
+                let backoff = Duration::from_secs(self.config.retry_backoff_secs)
+                    .saturating_mul(1 << (job.attempts - 1).min(16));
+                debug!("Job {} failed ({}), retrying in {:?}", id, error, backoff);
+                job.last_error = Some(error);
+                job.next_attempt = Instant::now() + backoff;
+                job.state = JobState::Queued;
+                "retried"
+            }
+            Some(error) => {
+                warn!("Job {} failed {} times, dead-lettering it: {}", id, job.attempts, error);
+                job.last_error = Some(error);
+                job.state = JobState::Dead;
+                "dead"
+            }
+            None => {
+                let result = if outcome.is_ok() { "done" } else { "failed" };
+                job.state = JobState::Done(outcome);
+                job.finished = Some(Instant::now());
+                result
+            }
+        };
+        let callback = match job.state {
+            JobState::Queued => None,
+            _ => job.callback.clone().map(|callback| (callback, job.report(id))),
+        };
+        self.save();
+        (result, callback)
+    }
+
+    pub fn list(&self) -> Vec<JobStatus> {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.prune(&mut jobs);
+        let mut list: Vec<_> = jobs
+            .iter()
+            .map(|(id, job)| JobStatus {
+                id: id.clone(),
+                tenant: job.request.tenant.clone(),
+                method: job.request.method.clone(),
+                url: job.request.url.clone(),
+                status: job.status(),
+                attempts: job.attempts,
+                last_error: job.last_error.clone(),
+                created: DateTime::<Utc>::from_timestamp(job.created as i64, 0)
+                    .unwrap_or_default()
+                    .to_rfc3339(),
+            })
+            .collect();
+        list.sort_by(|a, b| a.created.cmp(&b.created));
+        list
+    }
+
+    /// Queues a dead job again with a fresh set of attempts, unless it lost
+    /// its client credentials in a restart.
+    pub fn retry(&self, id: &str) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs
+            .get_mut(id)
+            .filter(|job| matches!(job.state, JobState::Dead) && !job.request.credentials_dropped)
+        else {
+            return false;
+        };
+        job.state = JobState::Queued;
+        job.attempts = 0;
+        job.next_attempt = Instant::now();
+        self.save();
+        drop(jobs);
+        self.wake.notify_one();
+        true
+    }
+
+    pub fn remove(&self, id: &str) -> bool {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.get(id).is_none_or(|job| matches!(job.state, JobState::Running)) {
+            return false;
+        }
+        jobs.remove(id);
+        self.save();
+        true
+    }
+}
+
+/// Hands due jobs to the workers as they free up, woken by new jobs and
+/// otherwise checking every second for retries coming due.
+pub fn spawn(state: Arc<AppState>) {
+    if !state.async_jobs.enabled() {
+        return;
+    }
+    if let Some(path) = state.async_jobs.path.clone() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                state.async_jobs.changed.notified().await;
+                let stored = state.async_jobs.stored();
+                let path = path.clone();
+                let result = tokio::task::spawn_blocking(move || write(&path, &stored)).await;
+                if let Err(err) = result.map_err(anyhow::Error::from).and_then(|written| written) {
+                    warn!("Failed to save the job queue: {:?}", err);
+                }
+            }
+        });
+    }
+    tokio::spawn(async move {
+        loop {
+            let Ok(permit) = state.async_jobs.workers.clone().acquire_owned().await else {
+                return;
+            };
+            match state.async_jobs.next() {
+                Some((id, request)) => {
+                    tokio::spawn(run(state.clone(), id, request, permit));
+                }
+                None => {
+                    drop(permit);
+                    let _ = tokio::time::timeout(Duration::from_secs(1), state.async_jobs.wake.notified()).await;
+                }
+            }
+        }
+    });
+}
+
+fn write(path: &Path, stored: &[StoredJob]) -> Result<()> {
+    let contents = json::to_string(&stored)?;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+// Whether the job can be sent again when it may already have reached
+// Roblox.
+fn repeatable(request: &QueuedRequest) -> bool {
+    matches!(request.method.as_str(), "GET" | "HEAD")
+        || request
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("idempotency-key"))
+}
+
+async fn run(state: Arc<AppState>, id: String, request: QueuedRequest, _permit: OwnedSemaphorePermit) {
+    debug!("Running job {} for {}", id, request.url);
+    let repeatable = repeatable(&request);
+    let outcome = match request.prepared() {
+        Ok(prepared) => envelope::send(&state, prepared).await,
+        Err(err) => Err(err),
+    };
+    let (outcome, retry) = match outcome {
+        Ok(response) => {
+            let retry = (response.status == Status::TooManyRequests
+                || (repeatable && response.status.class() == StatusClass::ServerError))
+                .then(|| format!("Upstream answered {}", response.status));
+            (Ok(response), retry)
+        }
+        Err(err) => match err.downcast::<Rejection>() {
+            Ok(rejection) => {
+                // An exhausted budget, or anything else out of capacity for
+                // now; both clear up with time.
+                let retry = [Status::TooManyRequests, Status::ServiceUnavailable]
+                    .contains(&rejection.status)
+                    .then(|| rejection.to_string());
+                (Err(rejection), retry)
+            }
+            Err(err) => {
+                let message = format!("{:#}", err);
+                (Err(Rejection::new(Status::BadGateway, "The upstream request failed")), repeatable.then_some(message))
+            }
+        },
+    };
+    let (result, callback) = state.async_jobs.finish(&id, outcome, retry);
+    state.metrics.incr("roproxy_async_jobs_total", &[("result", result)]);
+    if let Some((url, report)) = callback {
+        state.callbacks.deliver(url, report);
+    }
+}
+
+#[post("/async", data = "<envelope>")]
+async fn submit(
+    envelope: Json<Envelope>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<(Status, Json<Value>), ErrorResponse> {
+    let prepared = envelope::prepare(envelope.into_inner(), state, guard.request).await?;
+    let callback = state.callbacks.requested(guard.request)?;
+    let jobs = &state.async_jobs;
+    let id = new_id();
+    {
+        let mut queue = jobs.jobs.lock().unwrap();
+        jobs.prune(&mut queue);
+        let pending = queue
+            .values()
+            .filter(|job| matches!(job.state, JobState::Queued | JobState::Running))
+            .count();
+        if pending >= jobs.config.max_queued {
+            state.metrics.incr("roproxy_async_jobs_total", &[("result", "rejected")]);
+            return Err(ErrorResponse(
+                Rejection::new(Status::ServiceUnavailable, "Too many queued jobs")
+                    .with_header("Retry-After", 30)
+                    .into(),
+            ));
+        }
+        queue.insert(
+            id.clone(),
+            Job {
+                request: QueuedRequest::new(prepared),
+                state: JobState::Queued,
+                attempts: 0,
+                last_error: None,
+                created: unix_now(),
+                next_attempt: Instant::now(),
+                finished: None,
+                callback,
+            },
+        );
+        jobs.save();
+    }
+    jobs.wake.notify_one();
+    state.metrics.incr("roproxy_async_jobs_total", &[("result", "queued")]);
+
+    Ok((
+        Status::Accepted,
+        Json(json!({ "id": id, "status": "queued", "result": format!("/async/{}", id) })),
+    ))
+}
+
+/// The tenant whose jobs the caller may see, `None` without tenants.
+pub fn owner(state: &AppState, req: &Request<'_>) -> Result<Option<String>, ErrorResponse> {
+    if state.tenants.is_empty() {
+        return Ok(None);
+    }
+    let api_key = client_cert::api_key(req);
+    let Some((tenant, _)) = state.tenants.resolve(api_key, Path::new("")) else {
+        return Err(ErrorResponse(
+            Rejection::new(Status::Unauthorized, "Unknown or missing proxy key").into(),
+        ));
+    };
+    // Polling isn't rate limited, but a revoked or expired key still can't
+    // read what it submitted.
+    if let Some(key) = api_key.and_then(|api_key| tenant.key(api_key)) {
+        key.check_active(&state.metrics)?;
+    }
+    Ok(Some(tenant.name.clone()))
+}
+
+/// The job's upstream response once it has run, or a 202 with its status
+/// until then. Only the tenant that submitted a job can see it.
+#[get("/async/<id>")]
+fn result(id: &str, state: &State<Arc<AppState>>, guard: MyRequestGuard<'_>) -> Result<ProxyResponse, ErrorResponse> {
+    let tenant = owner(state, guard.request)?;
+    let jobs = &state.async_jobs;
+    let mut queue = jobs.jobs.lock().unwrap();
+    jobs.prune(&mut queue);
+    let Some(job) = queue.get(id).filter(|job| job.request.tenant == tenant) else {
+        return Err(ErrorResponse(Rejection::new(Status::NotFound, format!("No job {}", id)).into()));
+    };
+    match &job.state {
+        JobState::Done(Ok(response)) => return Ok(response.clone()),
+        JobState::Done(Err(rejection)) => return Err(ErrorResponse(rejection.clone().into())),
+        JobState::Dead => return Err(ErrorResponse(job.dead_letter().into())),
+        JobState::Queued | JobState::Running => {}
+    }
+    Ok(ProxyResponse {
+        status: Status::Accepted,
+        content_type: "application/json".to_string(),
+        body: json!({ "id": id, "status": job.status(), "attempts": job.attempts })
+            .to_string()
+            .into_bytes(),
+        headers: vec![("Retry-After".to_string(), "1".to_string())],
+        stream: None,
+        credential: None,
+    })
+}
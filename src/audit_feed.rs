@@ -0,0 +1,234 @@
+use crate::{
+    client_cert,
+    config::{AuditFeedGroupConfig, AuditFeedsConfig},
+    helpers, AppState, ErrorResponse, MyRequestGuard, Rejection,
+};
+use anyhow::{anyhow, Result};
+use rocket::{
+    http::Status,
+    serde::json::{json, Json, Value},
+    Route, State,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{debug, info, warn};
+
+/// Pages read per poll when catching up after a burst of activity.
+const MAX_PAGES: usize = 5;
+
+const MAX_LIMIT: usize = 100;
+
+pub fn routes() -> Vec<Route> {
+    routes![get_audit_feed]
+}
+
+#[derive(Default)]
+struct Feed {
+    /// Entries with the sequence number they were given, oldest first.
+    entries: VecDeque<(u64, Value)>,
+    /// Fingerprints of `entries`; Roblox gives audit log entries no ID.
+    seen: HashSet<[u8; 32]>,
+    last_id: u64,
+    seeded: bool,
+}
+
+fn fingerprint(entry: &Value) -> [u8; 32] {
+    Sha256::digest(entry.to_string()).into()
+}
+
+/// Group audit logs, polled in the background and numbered as they're
+/// found, so clients can ask for everything after the last entry they saw
+/// instead of paging through Roblox's newest-first cursors themselves.
+pub struct AuditFeeds {
+    feeds: HashMap<u64, Mutex<Feed>>,
+    max_entries: usize,
+}
+
+impl AuditFeeds {
+    pub fn new(config: &AuditFeedsConfig) -> Self {
+        AuditFeeds {
+            feeds: config
+                .groups
+                .iter()
+                .map(|group| (group.group_id, Mutex::default()))
+                .collect(),
+            max_entries: config.max_entries,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.feeds.is_empty()
+    }
+
+    fn seen(&self, group_id: u64, entry: &Value) -> bool {
+        self.feeds
+            .get(&group_id)
+            .is_some_and(|feed| feed.lock().unwrap().seen.contains(&fingerprint(entry)))
+    }
+
+    fn seeded(&self, group_id: u64) -> bool {
+        self.feeds
+            .get(&group_id)
+            .is_some_and(|feed| feed.lock().unwrap().seeded)
+    }
+
+    // Adds `entries`, oldest first, returning them with their numbers.
+    fn ingest(&self, group_id: u64, entries: Vec<Value>) -> Vec<(u64, Value)> {
+        let Some(feed) = self.feeds.get(&group_id) else {
+            return Vec::new();
+        };
+        let mut feed = feed.lock().unwrap();
+        feed.seeded = true;
+        let mut added = Vec::new();
+        for entry in entries {
+            if !feed.seen.insert(fingerprint(&entry)) {
+                continue;
+            }
+            feed.last_id += 1;
+            let id = feed.last_id;
+            feed.entries.push_back((id, entry.clone()));
+            added.push((id, entry));
+        }
+        while feed.entries.len() > self.max_entries {
+            if let Some((_, entry)) = feed.entries.pop_front() {
+                feed.seen.remove(&fingerprint(&entry));
+            }
+        }
+        added
+    }
+
+    /// Up to `limit` entries numbered after `after`, or the latest ones
+    /// without it, and the cursor to pass next time.
+    pub fn since(&self, group_id: u64, after: Option<u64>, limit: usize) -> Option<(u64, Vec<Value>)> {
+        let feed = self.feeds.get(&group_id)?.lock().unwrap();
+        let entries: Vec<_> = match after {
+            Some(after) => feed.entries.iter().filter(|(id, _)| *id > after).take(limit).collect(),
+            None => feed.entries.iter().skip(feed.entries.len().saturating_sub(limit)).collect(),
+        };
+        let cursor = entries.last().map_or(after.unwrap_or(feed.last_id), |(id, _)| *id);
+        Some((
+            cursor,
+            entries
+                .into_iter()
+                .map(|(id, entry)| json!({ "id": id, "entry": entry }))
+                .collect(),
+        ))
+    }
+}
+
+/// Starts one poller per configured group.
+pub fn spawn(state: Arc<AppState>, config: &AuditFeedsConfig) -> Result<()> {
+    for group in &config.groups {
+        let pool = group.pool.as_deref().unwrap_or("default");
+        if state.credential_pool(pool).is_none() {
+            return Err(anyhow!("Audit feed for group {} uses unknown pool {}", group.group_id, pool));
+        }
+        info!("Polling the audit log of group {} every {}s", group.group_id, config.poll_secs);
+        tokio::spawn(run(state.clone(), group.clone(), Duration::from_secs(config.poll_secs)));
+    }
+    Ok(())
+}
+
+async fn run(state: Arc<AppState>, group: AuditFeedGroupConfig, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        // What the first poll finds isn't news.
+        let seeded = state.audit_feeds.seeded(group.group_id);
+        let added = match poll(&state, &group).await {
+            Ok(added) => added,
+            Err(err) => {
+                warn!("Failed to poll the audit log of group {}: {:?}", group.group_id, err);
+                state
+                    .metrics
+                    .incr("roproxy_audit_feed_errors_total", &[("group", &group.group_id.to_string())]);
+                continue;
+            }
+        };
+        if added.is_empty() {
+            continue;
+        }
+        debug!("Found {} new audit log entries for group {}", added.len(), group.group_id);
+        state.metrics.add(
+            "roproxy_audit_feed_entries_total",
+            &[("group", &group.group_id.to_string())],
+            added.len() as u64,
+        );
+        if let (Some(webhook), true) = (&group.webhook, seeded) {
+            notify(&state, group.group_id, webhook, added).await;
+        }
+    }
+}
+
+// Reads newest-first until it reaches an entry it has already seen. The
+// first poll only takes the latest page rather than the whole history.
+async fn poll(state: &AppState, group: &AuditFeedGroupConfig) -> Result<Vec<(u64, Value)>> {
+    let feeds = &state.audit_feeds;
+    let pool = state
+        .credential_pool(group.pool.as_deref().unwrap_or("default"))
+        .ok_or_else(|| anyhow!("Unknown credential pool"))?;
+    let url = format!(
+        "https://groups.roblox.com/v1/groups/{}/audit-log?limit=100&sortOrder=Desc",
+        group.group_id
+    );
+    let seeded = feeds.seeded(group.group_id);
+
+    let mut fresh = Vec::new();
+    let mut cursor = None;
+    for _ in 0..MAX_PAGES {
+        let page = helpers::get_json(state, pool, &helpers::page_url(&url, cursor.as_deref())?).await?;
+        let entries = page["data"].as_array().cloned().unwrap_or_default();
+        let caught_up = entries.iter().any(|entry| feeds.seen(group.group_id, entry));
+        fresh.extend(entries.into_iter().take_while(|entry| !feeds.seen(group.group_id, entry)));
+        cursor = page["nextPageCursor"].as_str().map(str::to_string);
+        if caught_up || !seeded || cursor.is_none() {
+            break;
+        }
+    }
+    fresh.reverse();
+    Ok(feeds.ingest(group.group_id, fresh))
+}
+
+async fn notify(state: &AppState, group_id: u64, webhook: &str, added: Vec<(u64, Value)>) {
+    // `content` makes the payload readable as-is by Discord-style webhooks.
+    let payload = json!({
+        "content": format!("Group {} audit log: {} new", group_id, added.len()),
+        "groupId": group_id,
+        "entries": added
+            .into_iter()
+            .map(|(id, entry)| json!({ "id": id, "entry": entry }))
+            .collect::<Vec<_>>(),
+    });
+    match state.engine.client.post(webhook).json(&payload).send().await {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!("Audit feed webhook for group {} returned {}", group_id, response.status()),
+        Err(err) => warn!("Failed to send audit log entries for group {}: {:?}", group_id, err),
+    }
+}
+
+/// New audit log entries for a polled group, oldest first. Pass the returned
+/// `cursor` as `after` to get only what's new since.
+#[get("/helpers/groups/<group_id>/audit-feed?<after>&<limit>")]
+fn get_audit_feed(
+    group_id: u64,
+    after: Option<u64>,
+    limit: Option<usize>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let req = guard.request;
+    state.screen_client(req)?;
+    state.tenants.authenticate(client_cert::api_key(req), &state.metrics)?;
+    let limit = limit.unwrap_or(MAX_LIMIT).clamp(1, MAX_LIMIT);
+
+    let Some((cursor, entries)) = state.audit_feeds.since(group_id, after, limit) else {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, format!("Group {} has no audit feed", group_id)).into(),
+        ));
+    };
+    Ok(Json(json!({ "groupId": group_id, "cursor": cursor, "entries": entries })))
+}
@@ -0,0 +1,65 @@
+use crate::{config::AuditLogConfig, economy, request_log::RequestLog, servers};
+use anyhow::{Context, Result};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    serde::json,
+    Request, Response,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::Mutex,
+};
+use tracing::{info, warn};
+
+/// Records every call to a sensitive upstream once it's answered, whichever
+/// route it came in by and whether or not it was let through.
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    pub fn new(config: &AuditLogConfig) -> Result<Self> {
+        let file = config
+            .path
+            .as_ref()
+            .map(|path| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open audit log {}", path))
+            })
+            .transpose()?;
+        Ok(AuditLog {
+            file: file.map(Mutex::new),
+        })
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for AuditLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Audit log",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let entry = RequestLog::new(req, res.status().code);
+        let audited = |url: &str| economy::is_economy(url) || servers::is_server_control(url);
+        if !entry.upstream.as_deref().is_some_and(audited) {
+            return;
+        }
+        let Ok(line) = json::to_string(&entry) else {
+            return;
+        };
+        info!(target: "audit", "{}", line);
+        if let Some(file) = &self.file {
+            if let Err(err) = writeln!(file.lock().unwrap(), "{}", line) {
+                warn!("Failed to write to the audit log: {:?}", err);
+            }
+        }
+    }
+}
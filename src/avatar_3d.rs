@@ -0,0 +1,182 @@
+use crate::{
+    helpers::{self, Upstream},
+    AppState, ErrorResponse, MyRequestGuard, Rejection,
+};
+use anyhow::{Context, Result};
+use rocket::{
+    futures::{future::try_join_all, stream},
+    http::{ContentType, Status},
+    response::{self, stream::ReaderStream, Responder, Response},
+    serde::json::Value,
+    Request, State,
+};
+use std::{
+    io::{Cursor, Write},
+    sync::Arc,
+};
+use tracing::warn;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+/// Where Roblox's CDN serves the file with content hash `hash`, picking the
+/// host the way its own clients do.
+pub fn cdn_url(hash: &str) -> String {
+    let host = hash.bytes().take(32).fold(31, |i, byte| i ^ byte) % 8;
+    format!("https://t{}.rbxcdn.com/{}", host, hash)
+}
+
+fn valid_hash(hash: &str) -> bool {
+    !hash.is_empty() && hash.len() <= 128 && hash.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+async fn download(state: &AppState, hash: &str) -> Result<reqwest::Response> {
+    state
+        .engine
+        .client
+        .get(cdn_url(hash))
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to fetch {} from the CDN", hash))
+}
+
+pub enum Avatar3d {
+    Zip { user_id: u64, body: Vec<u8> },
+    /// Relayed from the CDN as it arrives.
+    File(reqwest::Response),
+}
+
+impl<'r> Responder<'r, 'static> for Avatar3d {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        match self {
+            Avatar3d::Zip { user_id, body } => {
+                response
+                    .header(ContentType::ZIP)
+                    .raw_header(
+                        "Content-Disposition",
+                        format!("attachment; filename=\"avatar-3d-{}.zip\"", user_id),
+                    )
+                    .sized_body(body.len(), Cursor::new(body));
+            }
+            Avatar3d::File(file) => {
+                let content_type = file
+                    .headers()
+                    .get("content-type")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(ContentType::parse_flexible)
+                    .unwrap_or(ContentType::Binary);
+                let chunks = stream::unfold(file, |mut file| async move {
+                    match file.chunk().await {
+                        Ok(chunk) => chunk.map(|chunk| (Cursor::new(chunk), file)),
+                        Err(err) => {
+                            warn!("CDN download failed midway: {:?}", err);
+                            None
+                        }
+                    }
+                });
+                response.header(content_type).streamed_body(ReaderStream::from(chunks));
+            }
+        }
+        response.ok()
+    }
+}
+
+/// A user's 3D avatar: its OBJ, MTL and textures zipped under their content
+/// hashes, which is how they refer to each other, with the manifest as
+/// `manifest.json`. `?file=<hash>` streams just that file, and
+/// `?file=manifest` the manifest listing them. While Roblox is still
+/// rendering the avatar it's a 503 to retry.
+#[get("/helpers/users/<user_id>/avatar-3d?<file>")]
+pub async fn avatar_3d(
+    user_id: u64,
+    file: Option<&str>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Avatar3d, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "avatar_3d")]);
+
+    // Uncached, so a render that's pending now is picked up once it's done.
+    let thumbnail = upstream
+        .get_fresh(&format!(
+            "https://thumbnails.roblox.com/v1/users/avatar-3d?userId={}",
+            user_id
+        ))
+        .await
+        .map_err(helpers::rejection)?;
+    let manifest_hash = match thumbnail["state"].as_str() {
+        Some("Completed") => thumbnail["imageUrl"]
+            .as_str()
+            .and_then(|url| url.rsplit('/').next())
+            .filter(|hash| valid_hash(hash))
+            .ok_or_else(|| {
+                ErrorResponse(Rejection::new(Status::BadGateway, "Roblox returned no 3D avatar manifest").into())
+            })?,
+        Some("Pending") => {
+            return Err(ErrorResponse(
+                Rejection::new(
+                    Status::ServiceUnavailable,
+                    format!("User {}'s 3D avatar is still being rendered", user_id),
+                )
+                .with_header("Retry-After", 2)
+                .into(),
+            ))
+        }
+        other => {
+            return Err(ErrorResponse(
+                Rejection::new(Status::NotFound, format!("User {} has no 3D avatar", user_id))
+                    .with_field("state", other)
+                    .into(),
+            ))
+        }
+    };
+
+    if file == Some("manifest") {
+        let manifest = download(state, manifest_hash).await.map_err(helpers::rejection)?;
+        return Ok(Avatar3d::File(manifest));
+    }
+    let manifest: Value = download(state, manifest_hash)
+        .await
+        .map_err(helpers::rejection)?
+        .json()
+        .await
+        .map_err(|err| helpers::rejection(anyhow::Error::new(err).context("Invalid 3D avatar manifest")))?;
+    let hashes: Vec<&str> = [&manifest["obj"], &manifest["mtl"]]
+        .into_iter()
+        .chain(manifest["textures"].as_array().into_iter().flatten())
+        .filter_map(Value::as_str)
+        .filter(|hash| valid_hash(hash))
+        .collect();
+
+    if let Some(file) = file {
+        if !hashes.contains(&file) {
+            return Err(ErrorResponse(
+                Rejection::new(Status::NotFound, format!("{} isn't part of user {}'s 3D avatar", file, user_id))
+                    .with_field("files", hashes)
+                    .into(),
+            ));
+        }
+        let file = download(state, file).await.map_err(helpers::rejection)?;
+        return Ok(Avatar3d::File(file));
+    }
+    let files = try_join_all(hashes.iter().map(|hash| async move {
+        let body = download(state, hash).await?.bytes().await?.to_vec();
+        anyhow::Ok((*hash, body))
+    }))
+    .await
+    .map_err(helpers::rejection)?;
+    let body = zip(&manifest, files)?;
+    Ok(Avatar3d::Zip { user_id, body })
+}
+
+fn zip(manifest: &Value, files: Vec<(&str, Vec<u8>)>) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    zip.start_file("manifest.json", options)?;
+    zip.write_all(manifest.to_string().as_bytes())?;
+    for (hash, body) in files {
+        zip.start_file(hash, options)?;
+        zip.write_all(&body)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
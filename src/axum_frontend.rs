@@ -0,0 +1,346 @@
+use crate::{
+    binary, body_too_large, cache, challenge, check_header_limits, check_policies,
+    config::AxumConfig,
+    connections::{self, ConnectionLimits},
+    credentials, csv_export, engine, identity, integrity, operations, projection, rewrite, signing, tags, AppState,
+    CacheKey, ProxyResponse, Rejection, UpstreamRequest, MAX_BODY, PROXY_HEADERS,
+};
+use anyhow::{Context, Result};
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, Uri},
+    response::Response,
+    Router,
+};
+use rocket::{
+    futures::stream,
+    http::{Method, Status},
+    serde::json::json,
+    tokio::io::AsyncReadExt,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+};
+use tracing::{error, info};
+
+/// Proxy features that need a Rocket request, refused here rather than
+/// silently ignored.
+const UNSUPPORTED_HEADERS: [&str; 7] = [
+    "X-Proxy-Session",
+    "Idempotency-Key",
+    "X-HTTP-Method-Override",
+    binary::REQUEST_HEADER,
+    binary::RESPONSE_HEADER,
+    integrity::HEADER,
+    integrity::REQUEST_HEADER,
+];
+
+#[derive(Clone)]
+struct Frontend {
+    state: Arc<AppState>,
+    limits: Arc<ConnectionLimits>,
+}
+
+/// Serves the catch-all proxy route on `axum.port` with axum, on top of the
+/// same `ProxyEngine`, tenants and policies as the Rocket listener. Clients
+/// are screened, authenticated, rate limited and checked the same way, and
+/// GETs share the response cache.
+///
+/// Only the plain route is served: helpers, envelopes, jobs and the admin
+/// API stay on Rocket, and so do sessions, idempotency keys, signed URLs,
+/// method overrides, base64 and integrity headers, which are refused here.
+/// There's no TLS or client certificates, and the client IP is the peer
+/// address, never a forwarding header.
+pub async fn spawn(state: Arc<AppState>, limits: Arc<ConnectionLimits>, config: &AxumConfig) -> Result<()> {
+    let Some(port) = config.port else {
+        return Ok(());
+    };
+    let listener = tokio::net::TcpListener::bind((config.address, port))
+        .await
+        .with_context(|| format!("Failed to bind the axum listener to {}:{}", config.address, port))?;
+    info!("Serving the proxy route with axum on {}:{}", config.address, port);
+    let app = Router::new().fallback(proxy).with_state(Frontend { state, limits });
+    tokio::spawn(async move {
+        let service = app.into_make_service_with_connect_info::<SocketAddr>();
+        if let Err(err) = axum::serve(listener, service).await {
+            error!("Axum listener failed: {}", err);
+        }
+    });
+    Ok(())
+}
+
+async fn proxy(
+    State(frontend): State<Frontend>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+) -> Response {
+    let ip = peer.ip();
+    let _slot = match frontend.limits.acquire(Some(ip)) {
+        Ok(slot) => slot,
+        Err(scope) => return rejected(&connections::rejection(Some(scope))),
+    };
+    let path = request.uri().path().to_string();
+    let response = match handle(&frontend.state, ip, request).await {
+        Ok(response) => respond(response),
+        Err(err) => match err.downcast_ref::<Rejection>() {
+            Some(rejection) => {
+                info!("Rejected request: {}", rejection);
+                rejected(rejection)
+            }
+            None => {
+                error!("{:?}", err);
+                let mut response = Response::new(Body::from("Internal Server Error"));
+                *response.status_mut() = axum::http::StatusCode::INTERNAL_SERVER_ERROR;
+                response
+            }
+        },
+    };
+    frontend.state.abuse.record(ip, &path, response.status().as_u16());
+    response
+}
+
+// The same steps as `handle_request`, for what this listener supports.
+async fn handle(state: &AppState, ip: IpAddr, request: Request) -> Result<ProxyResponse> {
+    let (parts, body) = request.into_parts();
+    let headers = &parts.headers;
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    state.screen(Some(ip), header("User-Agent"))?;
+    if let Some(name) = UNSUPPORTED_HEADERS.iter().find(|name| headers.contains_key(**name)) {
+        return Err(Rejection::new(Status::NotImplemented, format!("{} isn't supported on this listener", name)).into());
+    }
+    let method = match parts.method.as_str() {
+        "GET" => Method::Get,
+        "POST" => Method::Post,
+        "PUT" => Method::Put,
+        "DELETE" => Method::Delete,
+        _ => return Err(Rejection::new(Status::MethodNotAllowed, "Unsupported method").into()),
+    };
+
+    let mut params: Vec<(String, String)> = form_urlencoded::parse(parts.uri.query().unwrap_or_default().as_bytes())
+        .into_owned()
+        .collect();
+    if let Some((name, _)) = params
+        .iter()
+        .find(|(name, _)| [signing::SIGNATURE, operations::PARAM].contains(&name.as_str()))
+    {
+        return Err(Rejection::new(Status::NotImplemented, format!("{} isn't supported on this listener", name)).into());
+    }
+    let param = |name: &str| params.iter().find(|(param, _)| param == name).map(|(_, value)| value.clone());
+    let fields = param(projection::PARAM);
+    let csv = csv_export::wants_csv(param(csv_export::FORMAT).as_deref())?;
+    let columns = param(csv_export::COLUMNS);
+    params.retain(|(name, _)| ![projection::PARAM, csv_export::FORMAT, csv_export::COLUMNS].contains(&name.as_str()));
+    params.sort();
+
+    // Buffered, as the body is small and this keeps the listener simple.
+    let declared = header("Content-Length").and_then(|length| length.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > MAX_BODY.as_u64()) {
+        return Err(body_too_large().into());
+    }
+    let body = tokio::time::timeout(state.body_timeout, axum::body::to_bytes(body, MAX_BODY.as_u64() as usize))
+        .await
+        .map_err(|_| Rejection::new(Status::RequestTimeout, "Timed out reading the request body"))?
+        .map_err(|_| body_too_large())?;
+    let body = (method != Method::Get && !body.is_empty()).then_some(body);
+
+    let (url, mut response) = proxy_request(state, ip, method, &parts.uri, headers, params, body).await?;
+    state.engine.budgets.annotate(&url, &mut response);
+    let response = state.transforms.apply(&url, response, &state.metrics);
+    let response = match fields {
+        Some(fields) => projection::apply(response, &fields),
+        None => response,
+    };
+    Ok(if csv {
+        csv_export::apply(response, columns.as_deref())
+    } else {
+        response
+    })
+}
+
+// The same steps as `proxy_request`, for what this listener supports.
+async fn proxy_request(
+    state: &AppState,
+    ip: IpAddr,
+    method: Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    params: Vec<(String, String)>,
+    body: Option<Bytes>,
+) -> Result<(String, ProxyResponse)> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let api_key = header("X-Proxy-Key");
+    let mut path = uri.path().trim_start_matches('/').to_string();
+    let tenant = if state.tenants.is_empty() {
+        None
+    } else {
+        let Some((tenant, rest)) = state.tenants.resolve(api_key, Path::new(&path)) else {
+            return Err(Rejection::new(Status::Unauthorized, "Unknown or missing proxy key").into());
+        };
+        state
+            .metrics
+            .incr("roproxy_tenant_requests_total", &[("tenant", &tenant.name)]);
+        let key = api_key.and_then(|api_key| tenant.key(api_key));
+        if let Some(key) = key {
+            key.check_active(&state.metrics)?;
+        }
+        tenant.check_rate_limit(&state.metrics)?;
+        if let Some(key) = key {
+            key.check_tag(tags::parse(header(tags::HEADER))?, &state.metrics)?;
+            key.bandwidth().check(key.label(), &state.metrics)?;
+        }
+        path = rest.to_string_lossy().into_owned();
+        Some(tenant)
+    };
+    let namespace = tenant.as_ref().map(|tenant| tenant.name.as_str());
+    let key = tenant
+        .as_ref()
+        .zip(api_key)
+        .and_then(|(tenant, api_key)| tenant.key(api_key));
+
+    let mut target = path;
+    if !params.is_empty() {
+        target.push('?');
+        target.push_str(&form_urlencoded::Serializer::new(String::new()).extend_pairs(&params).finish());
+    }
+    let url = match header(rewrite::UPSTREAM_HEADER) {
+        Some(upstream) => state.upstream_targets.resolve(upstream, key, &target, &state.metrics)?,
+        None => match state.rewrites.apply(&target, &state.metrics) {
+            Some(url) => url,
+            None => format!("https://www.roblox.com/{}", target),
+        },
+    };
+    let url = state.engine.check_url(&url)?;
+    check_policies(state, Some(ip), key, method, &url).await?;
+    let identity = header(identity::HEADER);
+    state.engine.check_identity(identity, &url)?;
+
+    let mut forwarded = Vec::new();
+    for (name, value) in headers {
+        if let (false, Ok(value)) = (PROXY_HEADERS.contains(&name.as_str()), value.to_str()) {
+            forwarded.push((name.to_string(), value.to_string()));
+        }
+    }
+    check_header_limits(state, &forwarded)?;
+    let (pool_name, pool) = match &tenant {
+        Some(tenant) => (tenant.name.as_str(), &tenant.credentials),
+        None => ("default", &state.credentials),
+    };
+    let credential = challenge::challenge_id(&forwarded)
+        .and_then(|id| state.challenges.credential(pool_name, pool, id))
+        .or_else(|| pool.pick_for(credentials::affinity_of(None, api_key, Some(ip)).as_deref()));
+
+    let cacheable = engine::cacheable(method, false, |name| headers.contains_key(name));
+    let cache_ttl = match header(cache::TTL_HEADER) {
+        Some(value) if cacheable => Some(state.engine.cache.client_ttl(value, key)?),
+        _ => None,
+    };
+    let cache_key =
+        CacheKey::new(namespace, url.clone()).with_account(credential.as_ref().map(|credential| credential.name.as_str()));
+    if cacheable {
+        if let Some(response) = state.engine.cached(&cache_key, cache_ttl) {
+            return Ok((url, response));
+        }
+    }
+
+    let client = match &tenant {
+        Some(tenant) => format!("{} ({})", ip, tenant.name),
+        None => ip.to_string(),
+    };
+    let inflight = state.inflight.register(method.as_str(), &url, client);
+    let request = UpstreamRequest {
+        method,
+        url: url.clone(),
+        headers: forwarded,
+        body: body.map(Into::into),
+        credential: None,
+        identity: identity.map(str::to_string),
+        timeout: None,
+    }
+    .with_credential(credential);
+    let result = tokio::select! {
+        response = state.engine.forward(request) => response,
+        _ = inflight.cancelled() => {
+            state.metrics.incr("roproxy_requests_cancelled_total", &[]);
+            return Err(Rejection::new(
+                Status::ServiceUnavailable,
+                "Request was cancelled by an administrator",
+            )
+            .into());
+        }
+    };
+    if cacheable {
+        if let Some(stale) = state.engine.stale_on_error(&cache_key, &result) {
+            return Ok((url, stale));
+        }
+    }
+    let mut response = result?;
+    state.challenges.observe(pool_name, &response, &state.metrics);
+    if let Some(tenant) = &tenant {
+        state.metrics.incr(
+            "roproxy_tenant_responses_total",
+            &[("tenant", &tenant.name), ("status", response.status.code.to_string().as_str())],
+        );
+    }
+    if cacheable {
+        state.engine.store(&cache_key, &mut response, cache_ttl);
+    }
+    Ok((url, response))
+}
+
+fn respond(proxied: ProxyResponse) -> Response {
+    let mut response = match proxied.stream {
+        Some(stream) => {
+            let reader = Box::pin(stream.into_reader());
+            let chunks = stream::unfold(reader, |mut reader| async move {
+                let mut chunk = Vec::with_capacity(8 * 1024);
+                match reader.read_buf(&mut chunk).await {
+                    Ok(0) => None,
+                    Ok(_) => Some((Ok(Bytes::from(chunk)), reader)),
+                    Err(err) => Some((Err(err), reader)),
+                }
+            });
+            let mut response = Response::new(Body::from_stream(chunks));
+            // Stops buffering reverse proxies from holding events back.
+            response
+                .headers_mut()
+                .insert("X-Accel-Buffering", HeaderValue::from_static("no"));
+            response
+        }
+        None => Response::new(Body::from(proxied.body)),
+    };
+    *response.status_mut() = axum::http::StatusCode::from_u16(proxied.status.code).unwrap_or_default();
+    let headers = response.headers_mut();
+    if let Ok(content_type) = HeaderValue::from_str(&proxied.content_type) {
+        headers.insert("Content-Type", content_type);
+    }
+    // `content_type` wins over the upstream header, which it may correct.
+    for (name, value) in proxied.headers {
+        if ["content-length", "content-type"].contains(&name.to_lowercase().as_str()) {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(&value)) {
+            headers.append(name, value);
+        }
+    }
+    response
+}
+
+fn rejected(rejection: &Rejection) -> Response {
+    let mut body = json!({ "error": rejection.message });
+    for (name, value) in &rejection.fields {
+        body[name] = value.clone();
+    }
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = axum::http::StatusCode::from_u16(rejection.status.code).unwrap_or_default();
+    let headers = response.headers_mut();
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+    for (name, value) in &rejection.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            headers.append(name, value);
+        }
+    }
+    response
+}
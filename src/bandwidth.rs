@@ -0,0 +1,186 @@
+use crate::{client_cert, metrics::Metrics, AppState, Rejection};
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, Utc};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Status,
+    serde::Serialize,
+    Request, Response,
+};
+use std::sync::{Arc, Mutex};
+
+/// Bytes a proxy key has moved today and this month (UTC), and the quotas
+/// they're held to. Kept in memory, so a restart starts the count over; the
+/// `roproxy_key_bytes_total` metric is the durable record for billing.
+pub struct Bandwidth {
+    daily_quota: Option<u64>,
+    monthly_quota: Option<u64>,
+    usage: Mutex<Usage>,
+}
+
+struct Usage {
+    day: NaiveDate,
+    day_bytes: u64,
+    month_bytes: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BandwidthStatus {
+    pub day_bytes: u64,
+    pub daily_quota: Option<u64>,
+    pub month_bytes: u64,
+    pub monthly_quota: Option<u64>,
+}
+
+impl Usage {
+    // Starts the day's and, on the first, the month's count over.
+    fn roll(&mut self, today: NaiveDate) {
+        if today == self.day {
+            return;
+        }
+        if (today.year(), today.month()) != (self.day.year(), self.day.month()) {
+            self.month_bytes = 0;
+        }
+        self.day = today;
+        self.day_bytes = 0;
+    }
+}
+
+impl Bandwidth {
+    pub fn new(daily_quota: Option<u64>, monthly_quota: Option<u64>) -> Self {
+        Bandwidth {
+            daily_quota,
+            monthly_quota,
+            usage: Mutex::new(Usage {
+                day: Utc::now().date_naive(),
+                day_bytes: 0,
+                month_bytes: 0,
+            }),
+        }
+    }
+
+    /// Turns the request away with a 429 if the key used up a quota, until
+    /// the day or month it's for is over.
+    pub fn check(&self, label: &str, metrics: &Metrics) -> Result<()> {
+        let now = Utc::now();
+        let mut usage = self.usage.lock().unwrap();
+        usage.roll(now.date_naive());
+        let exceeded = if self.monthly_quota.is_some_and(|quota| usage.month_bytes >= quota) {
+            Some(("monthly", next_month(now)))
+        } else if self.daily_quota.is_some_and(|quota| usage.day_bytes >= quota) {
+            Some(("daily", next_day(now)))
+        } else {
+            None
+        };
+        let Some((period, resets)) = exceeded else {
+            return Ok(());
+        };
+        metrics.incr("roproxy_bandwidth_quota_exceeded_total", &[("key", label), ("period", period)]);
+        Err(Rejection::new(
+            Status::TooManyRequests,
+            format!("Proxy key {} used up its {} bandwidth quota", label, period),
+        )
+        .with_field("resets", resets.to_rfc3339())
+        .with_header("Retry-After", (resets - now).num_seconds().max(1))
+        .into())
+    }
+
+    pub fn record(&self, bytes: u64) {
+        let mut usage = self.usage.lock().unwrap();
+        usage.roll(Utc::now().date_naive());
+        usage.day_bytes += bytes;
+        usage.month_bytes += bytes;
+    }
+
+    pub fn status(&self) -> BandwidthStatus {
+        let mut usage = self.usage.lock().unwrap();
+        usage.roll(Utc::now().date_naive());
+        BandwidthStatus {
+            day_bytes: usage.day_bytes,
+            daily_quota: self.daily_quota,
+            month_bytes: usage.month_bytes,
+            monthly_quota: self.monthly_quota,
+        }
+    }
+}
+
+fn next_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    (now.date_naive() + ChronoDuration::days(1)).and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+fn next_month(now: DateTime<Utc>) -> DateTime<Utc> {
+    let (year, month) = if now.month() == 12 { (now.year() + 1, 1) } else { (now.year(), now.month() + 1) };
+    NaiveDate::from_ymd_opt(year, month, 1).unwrap().and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+/// Counts every request made with a proxy key against it, in the usage
+/// stats and in bytes: the request body as declared in `Content-Length` and
+/// the response body as sent. Streamed responses, whose size isn't known up
+/// front, count as empty.
+pub struct UsageAccounting;
+
+#[rocket::async_trait]
+impl Fairing for UsageAccounting {
+    fn info(&self) -> Info {
+        Info {
+            name: "Usage accounting",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Some(state) = req.rocket().state::<Arc<AppState>>() else {
+            return;
+        };
+        let Some(api_key) = client_cert::api_key(req) else {
+            return;
+        };
+        let Some((tenant, key)) = state
+            .tenants
+            .iter()
+            .find_map(|tenant| Some((tenant, tenant.key(api_key)?)))
+        else {
+            return;
+        };
+        let received = req
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .unwrap_or(0);
+        let sent = res.body().preset_size().unwrap_or(0) as u64;
+        key.bandwidth().record(received + sent);
+        state.usage.record(&tenant.name, key.label(), res.status().code, received, sent);
+        state.metrics.add("roproxy_key_bytes_total", &[("key", key.label()), ("direction", "in")], received);
+        state.metrics.add("roproxy_key_bytes_total", &[("key", key.label()), ("direction", "out")], sent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolls_over_days_and_months() {
+        let date = |month, day| NaiveDate::from_ymd_opt(2026, month, day).unwrap();
+        let mut usage = Usage {
+            day: date(1, 30),
+            day_bytes: 10,
+            month_bytes: 100,
+        };
+        usage.roll(date(1, 30));
+        assert_eq!((usage.day_bytes, usage.month_bytes), (10, 100));
+        usage.roll(date(1, 31));
+        assert_eq!((usage.day_bytes, usage.month_bytes), (0, 100));
+        usage.day_bytes = 5;
+        usage.roll(date(2, 1));
+        assert_eq!((usage.day_bytes, usage.month_bytes), (0, 0));
+    }
+
+    #[test]
+    fn quota_resets_at_the_next_period() {
+        let now = NaiveDate::from_ymd_opt(2026, 12, 31).unwrap().and_hms_opt(15, 0, 0).unwrap().and_utc();
+        assert_eq!(next_day(now).to_rfc3339(), "2027-01-01T00:00:00+00:00");
+        assert_eq!(next_month(now).to_rfc3339(), "2027-01-01T00:00:00+00:00");
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{config::Base64Config, ProxyResponse, Rejection};
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rocket::{
+    http::Status,
+    serde::json::json,
+    Request,
+};
+
+/// Set to `base64` when the request body is base64 and should be decoded
+/// before it's forwarded.
+pub const REQUEST_HEADER: &str = "X-Proxy-Request-Encoding";
+/// Set to `base64` to receive the response wrapped in a JSON envelope with
+/// the body base64-encoded, for clients that mangle binary bodies.
+pub const RESPONSE_HEADER: &str = "X-Proxy-Response-Encoding";
+
+fn wants_base64(req: &Request<'_>, header: &str) -> bool {
+    req.headers()
+        .get_one(header)
+        .is_some_and(|value| value.eq_ignore_ascii_case("base64"))
+}
+
+fn check_enabled(config: &Base64Config) -> Result<()> {
+    if !config.enabled {
+        return Err(Rejection::new(Status::BadRequest, "Base64 body mode is disabled").into());
+    }
+    Ok(())
+}
+
+pub fn decode_request(config: &Base64Config, req: &Request<'_>, body: Vec<u8>) -> Result<Vec<u8>> {
+    if !wants_base64(req, REQUEST_HEADER) {
+        return Ok(body);
+    }
+    check_enabled(config)?;
+    let trimmed: Vec<u8> = body.into_iter().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    let decoded = STANDARD
+        .decode(&trimmed)
+        .map_err(|_| Rejection::new(Status::BadRequest, "Request body is not valid base64"))?;
+    if decoded.len() > config.max_bytes {
+        return Err(Rejection::new(Status::PayloadTooLarge, "Decoded request body is too large")
+            .with_field("max_bytes", config.max_bytes)
+            .into());
+    }
+    Ok(decoded)
+}
+
+pub fn encode_response(config: &Base64Config, req: &Request<'_>, response: ProxyResponse) -> Result<ProxyResponse> {
+    if !wants_base64(req, RESPONSE_HEADER) || response.stream.is_some() {
+        return Ok(response);
+    }
+    check_enabled(config)?;
+    if response.body.len() > config.max_bytes {
+        return Err(Rejection::new(Status::BadGateway, "Upstream response is too large to encode")
+            .with_field("size", response.body.len())
+            .with_field("max_bytes", config.max_bytes)
+            .into());
+    }
+    let envelope = json!({
+        "status": response.status.code,
+        "contentType": response.content_type,
+        "size": response.body.len(),
+        "base64": STANDARD.encode(&response.body),
+    });
+    let mut headers = response.headers;
+    headers.retain(|(name, _)| {
+        !["etag", "content-encoding", "content-disposition"].contains(&name.to_lowercase().as_str())
+    });
+    Ok(ProxyResponse {
+        status: response.status,
+        content_type: "application/json".to_string(),
+        body: envelope.to_string().into_bytes(),
+        headers,
+        stream: None,
+        credential: None,
+    })
+}
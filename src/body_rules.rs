@@ -0,0 +1,95 @@
+use crate::{
+    config::{BodyRuleConfig, BodyRulesConfig},
+    engine::UpstreamRequest,
+    metrics::Metrics,
+    middleware::Middleware,
+    Rejection,
+};
+use anyhow::Result;
+use rocket::{
+    http::{Method, Status},
+    serde::json::{self, Value},
+};
+use tracing::debug;
+
+/// Rejects write requests whose JSON bodies break `body_rules` with a 400,
+/// so obviously malformed calls never spend upstream budget.
+pub struct BodyRules {
+    rules: Vec<BodyRuleConfig>,
+}
+
+impl BodyRules {
+    pub fn new(config: &BodyRulesConfig) -> Self {
+        BodyRules {
+            rules: config.rules.clone(),
+        }
+    }
+
+    fn find(&self, url: &str) -> Option<&BodyRuleConfig> {
+        self.rules
+            .iter()
+            .find(|rule| rule.prefixes.iter().any(|prefix| url.starts_with(prefix)))
+    }
+}
+
+/// Every way `body` breaks `rule`, in the order the rule lists them.
+fn violations(rule: &BodyRuleConfig, body: &Value) -> Vec<String> {
+    let mut violations = Vec::new();
+    for field in &rule.required {
+        if body.pointer(field).is_none() {
+            violations.push(format!("{} is required", field));
+        }
+    }
+    for field in &rule.banned {
+        if body.pointer(field).is_some() {
+            violations.push(format!("{} is not allowed", field));
+        }
+    }
+    for (field, max) in &rule.max_array_lengths {
+        if let Some(Value::Array(items)) = body.pointer(field) {
+            if items.len() > *max {
+                violations.push(format!("{} has {} items, at most {} are allowed", field, items.len(), max));
+            }
+        }
+    }
+    violations
+}
+
+#[rocket::async_trait]
+impl Middleware for BodyRules {
+    fn name(&self) -> &'static str {
+        "body-rules"
+    }
+
+    fn reads_body(&self, url: &str) -> bool {
+        self.find(url).is_some()
+    }
+
+    async fn before_upstream(&self, request: &mut UpstreamRequest, metrics: &Metrics) -> Result<()> {
+        let Some(rule) = self.find(&request.url) else {
+            return Ok(());
+        };
+        let body = match request.body.as_ref().map(|body| body.as_bytes()) {
+            Some(Some(bytes)) if !bytes.is_empty() => json::from_slice(bytes).map_err(|_| {
+                metrics.incr("roproxy_body_rejections_total", &[("rule", &rule.name)]);
+                Rejection::new(Status::BadRequest, "Request body is not valid JSON").with_field("rule", rule.name.clone())
+            })?,
+            // Streamed bodies can't be inspected; main buffers the ones a rule
+            // applies to.
+            Some(None) => return Ok(()),
+            _ if rule.required.is_empty() || request.method == Method::Get => return Ok(()),
+            _ => Value::Null,
+        };
+
+        let violations = violations(rule, &body);
+        if violations.is_empty() {
+            return Ok(());
+        }
+        debug!("Rejected body for {} under {}: {:?}", request.url, rule.name, violations);
+        metrics.incr("roproxy_body_rejections_total", &[("rule", &rule.name)]);
+        Err(Rejection::new(Status::BadRequest, "Request body failed validation")
+            .with_field("rule", rule.name.clone())
+            .with_field("violations", violations)
+            .into())
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{
+    config::{BudgetFamilyConfig, BudgetsConfig, ExhaustedPolicy},
+    metrics::Metrics,
+    ratelimit::TokenBucket,
+    shared_state::SharedState,
+    ProxyResponse, Rejection,
+};
+use anyhow::Result;
+use rocket::{http::Status, serde::Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// Says who turned a request away with a 429: `proxy` when a local budget
+/// did, `roblox` when the upstream did. Roblox's own `x-ratelimit-*` headers
+/// are relayed as they came.
+pub const THROTTLED_BY_HEADER: &str = "X-Proxy-Throttled-By";
+
+struct Family {
+    config: BudgetFamilyConfig,
+    bucket: TokenBucket,
+    /// Requests turned away because the queue was too long to wait out.
+    shed: AtomicU64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BudgetStatus {
+    pub name: String,
+    pub limit: u32,
+    pub window_secs: u64,
+    pub remaining: u32,
+    pub queued: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct QueueStatus {
+    pub name: String,
+    pub policy: &'static str,
+    pub queued: u32,
+    /// How long a request arriving now would be held back.
+    pub estimated_wait_ms: u64,
+    pub max_queue_ms: u64,
+    pub shed: u64,
+}
+
+/// Client-side model of Roblox's per-endpoint-family rate limits, so requests
+/// that would exceed them are held back or rejected locally instead of being
+/// answered with a 429 upstream. With shared state, each family's limit also
+/// holds across every replica.
+pub struct Budgets {
+    families: Vec<Family>,
+    shared: Arc<SharedState>,
+}
+
+impl Budgets {
+    pub fn new(config: &BudgetsConfig, shared: Arc<SharedState>) -> Self {
+        let families = config
+            .families
+            .iter()
+            .map(|family| Family {
+                bucket: TokenBucket::new(family.limit, Duration::from_secs(family.window_secs)),
+                config: family.clone(),
+                shed: AtomicU64::new(0),
+            })
+            .collect();
+        Budgets { families, shared }
+    }
+
+    fn family(&self, url: &str) -> Option<&Family> {
+        self.families
+            .iter()
+            .find(|family| family.config.prefixes.iter().any(|prefix| url.starts_with(prefix)))
+    }
+
+    /// Takes one request from the family `url` belongs to, waiting for it to
+    /// refill when the family queues.
+    pub async fn acquire(&self, url: &str, metrics: &Metrics) -> Result<()> {
+        let Some(family) = self.family(url) else {
+            return Ok(());
+        };
+        let name = family.config.name.as_str();
+
+        let max_wait = match family.config.on_exhausted {
+            ExhaustedPolicy::Reject => Duration::ZERO,
+            ExhaustedPolicy::Queue => Duration::from_millis(family.config.max_queue_ms),
+        };
+        let wait = match family.bucket.take(max_wait) {
+            Ok(wait) => wait,
+            Err(retry_after) => return Err(shed(family, retry_after, metrics)),
+        };
+
+        if !wait.is_zero() {
+            debug!("Queueing request to {} for {:?}", name, wait);
+            metrics.incr("roproxy_budget_queued_total", &[("family", name)]);
+            tokio::time::sleep(wait).await;
+        }
+        if self.shared.enabled() {
+            self.acquire_shared(family, max_wait.saturating_sub(wait), metrics).await?;
+        }
+        Ok(())
+    }
+
+    // Takes the request from the family's budget across all replicas too,
+    // waiting out a hold or the rest of a full window within `max_wait`.
+    async fn acquire_shared(&self, family: &Family, max_wait: Duration, metrics: &Metrics) -> Result<()> {
+        let name = family.config.name.as_str();
+        let deadline = Instant::now() + max_wait;
+        loop {
+            let wait = match self.shared.held(&format!("budget-hold:{}", name)).await {
+                Some(hold) => hold,
+                None => match self
+                    .shared
+                    .count(&format!("budget:{}", name), Duration::from_secs(family.config.window_secs))
+                    .await
+                {
+                    Some((count, reset)) if count > family.config.limit as u64 => reset,
+                    _ => return Ok(()),
+                },
+            };
+            if Instant::now() + wait > deadline {
+                return Err(shed(family, wait, metrics));
+            }
+            debug!("Queueing request to {} for {:?} on the shared budget", name, wait);
+            metrics.incr("roproxy_budget_queued_total", &[("family", name)]);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// How long background work should hold off before its next request to
+    /// `url`, so that `reserve` of the family's budget is left for
+    /// interactive traffic.
+    pub fn pace(&self, url: &str, reserve: f64) -> Duration {
+        self.family(url).map_or(Duration::ZERO, |family| {
+            family
+                .bucket
+                .wait_for(1.0 + reserve.clamp(0.0, 1.0) * family.config.limit as f64)
+        })
+    }
+
+    /// Roblox throttled us anyway, so our model is too generous right now.
+    /// Drain the family so the next requests wait for a refill, on every
+    /// replica.
+    pub async fn exhaust(&self, url: &str) {
+        let Some(family) = self.family(url) else {
+            return;
+        };
+        family.bucket.drain();
+        if self.shared.enabled() {
+            let name = format!("budget-hold:{}", family.config.name);
+            self.shared.hold(&name, family.bucket.wait()).await;
+        }
+    }
+
+    /// Adds the local budget the response's URL draws from, as
+    /// `X-Proxy-Budget-*` headers, and marks upstream 429s as Roblox's.
+    pub fn annotate(&self, url: &str, response: &mut ProxyResponse) {
+        if response.status == Status::TooManyRequests {
+            response
+                .headers
+                .push((THROTTLED_BY_HEADER.to_string(), "roblox".to_string()));
+        }
+        let Some(family) = self.family(url) else {
+            return;
+        };
+        let (remaining, _) = family.bucket.levels();
+        response.headers.extend([
+            ("X-Proxy-Budget-Family".to_string(), family.config.name.clone()),
+            ("X-Proxy-Budget-Limit".to_string(), family.config.limit.to_string()),
+            ("X-Proxy-Budget-Remaining".to_string(), remaining.to_string()),
+            (
+                "X-Proxy-Budget-Reset".to_string(),
+                family.bucket.until_full().as_secs_f64().ceil().to_string(),
+            ),
+        ]);
+    }
+
+    pub fn status(&self) -> Vec<BudgetStatus> {
+        self.families
+            .iter()
+            .map(|family| {
+                let (remaining, queued) = family.bucket.levels();
+                BudgetStatus {
+                    name: family.config.name.clone(),
+                    limit: family.config.limit,
+                    window_secs: family.config.window_secs,
+                    remaining,
+                    queued,
+                }
+            })
+            .collect()
+    }
+
+    pub fn queues(&self) -> Vec<QueueStatus> {
+        self.families
+            .iter()
+            .map(|family| QueueStatus {
+                name: family.config.name.clone(),
+                policy: family.config.on_exhausted.as_str(),
+                queued: family.bucket.levels().1,
+                estimated_wait_ms: family.bucket.wait().as_millis() as u64,
+                max_queue_ms: match family.config.on_exhausted {
+                    ExhaustedPolicy::Queue => family.config.max_queue_ms,
+                    ExhaustedPolicy::Reject => 0,
+                },
+                shed: family.shed.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+fn shed(family: &Family, retry_after: Duration, metrics: &Metrics) -> anyhow::Error {
+    let name = family.config.name.as_str();
+    family.shed.fetch_add(1, Ordering::Relaxed);
+    metrics.incr("roproxy_budget_rejections_total", &[("family", name)]);
+    Rejection::new(Status::TooManyRequests, format!("Proxy budget for {} is exhausted", name))
+        .with_header("Retry-After", retry_after.as_secs_f64().ceil() as u64)
+        .with_header(THROTTLED_BY_HEADER, "proxy")
+        .into()
+}
@@ -0,0 +1,438 @@
+use crate::{
+    async_jobs, client_cert,
+    config::BulkConfig,
+    envelope::{self, default_method, Envelope, Prepared},
+    AppState, ErrorResponse, MyRequestGuard, ProxyResponse, Rejection,
+};
+use anyhow::Result;
+use rocket::{
+    http::{Status, StatusClass},
+    serde::{
+        json::{json, Json, Value},
+        Deserialize, Serialize,
+    },
+    Route, State,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+use tracing::{debug, info};
+
+pub fn routes() -> Vec<Route> {
+    routes![submit, progress, cancel]
+}
+
+/// Stands for a chunk's items: comma-separated in the URL, a JSON array in
+/// the body.
+pub const PLACEHOLDER: &str = "{items}";
+
+/// Many requests of one shape, e.g. resolving 10,000 usernames: the
+/// envelope is sent once per chunk of `items`, with `{items}` filled in.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BulkRequest {
+    #[serde(default = "default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<Value>,
+    items: Vec<Value>,
+    #[serde(default = "default_chunk_size")]
+    chunk_size: usize,
+}
+
+fn default_chunk_size() -> usize {
+    1
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+struct ChunkResult {
+    items: Vec<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+struct BulkJob {
+    tenant: Option<String>,
+    items: usize,
+    chunks: usize,
+    results: Vec<ChunkResult>,
+    failed: usize,
+    cancelled: bool,
+    started: Option<Instant>,
+    finished: Option<Instant>,
+    callback: Option<String>,
+}
+
+impl BulkJob {
+    fn status(&self) -> &'static str {
+        match (self.started, self.finished) {
+            _ if self.cancelled => "cancelled",
+            (None, _) => "queued",
+            (Some(_), None) => "running",
+            (Some(_), Some(_)) => "done",
+        }
+    }
+
+    /// How far the job has got, as polled and as sent to its callback.
+    fn report(&self, id: &str) -> Value {
+        let completed = self.results.len();
+        let elapsed = self.started.map(|started| self.finished.unwrap_or_else(Instant::now) - started);
+        let eta_secs = match (elapsed, self.finished) {
+            (Some(elapsed), None) if completed > 0 => {
+                Some((elapsed.as_secs_f64() / completed as f64 * (self.chunks - completed) as f64).ceil() as u64)
+            }
+            _ => None,
+        };
+        json!({
+            "type": "bulk",
+            "id": id,
+            "status": self.status(),
+            "items": self.items,
+            "chunks": self.chunks,
+            "completed": completed,
+            "failed": self.failed,
+            "elapsed_secs": elapsed.map(|elapsed| elapsed.as_secs()),
+            "eta_secs": eta_secs,
+            "results": self.results,
+        })
+    }
+}
+
+/// Runs bulk jobs a chunk at a time, pacing each against the budget family
+/// it draws from so that `reserve` of it is left for everything else. A
+/// large job takes minutes instead of tripping Roblox's limits, and its
+/// progress and results so far can be polled at `GET /bulk/<id>`.
+pub struct BulkJobs {
+    config: BulkConfig,
+    jobs: Mutex<HashMap<String, BulkJob>>,
+    workers: Arc<Semaphore>,
+}
+
+impl BulkJobs {
+    pub fn new(config: &BulkConfig) -> Self {
+        BulkJobs {
+            config: config.clone(),
+            jobs: Mutex::default(),
+            workers: Arc::new(Semaphore::new(config.workers.max(1))),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    fn prune(&self, jobs: &mut HashMap<String, BulkJob>) {
+        let ttl = Duration::from_secs(self.config.result_ttl_secs);
+        jobs.retain(|_, job| job.finished.is_none_or(|finished| finished.elapsed() < ttl));
+    }
+}
+
+// The chunk's items as they go in a URL.
+fn url_items(items: &[Value]) -> Result<String> {
+    let items = items
+        .iter()
+        .map(|item| match item {
+            Value::String(text) => Ok(form_urlencoded::byte_serialize(text.as_bytes()).collect::<String>()),
+            Value::Number(number) => Ok(number.to_string()),
+            _ => Err(Rejection::new(Status::BadRequest, "items must be strings or numbers")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(items.join(","))
+}
+
+// `body` with every `"{items}"` string in it replaced by the chunk.
+fn fill_body(body: &Value, items: &[Value]) -> Value {
+    match body {
+        Value::String(text) if text == PLACEHOLDER => Value::Array(items.to_vec()),
+        Value::Array(values) => Value::Array(values.iter().map(|value| fill_body(value, items)).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), fill_body(value, items)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn has_placeholder(body: &Value) -> bool {
+    match body {
+        Value::String(text) => text == PLACEHOLDER,
+        Value::Array(values) => values.iter().any(has_placeholder),
+        Value::Object(fields) => fields.values().any(has_placeholder),
+        _ => false,
+    }
+}
+
+/// Splits `request` into one envelope per chunk of items.
+fn envelopes(request: &BulkRequest) -> Result<Vec<(Vec<Value>, Envelope)>> {
+    if !request.url.contains(PLACEHOLDER) && !request.body.as_ref().is_some_and(has_placeholder) {
+        return Err(Rejection::new(Status::BadRequest, format!("url or body must contain {}", PLACEHOLDER)).into());
+    }
+    request
+        .items
+        .chunks(request.chunk_size)
+        .map(|items| {
+            let envelope = Envelope {
+                method: request.method.clone(),
+                url: request.url.replace(PLACEHOLDER, &url_items(items)?),
+                headers: request.headers.clone(),
+                body: request.body.as_ref().map(|body| fill_body(body, items)),
+            };
+            Ok((items.to_vec(), envelope))
+        })
+        .collect()
+}
+
+// Whether a chunk is worth trying again: throttled, shed, or failed on the
+// way.
+fn retryable(outcome: &Result<ProxyResponse>) -> bool {
+    match outcome {
+        Ok(response) => {
+            response.status == Status::TooManyRequests || response.status.class() == StatusClass::ServerError
+        }
+        Err(err) => err.downcast_ref::<Rejection>().is_none_or(|rejection| {
+            [Status::TooManyRequests, Status::ServiceUnavailable].contains(&rejection.status)
+        }),
+    }
+}
+
+async fn run_chunk(state: &AppState, items: Vec<Value>, prepared: Prepared) -> ChunkResult {
+    let config = &state.bulk.config;
+    let mut attempt = 0;
+    let outcome = loop {
+        tokio::time::sleep(state.engine.budgets.pace(&prepared.url, config.reserve)).await;
+        let outcome = envelope::send(state, prepared.clone()).await;
+        if attempt >= config.chunk_retries || !retryable(&outcome) {
+            break outcome;
+        }
+        attempt += 1;
+        state.metrics.incr("roproxy_bulk_chunks_total", &[("result", "retried")]);
+        let backoff = Duration::from_secs(1 << attempt.min(6));
+        debug!("Retrying bulk chunk for {} in {:?}", prepared.url, backoff);
+        tokio::time::sleep(backoff).await;
+    };
+    let (result, chunk) = match outcome {
+        Ok(response) => (
+            if response.status.class() == StatusClass::Success { "ok" } else { "failed" },
+            ChunkResult {
+                items,
+                status: Some(response.status.code),
+                body: Some(
+                    response
+                        .json()
+                        .unwrap_or_else(|| Value::String(String::from_utf8_lossy(&response.body).into_owned())),
+                ),
+                error: None,
+            },
+        ),
+        Err(err) => (
+            "failed",
+            ChunkResult {
+                items,
+                status: err.downcast_ref::<Rejection>().map(|rejection| rejection.status.code),
+                body: None,
+                error: Some(format!("{:#}", err)),
+            },
+        ),
+    };
+    state.metrics.incr("roproxy_bulk_chunks_total", &[("result", result)]);
+    chunk
+}
+
+async fn run(state: Arc<AppState>, id: String, chunks: Vec<(Vec<Value>, Prepared)>) {
+    let Ok(_permit) = state.bulk.workers.clone().acquire_owned().await else {
+        return;
+    };
+    let started = Instant::now();
+    {
+        let mut jobs = state.bulk.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(&id).filter(|job| !job.cancelled) else {
+            return;
+        };
+        job.started = Some(started);
+    }
+    for (items, prepared) in chunks {
+        let result = run_chunk(&state, items, prepared).await;
+        let mut jobs = state.bulk.jobs.lock().unwrap();
+        let Some(job) = jobs.get_mut(&id) else {
+            return;
+        };
+        if result.error.is_some() || result.status.is_none_or(|status| !(200..300).contains(&status)) {
+            job.failed += 1;
+        }
+        job.results.push(result);
+        if job.cancelled {
+            break;
+        }
+    }
+    let mut jobs = state.bulk.jobs.lock().unwrap();
+    if let Some(job) = jobs.get_mut(&id) {
+        job.finished = Some(Instant::now());
+        info!(
+            "Bulk job {} {} after {:?}: {} of {} chunks failed",
+            id,
+            job.status(),
+            started.elapsed(),
+            job.failed,
+            job.chunks
+        );
+        state.metrics.incr("roproxy_bulk_jobs_total", &[("result", job.status())]);
+        if let Some(callback) = job.callback.clone() {
+            state.callbacks.deliver(callback, job.report(&id));
+        }
+    }
+}
+
+#[post("/bulk", data = "<request>")]
+async fn submit(
+    request: Json<BulkRequest>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<(Status, Json<Value>), ErrorResponse> {
+    let config = &state.bulk.config;
+    let reject = |message: String| ErrorResponse(Rejection::new(Status::BadRequest, message).into());
+    if request.items.is_empty() || request.items.len() > config.max_items {
+        return Err(reject(format!("items must hold 1 to {} values", config.max_items)));
+    }
+    if request.chunk_size == 0 || request.chunk_size > config.max_chunk_size {
+        return Err(reject(format!("chunk_size must be 1 to {}", config.max_chunk_size)));
+    }
+
+    state.screen_client(guard.request)?;
+    let tenant = state
+        .tenants
+        .authenticate(client_cert::api_key(guard.request), &state.metrics)?;
+    // Every chunk is checked up front, so a bad item fails the call instead
+    // of the job minutes in.
+    let mut chunks = Vec::new();
+    for (index, (items, envelope)) in envelopes(&request)?.into_iter().enumerate() {
+        let prepared = envelope::check(envelope, state, guard.request, tenant.as_deref())
+            .await
+            .map_err(|err| match err.downcast::<Rejection>() {
+                Ok(rejection) => rejection.with_field("chunk", index).into(),
+                Err(err) => err,
+            })?;
+        chunks.push((items, prepared));
+    }
+    let callback = state.callbacks.requested(guard.request)?;
+
+    let id = async_jobs::new_id();
+    {
+        let mut jobs = state.bulk.jobs.lock().unwrap();
+        state.bulk.prune(&mut jobs);
+        if jobs.values().filter(|job| job.finished.is_none()).count() >= config.max_jobs {
+            state.metrics.incr("roproxy_bulk_jobs_total", &[("result", "rejected")]);
+            return Err(ErrorResponse(
+                Rejection::new(Status::ServiceUnavailable, "Too many bulk jobs")
+                    .with_header("Retry-After", 60)
+                    .into(),
+            ));
+        }
+        jobs.insert(
+            id.clone(),
+            BulkJob {
+                tenant: tenant.map(|tenant| tenant.name.clone()),
+                items: request.items.len(),
+                chunks: chunks.len(),
+                results: Vec::new(),
+                failed: 0,
+                cancelled: false,
+                started: None,
+                finished: None,
+                callback,
+            },
+        );
+    }
+    let chunk_count = chunks.len();
+    tokio::spawn(run(state.inner().clone(), id.clone(), chunks));
+    state.metrics.incr("roproxy_bulk_jobs_total", &[("result", "queued")]);
+
+    Ok((
+        Status::Accepted,
+        Json(json!({
+            "id": id,
+            "status": "queued",
+            "items": request.items.len(),
+            "chunks": chunk_count,
+            "progress": format!("/bulk/{}", id),
+        })),
+    ))
+}
+
+/// How far the job has got, with the results of every chunk run so far
+/// in order and, while it runs, an estimate of the time left.
+#[get("/bulk/<id>")]
+fn progress(id: &str, state: &State<Arc<AppState>>, guard: MyRequestGuard<'_>) -> Result<Json<Value>, ErrorResponse> {
+    let tenant = async_jobs::owner(state, guard.request)?;
+    let mut jobs = state.bulk.jobs.lock().unwrap();
+    state.bulk.prune(&mut jobs);
+    let Some(job) = jobs.get(id).filter(|job| job.tenant == tenant) else {
+        return Err(ErrorResponse(Rejection::new(Status::NotFound, format!("No bulk job {}", id)).into()));
+    };
+    Ok(Json(job.report(id)))
+}
+
+/// Stops the job after the chunk in flight; results so far are kept.
+#[delete("/bulk/<id>")]
+fn cancel(id: &str, state: &State<Arc<AppState>>, guard: MyRequestGuard<'_>) -> Result<Status, ErrorResponse> {
+    let tenant = async_jobs::owner(state, guard.request)?;
+    let mut jobs = state.bulk.jobs.lock().unwrap();
+    let Some(job) = jobs
+        .get_mut(id)
+        .filter(|job| job.tenant == tenant && job.finished.is_none())
+    else {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, format!("No running bulk job {}", id)).into(),
+        ));
+    };
+    job.cancelled = true;
+    if job.started.is_none() {
+        job.finished = Some(Instant::now());
+        state.metrics.incr("roproxy_bulk_jobs_total", &[("result", "cancelled")]);
+        if let Some(callback) = job.callback.clone() {
+            state.callbacks.deliver(callback, job.report(id));
+        }
+    }
+    Ok(Status::Accepted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_chunks() {
+        let request = BulkRequest {
+            method: "POST".to_string(),
+            url: "https://users.roblox.com/v1/usernames/users".to_string(),
+            headers: HashMap::new(),
+            body: Some(json!({ "usernames": PLACEHOLDER, "excludeBannedUsers": true })),
+            items: ["a", "b", "c"].map(Value::from).to_vec(),
+            chunk_size: 2,
+        };
+        let chunks = envelopes(&request).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[1].1.body, Some(json!({ "usernames": ["c"], "excludeBannedUsers": true })));
+
+        let request = BulkRequest {
+            method: "GET".to_string(),
+            url: "https://users.roblox.com/v1/users?ids={items}".to_string(),
+            body: None,
+            items: vec![json!(1), json!("a b/c")],
+            ..request
+        };
+        assert_eq!(envelopes(&request).unwrap()[0].1.url, "https://users.roblox.com/v1/users?ids=1,a+b%2Fc");
+    }
+}
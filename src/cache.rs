@@ -0,0 +1,451 @@
+use crate::{
+    config::{CacheConfig, PostCacheRuleConfig},
+    disk_cache::DiskStore,
+    metrics::Metrics,
+    tenants::ApiKey,
+    ProxyResponse, Rejection,
+};
+use anyhow::{Context, Result};
+use regex::Regex;
+use rocket::http::Status;
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+/// Lets a trusted key say how fresh a GET must be, in seconds: cached
+/// responses older than that aren't served, and the response is cached for
+/// that long. Bounded by `cache.max_client_ttl_secs`; zero bypasses the cache.
+pub const TTL_HEADER: &str = "X-Proxy-Cache-TTL";
+
+/// Cached responses are keyed by upstream URL within a namespace, so tenants
+/// with their own credentials never see each other's responses, and by the
+/// pool account they were fetched as, so clients sent out as one account
+/// never get another's.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub namespace: Option<String>,
+    pub url: String,
+    /// Hex SHA-256 of the request body, for cached POSTs.
+    pub body: Option<String>,
+    pub account: Option<String>,
+}
+
+impl CacheKey {
+    pub fn new(namespace: Option<&str>, url: impl Into<String>) -> Self {
+        CacheKey {
+            namespace: namespace.map(str::to_string),
+            url: url.into(),
+            body: None,
+            account: None,
+        }
+    }
+
+    pub fn with_account(mut self, account: Option<&str>) -> Self {
+        self.account = account.map(str::to_string);
+        self
+    }
+
+    pub fn with_body(mut self, body: &[u8]) -> Self {
+        self.body = Some(hex::encode(Sha256::digest(body)));
+        self
+    }
+}
+
+struct CacheEntry {
+    response: ProxyResponse,
+    stored_at: Instant,
+    ttl: Duration,
+    hits: u64,
+    refreshing: bool,
+    size: usize,
+    /// Surrogate keys the entry can be purged by.
+    tags: Vec<String>,
+}
+
+// Approximate heap footprint of an entry, which is what the memory budget is
+// measured in.
+fn entry_size(key: &CacheKey, response: &ProxyResponse) -> usize {
+    key.url.len()
+        + key.namespace.as_ref().map_or(0, String::len)
+        + key.body.as_ref().map_or(0, String::len)
+        + key.account.as_ref().map_or(0, String::len)
+        + response.body.len()
+        + response.content_type.len()
+        + response
+            .headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum::<usize>()
+}
+
+#[derive(Default)]
+struct Entries {
+    map: HashMap<CacheKey, CacheEntry>,
+    bytes: usize,
+    /// Cache keys by surrogate key.
+    tagged: HashMap<String, HashSet<CacheKey>>,
+}
+
+impl Entries {
+    fn add(&mut self, key: CacheKey, entry: CacheEntry) {
+        self.bytes += entry.size;
+        for tag in &entry.tags {
+            self.tagged.entry(tag.clone()).or_default().insert(key.clone());
+        }
+        self.map.insert(key, entry);
+    }
+
+    fn remove(&mut self, key: &CacheKey) -> Option<CacheEntry> {
+        let entry = self.map.remove(key)?;
+        self.bytes -= entry.size;
+        for tag in &entry.tags {
+            if let Some(keys) = self.tagged.get_mut(tag) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.tagged.remove(tag);
+                }
+            }
+        }
+        Some(entry)
+    }
+}
+
+impl CacheEntry {
+    fn is_fresh(&self) -> bool {
+        self.stored_at.elapsed() < self.ttl
+    }
+
+    fn expires_in(&self) -> Duration {
+        self.ttl.saturating_sub(self.stored_at.elapsed())
+    }
+}
+
+/// In-memory cache of successful GET responses keyed by upstream URL,
+/// optionally mirrored to disk.
+pub struct ResponseCache {
+    entries: Mutex<Entries>,
+    enabled: bool,
+    ttl: Duration,
+    max_client_ttl: Duration,
+    max_entries: usize,
+    max_bytes: usize,
+    surrogate_keys: Vec<(String, Regex)>,
+    post_rules: Vec<PostCacheRuleConfig>,
+    disk: Option<DiskStore>,
+    metrics: Arc<Metrics>,
+}
+
+impl ResponseCache {
+    pub fn new(config: &CacheConfig, metrics: Arc<Metrics>) -> Result<Self> {
+        let disk = match &config.disk_dir {
+            Some(dir) if config.enabled => Some(DiskStore::open(dir)?),
+            _ => None,
+        };
+
+        let surrogate_keys = config
+            .surrogate_keys
+            .iter()
+            .map(|rule| {
+                let pattern = Regex::new(&rule.pattern)
+                    .with_context(|| format!("Invalid pattern for surrogate key {}", rule.name))?;
+                Ok((rule.name.clone(), pattern))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut entries = Entries::default();
+        if let Some(disk) = &disk {
+            let mut loaded = disk.load();
+            // Keep the freshest entries if the directory outgrew the limits.
+            loaded.sort_by_key(|entry| entry.age);
+            for entry in loaded {
+                let size = entry_size(&entry.key, &entry.response);
+                if entries.map.len() >= config.max_entries
+                    || entries.bytes + size > config.max_bytes
+                {
+                    disk.remove(&entry.key);
+                    continue;
+                }
+                let stored_at = Instant::now().checked_sub(entry.age).unwrap_or_else(Instant::now);
+                let tags = tags_for(&surrogate_keys, &entry.key.url);
+                entries.add(
+                    entry.key,
+                    CacheEntry {
+                        response: entry.response,
+                        stored_at,
+                        ttl: entry.ttl,
+                        hits: 0,
+                        refreshing: false,
+                        size,
+                        tags,
+                    },
+                );
+            }
+            info!(
+                "Loaded {} cached responses ({} bytes) from disk",
+                entries.map.len(),
+                entries.bytes
+            );
+        }
+
+        let cache = ResponseCache {
+            entries: Mutex::new(entries),
+            enabled: config.enabled,
+            ttl: Duration::from_secs(config.ttl_secs),
+            max_client_ttl: Duration::from_secs(config.max_client_ttl_secs),
+            max_entries: config.max_entries,
+            max_bytes: config.max_bytes,
+            surrogate_keys,
+            post_rules: config.post_rules.clone(),
+            disk,
+            metrics,
+        };
+        cache.report(&cache.entries.lock().unwrap());
+        Ok(cache)
+    }
+
+    /// A fresh cached response for `key`, if it's no older than `max_age`.
+    pub fn get(&self, key: &CacheKey, max_age: Option<Duration>) -> Option<ProxyResponse> {
+        if !self.enabled {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .map
+            .get_mut(key)
+            .filter(|entry| entry.is_fresh() && max_age.is_none_or(|max_age| entry.stored_at.elapsed() < max_age))?;
+        entry.hits += 1;
+        Some(entry.response.clone())
+    }
+
+    /// The entry for `key` even if it has expired, as long as it expired no
+    /// more than `max_stale` ago.
+    pub fn get_stale(&self, key: &CacheKey, max_stale: Duration) -> Option<ProxyResponse> {
+        if !self.enabled {
+            return None;
+        }
+        let entries = self.entries.lock().unwrap();
+        let entry = entries
+            .map
+            .get(key)
+            .filter(|entry| entry.stored_at.elapsed() < entry.ttl + max_stale)?;
+        Some(entry.response.clone())
+    }
+
+    /// The freshness a client asked for with [`TTL_HEADER`], if its key may.
+    pub fn client_ttl(&self, value: &str, key: Option<&ApiKey>) -> Result<Duration> {
+        if !key.is_some_and(ApiKey::may_set_cache_ttl) {
+            self.metrics.incr("roproxy_cache_ttl_rejections_total", &[("reason", "forbidden")]);
+            return Err(Rejection::new(Status::Forbidden, format!("This proxy key may not set {}", TTL_HEADER)).into());
+        }
+        let Ok(secs) = value.trim().parse() else {
+            self.metrics.incr("roproxy_cache_ttl_rejections_total", &[("reason", "invalid")]);
+            return Err(Rejection::new(Status::BadRequest, format!("{} must be a number of seconds", TTL_HEADER)).into());
+        };
+        self.metrics.incr("roproxy_cache_ttl_overrides_total", &[]);
+        Ok(Duration::from_secs(secs).min(self.max_client_ttl))
+    }
+
+    /// How long a POST to `url` is cached for, if a rule opts it in.
+    pub fn post_ttl(&self, url: &str) -> Option<Duration> {
+        let rule = self
+            .post_rules
+            .iter()
+            .find(|rule| rule.prefixes.iter().any(|prefix| url.starts_with(prefix)))?;
+        self.metrics.incr("roproxy_post_cache_lookups_total", &[("rule", &rule.name)]);
+        Some(Duration::from_secs(rule.ttl_secs))
+    }
+
+    /// Picks up to `limit` of the most-hit entries that expire within `ahead`
+    /// and marks them as being refreshed, returning their keys and TTLs.
+    pub fn refresh_candidates(&self, limit: usize, ahead: Duration) -> Vec<(CacheKey, Duration)> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut hot: Vec<_> = entries
+            .map
+            .iter()
+            // A POST can't be refetched without its body.
+            .filter(|(key, entry)| entry.hits > 0 && !entry.refreshing && key.body.is_none())
+            .map(|(key, entry)| (key.clone(), entry.hits))
+            .collect();
+        hot.sort_by_key(|(_, hits)| Reverse(*hits));
+        hot.truncate(limit);
+
+        hot.into_iter()
+            .filter_map(|(key, _)| {
+                let entry = entries.map.get_mut(&key)?;
+                if !entry.is_fresh() || entry.expires_in() > ahead {
+                    return None;
+                }
+                entry.refreshing = true;
+                Some((key, entry.ttl))
+            })
+            .collect()
+    }
+
+    /// Clears the refreshing mark after a failed refresh so the entry can be
+    /// picked again before it expires.
+    pub fn refresh_failed(&self, key: &CacheKey) {
+        if let Some(entry) = self.entries.lock().unwrap().map.get_mut(key) {
+            entry.refreshing = false;
+        }
+    }
+
+    /// Stores `response` if it's safe to share between clients. `ttl`
+    /// overrides the configured freshness. Returns whether it was stored.
+    pub fn insert(&self, key: &CacheKey, response: &ProxyResponse, ttl: Option<Duration>) -> bool {
+        if !self.enabled || !is_shareable(response) {
+            return false;
+        }
+        let size = entry_size(key, response);
+        // One oversized response shouldn't be able to flush most of the cache.
+        if size > self.max_bytes / 10 {
+            self.metrics.incr("roproxy_cache_rejected_total", &[("reason", "too_large")]);
+            return false;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        // Carry over half the previous hits so popularity survives a refresh
+        // but decays once a key stops being requested.
+        let hits = entries.remove(key).map_or(0, |previous| previous.hits / 2);
+
+        let full = |entries: &Entries| {
+            entries.map.len() >= self.max_entries || entries.bytes + size > self.max_bytes
+        };
+        if full(&entries) {
+            let expired: Vec<_> = entries
+                .map
+                .iter()
+                .filter(|(_, entry)| !entry.is_fresh())
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in expired {
+                self.evict(&mut entries, &key, "expired");
+            }
+        }
+        while full(&entries) {
+            // Evict whatever holds the most bytes per hit, so one large cold
+            // response goes before many small popular ones.
+            let victim = entries
+                .map
+                .iter()
+                .filter(|(_, entry)| !entry.refreshing)
+                .max_by(|(_, a), (_, b)| {
+                    let cost = |entry: &CacheEntry| entry.size as f64 / (entry.hits + 1) as f64;
+                    cost(a).total_cmp(&cost(b))
+                })
+                .map(|(key, _)| key.clone());
+            let Some(victim) = victim else { break };
+            let reason = if entries.map.len() >= self.max_entries {
+                "capacity"
+            } else {
+                "memory"
+            };
+            self.evict(&mut entries, &victim, reason);
+        }
+
+        let ttl = ttl.unwrap_or(self.ttl);
+        if let Some(disk) = &self.disk {
+            disk.save(key, response, ttl);
+        }
+        entries.add(
+            key.clone(),
+            CacheEntry {
+                response: response.clone(),
+                stored_at: Instant::now(),
+                ttl,
+                hits,
+                refreshing: false,
+                size,
+                tags: tags_for(&self.surrogate_keys, &key.url),
+            },
+        );
+        self.report(&entries);
+        true
+    }
+
+    /// Drops every entry tagged with surrogate key `tag`, in all namespaces.
+    /// Returns how many were dropped.
+    pub fn purge(&self, tag: &str) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let keys: Vec<_> = entries.tagged.get(tag).into_iter().flatten().cloned().collect();
+        for key in &keys {
+            self.evict(&mut entries, key, "purged");
+        }
+        self.report(&entries);
+        keys.len()
+    }
+
+    fn evict(&self, entries: &mut Entries, key: &CacheKey, reason: &str) {
+        entries.remove(key);
+        if let Some(disk) = &self.disk {
+            disk.remove(key);
+        }
+        self.metrics
+            .incr("roproxy_cache_evictions_total", &[("reason", reason)]);
+    }
+
+    fn report(&self, entries: &Entries) {
+        self.metrics
+            .set_gauge("roproxy_cache_entries", &[], entries.map.len() as i64);
+        self.metrics
+            .set_gauge("roproxy_cache_bytes", &[], entries.bytes as i64);
+    }
+}
+
+fn tags_for(rules: &[(String, Regex)], url: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    for (name, pattern) in rules {
+        for captures in pattern.captures_iter(url) {
+            let Some(ids) = captures.get(1) else { continue };
+            for id in ids.as_str().split(',').filter(|id| !id.is_empty()) {
+                let tag = format!("{}:{}", name, id);
+                if !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+    }
+    tags
+}
+
+fn is_shareable(response: &ProxyResponse) -> bool {
+    if response.status != Status::Ok || response.stream.is_some() {
+        return false;
+    }
+    response.headers.iter().all(|(name, value)| {
+        let name = name.to_lowercase();
+        if name == "set-cookie" || name == "x-proxy-truncated" {
+            return false;
+        }
+        if name == "cache-control" {
+            let value = value.to_lowercase();
+            return !["no-store", "private", "no-cache"]
+                .iter()
+                .any(|directive| value.contains(directive));
+        }
+        true
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surrogate_keys_come_from_paths_and_batch_queries() {
+        let cache = ResponseCache::new(&CacheConfig::default(), Arc::new(Metrics::default())).unwrap();
+        assert_eq!(
+            tags_for(&cache.surrogate_keys, "https://groups.roblox.com/v1/groups/456/users/123/roles"),
+            ["user:123", "group:456"]
+        );
+        assert_eq!(
+            tags_for(&cache.surrogate_keys, "https://games.roblox.com/v1/games?universeIds=1,2,1"),
+            ["universe:1", "universe:2"]
+        );
+        assert!(tags_for(&cache.surrogate_keys, "https://users.roblox.com/v1/users/authenticated").is_empty());
+    }
+}
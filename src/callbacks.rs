@@ -0,0 +1,107 @@
+use crate::{config::CallbacksConfig, metrics::Metrics, tenants::host_matches, upstream_signing, Rejection};
+use anyhow::Result;
+use reqwest::Client;
+use rocket::{
+    http::{Method, Status},
+    serde::json::Value,
+    Request,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, warn};
+
+/// Where an async or bulk job's result should be POSTed when it finishes:
+/// `X-Proxy-Callback: https://game.example.com/hooks/roproxy`.
+pub const HEADER: &str = "X-Proxy-Callback";
+
+/// Delivers job results to the callback URLs clients registered with
+/// them. Payloads are signed like upstream requests are, so a receiver can
+/// tell they came from the proxy, and delivery is retried with backoff
+/// until the receiver answers with a 2xx or `max_attempts` run out.
+pub struct Callbacks {
+    config: CallbacksConfig,
+    client: Client,
+    metrics: Arc<Metrics>,
+}
+
+impl Callbacks {
+    pub fn new(config: &CallbacksConfig, client: Client, metrics: Arc<Metrics>) -> Self {
+        Callbacks {
+            config: config.clone(),
+            client,
+            metrics,
+        }
+    }
+
+    /// The callback `req` asks for, if it's to an allowed host.
+    pub fn requested(&self, req: &Request<'_>) -> Result<Option<String>> {
+        let Some(callback) = req.headers().get_one(HEADER) else {
+            return Ok(None);
+        };
+        if self.config.hosts.is_empty() {
+            return Err(Rejection::new(Status::Forbidden, "Callbacks are disabled on this proxy").into());
+        }
+        let url = reqwest::Url::parse(callback)
+            .map_err(|_| Rejection::new(Status::BadRequest, format!("{} must be an absolute URL", HEADER)))?;
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+        if url.scheme() != "https" || !self.config.hosts.iter().any(|pattern| host_matches(pattern, &host)) {
+            return Err(Rejection::new(Status::Forbidden, format!("{} is not an allowed callback host", host))
+                .with_field("allowed", self.config.hosts.clone())
+                .into());
+        }
+        Ok(Some(url.to_string()))
+    }
+
+    /// POSTs `payload` to `url` in the background.
+    pub fn deliver(&self, url: String, payload: Value) {
+        let config = self.config.clone();
+        let client = self.client.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let body = payload.to_string();
+            let path = reqwest::Url::parse(&url).map_or_else(
+                |_| "/".to_string(),
+                |url| match url.query() {
+                    Some(query) => format!("{}?{}", url.path(), query),
+                    None => url.path().to_string(),
+                },
+            );
+            let attempts = config.max_attempts.max(1);
+            for attempt in 1..=attempts {
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let mut request = client
+                    .post(&url)
+                    .timeout(Duration::from_secs(config.timeout_secs))
+                    .header("Content-Type", "application/json")
+                    .header(&config.timestamp_header, timestamp.to_string());
+                if let Some(secret) = &config.secret {
+                    let signature =
+                        upstream_signing::signature(secret.as_bytes(), timestamp, Method::Post, &path, body.as_bytes());
+                    request = request.header(&config.header, signature);
+                }
+                let error = match request.body(body.clone()).send().await {
+                    Ok(response) if response.status().is_success() => {
+                        metrics.incr("roproxy_callbacks_total", &[("result", "delivered")]);
+                        return;
+                    }
+                    Ok(response) => format!("answered {}", response.status()),
+                    Err(err) => err.to_string(),
+                };
+                if attempt == attempts {
+                    break;
+                }
+                let backoff = Duration::from_secs(config.retry_backoff_secs).saturating_mul(1 << (attempt - 1).min(16));
+                debug!("Callback to {} {}, retrying in {:?}", url, error, backoff);
+                metrics.incr("roproxy_callbacks_total", &[("result", "retried")]);
+                tokio::time::sleep(backoff).await;
+            }
+            warn!("Giving up on callback to {} after {} attempts", url, attempts);
+            metrics.incr("roproxy_callbacks_total", &[("result", "failed")]);
+        });
+    }
+}
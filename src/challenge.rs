@@ -0,0 +1,181 @@
+use crate::{
+    client_cert,
+    config::ChallengesConfig,
+    credentials::{CredentialPool, PooledCredential},
+    metrics::Metrics,
+    request_log::LogContext,
+    AppState, ErrorResponse, MyRequestGuard, ProxyResponse, Rejection, UpstreamRequest,
+};
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rocket::{
+    http::{Method, Status},
+    serde::{
+        json::{json, Json},
+        Deserialize,
+    },
+    Request, State,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub const ID_HEADER: &str = "rblx-challenge-id";
+pub const TYPE_HEADER: &str = "rblx-challenge-type";
+pub const METADATA_HEADER: &str = "rblx-challenge-metadata";
+
+const CONTINUE_URL: &str = "https://apis.roblox.com/challenge/v1/continue";
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// The challenge a request is answering, if any.
+pub fn challenge_id(headers: &[(String, String)]) -> Option<&str> {
+    header(headers, ID_HEADER)
+}
+
+/// Whether Roblox wants a challenge (2FA, captcha) solved before it'll
+/// accept the request.
+pub fn is_challenge(response: &ProxyResponse) -> bool {
+    header(&response.headers, ID_HEADER).is_some()
+}
+
+/// Remembers which pooled account each outstanding challenge was issued to.
+/// Roblox ties a challenge to the account that triggered it, so the
+/// continuation and the retried request have to go out as that account
+/// rather than whichever one the pool would pick next.
+pub struct Challenges {
+    ttl: Duration,
+    issued: Mutex<HashMap<(String, String), (String, Instant)>>,
+}
+
+impl Challenges {
+    pub fn new(config: &ChallengesConfig) -> Self {
+        Challenges {
+            ttl: Duration::from_secs(config.ttl_secs),
+            issued: Mutex::default(),
+        }
+    }
+
+    /// Notes the challenge `response` carries, if any, against the pool its
+    /// account came from.
+    pub fn observe(&self, pool: &str, response: &ProxyResponse, metrics: &Metrics) {
+        let Some(id) = header(&response.headers, ID_HEADER) else {
+            return;
+        };
+        let kind = header(&response.headers, TYPE_HEADER).unwrap_or("unknown");
+        metrics.incr("roproxy_challenges_total", &[("type", kind)]);
+        let Some(credential) = &response.credential else {
+            return;
+        };
+
+        let mut issued = self.issued.lock().unwrap();
+        issued.retain(|_, (_, at)| at.elapsed() < self.ttl);
+        issued.insert(
+            (pool.to_string(), id.to_string()),
+            (credential.to_string(), Instant::now()),
+        );
+    }
+
+    /// The account challenge `id` was issued to, provided it came from the
+    /// pool named `pool_name`.
+    pub fn credential(&self, pool_name: &str, pool: &CredentialPool, id: &str) -> Option<Arc<PooledCredential>> {
+        let issued = self.issued.lock().unwrap();
+        let (name, at) = issued.get(&(pool_name.to_string(), id.to_string()))?;
+        if at.elapsed() >= self.ttl {
+            return None;
+        }
+        pool.member(name)
+    }
+}
+
+/// A solved challenge, as Roblox's continue endpoint takes it.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct Continuation {
+    challenge_id: String,
+    challenge_type: String,
+    /// JSON-encoded, e.g. `{"verificationToken":"...","rememberDevice":false}`.
+    challenge_metadata: String,
+}
+
+/// Submits a solved challenge as the account it was issued to. On success the
+/// response carries the `rblx-challenge-*` headers to retry the original
+/// request with.
+#[post("/challenge/continue", data = "<continuation>")]
+pub async fn continue_challenge(
+    continuation: Json<Continuation>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<ProxyResponse, ErrorResponse> {
+    submit(continuation.into_inner(), state, guard.request)
+        .await
+        .map_err(ErrorResponse)
+}
+
+async fn submit(continuation: Continuation, state: &AppState, req: &Request<'_>) -> Result<ProxyResponse> {
+    state.screen_client(req)?;
+    let api_key = client_cert::api_key(req);
+    let tenant = state.tenants.authenticate(api_key, &state.metrics)?;
+    if let Some(tenant) = &tenant {
+        let key = api_key.and_then(|api_key| tenant.key(api_key));
+        LogContext::set_tenant(req, &tenant.name, key.map(|key| key.label()));
+        if let Some(key) = key {
+            key.check_scope(Method::Post, CONTINUE_URL, &state.metrics)?;
+        }
+    }
+    LogContext::set_upstream(req, CONTINUE_URL);
+
+    let (pool_name, pool) = match &tenant {
+        Some(tenant) => (tenant.name.as_str(), &tenant.credentials),
+        None => ("default", &state.credentials),
+    };
+    let mut headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+    for name in ["Cookie", "x-csrf-token"] {
+        if let Some(value) = req.headers().get_one(name) {
+            headers.push((name.to_string(), value.to_string()));
+        }
+    }
+    // Without the issuing account (or the client's own cookie) Roblox would
+    // see the continuation come from a stranger.
+    let credential = state.challenges.credential(pool_name, pool, &continuation.challenge_id);
+    if credential.is_none() && !req.headers().contains("Cookie") {
+        return Err(Rejection::new(Status::NotFound, "Unknown or expired challenge").into());
+    }
+
+    let body = json!({
+        "challengeId": continuation.challenge_id,
+        "challengeType": continuation.challenge_type,
+        "challengeMetadata": continuation.challenge_metadata,
+    });
+    let request = UpstreamRequest {
+        method: Method::Post,
+        url: CONTINUE_URL.to_string(),
+        headers,
+        body: Some(body.to_string().into()),
+        credential: None,
+        identity: None,
+        timeout: None,
+    }
+    .with_credential(credential);
+    let mut response = state.engine.forward(request).await?;
+    state.metrics.incr(
+        "roproxy_challenge_continuations_total",
+        &[("status", response.status.code.to_string().as_str())],
+    );
+
+    if response.status.class().is_success() {
+        response.headers.extend([
+            (ID_HEADER.to_string(), continuation.challenge_id),
+            (TYPE_HEADER.to_string(), continuation.challenge_type),
+            (METADATA_HEADER.to_string(), STANDARD.encode(continuation.challenge_metadata)),
+        ]);
+    }
+    Ok(response)
+}
@@ -0,0 +1,209 @@
+use crate::{client_cert, config::ChangeItemsConfig, snapshots, AppState, ErrorResponse, MyRequestGuard, Rejection};
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Serialize,
+    },
+    Route, State,
+};
+use std::{collections::HashMap, sync::Arc};
+
+pub fn routes() -> Vec<Route> {
+    routes![get_changes]
+}
+
+/// One difference between two consecutive snapshots. Paths are JSON
+/// pointers, relative to the record for changes inside one of the job's
+/// `items`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde", tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    Added {
+        id: Value,
+        item: Value,
+    },
+    Removed {
+        id: Value,
+        item: Value,
+    },
+    Changed {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<Value>,
+        path: String,
+        from: Value,
+        to: Value,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChangeEvent {
+    /// When the snapshot showing the change was taken, in Unix seconds.
+    pub at: i64,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+/// Everything that differs between `previous` and `current`. Records under
+/// `items` are matched up by their ID, so a member leaving is one `removed`
+/// rather than a shift of every later array element.
+pub fn diff(previous: &Value, current: &Value, items: Option<&ChangeItemsConfig>) -> Vec<Change> {
+    let mut changes = Vec::new();
+    compare("", previous, current, items.map(|items| items.path.as_str()), None, &mut changes);
+    let Some(items) = items else {
+        return changes;
+    };
+
+    let records = |document: &Value| -> Vec<(Value, Value)> {
+        let Some(Value::Array(records)) = document.pointer(&items.path) else {
+            return Vec::new();
+        };
+        records
+            .iter()
+            .filter_map(|record| Some((record.pointer(&items.id)?.clone(), record.clone())))
+            .collect()
+    };
+    let before = records(previous);
+    let after = records(current);
+    let after_by_id: HashMap<String, &Value> = after.iter().map(|(id, record)| (id.to_string(), record)).collect();
+    let before_ids: Vec<String> = before.iter().map(|(id, _)| id.to_string()).collect();
+
+    for (id, record) in &before {
+        match after_by_id.get(&id.to_string()) {
+            Some(current) => compare("", record, current, None, Some(id), &mut changes),
+            None => changes.push(Change::Removed {
+                id: id.clone(),
+                item: record.clone(),
+            }),
+        }
+    }
+    for (id, record) in after {
+        if !before_ids.contains(&id.to_string()) {
+            changes.push(Change::Added { id, item: record });
+        }
+    }
+    changes
+}
+
+// Objects are compared key by key; anything else, arrays included, changes
+// as a whole.
+fn compare(
+    path: &str,
+    previous: &Value,
+    current: &Value,
+    skip: Option<&str>,
+    id: Option<&Value>,
+    changes: &mut Vec<Change>,
+) {
+    if skip == Some(path) || previous == current {
+        return;
+    }
+    if let (Value::Object(before), Value::Object(after)) = (previous, current) {
+        let child = |name: &str| format!("{}/{}", path, name.replace('~', "~0").replace('/', "~1"));
+        for (name, value) in before {
+            compare(&child(name), value, after.get(name).unwrap_or(&Value::Null), skip, id, changes);
+        }
+        for (name, value) in after.iter().filter(|(name, _)| !before.contains_key(*name)) {
+            compare(&child(name), &Value::Null, value, skip, id, changes);
+        }
+        return;
+    }
+    changes.push(Change::Changed {
+        id: id.cloned(),
+        path: path.to_string(),
+        from: previous.clone(),
+        to: current.clone(),
+    });
+}
+
+/// A one-line summary for chat webhooks, e.g. `2 added, 1 changed`.
+pub fn summary(changes: &[Change]) -> String {
+    let count = |kind: fn(&Change) -> bool| changes.iter().filter(|change| kind(change)).count();
+    [
+        (count(|change| matches!(change, Change::Added { .. })), "added"),
+        (count(|change| matches!(change, Change::Removed { .. })), "removed"),
+        (count(|change| matches!(change, Change::Changed { .. })), "changed"),
+    ]
+    .iter()
+    .filter(|(count, _)| *count > 0)
+    .map(|(count, kind)| format!("{} {}", count, kind))
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+#[get("/changes/<name>?<since>&<until>")]
+fn get_changes(
+    name: &str,
+    since: Option<&str>,
+    until: Option<&str>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let req = guard.request;
+    state.screen_client(req)?;
+    state.tenants.authenticate(client_cert::api_key(req), &state.metrics)?;
+    let since = since.map(snapshots::parse_time).transpose()?;
+    let until = until.map(snapshots::parse_time).transpose()?;
+
+    let Some(events) = state.snapshots.changes(name, since, until) else {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, format!("No snapshot job named {}", name)).into(),
+        ));
+    };
+    Ok(Json(json!({ "name": name, "events": events })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_matched_by_id() {
+        let items = ChangeItemsConfig {
+            path: "/members".to_string(),
+            id: "/userId".to_string(),
+        };
+        let previous = json!({
+            "memberCount": 2,
+            "members": [{ "rank": 1, "userId": 1 }, { "rank": 255, "userId": 2 }],
+        });
+        let current = json!({
+            "memberCount": 2,
+            "members": [{ "rank": 255, "userId": 2 }, { "rank": 10, "userId": 3 }],
+            "shout": "hi",
+        });
+        assert_eq!(
+            diff(&previous, &current, Some(&items)),
+            [
+                Change::Changed {
+                    id: None,
+                    path: "/shout".to_string(),
+                    from: Value::Null,
+                    to: json!("hi"),
+                },
+                Change::Removed {
+                    id: json!(1),
+                    item: json!({ "rank": 1, "userId": 1 }),
+                },
+                Change::Added {
+                    id: json!(3),
+                    item: json!({ "rank": 10, "userId": 3 }),
+                },
+            ]
+        );
+
+        let promoted = json!({ "members": [{ "rank": 254, "userId": 2 }] });
+        let changes = diff(&json!({ "members": [{ "rank": 255, "userId": 2 }] }), &promoted, Some(&items));
+        assert_eq!(
+            changes,
+            [Change::Changed {
+                id: Some(json!(2)),
+                path: "/rank".to_string(),
+                from: json!(255),
+                to: json!(254),
+            }]
+        );
+        assert_eq!(summary(&changes), "1 changed");
+    }
+}
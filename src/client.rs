@@ -0,0 +1,197 @@
+use crate::{
+    config::{AddressFamily, UpstreamConfig},
+    metrics::Metrics,
+    ssrf::UpstreamGuard,
+    timing,
+};
+use anyhow::{Context as _, Result};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, Client,
+};
+use rocket::futures::future::join_all;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::{Layer, Service};
+use tracing::{info, warn};
+
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36";
+
+pub fn build_client(config: &UpstreamConfig, metrics: Arc<Metrics>) -> Result<Client> {
+    let keepalive = (config.tcp_keepalive_secs > 0).then(|| Duration::from_secs(config.tcp_keepalive_secs));
+    // A redirect is as much a request as the original, so it has to stay
+    // within the allowlist too. Stopping hands the redirect to the client.
+    let guard = UpstreamGuard::new(config);
+    let redirects = redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= 10 {
+            attempt.error("too many redirects")
+        } else if guard.normalize(attempt.url().as_str()).is_err() {
+            warn!("Not following redirect to {}", attempt.url());
+            attempt.stop()
+        } else {
+            attempt.follow()
+        }
+    });
+    Client::builder()
+        .redirect(redirects)
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_timeout_secs))
+        .pool_max_idle_per_host(config.pool_max_idle_per_host)
+        .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+        .tcp_keepalive(keepalive)
+        .tcp_nodelay(config.tcp_nodelay)
+        .timeout(Duration::from_secs(30))
+        .user_agent(USER_AGENT)
+        .dns_resolver(Arc::new(TimedResolver {
+            metrics: metrics.clone(),
+            family: config.address_family,
+            racing: config.dual_stack_racing,
+        }))
+        .connector_layer(ConnectMetricsLayer { metrics })
+        .build()
+        .context("Failed to create HTTP client")
+}
+
+/// Opens `prewarm_connections` pooled connections to each prewarm host. The
+/// requests go out together so each needs its own connection, and the pool
+/// keeps them once answered.
+pub async fn prewarm(client: &Client, config: &UpstreamConfig) {
+    let connections = config.prewarm_connections.min(config.pool_max_idle_per_host);
+    if connections == 0 {
+        return;
+    }
+    join_all(config.prewarm_hosts.iter().map(|host| async move {
+        let results = join_all((0..connections).map(|_| client.head(host).send())).await;
+        let opened = results.iter().filter(|result| result.is_ok()).count();
+        if let Some(Err(err)) = results.iter().find(|result| result.is_err()) {
+            warn!("Prewarmed {}/{} connections to {}: {}", opened, connections, host, err);
+        } else {
+            info!("Prewarmed {} connections to {}", opened, host);
+        }
+    }))
+    .await;
+}
+
+// Wraps the system resolver so lookup latency shows up in metrics, and orders
+// or filters the addresses by family. hyper tries the first address's family
+// first and races the other one after a delay.
+struct TimedResolver {
+    metrics: Arc<Metrics>,
+    family: AddressFamily,
+    racing: bool,
+}
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let metrics = self.metrics.clone();
+        let (family, racing) = (self.family, self.racing);
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = tokio::net::lookup_host((host.as_str(), 0)).await;
+            let elapsed = started.elapsed();
+            metrics.observe("roproxy_upstream_dns_seconds", &[], elapsed);
+            timing::record(|timings| timings.dns += elapsed);
+            let addrs = result.inspect_err(|_| {
+                metrics.incr("roproxy_upstream_dns_failures_total", &[]);
+            })?;
+            let addrs = select_addrs(addrs.collect(), family, racing);
+            if addrs.is_empty() {
+                metrics.incr("roproxy_upstream_dns_failures_total", &[]);
+                return Err(format!("{} has no addresses in the configured family", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn select_addrs(mut addrs: Vec<SocketAddr>, family: AddressFamily, racing: bool) -> Vec<SocketAddr> {
+    let prefer_v4 = match family {
+        AddressFamily::Any => match addrs.first() {
+            Some(first) => first.is_ipv4(),
+            None => return addrs,
+        },
+        AddressFamily::PreferIpv4 => true,
+        AddressFamily::PreferIpv6 => false,
+        AddressFamily::Ipv4Only => {
+            addrs.retain(SocketAddr::is_ipv4);
+            return addrs;
+        }
+        AddressFamily::Ipv6Only => {
+            addrs.retain(SocketAddr::is_ipv6);
+            return addrs;
+        }
+    };
+    // Stable, so the resolver's order holds within each family.
+    addrs.sort_by_key(|addr| addr.is_ipv4() != prefer_v4);
+    if !racing && addrs.first().is_some_and(|first| first.is_ipv4() == prefer_v4) {
+        addrs.retain(|addr| addr.is_ipv4() == prefer_v4);
+    }
+    addrs
+}
+
+// Sits on reqwest's connector, which is only invoked when the pool has no idle
+// connection to hand out. Every call is therefore a fresh TCP + TLS handshake,
+// which is what we count and time here. hyper keeps the pool itself private, so
+// idle connections can't be counted directly; a low new-connection rate against
+// steady traffic means the pool is doing its job.
+#[derive(Clone)]
+struct ConnectMetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl<S> Layer<S> for ConnectMetricsLayer {
+    type Service = ConnectMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConnectMetrics {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ConnectMetrics<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, R> Service<R> for ConnectMetrics<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let metrics = self.metrics.clone();
+        let connecting = self.inner.call(req);
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = connecting.await;
+            let elapsed = started.elapsed();
+            metrics.observe("roproxy_upstream_connect_seconds", &[], elapsed);
+            // The connector resolves the host itself, so DNS is inside `elapsed`.
+            timing::record(|timings| timings.connect += elapsed.saturating_sub(timings.dns));
+            if result.is_ok() {
+                metrics.incr("roproxy_upstream_connections_opened_total", &[]);
+                metrics.mark("roproxy_upstream_connections_last_minute", &[]);
+            } else {
+                metrics.incr("roproxy_upstream_connect_failures_total", &[]);
+            }
+            result
+        })
+    }
+}
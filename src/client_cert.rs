@@ -0,0 +1,83 @@
+use crate::{metrics::Metrics, AppState};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    mtls::{x509::GeneralName, Certificate},
+    Data, Request,
+};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tracing::debug;
+
+/// The proxy key a verified client certificate stands in for.
+struct CertificateKey(Option<String>);
+
+/// The proxy key a request presents: `X-Proxy-Key`, or else the key its
+/// client certificate is mapped to.
+pub fn api_key<'r>(req: &'r Request<'_>) -> Option<&'r str> {
+    req.headers()
+        .get_one("X-Proxy-Key")
+        .or_else(|| req.local_cache(|| CertificateKey(None)).0.as_deref())
+}
+
+/// Maps client certificates, verified by the TLS listener against
+/// `tls.mutual.ca_certs`, to the scoped proxy key listing their fingerprint or
+/// one of their SANs. Without mutual TLS configured no certificate is ever
+/// presented and this does nothing.
+pub struct ClientCertificates;
+
+#[rocket::async_trait]
+impl Fairing for ClientCertificates {
+    fn info(&self) -> Info {
+        Info {
+            name: "Client certificates",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let Some(state) = req.rocket().state::<Arc<AppState>>() else {
+            return;
+        };
+        let Some(cert) = req.guard::<Certificate<'_>>().await.succeeded() else {
+            return;
+        };
+        let key = certificate_key(state, &cert, &state.metrics);
+        req.local_cache(|| CertificateKey(key));
+    }
+}
+
+fn certificate_key(state: &AppState, cert: &Certificate<'_>, metrics: &Metrics) -> Option<String> {
+    let fingerprint = hex::encode(Sha256::digest(cert.as_bytes()));
+    let sans: Vec<&str> = cert
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .map(|san| {
+            san.value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(name) | GeneralName::RFC822Name(name) | GeneralName::URI(name) => {
+                        Some(*name)
+                    }
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let key = state
+        .tenants
+        .iter()
+        .flat_map(|tenant| tenant.api_keys())
+        .find(|key| key.matches_certificate(&fingerprint, &sans));
+    let result = if key.is_some() { "matched" } else { "unmatched" };
+    metrics.incr("roproxy_client_certificates_total", &[("result", result)]);
+    debug!(
+        "Client certificate {} ({}) {}",
+        fingerprint,
+        sans.join(", "),
+        key.map_or("matched no key", |key| key.label())
+    );
+    key.map(|key| key.secret().to_string())
+}
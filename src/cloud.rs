@@ -0,0 +1,139 @@
+use crate::{
+    client_cert, helpers, request_log::LogContext, upload, AppState, ErrorResponse, MyRequestGuard, Rejection,
+    UpstreamRequest,
+};
+use anyhow::Result;
+use rocket::{
+    data::{ByteUnit, Data},
+    http::{Method, Status},
+    serde::json::{json, Json, Value},
+    Request, State,
+};
+use std::{sync::Arc, time::Duration};
+use tracing::info;
+
+const VERSION_TYPES: [&str; 2] = ["Published", "Saved"];
+
+/// The content type Open Cloud wants for a place file starting with `head`:
+/// binary `.rbxl` or XML `.rbxlx`.
+fn place_content_type(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"<roblox!") {
+        return Some("application/octet-stream");
+    }
+    let head = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let start = head.iter().position(|byte| !byte.is_ascii_whitespace())?;
+    let head = &head[start..];
+    (head.starts_with(b"<roblox") || head.starts_with(b"<?xml")).then_some("application/xml")
+}
+
+/// Uploads the request body, a `.rbxl` or `.rbxlx` file, as a new version of
+/// the place, streaming it through as it arrives. `?versionType=Saved` saves
+/// without publishing. Answers with the new `versionNumber`.
+#[post("/cloud/universes/<universe_id>/places/<place_id>/versions", data = "<data>")]
+pub async fn publish_place(
+    universe_id: u64,
+    place_id: u64,
+    data: Data<'_>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    publish(universe_id, place_id, data, state, guard.request)
+        .await
+        .map(Json)
+        .map_err(ErrorResponse)
+}
+
+async fn publish(
+    universe_id: u64,
+    place_id: u64,
+    mut data: Data<'_>,
+    state: &AppState,
+    req: &Request<'_>,
+) -> Result<Value> {
+    state.screen_client(req)?;
+    let version_type = req
+        .query_value::<&str>("versionType")
+        .and_then(Result::ok)
+        .unwrap_or("Published");
+    if !VERSION_TYPES.contains(&version_type) {
+        return Err(Rejection::new(Status::BadRequest, format!("Unknown versionType {}", version_type))
+            .with_field("supported", VERSION_TYPES.to_vec())
+            .into());
+    }
+    let url = format!(
+        "https://apis.roblox.com/universes/v1/{}/places/{}/versions?versionType={}",
+        universe_id, place_id, version_type
+    );
+
+    let api_key = client_cert::api_key(req);
+    let tenant = state.tenants.authenticate(api_key, &state.metrics)?;
+    if let Some(tenant) = &tenant {
+        let key = api_key.and_then(|api_key| tenant.key(api_key));
+        LogContext::set_tenant(req, &tenant.name, key.map(|key| key.label()));
+        if let Some(key) = key {
+            key.check_scope(Method::Post, &url, &state.metrics)?;
+        }
+    }
+    LogContext::set_upstream(req, &url);
+
+    let limit = ByteUnit::from(state.cloud.max_place_bytes);
+    let declared = req.headers().get_one("Content-Length").and_then(|length| length.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > limit.as_u64()) {
+        return Err(Rejection::new(Status::PayloadTooLarge, "Place file is too large")
+            .with_field("max_bytes", limit.as_u64())
+            .into());
+    }
+    let content_type = place_content_type(data.peek(64).await)
+        .ok_or_else(|| Rejection::new(Status::UnsupportedMediaType, "Expected an .rbxl or .rbxlx place file"))?;
+
+    let mut headers = vec![("Content-Type".to_string(), content_type.to_string())];
+    if let Some(length) = declared {
+        headers.push(("Content-Length".to_string(), length.to_string()));
+    }
+    let timeout = Duration::from_secs(state.cloud.publish_timeout_secs);
+    let (body, feed) = upload::stream_body(data, limit, timeout);
+    let pool = tenant
+        .as_ref()
+        .map_or(&state.credentials, |tenant| &tenant.credentials);
+    let request = UpstreamRequest {
+        method: Method::Post,
+        headers,
+        body: Some(body),
+        timeout: Some(timeout),
+        ..UpstreamRequest::get(url.clone())
+    }
+    .with_credentials(pool);
+    let (fed, response) = tokio::join!(feed, state.engine.forward(request));
+    let size = fed?;
+    let response = response?;
+    state.metrics.incr(
+        "roproxy_place_publishes_total",
+        &[("status", response.status.code.to_string().as_str())],
+    );
+
+    let version = helpers::check(&url, &response).map_err(helpers::rejection)?;
+    info!(
+        "Uploaded {} bytes as version {} of place {} ({})",
+        size, version["versionNumber"], place_id, version_type
+    );
+    Ok(json!({
+        "universeId": universe_id,
+        "placeId": place_id,
+        "versionType": version_type,
+        "versionNumber": version["versionNumber"],
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_place_formats_apart() {
+        assert_eq!(place_content_type(b"<roblox!\x89\xff\r\n"), Some("application/octet-stream"));
+        assert_eq!(place_content_type(b"<roblox xmlns:xmime="), Some("application/xml"));
+        assert_eq!(place_content_type(b"\xef\xbb\xbf\r\n<roblox version=\"4\">"), Some("application/xml"));
+        assert_eq!(place_content_type(b"PK\x03\x04"), None);
+        assert_eq!(place_content_type(b""), None);
+    }
+}
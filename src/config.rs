@@ -0,0 +1,1680 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr},
+};
+use rocket::{
+    figment::Figment,
+    serde::{json::Value, Deserialize},
+};
+
+/// Proxy settings, read from the `proxy` table of `Rocket.toml` or the
+/// matching `ROCKET_PROXY` environment variable. Every field has a default, so
+/// an absent table is valid.
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ProxyConfig {
+    pub idempotency: IdempotencyConfig,
+    pub budgets: BudgetsConfig,
+    pub cache: CacheConfig,
+    pub warming: WarmingConfig,
+    pub tenants: Vec<TenantConfig>,
+    pub sessions: SessionsConfig,
+    pub credentials: CredentialsConfig,
+    pub admin: AdminConfig,
+    pub axum: AxumConfig,
+    pub signed_urls: SignedUrlsConfig,
+    pub sse: SseConfig,
+    pub websocket: WebSocketConfig,
+    pub push: PushConfig,
+    pub transforms: TransformsConfig,
+    pub schemas: SchemaValidationConfig,
+    pub rewrites: RewritesConfig,
+    pub method_override: MethodOverrideConfig,
+    pub envelope: EnvelopeConfig,
+    pub base64: Base64Config,
+    pub content_types: ContentTypesConfig,
+    pub response_limit: ResponseLimitConfig,
+    pub connections: ConnectionsConfig,
+    pub header_limits: HeaderLimitsConfig,
+    pub user_agents: UserAgentsConfig,
+    pub abuse: AbuseConfig,
+    pub enumeration: EnumerationConfig,
+    pub challenges: ChallengesConfig,
+    pub upstream: UpstreamConfig,
+    pub adaptive_timeouts: AdaptiveTimeoutsConfig,
+    pub retries: RetriesConfig,
+    pub server_timing: ServerTimingConfig,
+    pub identities: IdentitiesConfig,
+    pub upstream_targets: UpstreamTargetsConfig,
+    pub host_methods: HostMethodsConfig,
+    pub scripts: ScriptsConfig,
+    pub response_headers: ResponseHeadersConfig,
+    pub set_cookies: SetCookieConfig,
+    pub body_rules: BodyRulesConfig,
+    pub minify_json: MinifyJsonConfig,
+    pub stale_if_error: StaleIfErrorConfig,
+    pub snapshots: SnapshotsConfig,
+    pub response_webhooks: ResponseWebhooksConfig,
+    pub audit_feeds: AuditFeedsConfig,
+    pub helpers: HelpersConfig,
+    pub trades: TradesConfig,
+    pub economy: EconomyConfig,
+    pub audit_log: AuditLogConfig,
+    pub cloud: CloudConfig,
+    pub log_sampling: LogSamplingConfig,
+    pub usage: UsageConfig,
+    pub upstream_signing: UpstreamSigningConfig,
+    pub tags: TagsConfig,
+    pub async_jobs: AsyncJobsConfig,
+    pub bulk: BulkConfig,
+    pub callbacks: CallbacksConfig,
+    pub shared_state: SharedStateConfig,
+    pub integrity: IntegrityConfig,
+}
+
+impl ProxyConfig {
+    pub fn from_figment(figment: &Figment) -> Result<Self> {
+        figment
+            .focus("proxy")
+            .extract()
+            .context("Invalid proxy configuration")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct IdempotencyConfig {
+    /// How long a completed response is replayed for the same key.
+    pub ttl_secs: u64,
+    /// Upper bound on stored responses; the oldest are dropped first.
+    pub max_entries: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig {
+            ttl_secs: 24 * 60 * 60,
+            max_entries: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    /// Freshness of cached GET responses unless a job or route says otherwise.
+    pub ttl_secs: u64,
+    pub max_entries: usize,
+    /// Memory budget for cached responses, counting bodies, headers and keys.
+    pub max_bytes: usize,
+    /// How many of the most-hit entries are refetched in the background
+    /// before they expire. Zero turns background refresh off.
+    pub refresh_top_n: usize,
+    /// How close to expiry a hot entry has to be before it's refreshed.
+    pub refresh_ahead_secs: u64,
+    /// Directory to mirror cached responses into, so they survive restarts.
+    pub disk_dir: Option<String>,
+    /// Longest freshness a client may ask for with `X-Proxy-Cache-TTL`.
+    pub max_client_ttl_secs: u64,
+    pub surrogate_keys: Vec<SurrogateKeyConfig>,
+    pub post_rules: Vec<PostCacheRuleConfig>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: true,
+            ttl_secs: 60,
+            max_entries: 5_000,
+            max_bytes: 64 * 1024 * 1024,
+            refresh_top_n: 50,
+            refresh_ahead_secs: 10,
+            disk_dir: None,
+            max_client_ttl_secs: 60 * 60,
+            surrogate_keys: [
+                ("user", r"/users/(\d+)"),
+                ("user", r"[?&]userIds?=([\d,]+)"),
+                ("group", r"/groups/(\d+)"),
+                ("group", r"[?&]groupIds?=([\d,]+)"),
+                ("universe", r"/universes/(\d+)"),
+                ("universe", r"[?&]universeIds?=([\d,]+)"),
+            ]
+            .map(|(name, pattern)| SurrogateKeyConfig {
+                name: name.to_string(),
+                pattern: pattern.to_string(),
+            })
+            .to_vec(),
+            post_rules: Vec::new(),
+        }
+    }
+}
+
+/// POST endpoints that only look things up, like
+/// `https://thumbnails.roblox.com/v1/batch` or
+/// `https://users.roblox.com/v1/users`, cached by URL and a hash of the
+/// request body. The first rule with a prefix of the URL wins. Nothing POSTed
+/// is cached without one.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PostCacheRuleConfig {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    /// Keep this short: entries can't be refreshed in the background or
+    /// purged by what's in the body.
+    pub ttl_secs: u64,
+}
+
+/// Tags cached responses whose upstream URL matches `pattern` with
+/// `<name>:<capture>`, e.g. `group:456`, so everything about one group can be
+/// purged at once with `DELETE /admin/cache/keys/group:456`. The first capture
+/// is split on commas, for batch endpoints taking `?userIds=1,2`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SurrogateKeyConfig {
+    pub name: String,
+    pub pattern: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct WarmingConfig {
+    pub jobs: Vec<WarmingJobConfig>,
+}
+
+/// Periodically fetches `urls` into the cache. `schedule` is a cron
+/// expression with a leading seconds field, evaluated in UTC, e.g.
+/// `0 */15 2-6 * * *` for every 15 minutes between 02:00 and 06:59.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct WarmingJobConfig {
+    pub name: String,
+    pub schedule: String,
+    /// Full upstream URLs, with query parameters in sorted order so they line
+    /// up with the cache keys of proxied requests.
+    pub urls: Vec<String>,
+    /// How long warmed entries stay fresh; should cover the gap until the
+    /// next run. Defaults to the cache TTL.
+    pub ttl_secs: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SessionsConfig {
+    pub enabled: bool,
+    /// Sessions unused for this long start over with an empty jar.
+    pub idle_ttl_secs: u64,
+    pub max_sessions: usize,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        SessionsConfig {
+            enabled: false,
+            idle_ttl_secs: 30 * 60,
+            max_sessions: 1_000,
+        }
+    }
+}
+
+/// A tenant is identified by one of its `api_keys` (sent as `X-Proxy-Key`) or
+/// by requests under `/<path_prefix>/`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TenantConfig {
+    pub name: String,
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeyConfig>,
+    pub path_prefix: Option<String>,
+    /// Injected as the `.ROBLOSECURITY` cookie on the tenant's requests.
+    pub roblosecurity: Option<String>,
+    /// Injected as the `x-api-key` header for Open Cloud.
+    pub open_cloud_key: Option<String>,
+    /// Further accounts rotated alongside the ones above.
+    #[serde(default)]
+    pub accounts: Vec<AccountConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+/// Either a bare key with full access or a key restricted to some upstream
+/// hosts and methods.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", untagged)]
+pub enum ApiKeyConfig {
+    Plain(String),
+    Scoped(Box<ScopedKeyConfig>),
+}
+
+impl ApiKeyConfig {
+    /// The key as a scoped one; a bare key is one left at every default.
+    pub fn scoped(&self) -> ScopedKeyConfig {
+        match self {
+            ApiKeyConfig::Plain(key) => ScopedKeyConfig {
+                key: key.clone(),
+                ..ScopedKeyConfig::default()
+            },
+            ApiKeyConfig::Scoped(scoped) => (**scoped).clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ScopedKeyConfig {
+    pub key: String,
+    /// Label for metrics and logs; defaults to a fingerprint of the key.
+    pub name: Option<String>,
+    /// Allowed upstream hosts, e.g. `thumbnails.roblox.com` or
+    /// `*.roblox.com`. Empty allows any.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Allowed HTTP methods. Empty allows any.
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Named upstreams the key may pick with `X-Proxy-Upstream`. Empty
+    /// allows none.
+    #[serde(default)]
+    pub upstreams: Vec<String>,
+    /// Client certificates that present this key instead of `X-Proxy-Key`,
+    /// as `sha256:<fingerprint>` or `san:<DNS name, email or URI>`. Needs
+    /// mutual TLS on the listener (`tls.mutual.ca_certs`).
+    #[serde(default)]
+    pub client_certs: Vec<String>,
+    /// Whether the key may set `X-Proxy-Cache-TTL`.
+    #[serde(default)]
+    pub cache_ttl: bool,
+    /// Operations that put the account at risk which the key may perform
+    /// where the deployment enables them, e.g. `trades`. Empty allows none.
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    /// Bytes the key may move per UTC day, request and response bodies
+    /// together, before it gets 429s. Unset is unlimited.
+    pub daily_bytes: Option<u64>,
+    /// Likewise per calendar month.
+    pub monthly_bytes: Option<u64>,
+    /// When the key stops working, as an RFC 3339 time or a `YYYY-MM-DD`
+    /// date (midnight UTC).
+    pub expires: Option<String>,
+    /// Refuses the key with a `key_revoked` error while still counting its
+    /// uses, to confirm nothing relies on it before it's deleted.
+    #[serde(default)]
+    pub revoked: bool,
+    /// Rate limits for requests sent with `X-Proxy-Tag`, by tag, on top of
+    /// the tenant's.
+    #[serde(default)]
+    pub tag_rate_limits: BTreeMap<String, RateLimitConfig>,
+}
+
+/// Accounts shared by every request that isn't served by a tenant.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CredentialsConfig {
+    pub strategy: RotationStrategy,
+    /// Pins each client to one account by consistent hashing, so a session,
+    /// proxy key or IP keeps its account across requests.
+    pub affinity: bool,
+    /// How long an account sits out after Roblox answers it with a 429.
+    pub throttle_cooldown_secs: u64,
+    pub accounts: Vec<AccountConfig>,
+    /// How often every account is checked against Roblox for expired or
+    /// revoked credentials. Zero turns the check off.
+    pub health_check_secs: u64,
+    /// Receives a JSON POST when an account is found to be unusable.
+    pub alert_webhook: Option<String>,
+    /// File holding accounts added through the admin API, encrypted with
+    /// `encryption_key`.
+    pub store_path: Option<String>,
+    /// Base64-encoded 32-byte AES-256-GCM key for `store_path`.
+    pub encryption_key: Option<String>,
+}
+
+impl Default for CredentialsConfig {
+    fn default() -> Self {
+        CredentialsConfig {
+            strategy: RotationStrategy::RoundRobin,
+            affinity: false,
+            throttle_cooldown_secs: 60,
+            accounts: Vec::new(),
+            health_check_secs: 15 * 60,
+            alert_webhook: None,
+            store_path: None,
+            encryption_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum RotationStrategy {
+    RoundRobin,
+    LeastRecentlyThrottled,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AccountConfig {
+    pub name: String,
+    pub roblosecurity: Option<String>,
+    pub open_cloud_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RateLimitConfig {
+    pub limit: u32,
+    pub window_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct BudgetsConfig {
+    pub families: Vec<BudgetFamilyConfig>,
+}
+
+/// A request budget shared by every upstream URL starting with one of
+/// `prefixes`, refilled continuously at `limit` requests per `window_secs`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BudgetFamilyConfig {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    pub limit: u32,
+    pub window_secs: u64,
+    #[serde(default)]
+    pub on_exhausted: ExhaustedPolicy,
+    /// Longest a request may be held back under `queue` before it's rejected.
+    #[serde(default = "default_max_queue_ms")]
+    pub max_queue_ms: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum ExhaustedPolicy {
+    #[default]
+    Queue,
+    Reject,
+}
+
+impl ExhaustedPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExhaustedPolicy::Queue => "queue",
+            ExhaustedPolicy::Reject => "reject",
+        }
+    }
+}
+
+fn default_max_queue_ms() -> u64 {
+    5_000
+}
+
+impl Default for BudgetsConfig {
+    fn default() -> Self {
+        // Roblox doesn't publish exact numbers; these sit just under the limits
+        // observed in practice for unauthenticated traffic from one IP.
+        let family = |name: &str, host: &str, limit| BudgetFamilyConfig {
+            name: name.to_string(),
+            prefixes: vec![format!("https://{}.roblox.com/", host)],
+            limit,
+            window_secs: 60,
+            on_exhausted: ExhaustedPolicy::Queue,
+            max_queue_ms: default_max_queue_ms(),
+        };
+        BudgetsConfig {
+            families: vec![
+                family("thumbnails", "thumbnails", 100),
+                family("users", "users", 60),
+                family("groups", "groups", 60),
+            ],
+        }
+    }
+}
+
+/// The `/admin` API is only mounted when a token is configured.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AdminConfig {
+    /// Expected as `Authorization: Bearer <token>` on admin requests.
+    pub token: Option<String>,
+    /// Serves `/admin`, `/metrics` and `/status` on their own listener
+    /// instead of the public one, so they can be firewalled separately.
+    /// Without it, `/metrics` needs the admin token.
+    pub port: Option<u16>,
+    /// Address that listener binds to.
+    pub address: IpAddr,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            token: None,
+            port: None,
+            address: Ipv4Addr::LOCALHOST.into(),
+        }
+    }
+}
+
+/// A second listener for the catch-all proxy route, served by axum instead
+/// of Rocket. Only used in builds with the `axum` feature.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AxumConfig {
+    /// Port the listener binds to; off when unset.
+    pub port: Option<u16>,
+    pub address: IpAddr,
+}
+
+impl Default for AxumConfig {
+    fn default() -> Self {
+        AxumConfig {
+            port: None,
+            address: Ipv4Addr::LOCALHOST.into(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SignedUrlsConfig {
+    /// HMAC key for `?expires=...&sig=...` URLs. Without one, signed URLs are
+    /// neither issued nor accepted.
+    pub secret: Option<String>,
+    /// Longest lifetime the admin API will sign a URL for.
+    pub max_ttl_secs: u64,
+}
+
+impl Default for SignedUrlsConfig {
+    fn default() -> Self {
+        SignedUrlsConfig {
+            secret: None,
+            max_ttl_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SseConfig {
+    /// Keep-alive comment sent to the client when upstream has been quiet
+    /// this long.
+    pub heartbeat_secs: u64,
+    /// Closes a stream upstream hasn't written to for this long.
+    pub idle_timeout_secs: u64,
+    /// Upper bound on a stream's total lifetime, for requests sent with
+    /// `Accept: text/event-stream`. Other requests keep the 30s timeout.
+    pub max_duration_secs: u64,
+}
+
+impl Default for SseConfig {
+    fn default() -> Self {
+        SseConfig {
+            heartbeat_secs: 15,
+            idle_timeout_secs: 5 * 60,
+            max_duration_secs: 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct WebSocketConfig {
+    pub enabled: bool,
+    /// URL prefixes `/ws/<host>/<path>` may connect to, e.g.
+    /// `wss://realtime.roblox.com/`, matched on scheme, host, port and path
+    /// segments. Anything else is refused, as is any host missing from
+    /// `upstream.allowed_hosts`.
+    pub upstreams: Vec<String>,
+    /// Closes a bridged connection after this long without a message in
+    /// either direction.
+    pub idle_timeout_secs: u64,
+    /// Largest message or frame relayed in either direction.
+    pub max_message_bytes: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        WebSocketConfig {
+            enabled: false,
+            upstreams: Vec::new(),
+            idle_timeout_secs: 5 * 60,
+            max_message_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct PushConfig {
+    pub enabled: bool,
+    /// Required as `Authorization: Bearer <token>` to publish, if set.
+    pub publish_token: Option<String>,
+    /// How long `/poll` holds a request open when no `wait` is given.
+    pub default_hold_secs: u64,
+    /// Cap on `wait`, kept under HttpService's own request timeout.
+    pub max_hold_secs: u64,
+    /// Messages kept per channel for pollers that fall behind.
+    pub max_messages: usize,
+    pub message_ttl_secs: u64,
+    pub max_message_bytes: usize,
+    pub max_channels: usize,
+}
+
+impl Default for PushConfig {
+    fn default() -> Self {
+        PushConfig {
+            enabled: false,
+            publish_token: None,
+            default_hold_secs: 20,
+            max_hold_secs: 25,
+            max_messages: 100,
+            message_ttl_secs: 5 * 60,
+            max_message_bytes: 64 * 1024,
+            max_channels: 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct TransformsConfig {
+    pub rules: Vec<TransformRuleConfig>,
+}
+
+/// Rewrites successful JSON responses from upstream URLs starting with one of
+/// `prefixes` using a jq-style `expression`, e.g.
+/// `{id, name, created}` or `.data | map({id, name})`. The first matching
+/// rule wins.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TransformRuleConfig {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    pub expression: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SchemaValidationConfig {
+    pub enabled: bool,
+    /// Adds `X-Schema-Valid: true|false` to every validated response.
+    pub add_header: bool,
+    pub rules: Vec<SchemaRuleConfig>,
+}
+
+/// A JSON Schema that successful JSON responses from upstream URLs starting
+/// with one of `prefixes` are expected to match, given inline or as a path.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SchemaRuleConfig {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    pub schema: Option<Value>,
+    pub schema_file: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RewritesConfig {
+    pub rules: Vec<RewriteRuleConfig>,
+}
+
+/// Maps request paths matching `pattern` (a regex over the path and sorted
+/// query, without the leading slash) to `target`, which may use `$1`-style
+/// captures. A target that isn't a full URL is a path on www.roblox.com, e.g.
+/// `^api/users/(\d+)$` to `https://users.roblox.com/v1/users/$1`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RewriteRuleConfig {
+    pub name: String,
+    pub pattern: String,
+    pub target: String,
+}
+
+/// Upstreams a request can be sent to by name with `X-Proxy-Upstream`, for
+/// paths that exist on several Roblox hosts. The path goes to the named
+/// upstream as-is, skipping rewrites.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct UpstreamTargetsConfig {
+    pub targets: Vec<UpstreamTargetConfig>,
+}
+
+impl Default for UpstreamTargetsConfig {
+    fn default() -> Self {
+        let targets = ["apis", "avatar", "catalog", "economy", "games", "groups", "thumbnails", "users"]
+            .map(|host| UpstreamTargetConfig {
+                name: host.to_string(),
+                url: format!("https://{}.roblox.com", host),
+            })
+            .to_vec();
+        UpstreamTargetsConfig { targets }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UpstreamTargetConfig {
+    pub name: String,
+    /// Base URL the request path is appended to.
+    pub url: String,
+}
+
+/// Lets POST requests carry `X-HTTP-Method-Override` for clients, like older
+/// HttpService code, that can't send other verbs directly.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MethodOverrideConfig {
+    pub enabled: bool,
+    /// Methods a POST may be turned into.
+    pub methods: Vec<String>,
+}
+
+impl Default for MethodOverrideConfig {
+    fn default() -> Self {
+        MethodOverrideConfig {
+            enabled: false,
+            methods: ["GET", "PUT", "PATCH", "DELETE"].map(String::from).to_vec(),
+        }
+    }
+}
+
+/// `POST /proxy`, where the target URL travels in a JSON body. Only mounted
+/// when enabled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct EnvelopeConfig {
+    pub enabled: bool,
+    /// Hosts envelopes may target, as `example.com` or `*.example.com`.
+    pub hosts: Vec<String>,
+}
+
+impl Default for EnvelopeConfig {
+    fn default() -> Self {
+        EnvelopeConfig {
+            enabled: false,
+            hosts: ["roblox.com", "*.roblox.com"].map(String::from).to_vec(),
+        }
+    }
+}
+
+/// `POST /async`, which queues a request in the `POST /proxy` format to run
+/// in the background. Only mounted when enabled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AsyncJobsConfig {
+    pub enabled: bool,
+    /// Jobs run at once.
+    pub workers: usize,
+    /// Jobs queued or running before new ones are refused.
+    pub max_queued: usize,
+    /// How long a finished job's result can be fetched.
+    pub result_ttl_secs: u64,
+    /// Runs a job gets before it's dead-lettered.
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubling for each one after.
+    pub retry_backoff_secs: u64,
+    /// File queued and dead-lettered jobs are kept in across restarts.
+    pub path: Option<String>,
+}
+
+impl Default for AsyncJobsConfig {
+    fn default() -> Self {
+        AsyncJobsConfig {
+            enabled: false,
+            workers: 2,
+            max_queued: 1_000,
+            result_ttl_secs: 10 * 60,
+            max_attempts: 3,
+            retry_backoff_secs: 30,
+            path: None,
+        }
+    }
+}
+
+/// `POST /bulk`, which runs many requests of one shape as a background job
+/// paced against the budgets. Only mounted when enabled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct BulkConfig {
+    pub enabled: bool,
+    /// Items one job may carry.
+    pub max_items: usize,
+    pub max_chunk_size: usize,
+    /// Jobs running at once; others wait their turn.
+    pub workers: usize,
+    /// Jobs queued or running before new ones are refused.
+    pub max_jobs: usize,
+    /// Share of each budget family that bulk jobs leave for other traffic.
+    pub reserve: f64,
+    /// Times a throttled or failed chunk is tried again.
+    pub chunk_retries: u32,
+    /// How long a finished job's results can be fetched.
+    pub result_ttl_secs: u64,
+}
+
+impl Default for BulkConfig {
+    fn default() -> Self {
+        BulkConfig {
+            enabled: false,
+            max_items: 10_000,
+            max_chunk_size: 100,
+            workers: 2,
+            max_jobs: 20,
+            reserve: 0.25,
+            chunk_retries: 3,
+            result_ttl_secs: 60 * 60,
+        }
+    }
+}
+
+/// Where async and bulk jobs may POST their results when they finish, and
+/// how. Callbacks are refused while `hosts` is empty.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CallbacksConfig {
+    /// Hosts callbacks may go to, as `example.com` or `*.example.com`.
+    pub hosts: Vec<String>,
+    /// Signs payloads the same way `upstream_signing` signs requests.
+    pub secret: Option<String>,
+    pub header: String,
+    pub timestamp_header: String,
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubling for each one after.
+    pub retry_backoff_secs: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for CallbacksConfig {
+    fn default() -> Self {
+        CallbacksConfig {
+            hosts: Vec::new(),
+            secret: None,
+            header: "X-Proxy-Signature".to_string(),
+            timestamp_header: "X-Proxy-Timestamp".to_string(),
+            max_attempts: 5,
+            retry_backoff_secs: 10,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// Redis shared by every replica of the proxy, so budgets, budget holds and
+/// CSRF tokens are coordinated instead of per instance. Off unless
+/// `redis_url` is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SharedStateConfig {
+    /// e.g. `redis://redis.internal:6379/0`.
+    pub redis_url: Option<String>,
+    /// Prepended to every key, so deployments can share one Redis.
+    pub key_prefix: String,
+    /// Longest a Redis call may take before local state is used instead.
+    pub timeout_ms: u64,
+    /// How long a CSRF token Roblox issued an account is reused for.
+    pub csrf_ttl_secs: u64,
+}
+
+impl Default for SharedStateConfig {
+    fn default() -> Self {
+        SharedStateConfig {
+            redis_url: None,
+            key_prefix: "roproxy:".to_string(),
+            timeout_ms: 250,
+            csrf_ttl_secs: 5 * 60,
+        }
+    }
+}
+
+/// Opt-in base64 bodies, requested per call with the
+/// `X-Proxy-Request-Encoding` and `X-Proxy-Response-Encoding` headers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct Base64Config {
+    pub enabled: bool,
+    /// Largest decoded request or raw response body that will be converted.
+    pub max_bytes: usize,
+}
+
+impl Default for Base64Config {
+    fn default() -> Self {
+        Base64Config {
+            enabled: false,
+            max_bytes: 2 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ContentTypesConfig {
+    pub rules: Vec<ContentTypeRuleConfig>,
+}
+
+/// Fixes up the content type of responses from upstream URLs starting with
+/// one of `prefixes`. The first matching rule wins.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ContentTypeRuleConfig {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    /// Relabels bodies that parse as JSON as `application/json`, whatever
+    /// type they were sent with.
+    #[serde(default = "default_true")]
+    pub sniff_json: bool,
+    /// Re-encodes text in other charsets as UTF-8 and labels it as such.
+    #[serde(default = "default_true")]
+    pub normalize_charset: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Caps how much of an upstream body is read into memory. Event streams are
+/// relayed as they arrive and aren't counted.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ResponseLimitConfig {
+    pub max_bytes: usize,
+    pub on_exceeded: OversizePolicy,
+}
+
+impl Default for ResponseLimitConfig {
+    fn default() -> Self {
+        ResponseLimitConfig {
+            max_bytes: 32 * 1024 * 1024,
+            on_exceeded: OversizePolicy::Reject,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum OversizePolicy {
+    /// Answer 502 instead of the response.
+    #[default]
+    Reject,
+    /// Return the first `max_bytes` with `X-Proxy-Truncated: true`.
+    Truncate,
+}
+
+impl OversizePolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OversizePolicy::Reject => "reject",
+            OversizePolicy::Truncate => "truncate",
+        }
+    }
+}
+
+/// Limits on simultaneous requests, answered with an immediate 503 when
+/// exceeded. Zero disables a limit. Rocket doesn't expose a header read
+/// timeout; idle keep-alive connections are bounded by its `keep_alive`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ConnectionsConfig {
+    pub max_connections: usize,
+    pub max_per_ip: usize,
+    /// How long a client may take to send its request body.
+    pub body_timeout_secs: u64,
+}
+
+impl Default for ConnectionsConfig {
+    fn default() -> Self {
+        ConnectionsConfig {
+            max_connections: 1_024,
+            max_per_ip: 64,
+            body_timeout_secs: 30,
+        }
+    }
+}
+
+/// Limits on the client headers forwarded upstream; requests over either are
+/// answered with 431.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct HeaderLimitsConfig {
+    pub max_count: usize,
+    /// Total size of names and values, counted as they'd be sent.
+    pub max_bytes: usize,
+}
+
+impl Default for HeaderLimitsConfig {
+    fn default() -> Self {
+        HeaderLimitsConfig {
+            max_count: 64,
+            max_bytes: 16 * 1024,
+        }
+    }
+}
+
+/// Rules matched against the client's `User-Agent`, first match wins, e.g.
+/// allowing `^Roblox/` and `^RobloxStudio/` and denying everything else.
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct UserAgentsConfig {
+    pub rules: Vec<UserAgentRuleConfig>,
+    /// Applied when no rule matches.
+    pub default_action: UserAgentAction,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UserAgentRuleConfig {
+    pub name: String,
+    /// Regex; an absent header matches as an empty string.
+    pub pattern: String,
+    #[serde(default)]
+    pub action: UserAgentAction,
+    /// Shared by every client the rule matches.
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub enum UserAgentAction {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// Heuristics that put a client IP under a temporary penalty. Counts reset
+/// every `window_secs`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AbuseConfig {
+    pub enabled: bool,
+    pub window_secs: u64,
+    /// Requests per window that count as a burst.
+    pub max_requests: u32,
+    /// Consecutive requests for adjacent numeric IDs that count as
+    /// enumeration.
+    pub sequential_ids: u32,
+    /// Share of 4xx responses that counts as abuse, once a client has made
+    /// `error_ratio_min_requests` requests in the window.
+    pub max_error_ratio: f64,
+    pub error_ratio_min_requests: u32,
+    pub penalty_secs: u64,
+    /// What a penalized client may still send; without one it's banned.
+    pub penalty_rate: Option<RateLimitConfig>,
+}
+
+impl Default for AbuseConfig {
+    fn default() -> Self {
+        AbuseConfig {
+            enabled: false,
+            window_secs: 60,
+            max_requests: 1_200,
+            sequential_ids: 50,
+            max_error_ratio: 0.8,
+            error_ratio_min_requests: 50,
+            penalty_secs: 10 * 60,
+            penalty_rate: Some(RateLimitConfig {
+                limit: 10,
+                window_secs: 60,
+            }),
+        }
+    }
+}
+
+/// Catches a client walking numeric IDs on one endpoint: within
+/// `window_secs`, at least `min_ids` distinct IDs that fill `min_density` of
+/// the range between the lowest and highest.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct EnumerationConfig {
+    pub enabled: bool,
+    pub window_secs: u64,
+    pub min_ids: u32,
+    pub min_density: f64,
+    /// Delay after the first strike, doubled with each one after it.
+    pub base_delay_ms: u64,
+    /// Past this delay requests are rejected instead.
+    pub max_delay_ms: u64,
+    pub cooldown_secs: u64,
+}
+
+impl Default for EnumerationConfig {
+    fn default() -> Self {
+        EnumerationConfig {
+            enabled: false,
+            window_secs: 60,
+            min_ids: 200,
+            min_density: 0.5,
+            base_delay_ms: 250,
+            max_delay_ms: 4_000,
+            cooldown_secs: 10 * 60,
+        }
+    }
+}
+
+/// How long the account a Roblox challenge was issued to stays pinned for
+/// the continuation and the retried request.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ChallengesConfig {
+    pub ttl_secs: u64,
+}
+
+impl Default for ChallengesConfig {
+    fn default() -> Self {
+        ChallengesConfig { ttl_secs: 10 * 60 }
+    }
+}
+
+/// How the proxy connects to Roblox.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct UpstreamConfig {
+    pub address_family: AddressFamily,
+    /// Whether a dual-stack host is raced across both families (the second
+    /// one starts 300ms after the first), or only reached over the
+    /// preferred one while it has addresses.
+    pub dual_stack_racing: bool,
+    pub connect_timeout_ms: u64,
+    /// Idle time before TCP keep-alive probes start, so NAT gateways don't
+    /// silently drop pooled connections. Probe interval and count are the
+    /// OS defaults. Zero disables keep-alive.
+    pub tcp_keepalive_secs: u64,
+    pub tcp_nodelay: bool,
+    /// Pooled connections idle this long are closed. Keep it under the
+    /// shortest idle timeout between here and Roblox.
+    pub pool_idle_timeout_secs: u64,
+    pub pool_max_idle_per_host: usize,
+    /// Origins (e.g. `https://users.roblox.com`) to open connections to
+    /// before serving, so the first requests after a deploy skip the TLS
+    /// handshake. They're closed again after `pool_idle_timeout_secs` idle.
+    pub prewarm_hosts: Vec<String>,
+    /// Connections opened per prewarm host, at most `pool_max_idle_per_host`.
+    pub prewarm_connections: usize,
+    /// Hosts any upstream request or redirect may reach, as `example.com`,
+    /// `*.example.com` or `host:port` for a non-default port. IP addresses
+    /// only match when listed as such.
+    pub allowed_hosts: Vec<String>,
+    /// Allows plain `http://` upstreams, e.g. for a local mock.
+    pub allow_http: bool,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            address_family: AddressFamily::Any,
+            dual_stack_racing: true,
+            connect_timeout_ms: 10_000,
+            tcp_keepalive_secs: 30,
+            tcp_nodelay: true,
+            pool_idle_timeout_secs: 15,
+            pool_max_idle_per_host: 10,
+            prewarm_hosts: Vec::new(),
+            prewarm_connections: 4,
+            allowed_hosts: ["roblox.com", "*.roblox.com"].map(String::from).to_vec(),
+            allow_http: false,
+        }
+    }
+}
+
+/// Which IP family upstream connections use. `any` keeps the resolver's
+/// order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum AddressFamily {
+    Any,
+    PreferIpv4,
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+/// Per-route upstream timeouts of `percentile` latency times `multiplier`,
+/// kept between `min_ms` and `max_ms`, over the last `window` requests.
+/// Routes with fewer than `min_samples` use the client's 30s timeout.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AdaptiveTimeoutsConfig {
+    pub enabled: bool,
+    pub percentile: f64,
+    pub multiplier: f64,
+    pub min_ms: u64,
+    pub max_ms: u64,
+    pub window: usize,
+    pub min_samples: usize,
+}
+
+impl Default for AdaptiveTimeoutsConfig {
+    fn default() -> Self {
+        AdaptiveTimeoutsConfig {
+            enabled: false,
+            percentile: 0.99,
+            multiplier: 2.0,
+            min_ms: 1_000,
+            max_ms: 30_000,
+            window: 200,
+            min_samples: 20,
+        }
+    }
+}
+
+/// Retries of GETs that failed to connect or got a 502, 503 or 504. Off
+/// unless `max_retries` is set. Retries per `window_secs` are capped at
+/// `budget_ratio` of the requests in it plus `min_retries`, so quiet
+/// instances can still retry.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct RetriesConfig {
+    pub max_retries: u32,
+    pub budget_ratio: f64,
+    pub min_retries: u32,
+    pub window_secs: u64,
+    /// Delay before the first retry, growing linearly after that.
+    pub backoff_ms: u64,
+}
+
+impl Default for RetriesConfig {
+    fn default() -> Self {
+        RetriesConfig {
+            max_retries: 0,
+            budget_ratio: 0.2,
+            min_retries: 10,
+            window_secs: 10,
+            backoff_ms: 100,
+        }
+    }
+}
+
+/// Adds a `Server-Timing` header (queue, dns, connect, ttfb and body) to
+/// every proxied response. Off by default since it reveals how the proxy is
+/// doing to anyone who asks.
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ServerTimingConfig {
+    pub enabled: bool,
+}
+
+/// The default headers sent upstream, as named profiles. `browser`, `studio`
+/// and `open-cloud` are built in; a custom profile with the same name replaces
+/// one. Clients pick a profile per request with `X-Proxy-Identity`, otherwise
+/// the first rule matching the upstream URL decides.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct IdentitiesConfig {
+    /// Used when no rule matches.
+    pub default: String,
+    pub profiles: Vec<IdentityProfileConfig>,
+    pub rules: Vec<IdentityRuleConfig>,
+}
+
+impl Default for IdentitiesConfig {
+    fn default() -> Self {
+        IdentitiesConfig {
+            default: "browser".to_string(),
+            profiles: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct IdentityProfileConfig {
+    pub name: String,
+    /// Only sent when the client didn't send that header itself.
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct IdentityRuleConfig {
+    pub name: String,
+    /// Upstream URL prefixes, e.g. `https://apis.roblox.com/cloud/`.
+    pub prefixes: Vec<String>,
+    pub profile: String,
+}
+
+/// Methods each upstream host may be sent, e.g. `thumbnails.roblox.com`
+/// limited to GET. The first rule matching the host wins; hosts no rule
+/// matches take any method.
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct HostMethodsConfig {
+    pub rules: Vec<HostMethodsRuleConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct HostMethodsRuleConfig {
+    pub name: String,
+    /// As `example.com` or `*.example.com`.
+    pub hosts: Vec<String>,
+    pub methods: Vec<String>,
+}
+
+/// The trades API. Trades can always be read, but sending, countering or
+/// accepting one can cost the account its items, so those also need
+/// `allow_writes` and a proxy key with the `trades` permission.
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct TradesConfig {
+    pub allow_writes: bool,
+}
+
+/// economy.roblox.com: transactions, Robux balances and purchases. Off
+/// unless `enabled`, and then only for proxy keys with the `economy`
+/// permission. Every call to it, allowed or not, goes to the audit log.
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct EconomyConfig {
+    pub enabled: bool,
+}
+
+/// A record of calls to sensitive upstreams (economy.roblox.com, server
+/// restarts and shutdowns), one JSON object per line. Also logged under the
+/// `audit` tracing target.
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AuditLogConfig {
+    /// File appended to; unset logs through tracing only.
+    pub path: Option<String>,
+}
+
+/// The `/cloud` routes, which wrap Open Cloud calls that take more than a
+/// plain proxied request. They authenticate with the pool's
+/// `open_cloud_key`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct CloudConfig {
+    /// Largest place file `/cloud/.../versions` accepts.
+    pub max_place_bytes: u64,
+    /// How long a place upload may take, both from the client and on to
+    /// Roblox.
+    pub publish_timeout_secs: u64,
+    /// Longest `?_wait=` the proxy will poll a long-running operation for.
+    pub max_wait_secs: u64,
+}
+
+impl Default for CloudConfig {
+    fn default() -> Self {
+        CloudConfig {
+            max_place_bytes: 100 * 1024 * 1024,
+            publish_timeout_secs: 10 * 60,
+            max_wait_secs: 120,
+        }
+    }
+}
+
+/// Which finished requests get an access log line, as a fraction of
+/// successful ones and of those answered with a 4xx or 5xx. Traced requests
+/// are always logged.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct LogSamplingConfig {
+    pub success_rate: f64,
+    pub error_rate: f64,
+}
+
+impl Default for LogSamplingConfig {
+    fn default() -> Self {
+        LogSamplingConfig {
+            success_rate: 1.0,
+            error_rate: 1.0,
+        }
+    }
+}
+
+/// Per-key, per-day usage, exported from `/admin/usage/export`.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct UsageConfig {
+    /// File usage is saved to and loaded from; unset keeps it in memory.
+    pub path: Option<String>,
+    pub flush_secs: u64,
+    /// Days kept before they're dropped.
+    pub retention_days: u64,
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        UsageConfig {
+            path: None,
+            flush_secs: 60,
+            retention_days: 400,
+        }
+    }
+}
+
+/// `X-Content-SHA256` on proxied responses. Clients can ask for it per
+/// request with `X-Proxy-Integrity: 1` while it's off, and can send it on
+/// a request to have the body checked.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct IntegrityConfig {
+    pub enabled: bool,
+}
+
+/// `X-Proxy-Tag` metrics, labelled with at most `max_tags` distinct tags.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct TagsConfig {
+    pub max_tags: usize,
+}
+
+impl Default for TagsConfig {
+    fn default() -> Self {
+        TagsConfig { max_tags: 100 }
+    }
+}
+
+/// HMAC signatures on requests to an operator's own upstreams, so the
+/// backend can trust they came through the proxy. See `upstream_signing.rs`
+/// for what's signed.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct UpstreamSigningConfig {
+    /// Carries the hex HMAC-SHA256 signature.
+    pub header: String,
+    /// Carries the Unix time the signature was made at, which it covers.
+    pub timestamp_header: String,
+    /// The first upstream whose prefix the URL starts with signs it.
+    pub upstreams: Vec<SignedUpstreamConfig>,
+}
+
+impl Default for UpstreamSigningConfig {
+    fn default() -> Self {
+        UpstreamSigningConfig {
+            header: "X-Proxy-Signature".to_string(),
+            timestamp_header: "X-Proxy-Timestamp".to_string(),
+            upstreams: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SignedUpstreamConfig {
+    /// Upstream URL prefix, e.g. `https://api.example.com/`.
+    pub prefix: String,
+    pub secret: String,
+}
+
+/// Lua scripts run around upstream requests to URLs starting with one of a
+/// rule's `prefixes`; the first matching rule wins. See `scripting.rs` for
+/// what a script sees.
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ScriptsConfig {
+    /// Per call, so a runaway loop fails the request instead of hanging it.
+    pub max_instructions: u32,
+    pub max_memory_bytes: usize,
+    pub rules: Vec<ScriptRuleConfig>,
+}
+
+impl Default for ScriptsConfig {
+    fn default() -> Self {
+        ScriptsConfig {
+            max_instructions: 1_000_000,
+            max_memory_bytes: 16 * 1024 * 1024,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ScriptRuleConfig {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    /// Path to a Lua file defining `before(request)`, `after(response)` or
+    /// both.
+    pub file: String,
+}
+
+/// Which upstream response headers reach clients. In `strict` mode only
+/// those matching `allowed` are forwarded, so Roblox and Cloudflare
+/// diagnostics (`cf-ray`, `server`, `roblox-machine-id`, ...) stay hidden.
+/// Entries ending in `*` match by prefix. Headers the proxy adds itself are
+/// never filtered.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ResponseHeadersConfig {
+    pub strict: bool,
+    pub allowed: Vec<String>,
+}
+
+impl Default for ResponseHeadersConfig {
+    fn default() -> Self {
+        ResponseHeadersConfig {
+            strict: true,
+            allowed: [
+                "content-type",
+                "content-length",
+                "content-encoding",
+                "content-language",
+                "content-disposition",
+                "cache-control",
+                "expires",
+                "last-modified",
+                "etag",
+                "vary",
+                "age",
+                "location",
+                "retry-after",
+                "set-cookie",
+                "www-authenticate",
+                "x-csrf-token",
+                "x-ratelimit-*",
+                // Read by the challenge handler as well as clients.
+                "rblx-challenge-*",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+/// What happens to `Set-Cookie` headers on their way to clients. Session jars
+/// (`X-Proxy-Session`) still receive every cookie first.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SetCookieConfig {
+    pub policy: SetCookiePolicy,
+    /// Cookies that carry a Roblox login, stripped under `strip_auth`.
+    pub auth_cookies: Vec<String>,
+}
+
+impl Default for SetCookieConfig {
+    fn default() -> Self {
+        SetCookieConfig {
+            policy: SetCookiePolicy::default(),
+            auth_cookies: vec![".ROBLOSECURITY".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "snake_case")]
+pub enum SetCookiePolicy {
+    StripAll,
+    /// Strip only `auth_cookies`, so an injected credential's session can't
+    /// reach a client.
+    #[default]
+    StripAuth,
+    Pass,
+}
+
+/// Checks on JSON request bodies sent to URLs starting with one of a rule's
+/// `prefixes`, made before anything is sent upstream; the first matching
+/// rule wins. Fields are JSON pointers, e.g. `/userIds` or `/data/0/id`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct BodyRulesConfig {
+    pub rules: Vec<BodyRuleConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct BodyRuleConfig {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    #[serde(default)]
+    pub required: Vec<String>,
+    #[serde(default)]
+    pub banned: Vec<String>,
+    /// Longest allowed array at each field, e.g. `{ "/userIds" = 100 }`.
+    #[serde(default)]
+    pub max_array_lengths: BTreeMap<String, usize>,
+}
+
+/// Strips insignificant whitespace from JSON responses, which HttpService
+/// clients otherwise download byte for byte.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct MinifyJsonConfig {
+    pub enabled: bool,
+    /// Upstream URL prefixes to minify; empty means every JSON response.
+    pub prefixes: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct StaleIfErrorConfig {
+    pub rules: Vec<StaleIfErrorRuleConfig>,
+}
+
+/// Serves the last good cached response for GETs under `prefixes` when
+/// Roblox fails (a 5xx, a 429 or no answer at all) or the proxy's own budget
+/// turns the request away, as long as it expired at most `max_stale_secs` ago.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct StaleIfErrorRuleConfig {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    pub max_stale_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct SnapshotsConfig {
+    /// Directory snapshots are appended to. Without it they're lost on restart.
+    pub dir: Option<String>,
+    /// Snapshots kept per job; the oldest go first.
+    pub max_per_job: usize,
+    pub jobs: Vec<SnapshotJobConfig>,
+}
+
+impl Default for SnapshotsConfig {
+    fn default() -> Self {
+        SnapshotsConfig {
+            dir: None,
+            max_per_job: 1000,
+            jobs: Vec::new(),
+        }
+    }
+}
+
+/// Fetches `url` on `schedule` (a cron expression with seconds, in UTC, as
+/// for warming jobs) and keeps the JSON response, served from
+/// `/snapshots/<name>?at=...`. What changed between snapshots is served from
+/// `/changes/<name>`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct SnapshotJobConfig {
+    pub name: String,
+    pub schedule: String,
+    pub url: String,
+    pub items: Option<ChangeItemsConfig>,
+    /// Receives a JSON POST with the changes each new snapshot brings.
+    pub webhook: Option<String>,
+}
+
+/// A list of records in each snapshot, matched up between snapshots by
+/// `id`, e.g. `path = "/data"` and `id = "/user/userId"` for group members.
+/// Both are JSON pointers; `id` is relative to a record.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ChangeItemsConfig {
+    pub path: String,
+    pub id: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct ResponseWebhooksConfig {
+    pub rules: Vec<ResponseWebhookRuleConfig>,
+}
+
+/// POSTs to `webhook` when a JSON response from a URL under `prefixes` has
+/// `equals` at the JSON pointer `pointer`, or anything at all there without
+/// `equals`. With `on_change` it only fires when the previous response from
+/// the same URL didn't match, e.g. when a presence flips to online.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ResponseWebhookRuleConfig {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    pub pointer: String,
+    pub equals: Option<Value>,
+    #[serde(default)]
+    pub on_change: bool,
+    pub webhook: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AuditFeedsConfig {
+    /// How often each group's audit log is checked for new entries.
+    pub poll_secs: u64,
+    /// Entries kept per group for `/helpers/groups/<id>/audit-feed`.
+    pub max_entries: usize,
+    pub groups: Vec<AuditFeedGroupConfig>,
+}
+
+impl Default for AuditFeedsConfig {
+    fn default() -> Self {
+        AuditFeedsConfig {
+            poll_secs: 60,
+            max_entries: 1000,
+            groups: Vec::new(),
+        }
+    }
+}
+
+/// Polls `group_id`'s audit log as an account from `pool` (a tenant's name,
+/// or the shared pool if unset), which has to be allowed to view it.
+/// `webhook` receives new entries as they're found.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AuditFeedGroupConfig {
+    pub group_id: u64,
+    pub pool: Option<String>,
+    pub webhook: Option<String>,
+}
+
+/// The `/helpers` routes, which each answer with what would otherwise take
+/// several proxied calls. Every upstream response they use is cached for the
+/// TTL of the section it belongs to.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct HelpersConfig {
+    /// Pages followed per paginated list before it's reported as truncated.
+    pub max_pages: usize,
+    pub social_counts_ttl_secs: u64,
+    pub social_lists_ttl_secs: u64,
+    pub inventory_ttl_secs: u64,
+    pub game_summary_ttl_secs: u64,
+    pub products_ttl_secs: u64,
+    pub localization_patch_size: usize,
+}
+
+impl Default for HelpersConfig {
+    fn default() -> Self {
+        HelpersConfig {
+            max_pages: 10,
+            social_counts_ttl_secs: 60,
+            social_lists_ttl_secs: 5 * 60,
+            inventory_ttl_secs: 2 * 60,
+            game_summary_ttl_secs: 30,
+            products_ttl_secs: 10 * 60,
+            localization_patch_size: 250,
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{config::ConnectionsConfig, metrics::Metrics, ErrorResponse, MyRequestGuard, Rejection};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{uri::Origin, Method, Status},
+    Data, Request,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Caps how many requests are being served at once, overall and per client
+/// IP, so a handful of slow or abusive clients can't tie up every worker.
+///
+/// Slots are taken once a request's headers are in, and a slow body is cut
+/// off by `body_timeout_secs`. Slow headers can't be timed out here: Rocket
+/// 0.5 builds its hyper server itself (the platform's, under Shuttle) and
+/// has no header read timeout to set, so that's left to whatever fronts the
+/// proxy, along with Rocket's `keep_alive` for idle connections.
+pub struct ConnectionLimits {
+    config: ConnectionsConfig,
+    counts: Mutex<Counts>,
+    metrics: Arc<Metrics>,
+}
+
+/// Holds one of the limited slots until the request is dropped.
+pub struct Slot {
+    limits: Arc<ConnectionLimits>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let mut counts = self.limits.counts.lock().unwrap();
+        counts.total -= 1;
+        if let Some(ip) = self.ip {
+            if let Some(count) = counts.per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+/// Whether the request got a slot, kept in the request's local cache.
+/// Requests the fairing never saw are admitted.
+#[derive(Default)]
+pub struct Admission(Option<Result<Slot, &'static str>>);
+
+impl Admission {
+    pub fn of<'r>(req: &'r Request<'_>) -> &'r Admission {
+        req.local_cache(Admission::default)
+    }
+
+    pub fn rejected(&self) -> Option<&'static str> {
+        match &self.0 {
+            Some(Err(scope)) => Some(scope),
+            _ => None,
+        }
+    }
+}
+
+impl ConnectionLimits {
+    pub fn new(config: ConnectionsConfig, metrics: Arc<Metrics>) -> Arc<Self> {
+        Arc::new(ConnectionLimits {
+            config,
+            counts: Mutex::default(),
+            metrics,
+        })
+    }
+
+    /// Takes a slot for a request from `ip`, or names the limit it's over.
+    pub fn acquire(self: &Arc<Self>, ip: Option<IpAddr>) -> Result<Slot, &'static str> {
+        let mut counts = self.counts.lock().unwrap();
+        let max_total = self.config.max_connections;
+        let max_per_ip = self.config.max_per_ip;
+        let scope = if max_total > 0 && counts.total >= max_total {
+            Some("global")
+        } else if max_per_ip > 0 && ip.is_some_and(|ip| counts.per_ip.get(&ip).copied().unwrap_or(0) >= max_per_ip) {
+            Some("ip")
+        } else {
+            None
+        };
+        if let Some(scope) = scope {
+            self.metrics
+                .incr("roproxy_connections_rejected_total", &[("scope", scope)]);
+            return Err(scope);
+        }
+
+        counts.total += 1;
+        if let Some(ip) = ip {
+            *counts.per_ip.entry(ip).or_default() += 1;
+        }
+        Ok(Slot {
+            limits: self.clone(),
+            ip,
+        })
+    }
+}
+
+pub struct ConnectionLimiter(pub Arc<ConnectionLimits>);
+
+#[rocket::async_trait]
+impl Fairing for ConnectionLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Connection limits",
+            kind: Kind::Request,
+        }
+    }
+
+    // Requests over the limit are rerouted to `over_limit`, so whatever
+    // route they were for never runs.
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let admission = self.0.acquire(req.client_ip());
+        let rejected = admission.is_err();
+        req.local_cache(|| Admission(Some(admission)));
+        if rejected {
+            req.set_method(Method::Get);
+            req.set_uri(Origin::parse(OVER_LIMIT).unwrap());
+        }
+    }
+}
+
+const OVER_LIMIT: &str = "/_roproxy/over-limit";
+
+#[get("/_roproxy/over-limit")]
+pub fn over_limit(guard: MyRequestGuard<'_>) -> ErrorResponse {
+    ErrorResponse(rejection(Admission::of(guard.request).rejected()).into())
+}
+
+/// What a client over the limit `scope` is answered with.
+pub fn rejection(scope: Option<&str>) -> Rejection {
+    let message = match scope {
+        Some("ip") => "Too many simultaneous requests from this address",
+        Some(_) => "Too many simultaneous requests",
+        None => "Service unavailable",
+    };
+    Rejection::new(Status::ServiceUnavailable, message).with_header("Retry-After", 1)
+}
@@ -0,0 +1,93 @@
+use crate::{
+    config::{ContentTypeRuleConfig, ContentTypesConfig},
+    metrics::Metrics,
+    ProxyResponse,
+};
+use encoding_rs::{Encoding, UTF_8};
+use rocket::serde::json::{self, Value};
+use tracing::debug;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Corrects content types some endpoints get wrong (JSON served as
+/// `text/html`, Latin-1 text, stray byte order marks) that would otherwise
+/// trip up `JSONDecode` on the client.
+pub struct ContentTypes {
+    rules: Vec<ContentTypeRuleConfig>,
+}
+
+impl ContentTypes {
+    pub fn new(config: &ContentTypesConfig) -> Self {
+        ContentTypes {
+            rules: config.rules.clone(),
+        }
+    }
+
+    pub fn normalize(&self, url: &str, response: &mut ProxyResponse, metrics: &Metrics) {
+        if response.stream.is_some() {
+            return;
+        }
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.prefixes.iter().any(|prefix| url.starts_with(prefix)))
+        else {
+            return;
+        };
+
+        if rule.normalize_charset && is_text(&response.content_type) && to_utf8(response) {
+            debug!("Normalized charset of {} ({})", url, rule.name);
+            metrics.incr("roproxy_content_type_fixes_total", &[("rule", &rule.name), ("fix", "charset")]);
+        }
+        if rule.sniff_json && !response.content_type.contains("json") && looks_like_json(&response.body) {
+            debug!("Relabeled {} response from {} as JSON ({})", response.content_type, url, rule.name);
+            response.content_type = "application/json; charset=utf-8".to_string();
+            metrics.incr("roproxy_content_type_fixes_total", &[("rule", &rule.name), ("fix", "json")]);
+        }
+    }
+}
+
+fn is_text(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+    essence.starts_with("text/")
+        || ["json", "xml", "javascript"].iter().any(|kind| essence.contains(kind))
+}
+
+fn charset(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+// Re-encodes the body as BOM-less UTF-8 with a matching label. Returns
+// whether anything changed.
+fn to_utf8(response: &mut ProxyResponse) -> bool {
+    let encoding = charset(&response.content_type)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+    let had_bom = response.body.starts_with(UTF8_BOM);
+    if encoding == UTF_8 && !had_bom && charset(&response.content_type).is_some() {
+        return false;
+    }
+
+    let (text, _, malformed) = encoding.decode(&response.body);
+    // Unlabeled bodies that aren't UTF-8 are left alone rather than guessed at.
+    if malformed && encoding == UTF_8 {
+        return false;
+    }
+    response.body = text.into_owned().into_bytes();
+    let essence = response.content_type.split(';').next().unwrap_or_default().trim();
+    response.content_type = format!("{}; charset=utf-8", essence);
+    true
+}
+
+fn looks_like_json(body: &[u8]) -> bool {
+    let body = body.strip_prefix(UTF8_BOM).unwrap_or(body);
+    let Some(first) = body.iter().find(|byte| !byte.is_ascii_whitespace()) else {
+        return false;
+    };
+    matches!(first, b'{' | b'[') && json::from_slice::<Value>(body).is_ok()
+}
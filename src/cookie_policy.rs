@@ -0,0 +1,74 @@
+use crate::{
+    config::{SetCookieConfig, SetCookiePolicy},
+    metrics::Metrics,
+};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Request, Response,
+};
+use std::sync::Arc;
+use tracing::debug;
+
+/// Applies `set_cookies.policy` to every response the proxy sends, whichever
+/// route produced it, so a login cookie Roblox hands an injected credential
+/// never reaches the client.
+pub struct CookiePolicy {
+    config: SetCookieConfig,
+    metrics: Arc<Metrics>,
+}
+
+impl CookiePolicy {
+    pub fn new(config: &SetCookieConfig, metrics: Arc<Metrics>) -> Self {
+        CookiePolicy {
+            config: config.clone(),
+            metrics,
+        }
+    }
+
+    pub fn allows(&self, set_cookie: &str) -> bool {
+        match self.config.policy {
+            SetCookiePolicy::Pass => true,
+            SetCookiePolicy::StripAll => false,
+            SetCookiePolicy::StripAuth => {
+                let name = cookie_name(set_cookie);
+                !self.config.auth_cookies.iter().any(|auth| auth.eq_ignore_ascii_case(name))
+            }
+        }
+    }
+}
+
+fn cookie_name(set_cookie: &str) -> &str {
+    set_cookie.split(['=', ';']).next().unwrap_or_default().trim()
+}
+
+#[rocket::async_trait]
+impl Fairing for CookiePolicy {
+    fn info(&self) -> Info {
+        Info {
+            name: "Set-Cookie policy",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, _: &'r Request<'_>, res: &mut Response<'r>) {
+        if self.config.policy == SetCookiePolicy::Pass {
+            return;
+        }
+        let (kept, stripped): (Vec<_>, Vec<_>) = res
+            .headers()
+            .get("Set-Cookie")
+            .map(str::to_string)
+            .partition(|cookie| self.allows(cookie));
+        if stripped.is_empty() {
+            return;
+        }
+        res.remove_header("Set-Cookie");
+        for cookie in kept {
+            res.adjoin_raw_header("Set-Cookie", cookie);
+        }
+        for cookie in &stripped {
+            debug!("Stripped Set-Cookie for {}", cookie_name(cookie));
+            self.metrics.incr("roproxy_set_cookies_stripped_total", &[]);
+        }
+    }
+}
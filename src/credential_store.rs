@@ -0,0 +1,226 @@
+use crate::{config::CredentialsConfig, credentials::Credentials};
+use aes_gcm::{
+    aead::{Aead, Generate, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rocket::serde::{json, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tracing::info;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct StoreFile {
+    /// Fingerprint of the key the accounts are encrypted with, so a wrong key
+    /// fails loudly at startup instead of as a decryption error per account.
+    key_id: String,
+    accounts: Vec<StoredAccount>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct StoredAccount {
+    pool: String,
+    name: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Secrets {
+    roblosecurity: Option<String>,
+    open_cloud_key: Option<String>,
+}
+
+pub struct StoreEntry {
+    pub pool: String,
+    pub name: String,
+    pub credentials: Credentials,
+}
+
+struct Inner {
+    key: [u8; 32],
+    entries: Vec<StoreEntry>,
+}
+
+/// Accounts added at runtime, persisted to a single JSON file with every
+/// secret sealed by AES-256-GCM. Names and pools are stored in the clear
+/// (and authenticated) so the file can be inspected without the key.
+pub struct CredentialStore {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+impl CredentialStore {
+    pub fn open(config: &CredentialsConfig) -> Result<Option<Self>> {
+        let Some(path) = &config.store_path else {
+            return Ok(None);
+        };
+        let encoded = config
+            .encryption_key
+            .as_deref()
+            .ok_or_else(|| anyhow!("credentials.store_path requires credentials.encryption_key"))?;
+        let key = decode_key(encoded).context("Invalid credentials.encryption_key")?;
+
+        let path = PathBuf::from(path);
+        let entries = if path.exists() {
+            load(&path, &key)?
+        } else {
+            Vec::new()
+        };
+        info!("Loaded {} stored credentials from {}", entries.len(), path.display());
+        Ok(Some(CredentialStore {
+            path,
+            inner: Mutex::new(Inner { key, entries }),
+        }))
+    }
+
+    pub fn entries(&self) -> Vec<StoreEntry> {
+        self.inner
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .map(|entry| StoreEntry {
+                pool: entry.pool.clone(),
+                name: entry.name.clone(),
+                credentials: entry.credentials.clone(),
+            })
+            .collect()
+    }
+
+    pub fn put(&self, pool: &str, name: &str, credentials: Credentials) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.retain(|entry| entry.pool != pool || entry.name != name);
+        inner.entries.push(StoreEntry {
+            pool: pool.to_string(),
+            name: name.to_string(),
+            credentials,
+        });
+        save(&self.path, &inner.key, &inner.entries)
+    }
+
+    pub fn remove(&self, pool: &str, name: &str) -> Result<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.entries.len();
+        inner.entries.retain(|entry| entry.pool != pool || entry.name != name);
+        if inner.entries.len() == before {
+            return Ok(false);
+        }
+        save(&self.path, &inner.key, &inner.entries)?;
+        Ok(true)
+    }
+
+    /// Re-encrypts every stored account under `new_key` and returns its
+    /// fingerprint. The old key stops working as soon as this returns, so the
+    /// configured key has to be updated before the next restart.
+    pub fn rotate_key(&self, new_key: &str) -> Result<(String, usize)> {
+        let key = decode_key(new_key)?;
+        let mut inner = self.inner.lock().unwrap();
+        save(&self.path, &key, &inner.entries)?;
+        inner.key = key;
+        Ok((key_id(&key), inner.entries.len()))
+    }
+}
+
+fn decode_key(encoded: &str) -> Result<[u8; 32]> {
+    let bytes = STANDARD.decode(encoded.trim()).context("Key is not valid base64")?;
+    bytes
+        .try_into()
+        .map_err(|_| anyhow!("Key must be exactly 32 bytes"))
+}
+
+fn key_id(key: &[u8; 32]) -> String {
+    hex::encode(&Sha256::digest(key)[..8])
+}
+
+fn aad(pool: &str, name: &str) -> Vec<u8> {
+    format!("{}\0{}", pool, name).into_bytes()
+}
+
+fn load(path: &Path, key: &[u8; 32]) -> Result<Vec<StoreEntry>> {
+    let contents = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let file: StoreFile = json::from_slice(&contents)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    if file.key_id != key_id(key) {
+        bail!(
+            "{} is encrypted with a different key (key id {})",
+            path.display(),
+            file.key_id
+        );
+    }
+
+    let cipher = Aes256Gcm::new(key.into());
+    file.accounts
+        .into_iter()
+        .map(|account| {
+            let nonce = STANDARD.decode(&account.nonce)?;
+            let nonce = Nonce::try_from(nonce.as_slice()).map_err(|_| anyhow!("Bad nonce length"))?;
+            let ciphertext = STANDARD.decode(&account.ciphertext)?;
+            let plaintext = cipher
+                .decrypt(
+                    &nonce,
+                    Payload {
+                        msg: &ciphertext,
+                        aad: &aad(&account.pool, &account.name),
+                    },
+                )
+                .map_err(|_| anyhow!("Failed to decrypt stored credential {}", account.name))?;
+            let secrets: Secrets = json::from_slice(&plaintext)?;
+            Ok(StoreEntry {
+                pool: account.pool,
+                name: account.name,
+                credentials: Credentials {
+                    roblosecurity: secrets.roblosecurity,
+                    open_cloud_key: secrets.open_cloud_key,
+                },
+            })
+        })
+        .collect()
+}
+
+fn save(path: &Path, key: &[u8; 32], entries: &[StoreEntry]) -> Result<()> {
+    let cipher = Aes256Gcm::new(key.into());
+    let accounts = entries
+        .iter()
+        .map(|entry| {
+            let secrets = json::to_string(&Secrets {
+                roblosecurity: entry.credentials.roblosecurity.clone(),
+                open_cloud_key: entry.credentials.open_cloud_key.clone(),
+            })?;
+            let nonce = Nonce::generate();
+            let ciphertext = cipher
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: secrets.as_bytes(),
+                        aad: &aad(&entry.pool, &entry.name),
+                    },
+                )
+                .map_err(|_| anyhow!("Failed to encrypt credential {}", entry.name))?;
+            Ok(StoredAccount {
+                pool: entry.pool.clone(),
+                name: entry.name.clone(),
+                nonce: STANDARD.encode(nonce),
+                ciphertext: STANDARD.encode(ciphertext),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let file = StoreFile {
+        key_id: key_id(key),
+        accounts,
+    };
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, json::to_string(&file)?)
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
@@ -0,0 +1,345 @@
+use crate::{
+    client_cert,
+    config::{AccountConfig, RotationStrategy},
+};
+use rocket::{serde::Serialize, Request};
+use sha2::{Digest, Sha256};
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+// Consecutive 401s before a credential is taken out of rotation.
+const MAX_AUTH_FAILURES: u32 = 3;
+
+/// Roblox credentials the proxy attaches to upstream requests on a client's
+/// behalf, so the client never holds them.
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub roblosecurity: Option<String>,
+    pub open_cloud_key: Option<String>,
+}
+
+impl Credentials {
+    /// Adds these credentials to the outgoing headers. Credentials the client
+    /// supplied itself, directly or through its session jar, are left alone.
+    pub fn apply(&self, headers: &mut Vec<(String, String)>) {
+        if let Some(cookie) = &self.roblosecurity {
+            if !has_cookie(headers, ".ROBLOSECURITY") {
+                set_cookie(headers, ".ROBLOSECURITY", cookie);
+            }
+        }
+        if let Some(key) = &self.open_cloud_key {
+            if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("x-api-key")) {
+                headers.push(("x-api-key".to_string(), key.clone()));
+            }
+        }
+    }
+}
+
+fn has_cookie(headers: &[(String, String)], name: &str) -> bool {
+    headers
+        .iter()
+        .filter(|(header, _)| header.eq_ignore_ascii_case("cookie"))
+        .flat_map(|(_, value)| value.split(';'))
+        .any(|cookie| cookie.trim().split_once('=').is_some_and(|(cookie, _)| cookie == name))
+}
+
+/// Sets `name` in the request's `Cookie` header, replacing any value the
+/// client sent for it and keeping its other cookies where they were.
+pub fn set_cookie(headers: &mut Vec<(String, String)>, name: &str, value: &str) {
+    let position = headers
+        .iter()
+        .position(|(header, _)| header.eq_ignore_ascii_case("cookie"))
+        .unwrap_or(headers.len());
+    let mut cookies: Vec<String> = Vec::new();
+    headers.retain(|(header, value)| {
+        if header.eq_ignore_ascii_case("cookie") {
+            cookies.extend(value.split(';').map(|cookie| cookie.trim().to_string()));
+            false
+        } else {
+            true
+        }
+    });
+    let prefix = format!("{}=", name);
+    cookies.retain(|cookie| !cookie.is_empty() && !cookie.starts_with(&prefix));
+    cookies.push(format!("{}{}", prefix, value));
+    headers.insert(position, ("Cookie".to_string(), cookies.join("; ")));
+}
+
+#[derive(Default)]
+struct Health {
+    unhealthy: Option<String>,
+    alerted: bool,
+    throttled_until: Option<Instant>,
+    last_throttled: Option<Instant>,
+    auth_failures: u32,
+    uses: u64,
+}
+
+/// One account in a [`CredentialPool`], tracking how Roblox has been
+/// answering requests made with it.
+pub struct PooledCredential {
+    pub name: String,
+    pub credentials: Credentials,
+    cooldown: Duration,
+    health: Mutex<Health>,
+}
+
+impl PooledCredential {
+    /// Updates the credential's health from the upstream status it got back.
+    pub fn report(&self, status: u16) {
+        let mut health = self.health.lock().unwrap();
+        match status {
+            429 => {
+                let now = Instant::now();
+                health.throttled_until = Some(now + self.cooldown);
+                health.last_throttled = Some(now);
+            }
+            401 => {
+                health.auth_failures += 1;
+                if health.auth_failures >= MAX_AUTH_FAILURES && health.unhealthy.is_none() {
+                    warn!("Taking credential {} out of rotation after repeated 401s", self.name);
+                    health.unhealthy = Some("Repeated 401 responses".to_string());
+                }
+            }
+            200..=299 => health.auth_failures = 0,
+            _ => {}
+        }
+    }
+
+    /// Takes the credential out of rotation. Returns whether nobody has been
+    /// alerted about it yet.
+    pub fn mark_unhealthy(&self, reason: &str) -> bool {
+        let mut health = self.health.lock().unwrap();
+        if health.unhealthy.is_none() {
+            warn!("Credential {} is unhealthy: {}", self.name, reason);
+        }
+        health.unhealthy = Some(reason.to_string());
+        !std::mem::replace(&mut health.alerted, true)
+    }
+
+    pub fn mark_healthy(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.unhealthy.take().is_some() {
+            info!("Credential {} is back in rotation", self.name);
+        }
+        health.alerted = false;
+        health.auth_failures = 0;
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.health.lock().unwrap().unhealthy.is_none()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CredentialStatus {
+    pub name: String,
+    pub healthy: bool,
+    pub throttled: bool,
+    pub uses: u64,
+    pub reason: Option<String>,
+}
+
+/// What pins a client to an account under `credentials.affinity`: its
+/// `X-Proxy-Session`, else a hash of its proxy key, else its IP. Affinity
+/// is persisted with async jobs, so the key itself never goes in it.
+pub fn affinity(req: &Request<'_>) -> Option<String> {
+    affinity_of(req.headers().get_one("X-Proxy-Session"), client_cert::api_key(req), req.client_ip())
+}
+
+/// [`affinity`] from its parts, for front-ends without a Rocket request.
+pub fn affinity_of(session: Option<&str>, api_key: Option<&str>, ip: Option<IpAddr>) -> Option<String> {
+    session
+        .map(|session| format!("session:{}", session))
+        .or_else(|| api_key.map(|key| format!("key:{}", hex::encode(Sha256::digest(key)))))
+        .or_else(|| ip.map(|ip| format!("ip:{}", ip)))
+}
+
+// Rendezvous hashing: each client goes to the account it weighs highest
+// against, so an account leaving or coming back only moves its own clients.
+fn weight(affinity: &str, account: &str) -> u64 {
+    let hash = Sha256::digest(format!("{}\n{}", affinity, account));
+    u64::from_be_bytes(hash[..8].try_into().expect("SHA-256 is 32 bytes"))
+}
+
+/// A set of Roblox accounts requests are spread across, skipping ones that
+/// are throttled or whose credentials stopped working.
+pub struct CredentialPool {
+    members: RwLock<Vec<Arc<PooledCredential>>>,
+    strategy: RotationStrategy,
+    affinity: bool,
+    cooldown: Duration,
+    next: AtomicUsize,
+}
+
+impl CredentialPool {
+    pub fn new(accounts: &[AccountConfig], strategy: RotationStrategy, affinity: bool, cooldown: Duration) -> Self {
+        let pool = CredentialPool {
+            members: RwLock::default(),
+            strategy,
+            affinity,
+            cooldown,
+            next: AtomicUsize::new(0),
+        };
+        for account in accounts {
+            pool.upsert(
+                &account.name,
+                Credentials {
+                    roblosecurity: account.roblosecurity.clone(),
+                    open_cloud_key: account.open_cloud_key.clone(),
+                },
+            );
+        }
+        pool
+    }
+
+    pub fn members(&self) -> Vec<Arc<PooledCredential>> {
+        self.members.read().unwrap().clone()
+    }
+
+    /// The account called `name`, while it's in the pool.
+    pub fn member(&self, name: &str) -> Option<Arc<PooledCredential>> {
+        self.members.read().unwrap().iter().find(|member| member.name == name).cloned()
+    }
+
+    /// Adds an account, replacing any existing one with the same name.
+    pub fn upsert(&self, name: &str, credentials: Credentials) {
+        let member = Arc::new(PooledCredential {
+            name: name.to_string(),
+            credentials,
+            cooldown: self.cooldown,
+            health: Mutex::default(),
+        });
+        let mut members = self.members.write().unwrap();
+        match members.iter_mut().find(|existing| existing.name == name) {
+            Some(existing) => *existing = member,
+            None => members.push(member),
+        }
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        let mut members = self.members.write().unwrap();
+        let before = members.len();
+        members.retain(|member| member.name != name);
+        members.len() != before
+    }
+
+    pub fn pick(&self) -> Option<Arc<PooledCredential>> {
+        self.pick_for(None)
+    }
+
+    /// Picks an account for the client `affinity` identifies, the same one
+    /// each time while it's available when the pool pins clients. Stateful
+    /// flows like CSRF tokens and challenges then stay on one account.
+    pub fn pick_for(&self, affinity: Option<&str>) -> Option<Arc<PooledCredential>> {
+        let members = self.members.read().unwrap();
+        if members.is_empty() {
+            return None;
+        }
+        let now = Instant::now();
+        let healthy: Vec<_> = members
+            .iter()
+            .filter(|member| member.is_healthy())
+            .collect();
+        if healthy.is_empty() {
+            warn!("Every pooled Roblox credential is unhealthy");
+            return None;
+        }
+        let available: Vec<_> = healthy
+            .iter()
+            .filter(|member| {
+                let health = member.health.lock().unwrap();
+                health.throttled_until.is_none_or(|until| until <= now)
+            })
+            .collect();
+
+        let picked = if available.is_empty() {
+            // All throttled: use whichever recovers first rather than failing.
+            healthy
+                .iter()
+                .min_by_key(|member| member.health.lock().unwrap().throttled_until)
+                .copied()
+        } else if let Some(affinity) = affinity.filter(|_| self.affinity) {
+            available
+                .iter()
+                .max_by_key(|member| weight(affinity, &member.name))
+                .map(|member| **member)
+        } else {
+            match self.strategy {
+                RotationStrategy::RoundRobin => {
+                    let index = self.next.fetch_add(1, Ordering::Relaxed) % available.len();
+                    Some(*available[index])
+                }
+                RotationStrategy::LeastRecentlyThrottled => available
+                    .iter()
+                    .min_by_key(|member| {
+                        let health = member.health.lock().unwrap();
+                        (health.last_throttled, health.uses)
+                    })
+                    .map(|member| **member),
+            }
+        }?;
+
+        picked.health.lock().unwrap().uses += 1;
+        Some(picked.clone())
+    }
+
+    pub fn status(&self) -> Vec<CredentialStatus> {
+        let now = Instant::now();
+        self.members
+            .read()
+            .unwrap()
+            .iter()
+            .map(|member| {
+                let health = member.health.lock().unwrap();
+                CredentialStatus {
+                    name: member.name.clone(),
+                    healthy: health.unhealthy.is_none(),
+                    throttled: health.throttled_until.is_some_and(|until| until > now),
+                    uses: health.uses,
+                    reason: health.unhealthy.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn losing_an_account_only_moves_its_clients() {
+        let accounts: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| AccountConfig {
+                name: name.to_string(),
+                roblosecurity: Some(format!("cookie-{}", name)),
+                open_cloud_key: None,
+            })
+            .collect();
+        let pool = CredentialPool::new(&accounts, RotationStrategy::RoundRobin, true, Duration::from_secs(60));
+        let clients: Vec<_> = (0..200).map(|i| format!("key:{}", i)).collect();
+        let before: Vec<_> = clients
+            .iter()
+            .map(|client| pool.pick_for(Some(client)).unwrap().name.clone())
+            .collect();
+        assert!(pool.remove("c"));
+        for (client, before) in clients.iter().zip(&before) {
+            let after = pool.pick_for(Some(client)).unwrap();
+            if before == "c" {
+                assert_ne!(after.name, "c");
+            } else {
+                assert_eq!(&after.name, before);
+            }
+        }
+    }
+}
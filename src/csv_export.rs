@@ -0,0 +1,155 @@
+use crate::{ProxyResponse, Rejection};
+use anyhow::Result;
+use rocket::{
+    http::Status,
+    serde::json::Value,
+};
+use std::collections::HashSet;
+
+/// Query parameter asking for a JSON list as CSV, `?_format=csv`. Like
+/// [`COLUMNS`], it's never forwarded upstream.
+pub const FORMAT: &str = "_format";
+
+/// Dotted paths picking and ordering the CSV's columns, e.g.
+/// `?_columns=id,user.name`. Without it every leaf field becomes a column.
+pub const COLUMNS: &str = "_columns";
+
+pub const CONTENT_TYPE: &str = "text/csv; charset=utf-8";
+
+/// Whether `format` asks for CSV. JSON is the default; anything else is a
+/// client error.
+pub fn wants_csv(format: Option<&str>) -> Result<bool> {
+    match format {
+        None | Some("json") => Ok(false),
+        Some("csv") => Ok(true),
+        Some(other) => Err(Rejection::new(Status::BadRequest, format!("Unsupported {} {}", FORMAT, other))
+            .with_field("supported", vec!["json", "csv"])
+            .into()),
+    }
+}
+
+/// Turns a successful JSON list response, either a bare array or Roblox's
+/// `{"data": [...]}`, into CSV. Anything else passes through untouched.
+pub fn apply(mut response: ProxyResponse, columns: Option<&str>) -> ProxyResponse {
+    let Some(value) = response.json() else {
+        return response;
+    };
+    let rows = match &value {
+        Value::Array(rows) => rows,
+        Value::Object(object) => match object.get("data") {
+            Some(Value::Array(rows)) => rows,
+            _ => return response,
+        },
+        _ => return response,
+    };
+    let csv = to_csv(rows, columns);
+    response.body = csv.into_bytes();
+    response.content_type = CONTENT_TYPE.to_string();
+    response.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("etag"));
+    response
+}
+
+pub fn to_csv(rows: &[Value], columns: Option<&str>) -> String {
+    let columns: Vec<String> = match columns {
+        Some(columns) => columns
+            .split(',')
+            .map(str::trim)
+            .filter(|column| !column.is_empty())
+            .map(str::to_string)
+            .collect(),
+        None => {
+            let mut seen = HashSet::new();
+            let mut columns = Vec::new();
+            for row in rows {
+                leaves("", row, &mut |path, _| {
+                    if seen.insert(path.to_string()) {
+                        columns.push(path.to_string());
+                    }
+                });
+            }
+            columns
+        }
+    };
+
+    let mut csv = String::new();
+    write_record(&mut csv, columns.iter().map(String::as_str));
+    for row in rows {
+        let cells: Vec<String> = columns.iter().map(|column| cell(lookup(row, column))).collect();
+        write_record(&mut csv, cells.iter().map(String::as_str));
+    }
+    csv
+}
+
+// Calls `visit` with the dotted path of every non-object value in `value`.
+// Arrays count as values; they don't line up across rows.
+fn leaves(path: &str, value: &Value, visit: &mut impl FnMut(&str, &Value)) {
+    match value {
+        Value::Object(object) if !object.is_empty() => {
+            for (name, value) in object {
+                let path = if path.is_empty() { name.clone() } else { format!("{}.{}", path, name) };
+                leaves(&path, value, visit);
+            }
+        }
+        _ => visit(if path.is_empty() { "value" } else { path }, value),
+    }
+}
+
+fn lookup<'a>(row: &'a Value, column: &str) -> Option<&'a Value> {
+    if !row.is_object() && column == "value" {
+        return Some(row);
+    }
+    column.split('.').try_fold(row, |value, part| value.get(part))
+}
+
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        // Spreadsheets run cells starting with these as formulas, and a
+        // display name is client-controlled.
+        Some(Value::String(text)) if text.starts_with(['=', '+', '-', '@', '\t', '\r']) => format!("'{}", text),
+        Some(Value::String(text)) => text.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+fn write_record<'a>(csv: &mut String, cells: impl Iterator<Item = &'a str>) {
+    for (index, cell) in cells.enumerate() {
+        if index > 0 {
+            csv.push(',');
+        }
+        if cell.contains([',', '"', '\n', '\r']) {
+            csv.push('"');
+            csv.push_str(&cell.replace('"', "\"\""));
+            csv.push('"');
+        } else {
+            csv.push_str(cell);
+        }
+    }
+    csv.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::json;
+
+    #[test]
+    fn nested_rows_flatten_into_columns() {
+        let rows = json!([
+            { "id": 1, "roles": [1, 2], "user": { "name": "a,b", "verified": true } },
+            { "id": 2, "note": "=HYPERLINK(\"x\")", "user": { "name": "say \"hi\"" } },
+        ]);
+        let rows = rows.as_array().unwrap();
+        assert_eq!(
+            to_csv(rows, None),
+            "id,roles,user.name,user.verified,note\r\n\
+             1,\"[1,2]\",\"a,b\",true,\r\n\
+             2,,\"say \"\"hi\"\"\",,\"'=HYPERLINK(\"\"x\"\")\"\r\n"
+        );
+        assert_eq!(
+            to_csv(rows, Some("user.name, id,missing")),
+            "user.name,id,missing\r\n\"a,b\",1,\r\n\"say \"\"hi\"\"\",2,\r\n"
+        );
+        assert_eq!(to_csv(json!([3, 4]).as_array().unwrap(), None), "value\r\n3\r\n4\r\n");
+    }
+}
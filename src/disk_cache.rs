@@ -0,0 +1,204 @@
+use crate::{cache::CacheKey, ProxyResponse};
+use anyhow::{Context, Result};
+use rocket::{
+    http::Status,
+    serde::{json::serde_json, Deserialize, Serialize},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, warn};
+
+// Everything but the body, written as one JSON line at the top of each file.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct EntryHeader {
+    namespace: Option<String>,
+    url: String,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    account: Option<String>,
+    status: u16,
+    content_type: String,
+    headers: Vec<(String, String)>,
+    stored_at: u64,
+    ttl_secs: u64,
+}
+
+pub struct LoadedEntry {
+    pub key: CacheKey,
+    pub response: ProxyResponse,
+    pub age: Duration,
+    pub ttl: Duration,
+}
+
+enum Change {
+    Save(PathBuf, EntryHeader, Vec<u8>),
+    Remove(PathBuf),
+}
+
+/// Write-through copy of the response cache on disk, one file per key, so a
+/// restart can start warm instead of sending every request upstream at once.
+///
+/// Changes are made by one writer thread in the order they were asked for,
+/// so a removal can't be overtaken by an earlier save of the same entry.
+pub struct DiskStore {
+    dir: PathBuf,
+    changes: mpsc::Sender<Change>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl DiskStore {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache directory {}", dir.display()))?;
+        let (changes, pending) = mpsc::channel();
+        thread::Builder::new()
+            .name("disk-cache".to_string())
+            .spawn(move || {
+                for change in pending {
+                    apply(change);
+                }
+            })
+            .context("Failed to start the disk cache writer")?;
+        Ok(DiskStore { dir, changes })
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        let mut hasher = Sha256::new();
+        if let Some(namespace) = &key.namespace {
+            hasher.update(namespace.as_bytes());
+            hasher.update(b"\n");
+        }
+        hasher.update(key.url.as_bytes());
+        if let Some(body) = &key.body {
+            hasher.update(b"\n");
+            hasher.update(body.as_bytes());
+        }
+        if let Some(account) = &key.account {
+            hasher.update(b"\naccount:");
+            hasher.update(account.as_bytes());
+        }
+        let digest = hasher.finalize();
+        self.dir.join(format!("{}.entry", hex::encode(digest)))
+    }
+
+    /// Reads every entry that is still fresh, deleting expired or unreadable
+    /// files along the way.
+    pub fn load(&self) -> Vec<LoadedEntry> {
+        let Ok(files) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let now = unix_now();
+        let mut loaded = Vec::new();
+
+        for file in files.flatten() {
+            let path = file.path();
+            if path.extension().is_none_or(|ext| ext != "entry") {
+                continue;
+            }
+            match read_entry(&path) {
+                Ok(entry) if now.saturating_sub(entry.0.stored_at) < entry.0.ttl_secs => {
+                    let (header, body) = entry;
+                    loaded.push(LoadedEntry {
+                        age: Duration::from_secs(now.saturating_sub(header.stored_at)),
+                        ttl: Duration::from_secs(header.ttl_secs),
+                        response: ProxyResponse {
+                            status: Status::new(header.status),
+                            content_type: header.content_type,
+                            body,
+                            headers: header.headers,
+                            stream: None,
+                            credential: None,
+                        },
+                        key: CacheKey {
+                            namespace: header.namespace,
+                            url: header.url,
+                            body: header.body,
+                            account: header.account,
+                        },
+                    });
+                }
+                Ok(_) => {
+                    let _ = fs::remove_file(&path);
+                }
+                Err(err) => {
+                    warn!("Discarding unreadable cache file {}: {:?}", path.display(), err);
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        loaded
+    }
+
+    pub fn save(&self, key: &CacheKey, response: &ProxyResponse, ttl: Duration) {
+        let header = EntryHeader {
+            namespace: key.namespace.clone(),
+            url: key.url.clone(),
+            body: key.body.clone(),
+            account: key.account.clone(),
+            status: response.status.code,
+            content_type: response.content_type.clone(),
+            headers: response.headers.clone(),
+            stored_at: unix_now(),
+            ttl_secs: ttl.as_secs(),
+        };
+        let _ = self.changes.send(Change::Save(self.path(key), header, response.body.clone()));
+    }
+
+    pub fn remove(&self, key: &CacheKey) {
+        let _ = self.changes.send(Change::Remove(self.path(key)));
+    }
+}
+
+fn apply(change: Change) {
+    match change {
+        Change::Save(path, header, body) => {
+            if let Err(err) = write_entry(&path, &header, &body) {
+                warn!("Failed to persist cache entry {}: {:?}", header.url, err);
+            }
+        }
+        Change::Remove(path) => {
+            if let Err(err) = fs::remove_file(&path) {
+                debug!("Failed to remove cache file {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+fn read_entry(path: &Path) -> Result<(EntryHeader, Vec<u8>)> {
+    let contents = fs::read(path)?;
+    let split = contents
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .context("Missing entry header")?;
+    let header = serde_json::from_slice(&contents[..split])?;
+    Ok((header, contents[split + 1..].to_vec()))
+}
+
+// Written to a temporary file and renamed so a crash mid-write never leaves a
+// truncated entry behind.
+fn write_entry(path: &Path, header: &EntryHeader, body: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp)?;
+    serde_json::to_writer(&mut file, header)?;
+    file.write_all(b"\n")?;
+    file.write_all(body)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
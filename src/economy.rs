@@ -0,0 +1,32 @@
+use crate::{config::EconomyConfig, metrics::Metrics, tenants::ApiKey, Rejection};
+use anyhow::Result;
+use rocket::http::Status;
+
+/// What a proxy key needs to be granted to reach economy.roblox.com.
+pub const PERMISSION: &str = "economy";
+
+pub fn is_economy(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .ok()
+        .is_some_and(|url| url.host_str().is_some_and(|host| host.eq_ignore_ascii_case("economy.roblox.com")))
+}
+
+/// Turns away requests to economy.roblox.com unless the deployment enables
+/// it and `key` has the `economy` permission.
+pub fn check(config: &EconomyConfig, key: Option<&ApiKey>, url: &str, metrics: &Metrics) -> Result<()> {
+    if !is_economy(url) {
+        return Ok(());
+    }
+    if !config.enabled {
+        metrics.incr("roproxy_economy_requests_total", &[("result", "disabled")]);
+        return Err(Rejection::new(Status::Forbidden, "economy.roblox.com is disabled on this proxy").into());
+    }
+    if !key.is_some_and(|key| key.has_permission(PERMISSION)) {
+        metrics.incr("roproxy_economy_requests_total", &[("result", "permission")]);
+        return Err(Rejection::new(Status::Forbidden, "This proxy key may not use economy.roblox.com")
+            .with_field("missing_scope", PERMISSION)
+            .into());
+    }
+    metrics.incr("roproxy_economy_requests_total", &[("result", "allowed")]);
+    Ok(())
+}
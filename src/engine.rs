@@ -0,0 +1,723 @@
+use crate::{
+    body_rules::BodyRules,
+    budget::Budgets,
+    cache::{CacheKey, ResponseCache},
+    client,
+    content_type::ContentTypes,
+    config::{OversizePolicy, ProxyConfig, ResponseLimitConfig, SseConfig, StaleIfErrorRuleConfig},
+    credentials::{CredentialPool, PooledCredential},
+    headers::{self, ResponseHeaderFilter},
+    identity::Identities,
+    latency::AdaptiveTimeouts,
+    metrics::Metrics,
+    middleware::{Middleware, Pipeline},
+    minify::JsonMinifier,
+    retry::RetryBudget,
+    schema::SchemaValidation,
+    scripting::Scripts,
+    shared_state::SharedState,
+    sse::EventStreamBody,
+    ssrf::UpstreamGuard,
+    timing, trace,
+    upstream_signing::UpstreamSigner,
+    webhooks::ResponseWebhooks,
+    ProxyResponse, Rejection,
+};
+use anyhow::{anyhow, Context, Result};
+use reqwest::Client;
+use rocket::http::{Method, Status, StatusClass};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info};
+
+pub struct UpstreamRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<reqwest::Body>,
+    pub credential: Option<Arc<PooledCredential>>,
+    /// Identity profile the client asked for, instead of the route's.
+    pub identity: Option<String>,
+    /// Instead of the adaptive or client timeout, for slow uploads.
+    pub timeout: Option<Duration>,
+}
+
+impl UpstreamRequest {
+    pub fn get(url: impl Into<String>) -> Self {
+        UpstreamRequest {
+            method: Method::Get,
+            url: url.into(),
+            headers: Vec::new(),
+            body: None,
+            credential: None,
+            identity: None,
+            timeout: None,
+        }
+    }
+
+    // Sends the request as the next account from `pool`. The account hears
+    // back how Roblox answered so throttled or revoked ones get skipped.
+    pub fn with_credentials(self, pool: &CredentialPool) -> Self {
+        self.with_credential(pool.pick())
+    }
+
+    pub fn with_credential(mut self, credential: Option<Arc<PooledCredential>>) -> Self {
+        self.credential = credential;
+        if let Some(credential) = &self.credential {
+            credential.credentials.apply(&mut self.headers);
+        }
+        self
+    }
+}
+
+// Roblox's rate limit headers and our timings described the request that
+// filled the cache, not this one.
+fn from_cache(mut response: ProxyResponse, marker: &str) -> ProxyResponse {
+    response.headers.retain(|(name, _)| {
+        let name = name.to_lowercase();
+        !name.starts_with("x-ratelimit-") && name != "server-timing"
+    });
+    response.headers.push(("X-Cache".to_string(), marker.to_string()));
+    response
+}
+
+/// Marks a response cut short by `response_limit`; such responses are never
+/// cached.
+const TRUNCATED_HEADER: &str = "X-Proxy-Truncated";
+
+/// The upstream half of the proxy. Given a request that has already been
+/// authenticated and routed, it applies the identity headers and URL guard,
+/// sends within the budgets with retries and adaptive timeouts, and reads the
+/// response back. It also fronts the shared response cache. Nothing here
+/// touches Rocket's request types, so routes, background jobs and tests all
+/// drive it the same way.
+pub struct ProxyEngine {
+    pub client: Client,
+    pub cache: ResponseCache,
+    pub budgets: Budgets,
+    pub shared: Arc<SharedState>,
+    metrics: Arc<Metrics>,
+    identities: Identities,
+    guard: UpstreamGuard,
+    timeouts: AdaptiveTimeouts,
+    retries: RetryBudget,
+    server_timing: bool,
+    sse: SseConfig,
+    response_limit: ResponseLimitConfig,
+    response_headers: ResponseHeaderFilter,
+    stale_if_error: Vec<StaleIfErrorRuleConfig>,
+    pipeline: Pipeline,
+}
+
+/// Whether a response may be served from, and stored in, the shared cache.
+/// Responses to requests the client put its own credentials on are per-user
+/// and must never be shared; those sent as a pool account are cached per
+/// account (see `CacheKey`).
+pub fn cacheable(method: Method, has_session: bool, has_header: impl Fn(&str) -> bool) -> bool {
+    method == Method::Get && shareable(has_session, has_header)
+}
+
+/// Whether a request carries nothing that makes its response the client's
+/// own.
+pub fn shareable(has_session: bool, has_header: impl Fn(&str) -> bool) -> bool {
+    !has_session
+        && !["cookie", "authorization", "x-api-key"]
+            .iter()
+            .any(|name| has_header(name))
+}
+
+impl ProxyEngine {
+    pub fn new(config: &ProxyConfig, metrics: Arc<Metrics>) -> Result<Self> {
+        let shared = Arc::new(SharedState::new(&config.shared_state, metrics.clone())?);
+        let mut engine = ProxyEngine {
+            client: client::build_client(&config.upstream, metrics.clone())?,
+            cache: ResponseCache::new(&config.cache, metrics.clone())?,
+            budgets: Budgets::new(&config.budgets, shared.clone()),
+            shared,
+            identities: Identities::new(&config.identities)?,
+            guard: UpstreamGuard::new(&config.upstream),
+            timeouts: AdaptiveTimeouts::new(&config.adaptive_timeouts),
+            retries: RetryBudget::new(&config.retries),
+            server_timing: config.server_timing.enabled,
+            sse: config.sse.clone(),
+            response_limit: config.response_limit.clone(),
+            response_headers: ResponseHeaderFilter::new(&config.response_headers),
+            stale_if_error: config.stale_if_error.rules.clone(),
+            pipeline: Pipeline::default(),
+            metrics,
+        };
+        engine.register(ContentTypes::new(&config.content_types));
+        engine.register(SchemaValidation::new(&config.schemas)?);
+        if !config.body_rules.rules.is_empty() {
+            engine.register(BodyRules::new(&config.body_rules));
+        }
+        if !config.scripts.rules.is_empty() {
+            engine.register(Scripts::new(&config.scripts)?);
+        }
+        if !config.response_webhooks.rules.is_empty() {
+            let client = engine.client.clone();
+            engine.register(ResponseWebhooks::new(&config.response_webhooks, client, engine.metrics.clone()));
+        }
+        // Last, so whatever the stages before it produced goes out minified.
+        if config.minify_json.enabled {
+            engine.register(JsonMinifier::new(&config.minify_json));
+        }
+        // After every stage that may change the request, so what's signed is
+        // what's sent.
+        if !config.upstream_signing.upstreams.is_empty() {
+            engine.register(UpstreamSigner::new(&config.upstream_signing));
+        }
+        Ok(engine)
+    }
+
+    /// Adds a stage to run around every upstream request, after those
+    /// already registered.
+    pub fn register(&mut self, stage: impl Middleware + 'static) {
+        self.pipeline.register(stage);
+    }
+
+    /// Whether a request to `url` has to be read whole before it's sent.
+    pub fn reads_body(&self, url: &str) -> bool {
+        self.pipeline.reads_body(url)
+    }
+
+    pub fn middleware(&self) -> Vec<&'static str> {
+        self.pipeline.names()
+    }
+
+    /// Normalizes an upstream URL, refusing anything outside the allowlist.
+    pub fn check_url(&self, url: &str) -> Result<String> {
+        self.guard.check(url, &self.metrics)
+    }
+
+    /// Fails with a 400 if the client asked for an unknown identity profile.
+    pub fn check_identity(&self, requested: Option<&str>, url: &str) -> Result<()> {
+        self.identities.profile(requested, url).map(|_| ())
+    }
+
+    /// A cached response for `key` no older than `max_age`, marked as a hit.
+    pub fn cached(&self, key: &CacheKey, max_age: Option<Duration>) -> Option<ProxyResponse> {
+        let Some(response) = self.cache.get(key, max_age) else {
+            self.metrics.incr("roproxy_cache_requests_total", &[("result", "miss")]);
+            trace::event(|| format!("Cache miss for {}", key.url));
+            return None;
+        };
+        debug!("Cache hit for {}", key.url);
+        trace::event(|| format!("Cache hit for {}", key.url));
+        self.metrics.incr("roproxy_cache_requests_total", &[("result", "hit")]);
+        Some(from_cache(response, "HIT"))
+    }
+
+    /// The last good cached response for `key`, marked `STALE-ERROR`, if
+    /// `result` is an upstream failure on a route with a stale-if-error rule.
+    pub fn stale_on_error(&self, key: &CacheKey, result: &Result<ProxyResponse>) -> Option<ProxyResponse> {
+        let status = match result {
+            Ok(response) => response.status,
+            // Anything that isn't a rejection means Roblox never answered.
+            Err(err) => err
+                .downcast_ref::<Rejection>()
+                .map_or(Status::BadGateway, |rejection| rejection.status),
+        };
+        if status != Status::TooManyRequests && status.class() != StatusClass::ServerError {
+            return None;
+        }
+        let rule = self
+            .stale_if_error
+            .iter()
+            .find(|rule| rule.prefixes.iter().any(|prefix| key.url.starts_with(prefix)))?;
+        let response = self.cache.get_stale(key, Duration::from_secs(rule.max_stale_secs))?;
+        info!("Serving stale {} after a {} ({})", key.url, status, rule.name);
+        trace::event(|| format!("Serving stale {} after a {} ({})", key.url, status, rule.name));
+        self.metrics.incr("roproxy_stale_if_error_total", &[("rule", &rule.name)]);
+        Some(from_cache(response, "STALE-ERROR"))
+    }
+
+    /// Caches `response` under `key`, for `ttl` rather than the configured
+    /// freshness if given, and marks it as a miss.
+    pub fn store(&self, key: &CacheKey, response: &mut ProxyResponse, ttl: Option<Duration>) {
+        if !ttl.is_some_and(|ttl| ttl.is_zero()) {
+            self.cache.insert(key, response, ttl);
+        }
+        response.headers.push(("X-Cache".to_string(), "MISS".to_string()));
+    }
+
+    /// Sends a request to Roblox under the route's identity profile and
+    /// budgets. Shared by client-facing routes and background jobs.
+    pub async fn forward(&self, request: UpstreamRequest) -> Result<ProxyResponse> {
+        if !self.server_timing {
+            return self.send(request).await;
+        }
+        let (response, timings) = timing::collect(self.send(request)).await;
+        let mut response = response?;
+        response
+            .headers
+            .push(("Server-Timing".to_string(), timings.header_value()));
+        Ok(response)
+    }
+
+    async fn send(&self, mut request: UpstreamRequest) -> Result<ProxyResponse> {
+        self.pipeline.before(&mut request, &self.metrics).await?;
+        let UpstreamRequest {
+            method,
+            url,
+            headers,
+            body,
+            credential,
+            identity,
+            timeout,
+        } = request;
+        let url = self.check_url(&url)?;
+
+        let mut request_builder = match method {
+            Method::Get => self.client.get(&url),
+            Method::Post => self.client.post(&url),
+            Method::Put => self.client.put(&url),
+            Method::Delete => self.client.delete(&url),
+            Method::Patch => self.client.patch(&url),
+            _ => return Err(anyhow!("Unsupported method")),
+        };
+
+
+        let wants_stream = headers.iter().any(|(name, value)| {
+            name.eq_ignore_ascii_case("accept") && value.contains("text/event-stream")
+        });
+        let adaptive_timeout = if wants_stream {
+            request_builder = request_builder.timeout(Duration::from_secs(self.sse.max_duration_secs));
+            None
+        } else if let Some(timeout) = timeout {
+            request_builder = request_builder.timeout(timeout);
+            None
+        } else {
+            self.timeouts.timeout(&url)
+        };
+        if let Some(timeout) = adaptive_timeout {
+            request_builder = request_builder.timeout(timeout);
+        }
+
+        let upstream_headers = headers::upstream_headers(self.identities.profile(identity.as_deref(), &url)?, headers)?;
+        trace::event(|| {
+            format!(
+                "Forwarding {} {} as {} with {}",
+                method,
+                url,
+                credential.as_ref().map_or("no credential", |credential| credential.name.as_str()),
+                trace::headers(
+                    upstream_headers
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.to_str().unwrap_or("<binary>")))
+                )
+            )
+        });
+        request_builder = request_builder.headers(upstream_headers);
+
+        if let Some(body) = body {
+            request_builder = request_builder.body(body);
+        }
+
+        self.retries.record_request();
+        let mut attempt = 0;
+        let response = loop {
+            // Only GETs are safe to send twice. Sending consumes the builder, so
+            // the copy for a retry is taken first.
+            let retry = (method == Method::Get && attempt < self.retries.max_retries())
+                .then(|| request_builder.try_clone())
+                .flatten();
+            let queued = Instant::now();
+            self.budgets.acquire(&url, &self.metrics).await?;
+            timing::record(|timings| timings.queue += queued.elapsed());
+
+            debug!("Sending request to Roblox API...");
+            let started = Instant::now();
+            let in_flight = self.metrics.hold_gauge("roproxy_upstream_requests_in_flight");
+            let response = request_builder.send().await;
+            drop(in_flight);
+            timing::record(|timings| timings.ttfb = started.elapsed());
+            self.metrics.observe(
+                "roproxy_upstream_request_seconds",
+                &[("method", method.as_str())],
+                started.elapsed(),
+            );
+            self.metrics.mark("roproxy_upstream_requests_last_minute", &[]);
+            match &response {
+                Ok(_) if !wants_stream => self.timeouts.record(&url, started.elapsed()),
+                Err(err) if err.is_timeout() && adaptive_timeout.is_some() => {
+                    self.metrics.mark("roproxy_upstream_errors_last_minute", &[]);
+                    self.metrics.incr("roproxy_adaptive_timeouts_total", &[]);
+                    self.timeouts.record(&url, started.elapsed());
+                    return Err(Rejection::new(Status::GatewayTimeout, "Upstream took too long to respond").into());
+                }
+                _ => {}
+            }
+            let retryable = match &response {
+                Ok(response) => [502, 503, 504].contains(&response.status().as_u16()),
+                Err(err) => err.is_connect(),
+            };
+            trace::event(|| match &response {
+                Ok(response) => format!("Attempt {} got {} in {:?}", attempt + 1, response.status(), started.elapsed()),
+                Err(err) => format!("Attempt {} failed in {:?}: {}", attempt + 1, started.elapsed(), err),
+            });
+            match retry {
+                Some(next) if retryable && self.retries.try_retry(&self.metrics) => {
+                    self.metrics.mark("roproxy_upstream_errors_last_minute", &[]);
+                    attempt += 1;
+                    info!("Retrying {} (attempt {})", url, attempt + 1);
+                    let backoff = self.retries.backoff(attempt);
+                    trace::event(|| format!("Retrying after {:?}", backoff));
+                    tokio::time::sleep(backoff).await;
+                    request_builder = next;
+                }
+                _ => {
+                    if retryable {
+                        trace::event(|| {
+                            let reason = if retry.is_none() { "not retryable or out of attempts" } else { "retry budget spent" };
+                            format!("Not retrying: {}", reason)
+                        });
+                    }
+                    break response;
+                }
+            }
+        };
+        let response = response
+            .inspect_err(|_| self.metrics.mark("roproxy_upstream_errors_last_minute", &[]))
+            .context("Failed to send request")?;
+
+        if let Some(addr) = response.remote_addr() {
+            let family = if addr.is_ipv4() { "ipv4" } else { "ipv6" };
+            self.metrics
+                .incr("roproxy_upstream_responses_by_family_total", &[("family", family)]);
+        }
+        let status = response.status();
+        if status.is_server_error() {
+            self.metrics.mark("roproxy_upstream_errors_last_minute", &[]);
+        }
+        debug!("Received response status: {}", status);
+        if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            self.budgets.exhaust(&url).await;
+        }
+        self.metrics
+            .incr("roproxy_upstream_responses_total", &[("status", status.as_str())]);
+        if let Some(credential) = &credential {
+            credential.report(status.as_u16());
+            self.metrics.incr(
+                "roproxy_credential_requests_total",
+                &[("credential", &credential.name), ("status", status.as_str())],
+            );
+        }
+
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|val| val.to_str().ok())
+            .unwrap_or("application/json")
+            .to_string();
+
+        let mut response_headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                if let Ok(val_str) = value.to_str() {
+                    let name_lower = name.to_string().to_lowercase();
+                    if !["transfer-encoding", "connection"].contains(&name_lower.as_str())
+                        && self.response_headers.allows(&name_lower)
+                    {
+                        Some((name.to_string(), val_str.to_string()))
+                    } else {
+                        None
+                    }
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        if content_type.starts_with("text/event-stream") {
+            info!("Relaying event stream from {}", url);
+            response_headers.retain(|(name, _)| !name.eq_ignore_ascii_case("content-length"));
+            return Ok(ProxyResponse {
+                status: Status::from_code(status.as_u16()).unwrap_or(Status::InternalServerError),
+                content_type,
+                body: Vec::new(),
+                headers: response_headers,
+                stream: Some(EventStreamBody::new(response, &self.sse)),
+                credential: credential.as_ref().map(|credential| credential.name.clone()),
+            });
+        }
+
+        let reading = Instant::now();
+        let (body, truncated) = self.read_body(&url, response).await?;
+        timing::record(|timings| timings.body = reading.elapsed());
+        debug!("Response body size: {} bytes", body.len());
+        if truncated {
+            response_headers.push((TRUNCATED_HEADER.to_string(), "true".to_string()));
+        }
+
+        // if let Ok(json_str) = String::from_utf8(body.to_vec()) {
+        //     info!("Response body: {}", json_str);
+        // }
+
+        let mut response = ProxyResponse {
+            status: Status::from_code(status.as_u16()).unwrap_or(Status::InternalServerError),
+            content_type,
+            body,
+            headers: response_headers,
+            stream: None,
+            credential: credential.as_ref().map(|credential| credential.name.clone()),
+        };
+        self.pipeline.after(&url, &mut response, &self.metrics).await?;
+        Ok(response)
+    }
+
+    // Reads at most `response_limit.max_bytes`, so one huge download can't take
+    // the instance's memory with it. Returns whether the body was truncated.
+    async fn read_body(&self, url: &str, mut response: reqwest::Response) -> Result<(Vec<u8>, bool)> {
+        let limit = &self.response_limit;
+        let oversize = || {
+            self.metrics.incr(
+                "roproxy_oversize_responses_total",
+                &[("action", limit.on_exceeded.as_str())],
+            );
+            tracing::warn!("Response from {} exceeds {} bytes", url, limit.max_bytes);
+            Rejection::new(Status::BadGateway, "Upstream response is too large")
+                .with_field("max_bytes", limit.max_bytes)
+        };
+        let reject = limit.on_exceeded == OversizePolicy::Reject;
+        if reject && response.content_length().is_some_and(|length| length > limit.max_bytes as u64) {
+            return Err(oversize().into());
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.context("Failed to read response body")? {
+            let room = limit.max_bytes - body.len();
+            if chunk.len() > room {
+                let rejection = oversize();
+                if reject {
+                    return Err(rejection.into());
+                }
+                body.extend_from_slice(&chunk[..room]);
+                return Ok((body, true));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok((body, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RetriesConfig, UpstreamConfig};
+    use std::sync::Mutex;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// A stand-in for Roblox answering with `statuses` in turn (the last one
+    /// repeats) and keeping the head of every request it gets.
+    async fn upstream(statuses: &'static [u16]) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        tokio::spawn(async move {
+            for n in 0.. {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let read = socket.read(&mut buf).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    head.extend_from_slice(&buf[..read]);
+                }
+                log.lock().unwrap().push(String::from_utf8_lossy(&head).to_lowercase());
+                let status = statuses[n.min(statuses.len() - 1)];
+                let reply = format!(
+                    "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{{}}",
+                    status
+                );
+                socket.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        (format!("http://{}", addr), seen)
+    }
+
+    fn engine(base: &str, max_retries: u32) -> ProxyEngine {
+        let config = ProxyConfig {
+            upstream: UpstreamConfig {
+                allowed_hosts: vec![base.trim_start_matches("http://").to_string()],
+                allow_http: true,
+                ..UpstreamConfig::default()
+            },
+            retries: RetriesConfig {
+                max_retries,
+                backoff_ms: 0,
+                ..RetriesConfig::default()
+            },
+            ..ProxyConfig::default()
+        };
+        ProxyEngine::new(&config, Arc::new(Metrics::default())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn identity_defaults_yield_to_client_headers() {
+        let (base, seen) = upstream(&[200]).await;
+        let engine = engine(&base, 0);
+        let mut request = UpstreamRequest::get(format!("{}/v1/users/1", base));
+        request.headers.push(("User-Agent".to_string(), "custom".to_string()));
+        let response = engine.forward(request).await.unwrap();
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(response.body, b"{}");
+
+        let head = &seen.lock().unwrap()[0];
+        assert!(head.starts_with("get /v1/users/1 "));
+        assert!(head.contains("user-agent: custom\r\n"));
+        assert!(head.contains("referer: https://www.roblox.com\r\n"));
+    }
+
+    #[tokio::test]
+    async fn gets_are_retried_on_bad_gateway() {
+        let (base, seen) = upstream(&[502, 200]).await;
+        let response = engine(&base, 1)
+            .forward(UpstreamRequest::get(format!("{}/x", base)))
+            .await
+            .unwrap();
+        assert_eq!(response.status, Status::Ok);
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn posts_are_never_retried() {
+        let (base, seen) = upstream(&[502, 200]).await;
+        let request = UpstreamRequest {
+            method: Method::Post,
+            body: Some("{}".into()),
+            ..UpstreamRequest::get(format!("{}/x", base))
+        };
+        let response = engine(&base, 1).forward(request).await.unwrap();
+        assert_eq!(response.status, Status::BadGateway);
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unlisted_hosts_are_refused() {
+        let (base, seen) = upstream(&[200]).await;
+        let Err(err) = engine(&base, 0).forward(UpstreamRequest::get("https://evil.com/x")).await else {
+            panic!("request to an unlisted host was sent");
+        };
+        assert_eq!(err.downcast_ref::<Rejection>().unwrap().status, Status::Forbidden);
+        assert!(seen.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cache_hits_drop_per_request_headers() {
+        let engine = engine("http://127.0.0.1:1", 0);
+        let key = CacheKey::new(None, "https://users.roblox.com/v1/users/1");
+        assert!(engine.cached(&key, None).is_none());
+
+        let mut response = ProxyResponse {
+            status: Status::Ok,
+            content_type: "application/json".to_string(),
+            body: b"{}".to_vec(),
+            headers: vec![
+                ("x-ratelimit-remaining".to_string(), "9".to_string()),
+                ("Server-Timing".to_string(), "upstream;dur=5".to_string()),
+                ("ETag".to_string(), "\"1\"".to_string()),
+            ],
+            stream: None,
+            credential: None,
+        };
+        engine.store(&key, &mut response, None);
+        assert!(response.headers.contains(&("X-Cache".to_string(), "MISS".to_string())));
+
+        let hit = engine.cached(&key, None).unwrap();
+        let names: Vec<_> = hit.headers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["ETag", "X-Cache"]);
+        assert_eq!(hit.headers[1].1, "HIT");
+    }
+
+    #[tokio::test]
+    async fn failures_fall_back_to_recently_expired_entries() {
+        let (base, _) = upstream(&[503]).await;
+        let mut engine = engine(&base, 0);
+        engine.stale_if_error = vec![StaleIfErrorRuleConfig {
+            name: "users".to_string(),
+            prefixes: vec![format!("{}/v1/users/", base)],
+            max_stale_secs: 60,
+        }];
+        let key = CacheKey::new(None, format!("{}/v1/users/1", base));
+        let mut response = ProxyResponse {
+            status: Status::Ok,
+            content_type: "application/json".to_string(),
+            body: b"{\"id\":1}".to_vec(),
+            headers: Vec::new(),
+            stream: None,
+            credential: None,
+        };
+        engine.store(&key, &mut response, Some(Duration::from_millis(1)));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(engine.cached(&key, None).is_none());
+
+        let failed = engine.forward(UpstreamRequest::get(key.url.clone())).await;
+        let stale = engine.stale_on_error(&key, &failed).unwrap();
+        assert_eq!(stale.body, b"{\"id\":1}");
+        assert_eq!(stale.headers, [("X-Cache".to_string(), "STALE-ERROR".to_string())]);
+
+        let refused: Result<ProxyResponse> = Err(Rejection::new(Status::Forbidden, "no").into());
+        assert!(engine.stale_on_error(&key, &refused).is_none());
+        let unlisted = CacheKey::new(None, format!("{}/v1/groups/1", base));
+        assert!(engine.stale_on_error(&unlisted, &failed).is_none());
+    }
+
+    struct Tag;
+
+    #[rocket::async_trait]
+    impl Middleware for Tag {
+        fn name(&self) -> &'static str {
+            "tag"
+        }
+
+        async fn before_upstream(&self, request: &mut UpstreamRequest, _metrics: &Metrics) -> Result<()> {
+            if request.url.ends_with("/blocked") {
+                return Err(Rejection::new(Status::Forbidden, "Blocked").into());
+            }
+            request.headers.push(("X-Tag".to_string(), "before".to_string()));
+            Ok(())
+        }
+
+        async fn after_upstream(&self, _url: &str, response: &mut ProxyResponse, _metrics: &Metrics) -> Result<()> {
+            response.headers.push(("X-Tag".to_string(), "after".to_string()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_middleware_wraps_requests() {
+        let (base, seen) = upstream(&[200]).await;
+        let mut engine = engine(&base, 0);
+        engine.register(Tag);
+        assert_eq!(engine.middleware(), ["content-types", "schemas", "tag"]);
+
+        let response = engine.forward(UpstreamRequest::get(format!("{}/x", base))).await.unwrap();
+        assert!(response.headers.contains(&("X-Tag".to_string(), "after".to_string())));
+        assert!(seen.lock().unwrap()[0].contains("x-tag: before\r\n"));
+
+        let Err(err) = engine.forward(UpstreamRequest::get(format!("{}/blocked", base))).await else {
+            panic!("blocked request was sent");
+        };
+        assert_eq!(err.downcast_ref::<Rejection>().unwrap().status, Status::Forbidden);
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn only_anonymous_gets_are_cacheable() {
+        assert!(cacheable(Method::Get, false, |_| false));
+        assert!(!cacheable(Method::Post, false, |_| false));
+        assert!(!cacheable(Method::Get, true, |_| false));
+        assert!(!cacheable(Method::Get, false, |name| name == "authorization"));
+        assert!(cacheable(Method::Get, false, |name| name == "accept"));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::{config::EnumerationConfig, metrics::Metrics, Rejection};
+use anyhow::Result;
+use rocket::http::Status;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+/// Scans tracked at once; expired ones are dropped when it's reached.
+const MAX_TRACKED_SCANS: usize = 10_000;
+const FILTER_BITS: usize = 4096;
+const FILTER_HASHES: u64 = 3;
+
+/// Which IDs a client asked for, in 512 bytes however many there are. A
+/// false positive only means an ID isn't counted, which errs towards
+/// leaving the client alone.
+struct SeenIds([u64; FILTER_BITS / 64]);
+
+impl SeenIds {
+    /// Adds `id`, returning whether it wasn't there yet.
+    fn insert(&mut self, id: u64) -> bool {
+        let hash = mix(id);
+        let (h1, h2) = (hash & 0xffff_ffff, hash >> 32);
+        let mut added = false;
+        for i in 0..FILTER_HASHES {
+            let bit = (h1.wrapping_add(i.wrapping_mul(h2)) % FILTER_BITS as u64) as usize;
+            let (word, mask) = (bit / 64, 1 << (bit % 64));
+            added |= self.0[word] & mask == 0;
+            self.0[word] |= mask;
+        }
+        added
+    }
+}
+
+// SplitMix64's finalizer, so neighbouring IDs land on unrelated bits.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// One client walking the IDs of one endpoint.
+struct Scan {
+    window_start: Instant,
+    seen: SeenIds,
+    distinct: u32,
+    low: u64,
+    high: u64,
+    flagged: bool,
+}
+
+impl Scan {
+    fn new(id: u64) -> Self {
+        Scan {
+            window_start: Instant::now(),
+            seen: SeenIds([0; FILTER_BITS / 64]),
+            distinct: 0,
+            low: id,
+            high: id,
+            flagged: false,
+        }
+    }
+
+    fn record(&mut self, id: u64) {
+        if self.seen.insert(id) {
+            self.distinct += 1;
+        }
+        self.low = self.low.min(id);
+        self.high = self.high.max(id);
+    }
+
+    /// Distinct IDs as a share of the range they span. Scrapers cover a range
+    /// densely, in whatever order; real traffic is scattered across it.
+    fn density(&self) -> f64 {
+        self.distinct as f64 / (self.high - self.low).saturating_add(1) as f64
+    }
+}
+
+struct Strikes {
+    level: u32,
+    last: Instant,
+}
+
+/// Notices a client walking through numeric IDs on one endpoint, e.g. every
+/// user from 1000 to 2000, and slows it down so a scraper can't spend the
+/// upstream budget everyone shares. Each window a client is caught in
+/// doubles the delay its requests get, until they're turned away outright.
+/// Strikes are forgotten after `cooldown_secs` without one.
+pub struct EnumerationGuard {
+    config: EnumerationConfig,
+    scans: Mutex<HashMap<(IpAddr, String), Scan>>,
+    strikes: Mutex<HashMap<IpAddr, Strikes>>,
+}
+
+impl EnumerationGuard {
+    pub fn new(config: &EnumerationConfig) -> Self {
+        EnumerationGuard {
+            config: config.clone(),
+            scans: Mutex::default(),
+            strikes: Mutex::default(),
+        }
+    }
+
+    /// Records the request to `url` and holds it back, or rejects it, if the
+    /// client has been caught enumerating.
+    pub async fn check(&self, ip: Option<IpAddr>, url: &str, metrics: &Metrics) -> Result<()> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+        let Some(ip) = ip else {
+            return Ok(());
+        };
+        if let Some((endpoint, id)) = endpoint_id(url) {
+            if self.record(ip, endpoint, id) {
+                self.strike(ip, metrics);
+            }
+        }
+
+        let Some(delay) = self.delay(ip) else {
+            return Ok(());
+        };
+        if delay > Duration::from_millis(self.config.max_delay_ms) {
+            metrics.incr("roproxy_enumeration_throttled_total", &[("action", "rejected")]);
+            return Err(Rejection::new(Status::TooManyRequests, "Client is scanning IDs too quickly")
+                .with_field("reason", "enumeration")
+                .with_header("Retry-After", self.config.cooldown_secs)
+                .into());
+        }
+        metrics.incr("roproxy_enumeration_throttled_total", &[("action", "delayed")]);
+        tokio::time::sleep(delay).await;
+        Ok(())
+    }
+
+    // Whether this request got the client caught on `endpoint`.
+    fn record(&self, ip: IpAddr, endpoint: String, id: u64) -> bool {
+        let window = Duration::from_secs(self.config.window_secs);
+        let mut scans = self.scans.lock().unwrap();
+        if scans.len() >= MAX_TRACKED_SCANS {
+            scans.retain(|_, scan| scan.window_start.elapsed() < window);
+        }
+        let scan = scans.entry((ip, endpoint)).or_insert_with(|| Scan::new(id));
+        if scan.window_start.elapsed() >= window {
+            *scan = Scan::new(id);
+        }
+        scan.record(id);
+        if scan.flagged || scan.distinct < self.config.min_ids || scan.density() < self.config.min_density {
+            return false;
+        }
+        scan.flagged = true;
+        true
+    }
+
+    fn strike(&self, ip: IpAddr, metrics: &Metrics) {
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        let mut strikes = self.strikes.lock().unwrap();
+        let strike = strikes.entry(ip).or_insert(Strikes {
+            level: 0,
+            last: Instant::now(),
+        });
+        if strike.last.elapsed() >= cooldown {
+            strike.level = 0;
+        }
+        strike.level += 1;
+        strike.last = Instant::now();
+        warn!("{} is enumerating IDs, throttling it (strike {})", ip, strike.level);
+        metrics.incr("roproxy_enumeration_flags_total", &[]);
+    }
+
+    fn delay(&self, ip: IpAddr) -> Option<Duration> {
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        let mut strikes = self.strikes.lock().unwrap();
+        let strike = strikes.get(&ip)?;
+        if strike.last.elapsed() >= cooldown {
+            strikes.remove(&ip);
+            return None;
+        }
+        let factor = 1u32.checked_shl(strike.level - 1).unwrap_or(u32::MAX);
+        Some(Duration::from_millis(self.config.base_delay_ms).saturating_mul(factor))
+    }
+}
+
+// The endpoint a URL's ID belongs to, with the ID blanked out, and the ID:
+// `https://users.roblox.com/v1/users/*` and 42.
+fn endpoint_id(url: &str) -> Option<(String, u64)> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let position = segments
+        .iter()
+        .rposition(|segment| !segment.is_empty() && segment.bytes().all(|byte| byte.is_ascii_digit()))?;
+    let id = segments[position].parse().ok()?;
+    let mut template = segments;
+    template[position] = "*";
+    Some((format!("{}/{}", url.origin().ascii_serialization(), template.join("/")), id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blanks_out_the_id() {
+        assert_eq!(
+            endpoint_id("https://users.roblox.com/v1/users/42/status"),
+            Some(("https://users.roblox.com/v1/users/*/status".to_string(), 42))
+        );
+        assert_eq!(endpoint_id("https://users.roblox.com/v1/users/authenticated"), None);
+    }
+
+    #[test]
+    fn dense_ranges_look_like_scans() {
+        let mut scan = Scan::new(1000);
+        // Shuffled, but covering the range.
+        for id in (500..600).rev().map(|i| i * 2).chain((1001..1200).step_by(2)) {
+            scan.record(id);
+        }
+        scan.record(1100);
+        assert!(scan.distinct >= 195);
+        assert!(scan.density() > 0.95);
+
+        let mut scattered = Scan::new(1);
+        for id in (0..200).map(|i| mix(i) % 1_000_000_000) {
+            scattered.record(id);
+        }
+        assert!(scattered.density() < 0.01);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    challenge, check_header_limits, check_policies, client_cert, credentials, identity, request_log::LogContext, tenants::{host_matches, Tenant}, AppState, ErrorResponse,
+    MyRequestGuard, ProxyResponse, Rejection, UpstreamRequest,
+};
+use anyhow::{anyhow, Result};
+use rocket::{
+    http::{Method, Status},
+    serde::{
+        json::{Json, Value},
+        Deserialize,
+    },
+    Request, Route, State,
+};
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+pub fn routes() -> Vec<Route> {
+    routes![envelope]
+}
+
+/// A request described in the body instead of the URL, which sidesteps
+/// HttpService's URL length limit and query-encoding pitfalls.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Envelope {
+    #[serde(default = "default_method")]
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Sent as-is when a string, as JSON otherwise.
+    #[serde(default)]
+    pub body: Option<Value>,
+}
+
+pub fn default_method() -> String {
+    "GET".to_string()
+}
+
+#[post("/proxy", data = "<envelope>")]
+async fn envelope(
+    envelope: Json<Envelope>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<ProxyResponse, ErrorResponse> {
+    let prepared = prepare(envelope.into_inner(), state, guard.request).await?;
+    send(state, prepared).await.map_err(ErrorResponse)
+}
+
+/// An envelope checked and authorized for the caller, ready to send. The
+/// account it goes out as is only picked when it's sent.
+#[derive(Clone)]
+pub struct Prepared {
+    pub method: Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+    pub identity: Option<String>,
+    /// Pins the caller to an account; see `credentials::affinity`.
+    pub affinity: Option<String>,
+    /// Credential pool to send it from: the tenant's, or "default".
+    pub pool: String,
+    pub tenant: Option<String>,
+}
+
+/// Checks `envelope` against the allowed hosts and the caller's key, and
+/// builds the upstream request.
+pub async fn prepare(envelope: Envelope, state: &AppState, req: &Request<'_>) -> Result<Prepared> {
+    state.screen_client(req)?;
+    let tenant = state.tenants.authenticate(client_cert::api_key(req), &state.metrics)?;
+    let prepared = check(envelope, state, req, tenant.as_deref()).await?;
+    state
+        .metrics
+        .incr("roproxy_envelope_requests_total", &[("method", prepared.method.as_str())]);
+    Ok(prepared)
+}
+
+/// `prepare` for a caller already authenticated as `tenant`, for routes
+/// that check many envelopes from one request.
+pub async fn check(
+    envelope: Envelope,
+    state: &AppState,
+    req: &Request<'_>,
+    tenant: Option<&Tenant>,
+) -> Result<Prepared> {
+    let Envelope {
+        method,
+        url,
+        headers,
+        body,
+    } = envelope;
+
+    let method = Method::from_str(&method.to_uppercase())
+        .ok()
+        .filter(|method| {
+            [Method::Get, Method::Post, Method::Put, Method::Patch, Method::Delete].contains(method)
+        })
+        .ok_or_else(|| Rejection::new(Status::BadRequest, format!("Unsupported method {}", method)))?;
+    let url = state.engine.check_url(&url)?;
+    let parsed = reqwest::Url::parse(&url)
+        .map_err(|_| Rejection::new(Status::BadRequest, "url must be an absolute URL"))?;
+    let host = parsed.host_str().unwrap_or_default().to_lowercase();
+    if parsed.scheme() != "https" || !state.envelope.hosts.iter().any(|pattern| host_matches(pattern, &host)) {
+        return Err(Rejection::new(Status::Forbidden, format!("{} is not an allowed upstream", host))
+            .with_field("allowed", state.envelope.hosts.clone())
+            .into());
+    }
+
+    let key = tenant.zip(client_cert::api_key(req)).and_then(|(tenant, api_key)| tenant.key(api_key));
+    if let Some(tenant) = tenant {
+        LogContext::set_tenant(req, &tenant.name, key.map(|key| key.label()));
+    }
+    LogContext::set_upstream(req, &url);
+    check_policies(state, req.client_ip(), key, method, &url).await?;
+
+    let mut headers: Vec<_> = headers
+        .into_iter()
+        .filter(|(name, _)| {
+            !["host", "connection", "content-length", "transfer-encoding", "x-proxy-key", "x-proxy-identity"]
+                .contains(&name.to_lowercase().as_str())
+        })
+        .collect();
+    check_header_limits(state, &headers)?;
+    let body = body.map(|body| match body {
+        Value::String(text) => text.into_bytes(),
+        other => {
+            if !headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("content-type")) {
+                headers.push(("Content-Type".to_string(), "application/json".to_string()));
+            }
+            other.to_string().into_bytes()
+        }
+    });
+
+    let tenant = tenant.map(|tenant| tenant.name.clone());
+    Ok(Prepared {
+        method,
+        url,
+        headers,
+        body,
+        identity: req.headers().get_one(identity::HEADER).map(str::to_string),
+        affinity: credentials::affinity(req),
+        pool: tenant.clone().unwrap_or_else(|| "default".to_string()),
+        tenant,
+    })
+}
+
+pub async fn send(state: &AppState, prepared: Prepared) -> Result<ProxyResponse> {
+    let Prepared {
+        method,
+        url,
+        headers,
+        body,
+        identity,
+        affinity,
+        pool: pool_name,
+        ..
+    } = prepared;
+    let pool = state
+        .credential_pool(&pool_name)
+        .ok_or_else(|| anyhow!("Unknown credential pool {}", pool_name))?;
+    let credential = challenge::challenge_id(&headers)
+        .and_then(|id| state.challenges.credential(&pool_name, pool, id))
+        .or_else(|| pool.pick_for(affinity.as_deref()));
+    let request = UpstreamRequest {
+        method,
+        url: url.clone(),
+        headers,
+        body: body.map(Into::into),
+        credential: None,
+        identity,
+        timeout: None,
+    }
+    .with_credential(credential);
+    let mut response = state.engine.forward(request).await?;
+    state.challenges.observe(&pool_name, &response, &state.metrics);
+    state.engine.budgets.annotate(&url, &mut response);
+    Ok(state.transforms.apply(&url, response, &state.metrics))
+}
@@ -0,0 +1,62 @@
+use crate::{helpers::Upstream, AppState, ErrorResponse, MyRequestGuard, Rejection};
+use rocket::{
+    http::Status,
+    serde::json::{json, Json, Value},
+    State,
+};
+use std::{sync::Arc, time::Duration};
+
+/// A game's name, playing count, visits, favorites and votes in one
+/// document, for dashboards that would otherwise make several calls per game
+/// per refresh. A section that fails is reported under `errors` and its
+/// fields left null without failing the rest.
+#[get("/helpers/games/<universe_id>/summary")]
+pub async fn game_summary(
+    universe_id: u64,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "game_summary")]);
+
+    let ttl = Duration::from_secs(state.helpers.game_summary_ttl_secs);
+    let details_url = format!("https://games.roblox.com/v1/games?universeIds={}", universe_id);
+    let votes_url = format!("https://games.roblox.com/v1/games/votes?universeIds={}", universe_id);
+    let favorites_url = format!("https://games.roblox.com/v1/games/{}/favorites/count", universe_id);
+    let (details, votes, favorites) = tokio::join!(
+        upstream.get(&details_url, ttl),
+        upstream.get(&votes_url, ttl),
+        upstream.get(&favorites_url, ttl),
+    );
+    if let Ok(details) = &details {
+        if details["data"].as_array().is_some_and(Vec::is_empty) {
+            return Err(ErrorResponse(
+                Rejection::new(Status::NotFound, format!("Universe {} doesn't exist", universe_id)).into(),
+            ));
+        }
+    }
+
+    let mut summary = json!({ "universeId": universe_id });
+    let mut errors = Vec::new();
+    let sections = [
+        ("details", details.map(|mut details| details["data"][0].take()), &[
+            ("name", "name"),
+            ("rootPlaceId", "rootPlaceId"),
+            ("playing", "playing"),
+            ("visits", "visits"),
+        ][..]),
+        ("votes", votes.map(|mut votes| votes["data"][0].take()), &[("upVotes", "upVotes"), ("downVotes", "downVotes")][..]),
+        ("favorites", favorites, &[("favorites", "favoritesCount")][..]),
+    ];
+    for (section, result, fields) in sections {
+        let source = result.unwrap_or_else(|err| {
+            errors.push(json!({ "section": section, "error": format!("{:#}", err) }));
+            Value::Null
+        });
+        for (field, upstream_field) in fields {
+            summary[*field] = source[*upstream_field].clone();
+        }
+    }
+    summary["errors"] = json!(errors);
+    Ok(Json(summary))
+}
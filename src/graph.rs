@@ -0,0 +1,306 @@
+use crate::{
+    cache::CacheKey, client_cert, credentials::CredentialPool, csv_export, projection::Projection, tenants::Tenant, AppState,
+    ErrorResponse, MyRequestGuard, ProxyResponse, Rejection, UpstreamRequest,
+};
+use anyhow::{anyhow, Context as _, Result};
+use rocket::{
+    futures::future::join_all,
+    http::{Method, Status},
+    serde::{
+        json::{self, json, Json, Value},
+        Deserialize,
+    },
+    Either, State,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+const MAX_USERS: usize = 100;
+
+/// Milliseconds the client is willing to wait. Whatever hasn't finished by
+/// then is reported under `timedOut` instead of failing the whole query.
+const DEADLINE_HEADER: &str = "X-Deadline-Ms";
+
+/// Fields to return from each section. Sections left out aren't fetched; an
+/// empty list returns the whole object. Dotted names reach into nested
+/// objects, e.g. `group.name`.
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct Fields {
+    user: Option<Vec<String>>,
+    presence: Option<Vec<String>>,
+    avatar: Option<Vec<String>>,
+    groups: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "camelCase")]
+pub struct GraphQuery {
+    user_ids: Vec<u64>,
+    fields: Fields,
+}
+
+/// Answers one query that would otherwise take a user, presence, avatar and
+/// groups call per user, batching where Roblox allows it and serving repeat
+/// lookups from the response cache. A failing section is reported under
+/// `errors` without failing the rest, and with `X-Deadline-Ms` a slow one is
+/// reported under `timedOut`. With `?_format=csv` the users come back as
+/// CSV instead, and failed or late sections are named in `X-Graph-Errors`
+/// and `X-Graph-Timed-Out`.
+#[post("/graph", data = "<query>")]
+pub async fn graph(
+    query: Json<GraphQuery>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Either<Json<Value>, ProxyResponse>, ErrorResponse> {
+    state.screen_client(guard.request)?;
+    let query_param = |name| guard.request.query_value::<&str>(name).and_then(Result::ok);
+    let csv = csv_export::wants_csv(query_param(csv_export::FORMAT)).map_err(ErrorResponse)?;
+    let api_key = client_cert::api_key(guard.request);
+    let tenant = state.tenants.authenticate(api_key, &state.metrics)?;
+    let GraphQuery { mut user_ids, fields } = query.into_inner();
+    let mut seen = HashSet::new();
+    user_ids.retain(|id| seen.insert(*id));
+    if user_ids.is_empty() || user_ids.len() > MAX_USERS {
+        return Err(ErrorResponse(
+            Rejection::new(Status::BadRequest, format!("userIds must hold 1 to {} IDs", MAX_USERS)).into(),
+        ));
+    }
+    let deadline = match guard.request.headers().get_one(DEADLINE_HEADER) {
+        Some(ms) => Some(ms.parse().map(|ms| Instant::now() + Duration::from_millis(ms)).map_err(|_| {
+            ErrorResponse(
+                Rejection::new(Status::BadRequest, format!("{} must be a number of milliseconds", DEADLINE_HEADER))
+                    .into(),
+            )
+        })?),
+        None => None,
+    };
+    state.metrics.incr("roproxy_graph_requests_total", &[]);
+
+    let fetcher = Fetcher {
+        state,
+        tenant: tenant.as_deref(),
+        deadline,
+    };
+    let ids = &user_ids;
+    let (users, presence, avatars, groups) = tokio::join!(
+        section(fields.user.as_ref(), || fetcher.users(ids)),
+        section(fields.presence.as_ref(), || fetcher.presence(ids)),
+        section(fields.avatar.as_ref(), || fetcher.avatars(ids)),
+        section(fields.groups.as_ref(), || fetcher.groups(ids)),
+    );
+
+    let mut errors = Vec::new();
+    let mut timed_out = Vec::new();
+    let mut sections = Vec::new();
+    for (name, selected, result) in [
+        ("user", &fields.user, users),
+        ("presence", &fields.presence, presence),
+        ("avatar", &fields.avatar, avatars),
+        ("groups", &fields.groups, groups),
+    ] {
+        match (selected, result) {
+            (Some(selected), Some(Ok(fetched))) => {
+                if !fetched.timed_out.is_empty() {
+                    state.metrics.incr("roproxy_graph_timeouts_total", &[("section", name)]);
+                    timed_out.push(json!({ "section": name, "userIds": fetched.timed_out }));
+                }
+                let projection = Projection::parse(selected.iter().map(String::as_str));
+                sections.push((name, projection, fetched.by_user));
+            }
+            (_, Some(Err(err))) => errors.push(json!({ "section": name, "error": format!("{:#}", err) })),
+            _ => {}
+        }
+    }
+
+    let users: Vec<Value> = user_ids
+        .iter()
+        .map(|id| {
+            let mut user = json!({ "id": id });
+            for (name, projection, by_user) in &sections {
+                user[*name] = by_user.get(id).map_or(Value::Null, |value| projection.apply(value));
+            }
+            user
+        })
+        .collect();
+
+    if csv {
+        let mut headers = Vec::new();
+        for (header, failed) in [("X-Graph-Errors", &errors), ("X-Graph-Timed-Out", &timed_out)] {
+            if !failed.is_empty() {
+                let sections: Vec<_> = failed.iter().filter_map(|failure| failure["section"].as_str()).collect();
+                headers.push((header.to_string(), sections.join(",")));
+            }
+        }
+        return Ok(Either::Right(ProxyResponse {
+            status: Status::Ok,
+            content_type: csv_export::CONTENT_TYPE.to_string(),
+            body: csv_export::to_csv(&users, query_param(csv_export::COLUMNS)).into_bytes(),
+            headers,
+            stream: None,
+            credential: None,
+        }));
+    }
+    Ok(Either::Left(Json(json!({ "users": users, "errors": errors, "timedOut": timed_out }))))
+}
+
+async fn section<F, Fut>(fields: Option<&Vec<String>>, fetch: F) -> Option<Result<Fetched>>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<Fetched>>,
+{
+    match fields {
+        Some(_) => Some(fetch().await),
+        None => None,
+    }
+}
+
+/// One section's results, keyed by user.
+#[derive(Default)]
+struct Fetched {
+    by_user: HashMap<u64, Value>,
+    /// Users whose part didn't finish before the deadline.
+    timed_out: Vec<u64>,
+}
+
+impl Fetched {
+    fn from_items(items: Vec<(u64, Option<Result<Value>>)>) -> Result<Self> {
+        let mut fetched = Fetched::default();
+        for (id, item) in items {
+            match item {
+                Some(item) => {
+                    fetched.by_user.insert(id, item?);
+                }
+                None => fetched.timed_out.push(id),
+            }
+        }
+        Ok(fetched)
+    }
+
+    fn timed_out(ids: &[u64]) -> Self {
+        Fetched {
+            by_user: HashMap::new(),
+            timed_out: ids.to_vec(),
+        }
+    }
+}
+
+struct Fetcher<'a> {
+    state: &'a AppState,
+    tenant: Option<&'a Tenant>,
+    deadline: Option<Instant>,
+}
+
+impl Fetcher<'_> {
+    // `None` if the deadline passed first.
+    async fn before_deadline<T>(&self, work: impl Future<Output = T>) -> Option<T> {
+        match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, work).await.ok(),
+            None => Some(work.await),
+        }
+    }
+
+    async fn users(&self, ids: &[u64]) -> Result<Fetched> {
+        let users = join_all(ids.iter().map(|id| async move {
+            let url = format!("https://users.roblox.com/v1/users/{}", id);
+            (*id, self.before_deadline(self.get(&url)).await)
+        }))
+        .await;
+        Fetched::from_items(users)
+    }
+
+    async fn presence(&self, ids: &[u64]) -> Result<Fetched> {
+        let body = json!({ "userIds": ids }).to_string().into_bytes();
+        let request = UpstreamRequest {
+            method: Method::Post,
+            url: "https://presence.roblox.com/v1/presence/users".to_string(),
+            headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+            body: Some(body.into()),
+            credential: None,
+            identity: None,
+            timeout: None,
+        };
+        let Some(response) = self.before_deadline(self.send(request)).await else {
+            return Ok(Fetched::timed_out(ids));
+        };
+        Ok(Fetched {
+            by_user: by_user(&response?["userPresences"], "userId"),
+            timed_out: Vec::new(),
+        })
+    }
+
+    async fn avatars(&self, ids: &[u64]) -> Result<Fetched> {
+        let joined: Vec<_> = ids.iter().map(u64::to_string).collect();
+        let url = format!(
+            "https://thumbnails.roblox.com/v1/users/avatar-headshot?format=Png&size=150x150&userIds={}",
+            joined.join(",")
+        );
+        let Some(response) = self.before_deadline(self.get(&url)).await else {
+            return Ok(Fetched::timed_out(ids));
+        };
+        Ok(Fetched {
+            by_user: by_user(&response?["data"], "targetId"),
+            timed_out: Vec::new(),
+        })
+    }
+
+    async fn groups(&self, ids: &[u64]) -> Result<Fetched> {
+        let groups = join_all(ids.iter().map(|id| async move {
+            let url = format!("https://groups.roblox.com/v2/users/{}/groups/roles", id);
+            let groups = self
+                .before_deadline(self.get(&url))
+                .await
+                .map(|groups| groups.map(|mut groups| groups["data"].take()));
+            (*id, groups)
+        }))
+        .await;
+        Fetched::from_items(groups)
+    }
+
+    async fn get(&self, url: &str) -> Result<Value> {
+        let credential = self.pool().pick();
+        let key = CacheKey::new(self.tenant.map(|tenant| tenant.name.as_str()), url)
+            .with_account(credential.as_ref().map(|credential| credential.name.as_str()));
+        if let Some(response) = self.state.engine.cache.get(&key, None) {
+            self.state.metrics.incr("roproxy_cache_requests_total", &[("result", "hit")]);
+            return json::from_slice(&response.body).context("Cached response isn't JSON");
+        }
+        self.state.metrics.incr("roproxy_cache_requests_total", &[("result", "miss")]);
+        let response = self.fetch(UpstreamRequest::get(url).with_credential(credential)).await?;
+        self.state.engine.cache.insert(&key, &response, None);
+        json::from_slice(&response.body).with_context(|| format!("{} didn't return JSON", url))
+    }
+
+    async fn send(&self, request: UpstreamRequest) -> Result<Value> {
+        let url = request.url.clone();
+        let response = self.fetch(request.with_credentials(self.pool())).await?;
+        json::from_slice(&response.body).with_context(|| format!("{} didn't return JSON", url))
+    }
+
+    fn pool(&self) -> &CredentialPool {
+        self.tenant.map_or(&self.state.credentials, |tenant| &tenant.credentials)
+    }
+
+    async fn fetch(&self, request: UpstreamRequest) -> Result<crate::ProxyResponse> {
+        let url = request.url.clone();
+        let response = self.state.engine.forward(request).await?;
+        if response.status.class() != rocket::http::StatusClass::Success {
+            return Err(anyhow!("{} returned {}", url, response.status.code));
+        }
+        Ok(response)
+    }
+}
+
+fn by_user(items: &Value, id_field: &str) -> HashMap<u64, Value> {
+    items
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| Some((item[id_field].as_u64()?, item.clone())))
+        .collect()
+}
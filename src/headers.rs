@@ -0,0 +1,188 @@
+use crate::{config::ResponseHeadersConfig, Rejection};
+use anyhow::Result;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use rocket::http::Status;
+
+/// Folds every `Cookie` header into one, where the first one was, keeping the
+/// cookies in the order they were sent. HTTP/1.1 only allows one `Cookie`
+/// header, and Roblox reads just the first of several.
+pub fn merge_cookies(headers: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut merged: Vec<(String, String)> = Vec::with_capacity(headers.len());
+    let mut cookie_index = None;
+    for (name, value) in headers {
+        if !name.eq_ignore_ascii_case("cookie") {
+            merged.push((name, value));
+            continue;
+        }
+        let value = value.trim().trim_end_matches(';').to_string();
+        match cookie_index {
+            Some(index) => {
+                let (_, cookies): &mut (String, String) = &mut merged[index];
+                cookies.push_str("; ");
+                cookies.push_str(&value);
+            }
+            None => {
+                cookie_index = Some(merged.len());
+                merged.push((name, value));
+            }
+        }
+    }
+    merged
+}
+
+/// Builds the upstream request's headers. `defaults` are only sent when the
+/// client didn't send that header itself. Repeated headers are all kept, in
+/// the order they came in; names go out in the order they were first seen.
+pub fn upstream_headers(defaults: &[(HeaderName, HeaderValue)], headers: Vec<(String, String)>) -> Result<HeaderMap> {
+    let headers = merge_cookies(headers);
+    let mut map = HeaderMap::new();
+    for (name, value) in defaults {
+        if !headers.iter().any(|(header, _)| header.eq_ignore_ascii_case(name.as_str())) {
+            map.append(name.clone(), value.clone());
+        }
+    }
+    for (name, value) in headers {
+        let invalid = || Rejection::new(Status::BadRequest, format!("Invalid {} header", name));
+        let header_name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid())?;
+        let header_value = HeaderValue::from_str(&value).map_err(|_| invalid())?;
+        map.append(header_name, header_value);
+    }
+    Ok(map)
+}
+
+/// Decides which upstream response headers are forwarded to clients.
+pub struct ResponseHeaderFilter {
+    /// `None` outside strict mode, when everything is forwarded.
+    allowed: Option<Vec<String>>,
+}
+
+impl ResponseHeaderFilter {
+    pub fn new(config: &ResponseHeadersConfig) -> Self {
+        ResponseHeaderFilter {
+            allowed: config
+                .strict
+                .then(|| config.allowed.iter().map(|name| name.to_lowercase()).collect()),
+        }
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        let Some(allowed) = &self.allowed else {
+            return true;
+        };
+        let name = name.to_lowercase();
+        allowed.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == *pattern,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProxyResponse;
+    use rocket::{local::blocking::Client, response::Responder};
+
+    fn pairs(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn cookies_merge_in_place_and_in_order() {
+        let merged = merge_cookies(pairs(&[
+            ("Accept", "*/*"),
+            ("Cookie", "a=1"),
+            ("X-Custom", "x"),
+            ("cookie", "b=2; c=3;"),
+        ]));
+        assert_eq!(merged, pairs(&[("Accept", "*/*"), ("Cookie", "a=1; b=2; c=3"), ("X-Custom", "x")]));
+    }
+
+    #[test]
+    fn single_cookie_is_untouched() {
+        let headers = pairs(&[("Cookie", "a=1; b=2")]);
+        assert_eq!(merge_cookies(headers.clone()), headers);
+    }
+
+    #[test]
+    fn repeated_headers_keep_every_value_in_order() {
+        let map = upstream_headers(
+            &[],
+            pairs(&[("X-Tag", "first"), ("Accept", "*/*"), ("x-tag", "second"), ("X-Tag", "third")]),
+        )
+        .unwrap();
+        let tags: Vec<_> = map.get_all("x-tag").iter().map(|value| value.to_str().unwrap()).collect();
+        assert_eq!(tags, ["first", "second", "third"]);
+        let names: Vec<_> = map.keys().map(|name| name.as_str()).collect();
+        assert_eq!(names, ["x-tag", "accept"]);
+    }
+
+    #[test]
+    fn client_headers_replace_defaults() {
+        let map = upstream_headers(
+            &[
+                (HeaderName::from_static("accept"), HeaderValue::from_static("application/json")),
+                (HeaderName::from_static("referer"), HeaderValue::from_static("https://www.roblox.com")),
+            ],
+            pairs(&[("Accept", "text/event-stream")]),
+        )
+        .unwrap();
+        let accept: Vec<_> = map.get_all("accept").iter().collect();
+        assert_eq!(accept, ["text/event-stream"]);
+        assert_eq!(map["referer"], "https://www.roblox.com");
+    }
+
+    #[test]
+    fn cookies_go_upstream_as_one_header() {
+        let map = upstream_headers(&[], pairs(&[("Cookie", "a=1"), ("Cookie", "b=2")])).unwrap();
+        let cookies: Vec<_> = map.get_all("cookie").iter().collect();
+        assert_eq!(cookies, ["a=1; b=2"]);
+    }
+
+    #[test]
+    fn invalid_header_is_rejected() {
+        let err = upstream_headers(&[], pairs(&[("X-Bad", "line\nbreak")])).unwrap_err();
+        assert!(err.downcast_ref::<Rejection>().is_some());
+    }
+
+    #[test]
+    fn repeated_response_headers_reach_the_client() {
+        let response = ProxyResponse {
+            status: Status::Ok,
+            content_type: "application/json".to_string(),
+            body: b"{}".to_vec(),
+            headers: pairs(&[
+                ("Set-Cookie", "a=1; Path=/"),
+                ("Vary", "Accept"),
+                ("Set-Cookie", "b=2; Path=/"),
+            ]),
+            stream: None,
+            credential: None,
+        };
+        let client = Client::debug_with(Vec::new()).unwrap();
+        let request = client.get("/");
+        let response = response.respond_to(request.inner()).unwrap();
+        let cookies: Vec<_> = response.headers().get("Set-Cookie").collect();
+        assert_eq!(cookies, ["a=1; Path=/", "b=2; Path=/"]);
+    }
+
+    #[test]
+    fn strict_mode_forwards_only_allowed_headers() {
+        let filter = ResponseHeaderFilter::new(&ResponseHeadersConfig::default());
+        for name in ["Content-Type", "ETag", "x-csrf-token", "X-RateLimit-Remaining", "rblx-challenge-id"] {
+            assert!(filter.allows(name), "{}", name);
+        }
+        for name in ["cf-ray", "Server", "roblox-machine-id", "report-to", "x-ratelimit"] {
+            assert!(!filter.allows(name), "{}", name);
+        }
+
+        let open = ResponseHeaderFilter::new(&ResponseHeadersConfig {
+            strict: false,
+            ..ResponseHeadersConfig::default()
+        });
+        assert!(open.allows("cf-ray"));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{
+    config::CredentialsConfig,
+    credentials::{Credentials, PooledCredential},
+    AppState, UpstreamRequest,
+};
+use anyhow::{anyhow, Result};
+use rocket::{
+    http::Method,
+    serde::json::{json, Value},
+};
+use std::{sync::Arc, time::Duration};
+use tracing::{debug, info, warn};
+
+const AUTHENTICATED_URL: &str = "https://users.roblox.com/v1/users/authenticated";
+const INTROSPECT_URL: &str = "https://apis.roblox.com/api-keys/v1/introspect";
+
+/// Periodically checks every pooled credential against Roblox, taking expired
+/// or revoked ones out of rotation and putting recovered ones back.
+pub fn spawn(state: Arc<AppState>, config: &CredentialsConfig) {
+    if config.health_check_secs == 0 {
+        return;
+    }
+    let period = Duration::from_secs(config.health_check_secs);
+    let webhook = config.alert_webhook.clone();
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let pools = std::iter::once(("default".to_string(), &state.credentials)).chain(
+                state
+                    .tenants
+                    .iter()
+                    .map(|tenant| (tenant.name.clone(), &tenant.credentials)),
+            );
+            for (pool, credentials) in pools {
+                for credential in credentials.members() {
+                    check_credential(&state, &pool, &credential, webhook.as_deref()).await;
+                }
+                let healthy = credentials.members().iter().filter(|c| c.is_healthy()).count();
+                state
+                    .metrics
+                    .set_gauge("roproxy_credentials_healthy", &[("pool", &pool)], healthy as i64);
+            }
+        }
+    });
+}
+
+async fn check_credential(
+    state: &AppState,
+    pool: &str,
+    credential: &PooledCredential,
+    webhook: Option<&str>,
+) {
+    let result = match validate(state, &credential.credentials).await {
+        Ok(None) => {
+            debug!("Credential {} in pool {} is valid", credential.name, pool);
+            credential.mark_healthy();
+            "valid"
+        }
+        Ok(Some(reason)) => {
+            if credential.mark_unhealthy(&reason) {
+                if let Some(webhook) = webhook {
+                    alert(state, webhook, pool, &credential.name, &reason).await;
+                }
+            }
+            "invalid"
+        }
+        // Roblox being down says nothing about the credential, so leave its
+        // health as it was.
+        Err(err) => {
+            warn!("Couldn't check credential {}: {:?}", credential.name, err);
+            "error"
+        }
+    };
+    state.metrics.incr(
+        "roproxy_credential_checks_total",
+        &[("credential", &credential.name), ("result", result)],
+    );
+}
+
+// Ok(Some(reason)) when Roblox rejected the credential, Err when the check
+// itself couldn't be completed.
+async fn validate(state: &AppState, credentials: &Credentials) -> Result<Option<String>> {
+    if let Some(cookie) = &credentials.roblosecurity {
+        let mut request = UpstreamRequest::get(AUTHENTICATED_URL);
+        Credentials {
+            roblosecurity: Some(cookie.clone()),
+            open_cloud_key: None,
+        }
+        .apply(&mut request.headers);
+        let response = state.engine.forward(request).await?;
+        match response.status.code {
+            200 => {}
+            401 | 403 => return Ok(Some(format!(".ROBLOSECURITY rejected with {}", response.status.code))),
+            code => return Err(anyhow!("Authenticated user check returned {}", code)),
+        }
+    }
+
+    if let Some(key) = &credentials.open_cloud_key {
+        let response = state
+            .engine
+            .forward(UpstreamRequest {
+                method: Method::Post,
+                url: INTROSPECT_URL.to_string(),
+                headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+                body: Some(json!({ "apiKey": key }).to_string().into()),
+                credential: None,
+                identity: None,
+                timeout: None,
+            })
+            .await?;
+        match response.status.code {
+            200 => {
+                let info: Value = rocket::serde::json::from_slice(&response.body)?;
+                if info["expired"].as_bool() == Some(true) {
+                    return Ok(Some("Open Cloud key expired".to_string()));
+                }
+                if info["enabled"].as_bool() == Some(false) {
+                    return Ok(Some("Open Cloud key disabled".to_string()));
+                }
+            }
+            400 | 401 | 403 => return Ok(Some(format!("Open Cloud key rejected with {}", response.status.code))),
+            code => return Err(anyhow!("Open Cloud key introspection returned {}", code)),
+        }
+    }
+
+    Ok(None)
+}
+
+async fn alert(state: &AppState, webhook: &str, pool: &str, credential: &str, reason: &str) {
+    // `content` makes the payload readable as-is by Discord-style webhooks.
+    let payload = json!({
+        "content": format!("Roblox credential {} ({}) is unhealthy: {}", credential, pool, reason),
+        "pool": pool,
+        "credential": credential,
+        "reason": reason,
+    });
+    match state.engine.client.post(webhook).json(&payload).send().await {
+        Ok(response) if response.status().is_success() => {
+            info!("Sent unhealthy credential alert for {}", credential);
+            state.metrics.incr("roproxy_credential_alerts_total", &[]);
+        }
+        Ok(response) => warn!("Credential alert webhook returned {}", response.status()),
+        Err(err) => warn!("Failed to send credential alert: {:?}", err),
+    }
+}
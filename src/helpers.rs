@@ -0,0 +1,426 @@
+use crate::{
+    cache::CacheKey,
+    challenge, client_cert,
+    credentials::{self, CredentialPool, PooledCredential},
+    tags,
+    tenants::{ApiKey, Tenant},
+    trace,
+    AppState, ProxyResponse, Rejection, UpstreamRequest,
+};
+use anyhow::{anyhow, Context, Result};
+use reqwest::Url;
+use rocket::{
+    futures::{
+        stream::{self, BoxStream},
+        Future, StreamExt,
+    },
+    http::{ContentType, Method, Status, StatusClass},
+    response::{self, stream::ReaderStream, Responder, Response},
+    serde::json::{self, json, Json, Value},
+    Request,
+};
+use std::{fmt, io::Cursor, sync::Arc, time::Duration};
+
+/// A response Roblox refused or failed, with the first error message it
+/// gave, which says more than the status does.
+#[derive(Debug)]
+pub struct UpstreamError {
+    pub url: String,
+    pub status: Status,
+    pub message: Option<String>,
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} returned {}", self.url, self.status.code)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+/// The response's JSON, or an `UpstreamError` if it wasn't a success.
+pub fn check(url: &str, response: &ProxyResponse) -> Result<Value> {
+    let body = response.json();
+    if response.status.class() != StatusClass::Success {
+        // Open Cloud puts its message at the top level.
+        let message = json::from_slice::<Value>(&response.body).ok().and_then(|body| {
+            body["errors"][0]["message"]
+                .as_str()
+                .or(body["message"].as_str())
+                .map(str::to_string)
+        });
+        return Err(UpstreamError {
+            url: url.to_string(),
+            status: response.status,
+            message,
+        }
+        .into());
+    }
+    body.ok_or_else(|| anyhow!("{} didn't return JSON", url))
+}
+
+/// Passes a client error from Roblox on as it is; anything else is a 502.
+pub fn rejection(err: anyhow::Error) -> anyhow::Error {
+    match err.downcast_ref::<UpstreamError>() {
+        Some(upstream) if upstream.status.class() == StatusClass::ClientError => Rejection::new(
+            upstream.status,
+            upstream.message.clone().unwrap_or_else(|| upstream.to_string()),
+        )
+        .into(),
+        _ if err.is::<Rejection>() => err,
+        _ => Rejection::new(Status::BadGateway, format!("{:#}", err)).into(),
+    }
+}
+
+// Hands Roblox's challenge to the caller with the headers it's solved with.
+fn challenge_rejection(response: &ProxyResponse) -> anyhow::Error {
+    let mut rejection = Rejection::new(
+        response.status,
+        "Roblox wants a challenge solved first: submit it to /challenge/continue, then retry with the rblx-challenge-* headers that returns",
+    );
+    for (name, value) in &response.headers {
+        if name.to_lowercase().starts_with("rblx-challenge-") {
+            rejection = rejection.with_header(name.clone(), value);
+        }
+    }
+    rejection.into()
+}
+
+/// `url` asking for the page after `cursor`, Roblox's `nextPageCursor`.
+pub fn page_url(url: &str, cursor: Option<&str>) -> Result<String> {
+    let mut url = Url::parse(url).with_context(|| format!("Invalid URL {}", url))?;
+    if let Some(cursor) = cursor {
+        url.query_pairs_mut().append_pair("cursor", cursor);
+    }
+    Ok(url.into())
+}
+
+/// One page of a paged list. `complete` is set on the last page fetched:
+/// whether it ended the list, or `helpers.max_pages` cut it short.
+pub struct Page {
+    pub items: Vec<Value>,
+    pub complete: Option<bool>,
+}
+
+/// Walks a paged list up to `max_pages`. `fetch` gets the page number, from
+/// 1, and the cursor the page before gave, and returns the page's items and
+/// the cursor to the next page, or `None` on the last one. Lists paged by
+/// number can return any cursor.
+pub fn paginate<'a, F, Fut>(max_pages: usize, mut fetch: F) -> BoxStream<'a, Result<Page>>
+where
+    F: FnMut(usize, Option<String>) -> Fut + Send + 'a,
+    Fut: Future<Output = Result<(Vec<Value>, Option<String>)>> + Send + 'a,
+{
+    stream::unfold(Some((1, None)), move |next| {
+        let fetched = next.map(|(number, cursor)| (number, (number <= max_pages).then(|| fetch(number, cursor))));
+        async move {
+            let (number, fetched) = fetched?;
+            let Some(fetched) = fetched else {
+                let page = Page {
+                    items: Vec::new(),
+                    complete: Some(false),
+                };
+                return Some((Ok(page), None));
+            };
+            Some(match fetched.await {
+                Ok((items, Some(cursor))) => (Ok(Page { items, complete: None }), Some((number + 1, Some(cursor)))),
+                Ok((items, None)) => (
+                    Ok(Page {
+                        items,
+                        complete: Some(true),
+                    }),
+                    None,
+                ),
+                Err(err) => (Err(err), None),
+            })
+        }
+    })
+    .boxed()
+}
+
+/// Every item of a paged list, and whether that was all of them.
+pub async fn collect(mut pages: BoxStream<'_, Result<Page>>) -> Result<(Vec<Value>, bool)> {
+    let mut items = Vec::new();
+    while let Some(page) = pages.next().await {
+        let page = page?;
+        items.extend(page.items);
+        if let Some(complete) = page.complete {
+            return Ok((items, complete));
+        }
+    }
+    Ok((items, false))
+}
+
+/// Whether the caller asked for a paged helper's items as NDJSON.
+pub fn wants_ndjson(req: &Request<'_>) -> bool {
+    req.accept().is_some_and(|accept| {
+        accept
+            .media_types()
+            .any(|media| media.top() == "application" && media.sub() == "x-ndjson")
+    })
+}
+
+/// A paged helper's answer: its JSON document, or with
+/// `Accept: application/x-ndjson` its items one per line as their pages
+/// arrive, then the document without them.
+pub enum Paged<'r> {
+    Json(Value),
+    Lines(BoxStream<'r, Value>),
+}
+
+impl<'r> Responder<'r, 'r> for Paged<'r> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'r> {
+        match self {
+            Paged::Json(document) => Json(document).respond_to(req),
+            Paged::Lines(lines) => Response::build()
+                .header(ContentType::new("application", "x-ndjson"))
+                .streamed_body(ReaderStream::from(lines.map(|line| Cursor::new(format!("{}\n", line)))))
+                .ok(),
+        }
+    }
+}
+
+/// Answers with `document`, holding the list's items under `field` and
+/// whether it was `truncated`, or streams it as `Paged::Lines`. Streamed,
+/// a page that fails after the first is reported under `error` in the last
+/// line; the first is fetched before answering, so a list that can't be
+/// read at all is still an error status.
+pub async fn paged<'r>(
+    req: &Request<'_>,
+    mut pages: BoxStream<'r, Result<Page>>,
+    field: &str,
+    mut document: Value,
+) -> Result<Paged<'r>> {
+    if !wants_ndjson(req) {
+        let (items, complete) = collect(pages).await?;
+        document[field] = Value::Array(items);
+        document["truncated"] = json!(!complete);
+        return Ok(Paged::Json(document));
+    }
+    let first = pages.next().await.transpose()?;
+    let pages = stream::iter(first.map(Ok)).chain(pages).boxed();
+    let lines = stream::unfold(Some((pages, document)), |state| async move {
+        let (mut pages, mut document) = state?;
+        let items = match pages.next().await {
+            Some(Ok(Page { items, complete: None })) => return Some((items, Some((pages, document)))),
+            Some(Ok(Page {
+                items,
+                complete: Some(complete),
+            })) => {
+                document["truncated"] = json!(!complete);
+                items
+            }
+            Some(Err(err)) => {
+                document["error"] = json!(format!("{:#}", err));
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        Some((items.into_iter().chain([document]).collect::<Vec<_>>(), None))
+    })
+    .flat_map(stream::iter)
+    .boxed();
+    Ok(Paged::Lines(lines))
+}
+
+/// Fetches `url` as an account from `pool`, failing on anything but a
+/// successful JSON response.
+pub async fn get_json(state: &AppState, pool: &CredentialPool, url: &str) -> Result<Value> {
+    let response = state
+        .engine
+        .forward(UpstreamRequest::get(url).with_credentials(pool))
+        .await?;
+    check(url, &response)
+}
+
+/// Fetches for one `/helpers` request, with the caller's tenant deciding the
+/// credentials and cache namespace, as for proxied requests.
+#[derive(Clone)]
+pub struct Upstream<'a> {
+    state: &'a AppState,
+    tenant: Option<Arc<Tenant>>,
+    api_key: Option<String>,
+    affinity: Option<String>,
+    /// The `rblx-challenge-*` headers of a retry answering a challenge.
+    challenge: Vec<(String, String)>,
+    trace: Option<String>,
+}
+
+impl<'a> Upstream<'a> {
+    pub fn authenticate(state: &'a AppState, req: &Request<'_>) -> Result<Self> {
+        state.screen_client(req)?;
+        let api_key = client_cert::api_key(req);
+        let tenant = state.tenants.authenticate(api_key, &state.metrics)?;
+        let tag = tags::tag(req)?;
+        if let Some(key) = tenant.as_ref().zip(api_key).and_then(|(tenant, api_key)| tenant.key(api_key)) {
+            key.check_tag(tag, &state.metrics)?;
+        }
+        let challenge = [challenge::ID_HEADER, challenge::TYPE_HEADER, challenge::METADATA_HEADER]
+            .into_iter()
+            .filter_map(|name| Some((name.to_string(), req.headers().get_one(name)?.to_string())))
+            .collect();
+        Ok(Upstream {
+            state,
+            tenant,
+            api_key: api_key.map(str::to_string),
+            affinity: credentials::affinity(req),
+            challenge,
+            trace: trace::id(req).map(str::to_string),
+        })
+    }
+
+    /// The proxy key the caller presented, if tenants are configured.
+    pub fn key(&self) -> Option<&ApiKey> {
+        self.tenant.as_ref()?.key(self.api_key.as_deref()?)
+    }
+
+    pub fn max_pages(&self) -> usize {
+        self.state.helpers.max_pages
+    }
+
+    fn pool(&self) -> &CredentialPool {
+        self.tenant
+            .as_ref()
+            .map_or(&self.state.credentials, |tenant| &tenant.credentials)
+    }
+
+    fn pool_name(&self) -> &str {
+        self.tenant.as_ref().map_or("default", |tenant| tenant.name.as_str())
+    }
+
+    /// `url`'s JSON, served from the response cache if it's been fetched in
+    /// the last `ttl`.
+    pub async fn get(&self, url: &str, ttl: Duration) -> Result<Value> {
+        let credential = self.pool().pick_for(self.affinity.as_deref());
+        let key = CacheKey::new(self.tenant.as_ref().map(|tenant| tenant.name.as_str()), url)
+            .with_account(credential.as_ref().map(|credential| credential.name.as_str()));
+        if let Some(response) = self.state.engine.cache.get(&key, Some(ttl)) {
+            self.state.metrics.incr("roproxy_cache_requests_total", &[("result", "hit")]);
+            self.trace(|| format!("Cache hit for {}", url)).await;
+            return json::from_slice(&response.body).context("Cached response isn't JSON");
+        }
+        self.state.metrics.incr("roproxy_cache_requests_total", &[("result", "miss")]);
+        self.trace(|| format!("Cache miss for {}", url)).await;
+        let (body, response) = self.fetch(url, credential).await?;
+        self.state.engine.cache.insert(&key, &response, Some(ttl));
+        Ok(body)
+    }
+
+    async fn trace(&self, message: impl FnOnce() -> String) {
+        trace::scope(self.trace.as_deref(), async { trace::event(message) }).await
+    }
+
+    /// `url`'s JSON, fetched without the cache, for answers that are about
+    /// to change.
+    pub async fn get_fresh(&self, url: &str) -> Result<Value> {
+        let credential = self.pool().pick_for(self.affinity.as_deref());
+        Ok(self.fetch(url, credential).await?.0)
+    }
+
+    async fn fetch(&self, url: &str, credential: Option<Arc<PooledCredential>>) -> Result<(Value, ProxyResponse)> {
+        let request = UpstreamRequest::get(url).with_credential(credential);
+        let response = trace::scope(self.trace.as_deref(), self.state.engine.forward(request)).await?;
+        Ok((check(url, &response)?, response))
+    }
+
+    pub async fn post(&self, url: &str, body: &Value) -> Result<Value> {
+        self.send(Method::Post, url, body).await
+    }
+
+    pub async fn patch(&self, url: &str, body: &Value) -> Result<Value> {
+        self.send(Method::Patch, url, body).await
+    }
+
+    // Sends `body` as JSON, answering Roblox's CSRF challenge: a 403 with an
+    // `x-csrf-token` header is retried once with that token, as the same
+    // account since the token is tied to it. The token is kept in the shared
+    // state for the next write as that account, from any replica. A challenge (2FA, captcha) is
+    // passed back to the caller to solve through `/challenge/continue`, and
+    // the retry carrying its headers goes out as the account it was issued
+    // to.
+    async fn send(&self, method: Method, url: &str, body: &Value) -> Result<Value> {
+        let credential = challenge::challenge_id(&self.challenge)
+            .and_then(|id| self.state.challenges.credential(self.pool_name(), self.pool(), id))
+            .or_else(|| self.pool().pick_for(self.affinity.as_deref()));
+        let csrf_key = credential
+            .as_ref()
+            .map(|credential| format!("csrf:{}:{}", self.pool_name(), credential.name));
+        let shared = &self.state.engine.shared;
+        let mut token = match &csrf_key {
+            Some(key) => shared.get(key).await,
+            None => None,
+        };
+        let mut refreshed = false;
+        loop {
+            let mut headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+            headers.extend(self.challenge.iter().cloned());
+            if let Some(token) = &token {
+                headers.push(("x-csrf-token".to_string(), token.clone()));
+            }
+            let request = UpstreamRequest {
+                method,
+                url: url.to_string(),
+                headers,
+                body: Some(body.to_string().into_bytes().into()),
+                credential: None,
+                identity: None,
+                timeout: None,
+            };
+            let response = trace::scope(
+                self.trace.as_deref(),
+                self.state.engine.forward(request.with_credential(credential.clone())),
+            )
+            .await?;
+            if challenge::is_challenge(&response) {
+                self.state
+                    .challenges
+                    .observe(self.pool_name(), &response, &self.state.metrics);
+                return Err(challenge_rejection(&response));
+            }
+            let challenge = response
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("x-csrf-token"))
+                .map(|(_, value)| value.clone());
+            match challenge {
+                Some(challenge) if response.status == Status::Forbidden && !refreshed => {
+                    if let Some(key) = &csrf_key {
+                        shared.set(key, &challenge, self.state.csrf_ttl).await;
+                    }
+                    token = Some(challenge);
+                    refreshed = true;
+                }
+                _ => return check(url, &response),
+            }
+        }
+    }
+
+    /// The `data` of every page of `url`, up to `helpers.max_pages`, and
+    /// whether that was all of them.
+    pub async fn pages(&self, url: &str, ttl: Duration) -> Result<(Vec<Value>, bool)> {
+        collect(self.page_stream(url, ttl)).await
+    }
+
+    /// `pages`, one page at a time as they arrive.
+    pub fn page_stream(&self, url: &str, ttl: Duration) -> BoxStream<'a, Result<Page>> {
+        let upstream = self.clone();
+        let url = url.to_string();
+        paginate(self.max_pages(), move |_, cursor| {
+            let upstream = upstream.clone();
+            let url = page_url(&url, cursor.as_deref());
+            async move {
+                let page = upstream.get(&url?, ttl).await?;
+                let items = page["data"].as_array().cloned().unwrap_or_default();
+                let cursor = page["nextPageCursor"]
+                    .as_str()
+                    .filter(|next| !next.is_empty())
+                    .map(str::to_string);
+                Ok((items, cursor))
+            }
+        })
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{config::HostMethodsConfig, metrics::Metrics, tenants::host_matches, Rejection};
+use anyhow::Result;
+use rocket::http::{Method, Status};
+
+struct Rule {
+    name: String,
+    hosts: Vec<String>,
+    methods: Vec<String>,
+}
+
+/// Limits the methods each upstream host may be sent, so a leaked proxy key
+/// can't write to hosts the deployment only reads from. The first rule
+/// matching the host wins; hosts no rule matches take any method.
+pub struct HostMethods {
+    rules: Vec<Rule>,
+}
+
+impl HostMethods {
+    pub fn new(config: &HostMethodsConfig) -> Self {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| Rule {
+                name: rule.name.clone(),
+                hosts: rule.hosts.iter().map(|host| host.to_lowercase()).collect(),
+                methods: rule.methods.iter().map(|method| method.to_uppercase()).collect(),
+            })
+            .collect();
+        HostMethods { rules }
+    }
+
+    pub fn check(&self, method: Method, url: &str, metrics: &Metrics) -> Result<()> {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.hosts.iter().any(|pattern| host_matches(pattern, &host)))
+        else {
+            return Ok(());
+        };
+        if rule.methods.iter().any(|allowed| allowed == method.as_str()) {
+            return Ok(());
+        }
+        metrics.incr(
+            "roproxy_host_method_rejections_total",
+            &[("rule", &rule.name), ("method", method.as_str())],
+        );
+        Err(Rejection::new(
+            Status::MethodNotAllowed,
+            format!("{} is not allowed against {}", method, host),
+        )
+        .with_header("Allow", rule.methods.join(", "))
+        .with_field("allowed", rule.methods.clone())
+        .into())
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{challenge, config::IdempotencyConfig, ProxyResponse};
+use rocket::http::Method;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Each entry keeps the fingerprint of the request that claimed it, so a key
+/// reused for a different request is caught rather than replayed.
+enum Entry {
+    InProgress {
+        fingerprint: String,
+    },
+    Done {
+        response: ProxyResponse,
+        stored_at: Instant,
+        fingerprint: String,
+    },
+}
+
+type Entries = Arc<Mutex<HashMap<String, Entry>>>;
+
+/// Remembers responses to POST/PUT requests carrying an `Idempotency-Key` so a
+/// client retrying after a timeout gets the original result instead of
+/// performing the write twice.
+pub struct IdempotencyStore {
+    entries: Entries,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+pub enum Claim {
+    /// First time this key is seen; the caller must forward the request.
+    Owned(IdempotencyGuard),
+    /// A stored response exists for this key.
+    Replay(ProxyResponse),
+    /// Another request with this key hasn't finished yet.
+    InProgress,
+    /// The key was already used for a request with a different query or
+    /// body.
+    Mismatch,
+}
+
+/// What a request is checked against when its key is seen again: the target
+/// with its query, and the body.
+pub fn fingerprint(target: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(target.as_bytes());
+    hasher.update([0]);
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+impl IdempotencyStore {
+    pub fn new(config: &IdempotencyConfig) -> Self {
+        IdempotencyStore {
+            entries: Arc::default(),
+            ttl: Duration::from_secs(config.ttl_secs),
+            max_entries: config.max_entries,
+        }
+    }
+
+    pub fn claim(&self, method: Method, path: &str, key: &str, fingerprint: String) -> Claim {
+        let key = format!("{} /{} {}", method, path, key);
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(&key) {
+            Some(Entry::InProgress { fingerprint: claimed }) if *claimed != fingerprint => return Claim::Mismatch,
+            Some(Entry::InProgress { .. }) => return Claim::InProgress,
+            Some(Entry::Done {
+                response,
+                stored_at,
+                fingerprint: claimed,
+            }) if stored_at.elapsed() < self.ttl => {
+                if *claimed != fingerprint {
+                    return Claim::Mismatch;
+                }
+                return Claim::Replay(response.clone());
+            }
+            _ => {}
+        }
+
+        entries.insert(key.clone(), Entry::InProgress { fingerprint: fingerprint.clone() });
+        Claim::Owned(IdempotencyGuard {
+            entries: self.entries.clone(),
+            key,
+            fingerprint,
+            ttl: self.ttl,
+            max_entries: self.max_entries,
+            completed: false,
+        })
+    }
+}
+
+/// Marks a key as in progress until the response is stored. Dropping the guard
+/// without completing it (the upstream call failed) releases the key so the
+/// client can retry.
+pub struct IdempotencyGuard {
+    entries: Entries,
+    key: String,
+    fingerprint: String,
+    ttl: Duration,
+    max_entries: usize,
+    completed: bool,
+}
+
+impl IdempotencyGuard {
+    pub fn complete(mut self, response: &ProxyResponse) {
+        self.completed = true;
+        let mut entries = self.entries.lock().unwrap();
+
+        // 5xx responses are usually transient, so let the retry reach Roblox.
+        // So does a challenge, which is answered by repeating the request.
+        // Streams can't be replayed at all.
+        if response.status.code >= 500 || response.stream.is_some() || challenge::is_challenge(response) {
+            entries.remove(&self.key);
+            return;
+        }
+
+        if entries.len() >= self.max_entries {
+            let ttl = self.ttl;
+            entries.retain(|_, entry| match entry {
+                Entry::InProgress { .. } => true,
+                Entry::Done { stored_at, .. } => stored_at.elapsed() < ttl,
+            });
+        }
+        while entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .filter_map(|(key, entry)| match entry {
+                    Entry::Done { stored_at, .. } => Some((key.clone(), *stored_at)),
+                    Entry::InProgress { .. } => None,
+                })
+                .min_by_key(|(_, stored_at)| *stored_at);
+            match oldest {
+                Some((key, _)) => entries.remove(&key),
+                None => break,
+            };
+        }
+
+        entries.insert(
+            self.key.clone(),
+            Entry::Done {
+                response: response.clone(),
+                stored_at: Instant::now(),
+                fingerprint: self.fingerprint.clone(),
+            },
+        );
+    }
+}
+
+impl Drop for IdempotencyGuard {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.entries.lock().unwrap().remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Status;
+
+    #[test]
+    fn reused_key_must_match_the_request() {
+        let store = IdempotencyStore::new(&IdempotencyConfig::default());
+        let first = fingerprint("v1/items?a=1", b"{}");
+        let Claim::Owned(guard) = store.claim(Method::Post, "v1/items", "k", first.clone()) else {
+            panic!("first claim should own the key");
+        };
+        assert!(matches!(
+            store.claim(Method::Post, "v1/items", "k", fingerprint("v1/items?a=2", b"{}")),
+            Claim::Mismatch
+        ));
+        guard.complete(&ProxyResponse {
+            status: Status::Ok,
+            content_type: "application/json".to_string(),
+            body: b"{}".to_vec(),
+            headers: Vec::new(),
+            stream: None,
+            credential: None,
+        });
+        assert!(matches!(store.claim(Method::Post, "v1/items", "k", first), Claim::Replay(_)));
+        assert!(matches!(
+            store.claim(Method::Post, "v1/items", "k", fingerprint("v1/items?a=1", b"{\"x\":1}")),
+            Claim::Mismatch
+        ));
+    }
+}
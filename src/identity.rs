@@ -0,0 +1,95 @@
+use crate::{
+    config::{IdentitiesConfig, IdentityRuleConfig},
+    Rejection,
+};
+use anyhow::{anyhow, Context, Result};
+use reqwest::header::{HeaderName, HeaderValue};
+use rocket::http::Status;
+use std::collections::HashMap;
+
+/// Picks the identity profile for one request, overriding the route's.
+pub const HEADER: &str = "X-Proxy-Identity";
+
+const JSON: (&str, &str) = ("accept", "application/json");
+
+const BUILT_IN: &[(&str, &[(&str, &str)])] = &[
+    (
+        "browser",
+        &[
+            JSON,
+            ("user-agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36"),
+            ("referer", "https://www.roblox.com"),
+            ("origin", "https://www.roblox.com"),
+        ],
+    ),
+    ("studio", &[JSON, ("user-agent", "RobloxStudio/WinInet")]),
+    ("open-cloud", &[JSON, ("user-agent", "rusty-roproxy")]),
+];
+
+pub type Profile = Vec<(HeaderName, HeaderValue)>;
+
+/// Named sets of default headers sent upstream (the client's own headers
+/// still win), so a route can look like a browser, Studio or an Open Cloud
+/// client as the endpoint expects.
+pub struct Identities {
+    profiles: HashMap<String, Profile>,
+    rules: Vec<IdentityRuleConfig>,
+    default: String,
+}
+
+impl Identities {
+    pub fn new(config: &IdentitiesConfig) -> Result<Self> {
+        let mut profiles = HashMap::new();
+        for (name, headers) in BUILT_IN {
+            let headers = headers
+                .iter()
+                .map(|(name, value)| (HeaderName::from_static(name), HeaderValue::from_static(value)))
+                .collect();
+            profiles.insert(name.to_string(), headers);
+        }
+        for profile in &config.profiles {
+            let headers = profile
+                .headers
+                .iter()
+                .map(|(name, value)| {
+                    Ok((
+                        HeaderName::from_bytes(name.as_bytes())?,
+                        HeaderValue::from_str(value)?,
+                    ))
+                })
+                .collect::<Result<_>>()
+                .with_context(|| format!("Invalid header in identity profile {}", profile.name))?;
+            profiles.insert(profile.name.clone(), headers);
+        }
+
+        if !profiles.contains_key(&config.default) {
+            return Err(anyhow!("Unknown default identity profile {}", config.default));
+        }
+        for rule in &config.rules {
+            if !profiles.contains_key(&rule.profile) {
+                return Err(anyhow!("Unknown identity profile {} for rule {}", rule.profile, rule.name));
+            }
+        }
+        Ok(Identities {
+            profiles,
+            rules: config.rules.clone(),
+            default: config.default.clone(),
+        })
+    }
+
+    /// The default headers for a request to `url`, from the profile the
+    /// client asked for or else the first rule matching `url`.
+    pub fn profile(&self, requested: Option<&str>, url: &str) -> Result<&Profile> {
+        let name = match requested {
+            Some(name) => name,
+            None => self
+                .rules
+                .iter()
+                .find(|rule| rule.prefixes.iter().any(|prefix| url.starts_with(prefix)))
+                .map_or(self.default.as_str(), |rule| rule.profile.as_str()),
+        };
+        self.profiles.get(name).ok_or_else(|| {
+            Rejection::new(Status::BadRequest, format!("Unknown identity profile {}", name)).into()
+        })
+    }
+}
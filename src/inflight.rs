@@ -0,0 +1,109 @@
+use rocket::serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+use tokio::sync::Notify;
+
+struct Entry {
+    method: String,
+    url: String,
+    client: String,
+    started: Instant,
+    cancel: Arc<Notify>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct InFlightStatus {
+    id: u64,
+    method: String,
+    url: String,
+    client: String,
+    elapsed_ms: u128,
+}
+
+type Entries = Arc<Mutex<HashMap<u64, Entry>>>;
+
+/// Requests currently waiting on Roblox, so a stuck one can be found and
+/// cancelled without restarting the service.
+#[derive(Default)]
+pub struct InFlight {
+    entries: Entries,
+    next_id: AtomicU64,
+}
+
+impl InFlight {
+    pub fn register(&self, method: &str, url: &str, client: String) -> InFlightGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let cancel = Arc::new(Notify::new());
+        self.entries.lock().unwrap().insert(
+            id,
+            Entry {
+                method: method.to_string(),
+                url: url.to_string(),
+                client,
+                started: Instant::now(),
+                cancel: cancel.clone(),
+            },
+        );
+        InFlightGuard {
+            entries: self.entries.clone(),
+            id,
+            cancel,
+        }
+    }
+
+    pub fn list(&self) -> Vec<InFlightStatus> {
+        let mut list: Vec<_> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, entry)| InFlightStatus {
+                id: *id,
+                method: entry.method.clone(),
+                url: entry.url.clone(),
+                client: entry.client.clone(),
+                elapsed_ms: entry.started.elapsed().as_millis(),
+            })
+            .collect();
+        list.sort_by_key(|status| status.id);
+        list
+    }
+
+    /// Returns false if no request with this ID is in flight.
+    pub fn cancel(&self, id: u64) -> bool {
+        match self.entries.lock().unwrap().get(&id) {
+            Some(entry) => {
+                entry.cancel.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Keeps a request listed until it finishes.
+pub struct InFlightGuard {
+    entries: Entries,
+    id: u64,
+    cancel: Arc<Notify>,
+}
+
+impl InFlightGuard {
+    /// Resolves once an administrator cancels the request.
+    pub async fn cancelled(&self) {
+        self.cancel.notified().await
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.entries.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{metrics::Metrics, Rejection};
+use anyhow::Result;
+use rocket::{http::Status, Request};
+use sha2::{Digest, Sha256};
+
+/// Hex SHA-256 of a body. On responses it covers the body as sent, so a
+/// client relaying a large asset can tell it arrived whole. On requests the
+/// proxy checks it against the body before forwarding anything.
+pub const HEADER: &str = "X-Content-SHA256";
+/// Asks for `X-Content-SHA256` on this response, with `integrity.enabled`
+/// off: `X-Proxy-Integrity: 1`.
+pub const REQUEST_HEADER: &str = "X-Proxy-Integrity";
+
+pub fn digest(body: &[u8]) -> String {
+    hex::encode(Sha256::digest(body))
+}
+
+/// Whether the response to `req` gets `X-Content-SHA256`.
+pub fn wanted(req: &Request<'_>, enabled: bool) -> bool {
+    enabled || matches!(req.headers().get_one(REQUEST_HEADER).map(str::trim), Some("1" | "true"))
+}
+
+/// Refuses a request body that doesn't match the `X-Content-SHA256` the
+/// client sent with it, before it goes upstream. Base64 bodies are checked
+/// once decoded.
+pub fn verify(req: &Request<'_>, body: &[u8], metrics: &Metrics) -> Result<()> {
+    let Some(expected) = req.headers().get_one(HEADER) else {
+        return Ok(());
+    };
+    let actual = digest(body);
+    if expected.trim().eq_ignore_ascii_case(&actual) {
+        return Ok(());
+    }
+    metrics.incr("roproxy_integrity_mismatches_total", &[]);
+    Err(Rejection::new(Status::BadRequest, format!("Request body doesn't match {}", HEADER))
+        .with_field("code", "content_hash_mismatch")
+        .with_field("actual", actual)
+        .into())
+}
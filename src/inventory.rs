@@ -0,0 +1,110 @@
+use crate::{
+    helpers::{self, Page, Paged, Upstream, UpstreamError},
+    AppState, ErrorResponse, MyRequestGuard, Rejection,
+};
+use anyhow::Result;
+use rocket::{
+    futures::StreamExt,
+    http::Status,
+    serde::json::{json, Value},
+    State,
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Items per catalog details lookup, Roblox's cap.
+const DETAILS_BATCH: usize = 120;
+
+fn price_param(name: &str, value: Option<&str>) -> Result<Option<u64>> {
+    value
+        .map(|value| {
+            value.parse().map_err(|_| {
+                Rejection::new(Status::BadRequest, format!("{} must be a whole number of Robux", name)).into()
+            })
+        })
+        .transpose()
+}
+
+/// A user's inventory of the given asset types (`?assetTypes=Hat,Face`),
+/// paged through up to `helpers.max_pages`. With `minPrice` or `maxPrice`
+/// only items on sale within that range are kept, and each gets its
+/// `price`. A private inventory is a 403 saying so rather than Roblox's
+/// error. Streamed as NDJSON on request; see `helpers::Paged`.
+#[get("/helpers/users/<user_id>/inventory")]
+pub async fn inventory<'r>(
+    user_id: u64,
+    state: &'r State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Paged<'r>, ErrorResponse> {
+    let req = guard.request;
+    let upstream = Upstream::authenticate(state, req)?;
+    let param = |name| req.query_value::<&str>(name).and_then(Result::ok);
+    let asset_types = param("assetTypes").unwrap_or_default();
+    if asset_types.is_empty() || !asset_types.chars().all(|c| c.is_ascii_alphanumeric() || c == ',') {
+        return Err(ErrorResponse(
+            Rejection::new(Status::BadRequest, "assetTypes must list asset types, e.g. Hat,Face").into(),
+        ));
+    }
+    let min_price = price_param("minPrice", param("minPrice"))?;
+    let max_price = price_param("maxPrice", param("maxPrice"))?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "inventory")]);
+
+    let url = format!(
+        "https://inventory.roblox.com/v2/users/{}/inventory?assetTypes={}&limit=100&sortOrder=Asc",
+        user_id, asset_types
+    );
+    let ttl = Duration::from_secs(state.helpers.inventory_ttl_secs);
+    let mut pages = upstream.page_stream(&url, ttl);
+    if min_price.is_some() || max_price.is_some() {
+        // Priced a page at a time, so streamed items don't wait on the rest.
+        pages = pages
+            .then(move |page| {
+                let upstream = upstream.clone();
+                async move {
+                    let Page { mut items, complete } = page?;
+                    let prices = prices(&upstream, &items).await.map_err(helpers::rejection)?;
+                    items.retain_mut(|item| {
+                        let Some(price) = item["assetId"].as_u64().and_then(|id| prices.get(&id).copied()) else {
+                            return false;
+                        };
+                        item["price"] = json!(price);
+                        min_price.is_none_or(|min| price >= min) && max_price.is_none_or(|max| price <= max)
+                    });
+                    Ok(Page { items, complete })
+                }
+            })
+            .boxed();
+    }
+    helpers::paged(req, pages, "items", json!({ "userId": user_id }))
+        .await
+        .map_err(|err| {
+            ErrorResponse(match err.downcast_ref::<UpstreamError>() {
+                Some(upstream) if upstream.status == Status::Forbidden => {
+                    Rejection::new(Status::Forbidden, format!("User {}'s inventory is private", user_id))
+                        .with_field("userId", user_id)
+                        .with_field("private", true)
+                        .into()
+                }
+                _ => helpers::rejection(err),
+            })
+        })
+}
+
+// Prices of the items on sale among `items`, by asset ID.
+async fn prices(upstream: &Upstream<'_>, items: &[Value]) -> Result<HashMap<u64, u64>> {
+    let ids: Vec<u64> = items.iter().filter_map(|item| item["assetId"].as_u64()).collect();
+    let mut prices = HashMap::new();
+    for batch in ids.chunks(DETAILS_BATCH) {
+        let body = json!({
+            "items": batch.iter().map(|id| json!({ "itemType": "Asset", "id": id })).collect::<Vec<_>>(),
+        });
+        let details = upstream
+            .post("https://catalog.roblox.com/v1/catalog/items/details", &body)
+            .await?;
+        for detail in details["data"].as_array().into_iter().flatten() {
+            if let (Some(id), Some(price)) = (detail["id"].as_u64(), detail["price"].as_u64()) {
+                prices.insert(id, price);
+            }
+        }
+    }
+    Ok(prices)
+}
@@ -0,0 +1,90 @@
+use crate::config::AdaptiveTimeoutsConfig;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Routes tracked at once; new ones past this get the client's fixed timeout.
+const MAX_ROUTES: usize = 1_000;
+
+#[derive(Default)]
+struct Route {
+    samples: VecDeque<Duration>,
+    timeout: Option<Duration>,
+}
+
+/// Derives a per-route upstream timeout from recent latencies, so a route
+/// that's always slow isn't cut off while one that's normally quick fails
+/// fast when a request hangs.
+pub struct AdaptiveTimeouts {
+    config: AdaptiveTimeoutsConfig,
+    routes: Mutex<HashMap<String, Route>>,
+}
+
+impl AdaptiveTimeouts {
+    pub fn new(config: &AdaptiveTimeoutsConfig) -> Self {
+        AdaptiveTimeouts {
+            config: config.clone(),
+            routes: Mutex::default(),
+        }
+    }
+
+    /// The timeout for `url`, once its route has enough samples.
+    pub fn timeout(&self, url: &str) -> Option<Duration> {
+        if !self.config.enabled {
+            return None;
+        }
+        self.routes.lock().unwrap().get(&route(url)?)?.timeout
+    }
+
+    /// Records how long `url` took to answer. Requests cut off by the
+    /// timeout are recorded at the timeout, so a route that slows down for
+    /// good pushes its own timeout up.
+    pub fn record(&self, url: &str, elapsed: Duration) {
+        let config = &self.config;
+        if !config.enabled {
+            return;
+        }
+        let Some(route) = route(url) else {
+            return;
+        };
+        let mut routes = self.routes.lock().unwrap();
+        if routes.len() >= MAX_ROUTES && !routes.contains_key(&route) {
+            return;
+        }
+        let route = routes.entry(route).or_default();
+        route.samples.push_back(elapsed);
+        if route.samples.len() > config.window {
+            route.samples.pop_front();
+        }
+        if route.samples.len() < config.min_samples {
+            return;
+        }
+
+        let mut sorted: Vec<_> = route.samples.iter().copied().collect();
+        sorted.sort();
+        let index = ((sorted.len() as f64 * config.percentile).ceil() as usize).clamp(1, sorted.len()) - 1;
+        let timeout = sorted[index].mul_f64(config.multiplier);
+        route.timeout = Some(timeout.clamp(
+            Duration::from_millis(config.min_ms),
+            Duration::from_millis(config.max_ms),
+        ));
+    }
+}
+
+// Host and path with ID segments folded together, so `/v1/users/1` and
+// `/v1/users/2` share a route.
+fn route(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let mut route = url.host_str()?.to_string();
+    for segment in url.path().split('/').filter(|segment| !segment.is_empty()) {
+        route.push('/');
+        if segment.bytes().all(|byte| byte.is_ascii_digit()) {
+            route.push('*');
+        } else {
+            route.push_str(segment);
+        }
+    }
+    Some(route)
+}
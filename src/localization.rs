@@ -0,0 +1,135 @@
+use crate::{
+    helpers::{self, Upstream},
+    AppState, ErrorResponse, MyRequestGuard, Rejection,
+};
+use anyhow::Result;
+use rocket::{
+    http::Status,
+    serde::{
+        json::{json, Json, Value},
+        Deserialize,
+    },
+    State,
+};
+use std::sync::Arc;
+
+const TABLES: &str = "https://localizationtables.roblox.com/v1/localization-table/tables";
+
+// `path` under the table `table_id`, scoped to `game_id` if given.
+fn table_url(table_id: &str, path: &str, game_id: Option<u64>) -> Result<String> {
+    // Table IDs are GUIDs.
+    if table_id.len() != 36 || !table_id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        return Err(Rejection::new(Status::BadRequest, format!("Invalid localization table ID {}", table_id)).into());
+    }
+    Ok(match game_id {
+        Some(game_id) => format!("{}/{}{}?gameId={}", TABLES, table_id, path, game_id),
+        None => format!("{}/{}{}", TABLES, table_id, path),
+    })
+}
+
+/// The localization tables of a universe, which is its automatic
+/// translation table.
+#[get("/helpers/games/<universe_id>/localization-tables")]
+pub async fn localization_tables(
+    universe_id: u64,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "localization_tables")]);
+
+    let settings = upstream
+        .get_fresh(&format!(
+            "https://gameinternationalization.roblox.com/v1/autolocalization/games/{}",
+            universe_id
+        ))
+        .await
+        .map_err(helpers::rejection)?;
+    let mut tables = Vec::new();
+    if let Some(table_id) = settings["autoLocalizationTableId"].as_str() {
+        let table = upstream
+            .get_fresh(&table_url(table_id, "", None)?)
+            .await
+            .map_err(helpers::rejection)?;
+        tables.push(table);
+    }
+    Ok(Json(json!({ "universeId": universe_id, "tables": tables })))
+}
+
+/// Every entry of a localization table, paged through up to
+/// `helpers.max_pages`. Fetched fresh, so a sync sees its own patches.
+#[get("/helpers/localization-tables/<table_id>/entries?<game_id>")]
+pub async fn localization_entries(
+    table_id: &str,
+    game_id: Option<u64>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    let url = table_url(table_id, "/entries", game_id)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "localization_entries")]);
+
+    let mut entries = Vec::new();
+    let mut cursor = None;
+    let mut complete = false;
+    for _ in 0..upstream.max_pages() {
+        let page = upstream
+            .get_fresh(&helpers::page_url(&url, cursor.as_deref())?)
+            .await
+            .map_err(helpers::rejection)?;
+        entries.extend(page["entries"].as_array().into_iter().flatten().cloned());
+        cursor = match page["nextPageCursor"].as_str() {
+            Some(next) if !next.is_empty() => Some(next.to_string()),
+            _ => {
+                complete = true;
+                break;
+            }
+        };
+    }
+    Ok(Json(json!({ "tableId": table_id, "entries": entries, "truncated": !complete })))
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct EntriesPatch {
+    entries: Vec<Value>,
+}
+
+/// Adds, updates or deletes entries of a localization table, in batches of
+/// `helpers.localization_patch_size` so large sets stay under Roblox's
+/// per-request limit. Stops at the first batch that fails, saying how many
+/// entries went through before it.
+#[patch("/helpers/localization-tables/<table_id>?<game_id>", data = "<patch>")]
+pub async fn patch_localization_entries(
+    table_id: &str,
+    game_id: Option<u64>,
+    patch: Json<EntriesPatch>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    let url = table_url(table_id, "", game_id)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "localization_patch")]);
+
+    let mut patched = 0;
+    let mut modified = Vec::new();
+    let mut failed = Vec::new();
+    for batch in patch.entries.chunks(state.helpers.localization_patch_size.max(1)) {
+        let result = upstream
+            .patch(&url, &json!({ "entries": batch }))
+            .await
+            .map_err(|err| match helpers::rejection(err).downcast::<Rejection>() {
+                Ok(rejection) => rejection.with_field("patched", patched).into(),
+                Err(err) => err,
+            })?;
+        patched += batch.len();
+        modified.extend(result["modifiedEntriesAndTranslations"].as_array().into_iter().flatten().cloned());
+        failed.extend(result["failedEntriesAndTranslations"].as_array().into_iter().flatten().cloned());
+    }
+    Ok(Json(json!({
+        "tableId": table_id,
+        "patched": patched,
+        "modifiedEntriesAndTranslations": modified,
+        "failedEntriesAndTranslations": failed,
+    })))
+}
@@ -1,16 +1,147 @@
 #[macro_use]
 extern crate rocket;
 
-use anyhow::{anyhow, Context, Result};
-use reqwest::Client;
+mod abuse;
+mod async_jobs;
+mod admin;
+mod audit_feed;
+mod audit_log;
+mod avatar_3d;
+#[cfg(feature = "axum")]
+mod axum_frontend;
+mod bandwidth;
+mod binary;
+mod body_rules;
+mod budget;
+mod bulk;
+mod cache;
+mod callbacks;
+mod challenge;
+mod changes;
+mod client;
+mod client_cert;
+mod cloud;
+mod config;
+mod connections;
+mod content_type;
+mod cookie_policy;
+mod credential_store;
+mod credentials;
+mod csv_export;
+mod disk_cache;
+mod economy;
+mod engine;
+mod enumeration;
+mod game_summary;
+mod envelope;
+mod graph;
+mod headers;
+mod helpers;
+mod health;
+mod host_methods;
+mod identity;
+mod idempotency;
+mod inflight;
+mod integrity;
+mod inventory;
+mod latency;
+mod localization;
+mod me;
+mod metrics;
+mod middleware;
+mod minify;
+mod operations;
+mod products;
+mod projection;
+mod push;
+mod ratelimit;
+mod request_log;
+mod retry;
+mod rewrite;
+mod schema;
+mod scripting;
+mod servers;
+mod sessions;
+mod shared_state;
+mod signing;
+mod social;
+mod snapshots;
+mod sse;
+mod ssrf;
+mod status_page;
+mod tags;
+mod tenants;
+mod timing;
+mod trace;
+mod trades;
+mod transform;
+mod upload;
+mod upstream_signing;
+mod usage;
+mod user_agent;
+mod warming;
+mod webhooks;
+mod websocket;
+
+use anyhow::{Context, Result};
+use abuse::{AbuseDetector, AbuseMonitor};
+use async_jobs::AsyncJobs;
+use audit_feed::AuditFeeds;
+use audit_log::AuditLog;
+use bandwidth::UsageAccounting;
+use budget::{BudgetStatus, QueueStatus};
+use bulk::BulkJobs;
+use cache::CacheKey;
+use callbacks::Callbacks;
+use challenge::Challenges;
+use client_cert::ClientCertificates;
+use cookie_policy::CookiePolicy;
+use config::{
+    Base64Config, CloudConfig, EconomyConfig, EnvelopeConfig, HeaderLimitsConfig, HelpersConfig, IntegrityConfig,
+    MethodOverrideConfig, ProxyConfig, TradesConfig, WebSocketConfig,
+};
+use credential_store::CredentialStore;
+use credentials::{CredentialPool, CredentialStatus};
+use engine::{ProxyEngine, UpstreamRequest};
+use enumeration::EnumerationGuard;
+use host_methods::HostMethods;
+use idempotency::{Claim, IdempotencyStore};
+use inflight::InFlight;
+use metrics::Metrics;
+use push::PushChannels;
+use connections::{ConnectionLimiter, ConnectionLimits};
+use request_log::{AccessLog, LogContext, RequestLog, RequestLogger};
+use rewrite::{Rewrites, UpstreamTargets};
+use sessions::SessionJars;
+use signing::UrlSigner;
+use snapshots::Snapshots;
+use sse::EventStreamBody;
+use tags::{TagMetrics, Tags};
+use tenants::{ApiKey, Tenants};
+use trace::Tracing;
+use transform::Transforms;
+use usage::UsageStats;
+use user_agent::UserAgentPolicy;
 use rocket::{
-    data::ToByteUnit,
-    http::{ContentType, Header, Method, Status},
+    data::{ByteUnit, ToByteUnit},
+    http::{ContentType, Method, Status, StatusClass},
     request::{FromRequest, Outcome},
     response::{self, Response},
-    routes, Data, Request, State,
+    routes,
+    serde::json::{self, json, Json, Value},
+    Data, Request, State,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Cursor,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use std::{collections::HashMap, convert::Infallible, io::Cursor, path::PathBuf, time::Duration};
+use tokio::sync::broadcast;
 use tracing::{debug, error, info};
 
 // A custom guard that holds the entire Request and passes it along.
@@ -20,7 +151,7 @@ struct MyRequestGuard<'r> {
 
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for MyRequestGuard<'r> {
-    type Error = Infallible;
+    type Error = ();
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         let converted: &'r Request<'r> = unsafe {
@@ -40,6 +171,24 @@ impl From<anyhow::Error> for ErrorResponse {
 
 impl<'r> response::Responder<'r, 'static> for ErrorResponse {
     fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        if let Some(rejection) = self.0.downcast_ref::<Rejection>() {
+            info!("Rejected request: {}", rejection);
+            let mut body = json!({ "error": rejection.message });
+            for (name, value) in &rejection.fields {
+                body[name] = value.clone();
+            }
+            let body = body.to_string();
+            let mut response = Response::build();
+            response
+                .status(rejection.status)
+                .header(ContentType::JSON)
+                .sized_body(body.len(), Cursor::new(body));
+            for (name, value) in &rejection.headers {
+                response.raw_header(name.clone(), value.clone());
+            }
+            return response.ok();
+        }
+
         error!("{:?}", self.0);
         Response::build()
             .status(Status::InternalServerError)
@@ -49,44 +198,227 @@ impl<'r> response::Responder<'r, 'static> for ErrorResponse {
     }
 }
 
+// An error that maps to a specific client-facing status instead of a 500.
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    status: Status,
+    message: String,
+    headers: Vec<(String, String)>,
+    fields: Vec<(String, Value)>,
+}
+
+impl Rejection {
+    pub fn new(status: Status, message: impl Into<String>) -> Self {
+        Rejection {
+            status,
+            message: message.into(),
+            headers: Vec::new(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl ToString) -> Self {
+        self.headers.push((name.into(), value.to_string()));
+        self
+    }
+
+    /// Adds a field to the JSON error body next to `error`.
+    pub fn with_field(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.fields.push((name.into(), value.into()));
+        self
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status.code, self.message)
+    }
+}
+
+impl std::error::Error for Rejection {}
+
 struct AppState {
-    client: Client,
+    engine: ProxyEngine,
+    metrics: Arc<Metrics>,
+    idempotency: IdempotencyStore,
+    tenants: Tenants,
+    sessions: SessionJars,
+    credentials: CredentialPool,
+    credential_store: Option<CredentialStore>,
+    admin_token: Option<String>,
+    signer: Option<UrlSigner>,
+    inflight: InFlight,
+    started: Instant,
+    request_log: broadcast::Sender<RequestLog>,
+    websocket: WebSocketConfig,
+    push: PushChannels,
+    snapshots: Snapshots,
+    audit_feeds: AuditFeeds,
+    helpers: HelpersConfig,
+    trades: TradesConfig,
+    economy: EconomyConfig,
+    cloud: CloudConfig,
+    transforms: Transforms,
+    rewrites: Rewrites,
+    method_override: MethodOverrideConfig,
+    envelope: EnvelopeConfig,
+    base64: Base64Config,
+    body_timeout: Duration,
+    header_limits: HeaderLimitsConfig,
+    user_agents: UserAgentPolicy,
+    abuse: Arc<AbuseDetector>,
+    enumeration: EnumerationGuard,
+    challenges: Challenges,
+    upstream_targets: UpstreamTargets,
+    host_methods: HostMethods,
+    usage: UsageStats,
+    integrity: IntegrityConfig,
+    tags: Tags,
+    async_jobs: AsyncJobs,
+    bulk: BulkJobs,
+    callbacks: Callbacks,
+    /// How long helpers reuse a CSRF token Roblox issued an account.
+    csrf_ttl: Duration,
 }
 
+impl AppState {
+    // Turns away clients the operator doesn't want, before any work is done
+    // for them.
+    fn screen_client(&self, req: &Request<'_>) -> Result<()> {
+        self.screen(req.client_ip(), req.headers().get_one("User-Agent"))
+    }
+
+    fn screen(&self, ip: Option<IpAddr>, user_agent: Option<&str>) -> Result<()> {
+        self.user_agents.check(user_agent, &self.metrics)?;
+        self.abuse.check(ip)
+    }
+
+    // The shared pool is "default"; every tenant's pool goes by its name.
+    fn credential_pool(&self, name: &str) -> Option<&CredentialPool> {
+        if name == "default" {
+            return Some(&self.credentials);
+        }
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.name == name)
+            .map(|tenant| &tenant.credentials)
+    }
+}
+
+#[derive(Clone)]
 struct ProxyResponse {
     status: Status,
     content_type: String,
     body: Vec<u8>,
     headers: Vec<(String, String)>,
+    /// Set instead of `body` for event streams, which are relayed as they
+    /// arrive.
+    stream: Option<EventStreamBody>,
+    /// The pool account that answered. Kept out of `headers`, which the
+    /// client sees.
+    credential: Option<String>,
+}
+
+impl ProxyResponse {
+    // The body of a successful JSON response, for rewriting on the way out.
+    fn json(&self) -> Option<Value> {
+        if self.stream.is_some()
+            || self.status.class() != StatusClass::Success
+            || !self.content_type.contains("json")
+        {
+            return None;
+        }
+        json::from_slice(&self.body).ok()
+    }
+
+    fn set_json(&mut self, value: &Value) {
+        self.body = value.to_string().into_bytes();
+        // Validators describe the upstream representation, not this one.
+        self.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("etag"));
+    }
 }
 
 impl<'r> rocket::response::Responder<'r, 'static> for ProxyResponse {
-    fn respond_to(self, _: &'r Request<'_>) -> rocket::response::Result<'static> {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
         let mut response = Response::build();
         response.status(self.status);
-        
-        response.raw_header("Content-Length", self.body.len().to_string());
-        
+
         if let Some(ct) = ContentType::parse_flexible(&self.content_type) {
             response.header(ct);
         }
 
+        // `content_type` wins over the upstream header, which it may correct.
         for (name, value) in self.headers {
-            if name.to_lowercase() != "content-length" {
-                response.header(Header::new(name, value));
+            if !["content-length", "content-type"].contains(&name.to_lowercase().as_str()) {
+                response.raw_header_adjoin(name, value);
             }
         }
 
-        response.sized_body(self.body.len(), Cursor::new(self.body));
+        match self.stream {
+            Some(stream) => {
+                // Stops buffering reverse proxies from holding events back.
+                response.raw_header("X-Accel-Buffering", "no");
+                response.streamed_body(stream.into_reader());
+            }
+            // Sized, so Content-Length is set for us. Setting it by hand as
+            // well sends it twice, which HTTP/2 clients reject.
+            None => {
+                let enabled = req.rocket().state::<Arc<AppState>>().is_some_and(|state| state.integrity.enabled);
+                if integrity::wanted(req, enabled) {
+                    response.raw_header(integrity::HEADER, integrity::digest(&self.body));
+                }
+                response.sized_body(self.body.len(), Cursor::new(self.body));
+            }
+        }
         response.ok()
     }
 }
 
+#[get("/metrics")]
+fn get_metrics(state: &State<Arc<AppState>>) -> String {
+    state.metrics.render()
+}
+
+/// `/metrics` when it shares the public listener. Admin only there, since
+/// its labels name accounts, tenants and keys.
+#[get("/metrics")]
+fn get_public_metrics(state: &State<Arc<AppState>>, token: admin::AdminToken<'_>) -> Result<String, ErrorResponse> {
+    token.check(state)?;
+    Ok(state.metrics.render())
+}
+
+#[get("/status/budgets")]
+fn get_budgets(state: &State<Arc<AppState>>) -> Json<Vec<BudgetStatus>> {
+    Json(state.engine.budgets.status())
+}
+
+/// Backlog of each budget family, so clients can pace themselves before
+/// they're queued or shed.
+#[get("/status/queue")]
+fn get_queue(state: &State<Arc<AppState>>) -> Json<Vec<QueueStatus>> {
+    Json(state.engine.budgets.queues())
+}
+
+/// Health of every pool's accounts. Admin only, since it names them.
+#[get("/status/credentials")]
+fn get_credentials(
+    state: &State<Arc<AppState>>,
+    token: admin::AdminToken<'_>,
+) -> Result<Json<HashMap<String, Vec<CredentialStatus>>>, ErrorResponse> {
+    token.check(state)?;
+    let mut pools = HashMap::new();
+    pools.insert("default".to_string(), state.credentials.status());
+    for tenant in state.tenants.iter() {
+        pools.insert(tenant.name.clone(), tenant.credentials.status());
+    }
+    Ok(Json(pools))
+}
+
 #[get("/<path..>?<params..>")]
 async fn get_request(
     path: PathBuf,
     params: HashMap<String, String>,
-    state: &State<AppState>,
+    state: &State<Arc<AppState>>,
     guard: MyRequestGuard<'_>,
 ) -> Result<ProxyResponse, ErrorResponse> {
     handle_request(Method::Get, path, Some(params), None, state, guard.request)
@@ -99,20 +431,46 @@ async fn post_request(
     path: PathBuf,
     params: HashMap<String, String>,
     data: Data<'_>,
-    state: &State<AppState>,
+    state: &State<Arc<AppState>>,
     guard: MyRequestGuard<'_>,
 ) -> Result<ProxyResponse, ErrorResponse> {
-    handle_request(Method::Post, path, Some(params), Some(data), state, guard.request)
+    let method = overridden_method(state, guard.request)?;
+    // A GET with a body is rarely what the client meant and some upstreams
+    // reject it outright.
+    let data = (method != Method::Get).then_some(data);
+    handle_request(method, path, Some(params), data, state, guard.request)
         .await
         .map_err(ErrorResponse)
 }
 
+// The method a POST should be sent upstream as, per X-HTTP-Method-Override.
+fn overridden_method(state: &AppState, req: &Request<'_>) -> Result<Method> {
+    let config = &state.method_override;
+    let Some(requested) = req.headers().get_one("X-HTTP-Method-Override") else {
+        return Ok(Method::Post);
+    };
+    if !config.enabled {
+        return Ok(Method::Post);
+    }
+    let method = Method::from_str(requested)
+        .ok()
+        .filter(|_| config.methods.iter().any(|allowed| allowed.eq_ignore_ascii_case(requested)))
+        .ok_or_else(|| {
+            Rejection::new(Status::BadRequest, "Unsupported X-HTTP-Method-Override")
+                .with_field("allowed", json!(config.methods))
+        })?;
+    state
+        .metrics
+        .incr("roproxy_method_overrides_total", &[("method", method.as_str())]);
+    Ok(method)
+}
+
 #[put("/<path..>?<params..>", data = "<data>")]
 async fn put_request(
     path: PathBuf,
     params: HashMap<String, String>,
     data: Data<'_>,
-    state: &State<AppState>,
+    state: &State<Arc<AppState>>,
     guard: MyRequestGuard<'_>,
 ) -> Result<ProxyResponse, ErrorResponse> {
     handle_request(Method::Put, path, Some(params), Some(data), state, guard.request)
@@ -124,7 +482,7 @@ async fn put_request(
 async fn delete_request(
     path: PathBuf,
     params: HashMap<String, String>,
-    state: &State<AppState>,
+    state: &State<Arc<AppState>>,
     guard: MyRequestGuard<'_>,
 ) -> Result<ProxyResponse, ErrorResponse> {
     handle_request(Method::Delete, path, Some(params), None, state, guard.request)
@@ -137,136 +495,627 @@ async fn handle_request(
     path: PathBuf,
     query_params: Option<HashMap<String, String>>,
     data: Option<Data<'_>>,
-    state: &State<AppState>,
+    state: &AppState,
     req: &Request<'_>,
 ) -> Result<ProxyResponse> {
+    let param = |name| query_params.as_ref().and_then(|params| params.get(name)).cloned();
+    let fields = param(projection::PARAM);
+    let csv = csv_export::wants_csv(param(csv_export::FORMAT).as_deref())?;
+    let columns = param(csv_export::COLUMNS);
+    let (url, mut response) = trace::scope(
+        trace::id(req),
+        proxy_request(method, path, query_params, data, state, req),
+    )
+    .await?;
+    state.engine.budgets.annotate(&url, &mut response);
+    let response = state.transforms.apply(&url, response, &state.metrics);
+    let response = match fields {
+        Some(fields) => projection::apply(response, &fields),
+        None => response,
+    };
+    let response = if csv {
+        csv_export::apply(response, columns.as_deref())
+    } else {
+        response
+    };
+    binary::encode_response(&state.base64, req, response)
+}
+
+// Resolves, authorizes and forwards the request, returning the upstream URL
+// alongside the response so rules keyed on it can run afterwards.
+async fn proxy_request(
+    method: Method,
+    mut path: PathBuf,
+    mut query_params: Option<HashMap<String, String>>,
+    data: Option<Data<'_>>,
+    state: &AppState,
+    req: &Request<'_>,
+) -> Result<(String, ProxyResponse)> {
+    state.screen_client(req)?;
+
+    // A valid signature stands in for the proxy key, for the tenant (if any)
+    // the URL was issued to.
+    let signed = match (&state.signer, query_params.as_mut()) {
+        (Some(signer), Some(params)) if params.contains_key(signing::SIGNATURE) => {
+            let verified = signer.verify(method, &path.to_string_lossy(), params);
+            let result = if verified.is_ok() { "accepted" } else { "rejected" };
+            state.metrics.incr("roproxy_signed_url_requests_total", &[("result", result)]);
+            Some(verified?)
+        }
+        _ => None,
+    };
+    let wait = query_params
+        .as_mut()
+        .and_then(|params| params.remove(operations::PARAM))
+        .map(|wait| operations::parse_wait(&wait, Duration::from_secs(state.cloud.max_wait_secs)))
+        .transpose()?;
+    if let Some(params) = query_params.as_mut() {
+        for name in [projection::PARAM, csv_export::FORMAT, csv_export::COLUMNS] {
+            params.remove(name);
+        }
+    }
+
+    let tenant = if state.tenants.is_empty() {
+        None
+    } else {
+        let resolved = match signed {
+            Some(signed_tenant) => signed_tenant
+                .and_then(|name| state.tenants.get(&name))
+                .map(|tenant| (tenant, path.clone())),
+            None => state.tenants.resolve(client_cert::api_key(req), &path),
+        };
+        let Some((tenant, rest)) = resolved else {
+            return Err(Rejection::new(Status::Unauthorized, "Unknown or missing proxy key").into());
+        };
+        state
+            .metrics
+            .incr("roproxy_tenant_requests_total", &[("tenant", &tenant.name)]);
+        let key = client_cert::api_key(req).and_then(|api_key| tenant.key(api_key));
+        LogContext::set_tenant(req, &tenant.name, key.map(|key| key.label()));
+        if let Some(key) = key {
+            key.check_active(&state.metrics)?;
+        }
+        tenant.check_rate_limit(&state.metrics)?;
+        if let Some(key) = key {
+            key.check_tag(tags::tag(req)?, &state.metrics)?;
+            key.bandwidth().check(key.label(), &state.metrics)?;
+        }
+        path = rest;
+        Some(tenant)
+    };
+    let namespace = tenant.as_ref().map(|tenant| tenant.name.as_str());
+
     let path_str = path.to_string_lossy();
     
-    let mut url = format!("https://www.roblox.com/{}", path_str);
-    
+    let mut target = path_str.to_string();
     if let Some(params) = query_params {
         if !params.is_empty() {
-            info!("Query parameters: {:?}", params);
+            debug!("Query parameters: {:?}", params);
+            // Sorted so the same query always maps to the same cache key.
+            let mut params: Vec<_> = params.iter().collect();
+            params.sort();
             let query_string: String = params
                 .iter()
                 .map(|(k, v)| format!("{}={}", k, v))
                 .collect::<Vec<_>>()
                 .join("&");
-            url.push('?');
-            url.push_str(&query_string);
+            target.push('?');
+            target.push_str(&query_string);
         }
     }
+    let url = if let Some(upstream) = req.headers().get_one(rewrite::UPSTREAM_HEADER) {
+        let key = tenant
+            .as_ref()
+            .zip(client_cert::api_key(req))
+            .and_then(|(tenant, api_key)| tenant.key(api_key));
+        state.upstream_targets.resolve(upstream, key, &target, &state.metrics)?
+    } else {
+        match state.rewrites.apply(&target, &state.metrics) {
+            Some(url) => url,
+            None => format!("https://www.roblox.com/{}", target),
+        }
+    };
+    // Normalized before anything matches URL prefixes against it.
+    let url = state.engine.check_url(&url)?;
     // info!("Incoming request method: {:?}", method);
     // info!("Incoming request path: {:?}", path);
     // info!("Incoming request headers:");
     // for header in req.headers().iter() {
     //     info!("  {}: {}", header.name(), header.value());
     // }
-    info!("Full URL: {}", url);
-
-    let mut request_builder = match method {
-        Method::Get => state.client.get(&url),
-        Method::Post => state.client.post(&url),
-        Method::Put => state.client.put(&url),
-        Method::Delete => state.client.delete(&url),
-        _ => return Err(anyhow!("Unsupported method")),
-    };
+    debug!("Full URL: {}", url);
+    trace::event(|| format!("{} {} -> {}", method, req.uri(), url));
+    LogContext::set_upstream(req, &url);
 
-    request_builder = request_builder
-        .header("Accept", "application/json")
-        .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36")
-        .header("Referer", "https://www.roblox.com")
-        .header("Origin", "https://www.roblox.com");
+    let key = tenant
+        .as_ref()
+        .zip(client_cert::api_key(req))
+        .and_then(|(tenant, api_key)| tenant.key(api_key));
+    check_policies(state, req.client_ip(), key, method, &url).await?;
+    // Checked up front so an unknown profile fails the same way whether or
+    // not the response is cached.
+    let identity = req.headers().get_one(identity::HEADER);
+    state.engine.check_identity(identity, &url)?;
+
+    let idempotency_key = match method {
+        Method::Post | Method::Put => req.headers().get_one("Idempotency-Key"),
+        _ => None,
+    };
+    let session_jar = req.headers().get_one("X-Proxy-Session").and_then(|id| match namespace {
+        Some(namespace) => state.sessions.jar(&format!("{}:{}", namespace, id)),
+        None => state.sessions.jar(id),
+    });
 
+    let mut headers = Vec::new();
     for header in req.headers().iter() {
         let name_lower = header.name().to_string().to_lowercase();
-        if !["host", "connection", "content-length", "transfer-encoding", "user-agent", "roblox-id"].contains(&name_lower.as_str()) {
+        if !PROXY_HEADERS.contains(&name_lower.as_str()) {
             debug!("Forwarding header: {} = {}", header.name(), header.value());
-            request_builder = request_builder.header(header.name().as_str(), header.value());
+            headers.push((header.name().to_string(), header.value().to_string()));
+        }
+    }
+    check_header_limits(state, &headers)?;
+    if let Some(jar) = &session_jar {
+        sessions::attach_cookies(jar, &url, &mut headers);
+    }
+    let (pool_name, credentials) = match &tenant {
+        Some(tenant) => (tenant.name.as_str(), &tenant.credentials),
+        None => ("default", &state.credentials),
+    };
+    // A retry answering a challenge has to come from the account it was
+    // issued to.
+    let credential = challenge::challenge_id(&headers)
+        .and_then(|id| state.challenges.credential(pool_name, credentials, id))
+        .or_else(|| credentials.pick_for(crate::credentials::affinity(req).as_deref()));
+
+    let cacheable = engine::cacheable(method, session_jar.is_some(), |name| req.headers().contains(name));
+    let cache_ttl = match req.headers().get_one(cache::TTL_HEADER) {
+        Some(value) if cacheable => {
+            let key = tenant
+                .as_ref()
+                .zip(client_cert::api_key(req))
+                .and_then(|(tenant, api_key)| tenant.key(api_key));
+            Some(state.engine.cache.client_ttl(value, key)?)
+        }
+        _ => None,
+    };
+    // Keyed by the account too: what Roblox answers can depend on who's
+    // asking.
+    let cache_key =
+        CacheKey::new(namespace, url.clone()).with_account(credential.as_ref().map(|credential| credential.name.as_str()));
+    if cacheable {
+        if let Some(response) = state.engine.cached(&cache_key, cache_ttl) {
+            return Ok((url, response));
         }
     }
+    // Looked up by body hash once the body has been read.
+    let post_cache_ttl = match method {
+        Method::Post if engine::shareable(session_jar.is_some(), |name| req.headers().contains(name)) => {
+            state.engine.cache.post_ttl(&url)
+        }
+        _ => None,
+    };
 
-    if let Some(data) = data {
-        let body_bytes = data
-            .open(5_i32.mebibytes())
-            .into_bytes()
-            .await
-            .context("Failed to read request body")?;
-        
-        debug!("Request body size: {} bytes", body_bytes.len());
-        request_builder = request_builder.body(body_bytes.to_vec());
+    // Turned away before any of the body is read. Rocket has already answered
+    // `Expect: 100-continue` by now (it peeks at every body before routing),
+    // but a client still streaming gets the 413 and the connection closed
+    // instead of an upload that goes nowhere.
+    let declared = req.headers().get_one("Content-Length").and_then(|length| length.parse::<u64>().ok());
+    if data.is_some() && declared.is_some_and(|length| length > MAX_BODY.as_u64()) {
+        return Err(body_too_large().into());
     }
+    // Bodies are streamed upstream as they arrive, except base64 ones, which
+    // have to be decoded whole, those with a hash to check, those an
+    // Idempotency-Key is checked against, and those a middleware stage wants
+    // to read.
+    let (body, feed) = match data {
+        Some(data)
+            if req.headers().contains(binary::REQUEST_HEADER)
+                || req.headers().contains(integrity::HEADER)
+                || post_cache_ttl.is_some()
+                || idempotency_key.is_some()
+                || state.engine.reads_body(&url) =>
+        {
+            let body_bytes = tokio::time::timeout(state.body_timeout, data.open(MAX_BODY).into_bytes())
+                .await
+                .map_err(|_| Rejection::new(Status::RequestTimeout, "Timed out reading the request body"))?
+                .context("Failed to read request body")?;
+            if !body_bytes.is_complete() {
+                return Err(body_too_large().into());
+            }
 
-    info!("Sending request to Roblox API...");
-    let response = request_builder
-        .send()
-        .await
-        .context("Failed to send request")?;
-
-    let status = response.status();
-    info!("Received response status: {}", status);
-
-    let content_type = response
-        .headers()
-        .get("content-type")
-        .and_then(|val| val.to_str().ok())
-        .unwrap_or("application/json")
-        .to_string();
-
-    let response_headers: Vec<(String, String)> = response
-        .headers()
-        .iter()
-        .filter_map(|(name, value)| {
-            if let Ok(val_str) = value.to_str() {
-                let name_lower = name.to_string().to_lowercase();
-                if !["transfer-encoding", "connection"].contains(&name_lower.as_str()) {
-                    Some((name.to_string(), val_str.to_string()))
-                } else {
-                    None
+            debug!("Request body size: {} bytes", body_bytes.len());
+            let body = binary::decode_request(&state.base64, req, body_bytes.into_inner())?;
+            integrity::verify(req, &body, &state.metrics)?;
+            (Some(body.into()), None)
+        }
+        Some(data) => {
+            // Roblox gets the length the client declared rather than a
+            // chunked body, which some endpoints refuse.
+            if let Some(length) = declared {
+                headers.push(("Content-Length".to_string(), length.to_string()));
+            }
+            let (body, feed) = upload::stream_body(data, MAX_BODY, state.body_timeout);
+            (Some(body), Some(feed))
+        }
+        None => (None, None),
+    };
+    // Without tenants, keys are scoped to the client's address so two
+    // clients can't replay each other's responses.
+    let idempotency_scope = match (namespace, req.client_ip()) {
+        (Some(namespace), _) => format!("{}:{}", namespace, path_str),
+        (None, Some(ip)) => format!("{}:{}", ip, path_str),
+        (None, None) => path_str.to_string(),
+    };
+    let idempotency = match idempotency_key {
+        Some(key) => {
+            let bytes = body.as_ref().and_then(reqwest::Body::as_bytes).unwrap_or_default();
+            let fingerprint = idempotency::fingerprint(&target, bytes);
+            match state.idempotency.claim(method, &idempotency_scope, key, fingerprint) {
+                Claim::Replay(mut response) => {
+                    info!("Replaying stored response for Idempotency-Key {}", key);
+                    response
+                        .headers
+                        .push(("Idempotent-Replayed".to_string(), "true".to_string()));
+                    return Ok((url, response));
+                }
+                Claim::InProgress => {
+                    return Err(Rejection::new(
+                        Status::Conflict,
+                        "A request with this Idempotency-Key is still in progress",
+                    )
+                    .into())
+                }
+                Claim::Mismatch => {
+                    return Err(Rejection::new(
+                        Status::UnprocessableEntity,
+                        "This Idempotency-Key was already used for a different request",
+                    )
+                    .into())
                 }
-            } else {
-                None
+                Claim::Owned(guard) => Some(guard),
             }
-        })
-        .collect();
+        }
+        None => None,
+    };
+    let post_cache = post_cache_ttl.map(|ttl| {
+        let bytes = body.as_ref().and_then(reqwest::Body::as_bytes).unwrap_or_default();
+        (cache_key.clone().with_body(bytes), ttl)
+    });
+    if let Some((key, _)) = &post_cache {
+        if let Some(response) = state.engine.cached(key, None) {
+            return Ok((url, response));
+        }
+    }
 
-    let body = response.bytes().await.context("Failed to read response body")?;
-    info!("Response body size: {} bytes", body.len());
+    let client = match (req.client_ip(), &tenant) {
+        (Some(ip), Some(tenant)) => format!("{} ({})", ip, tenant.name),
+        (Some(ip), None) => ip.to_string(),
+        (None, Some(tenant)) => tenant.name.clone(),
+        (None, None) => "unknown".to_string(),
+    };
+    let inflight = state.inflight.register(method.as_str(), &url, client);
+    let request = UpstreamRequest {
+        method,
+        url: url.clone(),
+        headers,
+        body,
+        credential: None,
+        identity: identity.map(str::to_string),
+        timeout: None,
+    }
+    .with_credential(credential.clone());
+    let upstream = async {
+        let Some(feed) = feed else {
+            return state.engine.forward(request).await;
+        };
+        let (fed, response) = tokio::join!(feed, state.engine.forward(request));
+        // A body that was cut off explains the upstream failure better than
+        // the upstream error does.
+        let size = fed?;
+        debug!("Request body size: {} bytes", size);
+        response
+    };
+    let result = tokio::select! {
+        response = upstream => response,
+        _ = inflight.cancelled() => {
+            state.metrics.incr("roproxy_requests_cancelled_total", &[]);
+            return Err(Rejection::new(
+                Status::ServiceUnavailable,
+                "Request was cancelled by an administrator",
+            )
+            .into());
+        }
+    };
+    if cacheable {
+        if let Some(stale) = state.engine.stale_on_error(&cache_key, &result) {
+            return Ok((url, stale));
+        }
+    }
+    let mut proxy_response = result?;
+    if let Some(wait) = wait {
+        proxy_response = operations::settle(state, &url, proxy_response, credential, wait).await?;
+    }
 
-    // if let Ok(json_str) = String::from_utf8(body.to_vec()) {
-    //     info!("Response body: {}", json_str);
-    // }
+    if let Some(jar) = &session_jar {
+        sessions::store_cookies(jar, &url, &mut proxy_response.headers);
+    }
+    state.challenges.observe(pool_name, &proxy_response, &state.metrics);
 
-    Ok(ProxyResponse {
-        status: Status::from_code(status.as_u16()).unwrap_or(Status::InternalServerError),
-        content_type,
-        body: body.to_vec(),
-        headers: response_headers,
-    })
+    if let Some(tenant) = &tenant {
+        state.metrics.incr(
+            "roproxy_tenant_responses_total",
+            &[("tenant", &tenant.name), ("status", proxy_response.status.code.to_string().as_str())],
+        );
+    }
+
+    if let Some(guard) = idempotency {
+        guard.complete(&proxy_response);
+    }
+
+    if cacheable {
+        state.engine.store(&cache_key, &mut proxy_response, cache_ttl);
+    }
+    if let Some((key, ttl)) = &post_cache {
+        state.engine.store(key, &mut proxy_response, Some(*ttl));
+    }
+
+    Ok((url, proxy_response))
+}
+
+/// Largest request body forwarded upstream.
+const MAX_BODY: ByteUnit = ByteUnit::Mebibyte(5);
+
+/// Client headers that are for the proxy or the connection to it, never
+/// forwarded upstream.
+const PROXY_HEADERS: [&str; 18] = ["host", "connection", "content-length", "transfer-encoding", "expect", "user-agent", "roblox-id", "x-proxy-key", "x-proxy-session", "x-http-method-override", "x-proxy-request-encoding", "x-proxy-response-encoding", "x-proxy-identity", "x-proxy-upstream", "x-proxy-cache-ttl", "x-proxy-trace", "x-proxy-integrity", "x-proxy-tag"];
+
+fn body_too_large() -> Rejection {
+    Rejection::new(Status::PayloadTooLarge, "Request body is too large").with_field("max_bytes", MAX_BODY.as_u64())
+}
+
+// The checks every upstream URL goes through, whether it came in on the
+// catch-all route or in an envelope.
+async fn check_policies(
+    state: &AppState,
+    ip: Option<IpAddr>,
+    key: Option<&ApiKey>,
+    method: Method,
+    url: &str,
+) -> Result<()> {
+    if let Some(key) = key {
+        key.check_scope(method, url, &state.metrics)?;
+    }
+    trades::check(&state.trades, key, method, url, &state.metrics)?;
+    economy::check(&state.economy, key, url, &state.metrics)?;
+    state.host_methods.check(method, url, &state.metrics)?;
+    state.enumeration.check(ip, url, &state.metrics).await
+}
+
+// Rejects client headers Roblox would refuse anyway, with a clearer error
+// than the bare 400 it answers oversized requests with.
+fn check_header_limits(state: &AppState, headers: &[(String, String)]) -> Result<()> {
+    let limits = &state.header_limits;
+    let bytes: usize = headers.iter().map(|(name, value)| name.len() + value.len() + 4).sum();
+    let exceeded = if headers.len() > limits.max_count {
+        Some(("count", headers.len(), limits.max_count))
+    } else if bytes > limits.max_bytes {
+        Some(("bytes", bytes, limits.max_bytes))
+    } else {
+        None
+    };
+    let Some((limit, actual, max)) = exceeded else {
+        return Ok(());
+    };
+    state
+        .metrics
+        .incr("roproxy_header_limit_rejections_total", &[("limit", limit)]);
+    Err(Rejection::new(
+        Status::new(431),
+        format!("Request headers exceed the {} limit", limit),
+    )
+    .with_field("limit", limit)
+    .with_field("actual", actual)
+    .with_field("max", max)
+    .into())
 }
 
 #[shuttle_runtime::main]
 async fn main() -> shuttle_rocket::ShuttleRocket {
-    let client = Client::builder()
-        .pool_idle_timeout(Duration::from_secs(15))
-        .pool_max_idle_per_host(10)
-        .timeout(Duration::from_secs(30))
-        .user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36")
-        .build()
-        .context("Failed to create HTTP client")?;
+    let figment = rocket::Config::figment()
+        .merge(("limits", rocket::data::Limits::new().limit("data-form", 5_i32.mebibytes())));
+    let config = ProxyConfig::from_figment(&figment)?;
 
-    let state = AppState { client };
+    let metrics = Arc::new(Metrics::default());
+    let connection_limits = ConnectionLimits::new(config.connections.clone(), metrics.clone());
 
+    let engine = ProxyEngine::new(&config, metrics.clone())?;
+    let callbacks = Callbacks::new(&config.callbacks, engine.client.clone(), metrics.clone());
+    let cookie_policy = CookiePolicy::new(&config.set_cookies, metrics.clone());
+    let audit_log = AuditLog::new(&config.audit_log)?;
+    tracing::info!("Upstream middleware: {}", engine.middleware().join(", "));
+
+    let state = AppState {
+        engine,
+        idempotency: IdempotencyStore::new(&config.idempotency),
+        tenants: Tenants::new(&config.tenants, &config.credentials)?,
+        sessions: SessionJars::new(&config.sessions),
+        credentials: CredentialPool::new(
+            &config.credentials.accounts,
+            config.credentials.strategy,
+            config.credentials.affinity,
+            Duration::from_secs(config.credentials.throttle_cooldown_secs),
+        ),
+        credential_store: CredentialStore::open(&config.credentials)?,
+        admin_token: config.admin.token.clone(),
+        signer: UrlSigner::new(&config.signed_urls),
+        inflight: InFlight::default(),
+        started: Instant::now(),
+        request_log: broadcast::channel(1024).0,
+        websocket: config.websocket,
+        push: PushChannels::new(config.push),
+        snapshots: Snapshots::new(&config.snapshots)?,
+        audit_feeds: AuditFeeds::new(&config.audit_feeds),
+        helpers: config.helpers.clone(),
+        trades: config.trades,
+        economy: config.economy,
+        cloud: config.cloud.clone(),
+        transforms: Transforms::new(&config.transforms)?,
+        rewrites: Rewrites::new(&config.rewrites)?,
+        method_override: config.method_override,
+        envelope: config.envelope,
+        base64: config.base64,
+        body_timeout: Duration::from_secs(config.connections.body_timeout_secs),
+        header_limits: config.header_limits,
+        user_agents: UserAgentPolicy::new(&config.user_agents)?,
+        abuse: AbuseDetector::new(config.abuse.clone(), metrics.clone()),
+        enumeration: EnumerationGuard::new(&config.enumeration),
+        challenges: Challenges::new(&config.challenges),
+        upstream_targets: UpstreamTargets::new(&config.upstream_targets)?,
+        host_methods: HostMethods::new(&config.host_methods),
+        usage: UsageStats::new(&config.usage)?,
+        integrity: config.integrity.clone(),
+        tags: Tags::new(&config.tags),
+        async_jobs: AsyncJobs::new(&config.async_jobs)?,
+        bulk: BulkJobs::new(&config.bulk),
+        callbacks,
+        csrf_ttl: Duration::from_secs(config.shared_state.csrf_ttl_secs),
+        metrics,
+    };
+    if let Some(store) = &state.credential_store {
+        for entry in store.entries() {
+            match state.credential_pool(&entry.pool) {
+                Some(pool) => pool.upsert(&entry.name, entry.credentials),
+                None => tracing::warn!("Ignoring stored credential {} for unknown pool {}", entry.name, entry.pool),
+            }
+        }
+    }
+    let state = Arc::new(state);
+    client::prewarm(&state.engine.client, &config.upstream).await;
+
+    warming::spawn(state.clone(), &config.warming.jobs)?;
+    warming::spawn_refresher(state.clone(), &config.cache);
+    snapshots::spawn(state.clone(), &config.snapshots.jobs)?;
+    audit_feed::spawn(state.clone(), &config.audit_feeds)?;
+    health::spawn(state.clone(), &config.credentials);
+    usage::spawn(state.clone(), &config.usage);
+    async_jobs::spawn(state.clone());
+    #[cfg(feature = "axum")]
+    axum_frontend::spawn(state.clone(), connection_limits.clone(), &config.axum).await?;
+
+    let mut internal_routes = routes![
+        status_page::get_status,
+        get_budgets,
+        get_queue,
+        get_credentials
+    ];
+    if state.admin_token.is_some() {
+        internal_routes.extend(admin::routes());
+    }
+    let push_routes = if state.push.enabled() {
+        push::routes()
+    } else {
+        Vec::new()
+    };
+    let snapshot_routes = if state.snapshots.enabled() {
+        [snapshots::routes(), changes::routes()].concat()
+    } else {
+        Vec::new()
+    };
+    let audit_feed_routes = if state.audit_feeds.enabled() {
+        audit_feed::routes()
+    } else {
+        Vec::new()
+    };
+    let envelope_routes = if state.envelope.enabled {
+        envelope::routes()
+    } else {
+        Vec::new()
+    };
+    let async_routes = if state.async_jobs.enabled() {
+        async_jobs::routes()
+    } else {
+        Vec::new()
+    };
+    let bulk_routes = if state.bulk.enabled() {
+        bulk::routes()
+    } else {
+        Vec::new()
+    };
+    // Without their own listener, the internal routes share the public one.
+    let public_internal_routes = match config.admin.port {
+        Some(port) => {
+            let internal = rocket::custom(
+                figment
+                    .clone()
+                    .merge(("port", port))
+                    .merge(("address", config.admin.address)),
+            )
+            .mount("/", internal_routes)
+            .mount("/", routes![get_metrics])
+            .manage(state.clone());
+            tokio::spawn(async move {
+                if let Err(err) = internal.launch().await {
+                    tracing::error!("Internal listener failed: {}", err);
+                }
+            });
+            Vec::new()
+        }
+        None => {
+            internal_routes.extend(routes![get_public_metrics]);
+            internal_routes
+        }
+    };
     let rocket = rocket::build()
+        .mount("/", public_internal_routes)
+        .mount("/", push_routes)
+        .mount("/", snapshot_routes)
+        .mount("/", audit_feed_routes)
+        .mount("/", envelope_routes)
+        .mount("/", async_routes)
+        .mount("/", bulk_routes)
         .mount(
             "/",
-            routes![get_request, post_request, put_request, delete_request],
+            routes![
+                websocket::websocket,
+                graph::graph,
+                social::social,
+                inventory::inventory,
+                game_summary::game_summary,
+                products::developer_products,
+                products::game_passes,
+                avatar_3d::avatar_3d,
+                localization::localization_tables,
+                localization::localization_entries,
+                localization::patch_localization_entries,
+                trades::list_trades,
+                trades::get_trade,
+                trades::send_trade,
+                trades::accept_trade,
+                servers::restart_servers,
+                servers::shutdown_servers,
+                cloud::publish_place,
+                challenge::continue_challenge,
+                me::me,
+                connections::over_limit,
+                get_request,
+                post_request,
+                put_request,
+                delete_request
+            ],
         )
+        .attach(ConnectionLimiter(connection_limits))
+        .attach(ClientCertificates)
+        .attach(AbuseMonitor(state.abuse.clone()))
+        .attach(cookie_policy)
+        .attach(RequestLogger::new(state.request_log.clone()))
+        .attach(AccessLog::new(&config.log_sampling))
+        .attach(audit_log)
+        .attach(Tracing)
+        .attach(UsageAccounting)
+        .attach(TagMetrics)
         .manage(state)
-        .configure(
-            rocket::Config::figment()
-                .merge(("limits", rocket::data::Limits::new().limit("data-form", 5_i32.mebibytes()))),
-        );
+        .configure(figment);
 
     Ok(rocket.into())
 }
@@ -0,0 +1,64 @@
+use crate::{client_cert, usage, AppState, ErrorResponse, MyRequestGuard, Rejection};
+use chrono::Utc;
+use rocket::{
+    http::Status,
+    serde::json::{json, Json, Value},
+    State,
+};
+use std::sync::Arc;
+
+/// The calling proxy key's scopes, what's left of its quotas and its
+/// tenant's rate limit, and today's request and error counts, so a client
+/// can see why it's being turned away. Answers even when the key is over its
+/// limits, and doesn't take from its tenant's rate limit.
+#[get("/me")]
+pub fn me(state: &State<Arc<AppState>>, guard: MyRequestGuard<'_>) -> Result<Json<Value>, ErrorResponse> {
+    if state.tenants.is_empty() {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, "This proxy isn't configured with proxy keys").into(),
+        ));
+    }
+    let key_and_tenant = client_cert::api_key(guard.request).and_then(|api_key| {
+        state
+            .tenants
+            .iter()
+            .find_map(|tenant| Some((tenant, tenant.key(api_key)?)))
+    });
+    let Some((tenant, key)) = key_and_tenant else {
+        return Err(ErrorResponse(Rejection::new(Status::Unauthorized, "Unknown or missing proxy key").into()));
+    };
+
+    key.check_active(&state.metrics)?;
+
+    let bandwidth = key.bandwidth().status();
+    let remaining = |quota: Option<u64>, used: u64| quota.map(|quota| quota.saturating_sub(used));
+    let today = Utc::now().date_naive();
+    let usage = state
+        .usage
+        .rows(today, today)
+        .into_iter()
+        .find(|row| row.tenant == tenant.name && row.key == key.label())
+        .unwrap_or_default();
+    let error_rate = if usage.requests == 0 { 0.0 } else { usage.errors as f64 / usage.requests as f64 };
+    Ok(Json(json!({
+        "tenant": tenant.name,
+        "key": key.label(),
+        "scopes": key.scopes(),
+        "expires": key.expires().map(|expires| expires.to_rfc3339()),
+        "bandwidth": {
+            "day_bytes": bandwidth.day_bytes,
+            "daily_quota": bandwidth.daily_quota,
+            "daily_remaining": remaining(bandwidth.daily_quota, bandwidth.day_bytes),
+            "month_bytes": bandwidth.month_bytes,
+            "monthly_quota": bandwidth.monthly_quota,
+            "monthly_remaining": remaining(bandwidth.monthly_quota, bandwidth.month_bytes),
+        },
+        "rate_limit": tenant.rate_limit_status(),
+        "today": {
+            "date": today.format(usage::DATE_FORMAT).to_string(),
+            "requests": usage.requests,
+            "errors": usage.errors,
+            "error_rate": error_rate,
+        },
+    })))
+}
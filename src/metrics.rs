@@ -0,0 +1,177 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Bucket bounds (seconds) shared by every histogram. Covers DNS lookups in the
+// low milliseconds up to the 30s client timeout.
+const BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+type Key = (&'static str, String);
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// In-process metrics registry rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<Key, u64>>,
+    gauges: Mutex<BTreeMap<Key, i64>>,
+    histograms: Mutex<BTreeMap<Key, Histogram>>,
+    windows: Mutex<BTreeMap<Key, VecDeque<Instant>>>,
+}
+
+fn key(name: &'static str, labels: &[(&str, &str)]) -> Key {
+    let rendered = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(",");
+    (name, rendered)
+}
+
+fn series(name: &str, labels: &str, extra: Option<String>) -> String {
+    let labels = match (labels.is_empty(), extra) {
+        (true, None) => return name.to_string(),
+        (true, Some(extra)) => extra,
+        (false, None) => labels.to_string(),
+        (false, Some(extra)) => format!("{},{}", labels, extra),
+    };
+    format!("{}{{{}}}", name, labels)
+}
+
+impl Metrics {
+    pub fn incr(&self, name: &'static str, labels: &[(&str, &str)]) {
+        self.add(name, labels, 1);
+    }
+
+    pub fn add(&self, name: &'static str, labels: &[(&str, &str)], value: u64) {
+        *self.counters.lock().unwrap().entry(key(name, labels)).or_default() += value;
+    }
+
+    pub fn set_gauge(&self, name: &'static str, labels: &[(&str, &str)], value: i64) {
+        self.gauges.lock().unwrap().insert(key(name, labels), value);
+    }
+
+    pub fn add_gauge(&self, name: &'static str, labels: &[(&str, &str)], delta: i64) {
+        *self.gauges.lock().unwrap().entry(key(name, labels)).or_default() += delta;
+    }
+
+    /// Raises a gauge by one until the returned guard is dropped, so requests
+    /// abandoned mid-flight don't leave it inflated.
+    pub fn hold_gauge(&self, name: &'static str) -> GaugeGuard<'_> {
+        self.add_gauge(name, &[], 1);
+        GaugeGuard {
+            metrics: self,
+            name,
+        }
+    }
+
+    pub fn observe(&self, name: &'static str, labels: &[(&str, &str)], value: Duration) {
+        let secs = value.as_secs_f64();
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms.entry(key(name, labels)).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += secs;
+    }
+
+    /// Records an event in a sliding one-minute window, exported as a gauge
+    /// holding the number of events seen in the last 60 seconds.
+    pub fn mark(&self, name: &'static str, labels: &[(&str, &str)]) {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        let events = windows.entry(key(name, labels)).or_default();
+        events.push_back(now);
+        while events.front().is_some_and(|t| now.duration_since(*t) > WINDOW) {
+            events.pop_front();
+        }
+    }
+
+    pub fn counter(&self, name: &'static str, labels: &[(&str, &str)]) -> u64 {
+        self.counters.lock().unwrap().get(&key(name, labels)).copied().unwrap_or(0)
+    }
+
+    /// Events recorded with [`Metrics::mark`] in the last 60 seconds.
+    pub fn window_count(&self, name: &'static str, labels: &[(&str, &str)]) -> usize {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        let Some(events) = windows.get_mut(&key(name, labels)) else {
+            return 0;
+        };
+        while events.front().is_some_and(|t| now.duration_since(*t) > WINDOW) {
+            events.pop_front();
+        }
+        events.len()
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut last_type = None;
+        let mut header = |out: &mut String, name: &str, kind: &str| {
+            if last_type.as_deref() != Some(name) {
+                let _ = writeln!(out, "# TYPE {} {}", name, kind);
+                last_type = Some(name.to_string());
+            }
+        };
+
+        for ((name, labels), value) in self.counters.lock().unwrap().iter() {
+            header(&mut out, name, "counter");
+            let _ = writeln!(out, "{} {}", series(name, labels, None), value);
+        }
+
+        for ((name, labels), value) in self.gauges.lock().unwrap().iter() {
+            header(&mut out, name, "gauge");
+            let _ = writeln!(out, "{} {}", series(name, labels, None), value);
+        }
+
+        let now = Instant::now();
+        for ((name, labels), events) in self.windows.lock().unwrap().iter_mut() {
+            while events.front().is_some_and(|t| now.duration_since(*t) > WINDOW) {
+                events.pop_front();
+            }
+            header(&mut out, name, "gauge");
+            let _ = writeln!(out, "{} {}", series(name, labels, None), events.len());
+        }
+
+        for ((name, labels), histogram) in self.histograms.lock().unwrap().iter() {
+            header(&mut out, name, "histogram");
+            let bucket_name = format!("{}_bucket", name);
+            for (count, bound) in histogram.buckets.iter().zip(BUCKETS) {
+                let le = Some(format!("le=\"{}\"", bound));
+                let _ = writeln!(out, "{} {}", series(&bucket_name, labels, le), count);
+            }
+            let inf = Some("le=\"+Inf\"".to_string());
+            let _ = writeln!(out, "{} {}", series(&bucket_name, labels, inf), histogram.count);
+            let _ = writeln!(out, "{} {}", series(&format!("{}_sum", name), labels, None), histogram.sum);
+            let _ = writeln!(out, "{} {}", series(&format!("{}_count", name), labels, None), histogram.count);
+        }
+
+        out
+    }
+}
+
+pub struct GaugeGuard<'a> {
+    metrics: &'a Metrics,
+    name: &'static str,
+}
+
+impl Drop for GaugeGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.add_gauge(self.name, &[], -1);
+    }
+}
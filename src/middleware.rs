@@ -0,0 +1,87 @@
+use crate::{content_type::ContentTypes, engine::UpstreamRequest, metrics::Metrics, schema::SchemaValidation, ProxyResponse};
+use anyhow::Result;
+use tracing::debug;
+
+/// A stage the engine runs around every upstream request. Stages run in the
+/// order they were registered, for both hooks, like Rocket's fairings.
+#[rocket::async_trait]
+pub trait Middleware: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Whether `before_upstream` needs the whole request body for `url`.
+    /// Bodies are otherwise streamed and reach it as a stream.
+    fn reads_body(&self, _url: &str) -> bool {
+        false
+    }
+
+    /// Runs before the request is sent, and before the URL guard so a
+    /// rewritten URL is still checked. An error is returned to the client and
+    /// nothing is sent.
+    async fn before_upstream(&self, _request: &mut UpstreamRequest, _metrics: &Metrics) -> Result<()> {
+        Ok(())
+    }
+
+    /// Runs on the buffered response. Event streams are relayed as they
+    /// arrive and skip this hook.
+    async fn after_upstream(&self, _url: &str, _response: &mut ProxyResponse, _metrics: &Metrics) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Middleware>>,
+}
+
+impl Pipeline {
+    pub fn register(&mut self, stage: impl Middleware + 'static) {
+        debug!("Registered middleware {}", stage.name());
+        self.stages.push(Box::new(stage));
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    pub fn reads_body(&self, url: &str) -> bool {
+        self.stages.iter().any(|stage| stage.reads_body(url))
+    }
+
+    pub async fn before(&self, request: &mut UpstreamRequest, metrics: &Metrics) -> Result<()> {
+        for stage in &self.stages {
+            stage.before_upstream(request, metrics).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn after(&self, url: &str, response: &mut ProxyResponse, metrics: &Metrics) -> Result<()> {
+        for stage in &self.stages {
+            stage.after_upstream(url, response, metrics).await?;
+        }
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl Middleware for ContentTypes {
+    fn name(&self) -> &'static str {
+        "content-types"
+    }
+
+    async fn after_upstream(&self, url: &str, response: &mut ProxyResponse, metrics: &Metrics) -> Result<()> {
+        self.normalize(url, response, metrics);
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl Middleware for SchemaValidation {
+    fn name(&self) -> &'static str {
+        "schemas"
+    }
+
+    async fn after_upstream(&self, url: &str, response: &mut ProxyResponse, metrics: &Metrics) -> Result<()> {
+        self.check(url, response, metrics);
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use crate::{config::MinifyJsonConfig, metrics::Metrics, middleware::Middleware, ProxyResponse};
+use anyhow::Result;
+
+/// Removes whitespace outside JSON strings. Keys keep their order and numbers
+/// their exact text, which a parse and re-serialize wouldn't guarantee.
+fn minify(json: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(json.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    for &byte in json {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+        } else if byte == b'"' {
+            in_string = true;
+        } else if byte.is_ascii_whitespace() {
+            continue;
+        }
+        out.push(byte);
+    }
+    out
+}
+
+pub struct JsonMinifier {
+    prefixes: Vec<String>,
+}
+
+impl JsonMinifier {
+    pub fn new(config: &MinifyJsonConfig) -> Self {
+        JsonMinifier {
+            prefixes: config.prefixes.clone(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Middleware for JsonMinifier {
+    fn name(&self) -> &'static str {
+        "minify-json"
+    }
+
+    async fn after_upstream(&self, url: &str, response: &mut ProxyResponse, metrics: &Metrics) -> Result<()> {
+        let applies = self.prefixes.is_empty() || self.prefixes.iter().any(|prefix| url.starts_with(prefix));
+        if !applies || !response.content_type.contains("json") {
+            return Ok(());
+        }
+        let minified = minify(&response.body);
+        let saved = response.body.len() - minified.len();
+        if saved > 0 {
+            metrics.add("roproxy_json_minified_bytes_saved_total", &[], saved as u64);
+            response.body = minified;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whitespace_inside_strings_survives() {
+        let json = br#"{ "name" : "a b\t",
+            "quote": "say \"hi there\"", "slash": "\\ ", "ids": [ 1, 2.50 ] }"#;
+        assert_eq!(
+            minify(json),
+            br#"{"name":"a b\t","quote":"say \"hi there\"","slash":"\\ ","ids":[1,2.50]}"#
+        );
+        assert_eq!(minify(b"{\"a\":\"\xe2\x80\x83 x\"}\n"), b"{\"a\":\"\xe2\x80\x83 x\"}");
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{credentials::PooledCredential, metrics::Metrics, AppState, ProxyResponse, Rejection, UpstreamRequest};
+use anyhow::Result;
+use rocket::{
+    http::{Status, StatusClass},
+    serde::json::Value,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::debug;
+
+/// Query parameter asking the proxy to wait for a long-running Open Cloud
+/// operation to finish, e.g. `?_wait=30s`. It's never forwarded upstream.
+pub const PARAM: &str = "_wait";
+
+const FIRST_POLL: Duration = Duration::from_millis(500);
+const MAX_POLL: Duration = Duration::from_secs(5);
+
+/// How long `?_wait=` asks to wait: seconds, with an optional `s` or `m`
+/// suffix, capped at `max`.
+pub fn parse_wait(value: &str, max: Duration) -> Result<Duration> {
+    let value = value.trim();
+    let (number, unit) = match value.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        None => (value.strip_suffix('s').unwrap_or(value), 1),
+    };
+    let secs = number
+        .parse::<u64>()
+        .map_err(|_| Rejection::new(Status::BadRequest, format!("Invalid {} duration {}", PARAM, value)))?;
+    Ok(Duration::from_secs(secs.saturating_mul(unit)).min(max))
+}
+
+/// Where to poll the operation at `path`, relative to the API version of the
+/// request that started it: `operations/x` from `/assets/v1/assets` is
+/// `/assets/v1/operations/x`, while v2 paths may already carry their prefix.
+pub fn operation_url(request_url: &str, path: &str) -> Option<String> {
+    let url = reqwest::Url::parse(request_url).ok()?;
+    let segments: Vec<&str> = url.path_segments()?.collect();
+    let version = segments.iter().position(|segment| {
+        segment.len() > 1 && segment.starts_with('v') && segment[1..].bytes().all(|byte| byte.is_ascii_digit())
+    })?;
+    let base = segments[..=version].join("/");
+    let path = path.trim_start_matches('/');
+    let origin = url.origin().ascii_serialization();
+    if path.starts_with(&format!("{}/", base)) {
+        Some(format!("{}/{}", origin, path))
+    } else {
+        Some(format!("{}/{}/{}", origin, base, path))
+    }
+}
+
+// The operation a response is a handle to, if it's one still running.
+fn pending_operation(response: &ProxyResponse) -> Option<String> {
+    let value = response.json()?;
+    let path = value["path"].as_str()?;
+    let running = path.split('/').any(|segment| segment == "operations") && value["done"] != Value::Bool(true);
+    running.then(|| path.to_string())
+}
+
+/// Polls the operation `response` hands back until it's done or `wait` runs
+/// out, answering with the finished operation. One still running by then is
+/// returned as is with a 202, so the client can poll it itself. Anything
+/// that isn't a pending operation passes through untouched.
+pub async fn settle(
+    state: &AppState,
+    url: &str,
+    mut response: ProxyResponse,
+    credential: Option<Arc<PooledCredential>>,
+    wait: Duration,
+) -> Result<ProxyResponse> {
+    let Some(path) = pending_operation(&response) else {
+        return Ok(response);
+    };
+    let Some(poll_url) = operation_url(url, &path) else {
+        return Ok(response);
+    };
+    let deadline = Instant::now() + wait;
+    let mut delay = FIRST_POLL;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            record(&state.metrics, "timeout");
+            response.status = Status::Accepted;
+            response.headers.push(("Retry-After".to_string(), MAX_POLL.as_secs().to_string()));
+            return Ok(response);
+        }
+        tokio::time::sleep(delay.min(remaining)).await;
+        delay = (delay * 2).min(MAX_POLL);
+
+        debug!("Polling operation {}", poll_url);
+        response = state
+            .engine
+            .forward(UpstreamRequest::get(&poll_url).with_credential(credential.clone()))
+            .await?;
+        if response.status.class() != StatusClass::Success {
+            record(&state.metrics, "error");
+            return Ok(response);
+        }
+        if pending_operation(&response).is_none() {
+            record(&state.metrics, "done");
+            return Ok(response);
+        }
+    }
+}
+
+fn record(metrics: &Metrics, result: &str) {
+    metrics.incr("roproxy_operation_waits_total", &[("result", result)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_waits() {
+        let max = Duration::from_secs(120);
+        assert_eq!(parse_wait("30s", max).unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_wait("45", max).unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_wait("1m", max).unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_wait("10m", max).unwrap(), max);
+        assert!(parse_wait("soon", max).is_err());
+        assert!(parse_wait("-5s", max).is_err());
+    }
+
+    #[test]
+    fn resolves_operation_urls() {
+        assert_eq!(
+            operation_url("https://apis.roblox.com/assets/v1/assets", "operations/abc").as_deref(),
+            Some("https://apis.roblox.com/assets/v1/operations/abc")
+        );
+        assert_eq!(
+            operation_url(
+                "https://apis.roblox.com/cloud/v2/universes/1/places/2:publish",
+                "cloud/v2/universes/1/operations/xyz"
+            )
+            .as_deref(),
+            Some("https://apis.roblox.com/cloud/v2/universes/1/operations/xyz")
+        );
+        assert_eq!(
+            operation_url("https://apis.roblox.com/cloud/v2/universes/1/places/2", "universes/1/operations/xyz")
+                .as_deref(),
+            Some("https://apis.roblox.com/cloud/v2/universes/1/operations/xyz")
+        );
+        assert_eq!(operation_url("https://apis.roblox.com/assets/assets", "operations/abc"), None);
+    }
+}
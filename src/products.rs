@@ -0,0 +1,72 @@
+use crate::{
+    helpers::{self, Page, Paged, Upstream},
+    AppState, ErrorResponse, MyRequestGuard,
+};
+use anyhow::Result;
+use rocket::{
+    futures::stream::BoxStream,
+    serde::json::json,
+    State,
+};
+use std::{sync::Arc, time::Duration};
+
+/// Every developer product of a universe, paged through up to
+/// `helpers.max_pages`. Streamed as NDJSON on request; see `helpers::Paged`.
+#[get("/helpers/games/<universe_id>/developer-products")]
+pub async fn developer_products<'r>(
+    universe_id: u64,
+    state: &'r State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Paged<'r>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "developer_products")]);
+
+    let ttl = Duration::from_secs(state.helpers.products_ttl_secs);
+    let pages = numbered_pages(&upstream, universe_id, ttl);
+    let document = json!({ "universeId": universe_id });
+    helpers::paged(guard.request, pages, "developerProducts", document)
+        .await
+        .map_err(|err| ErrorResponse(helpers::rejection(err)))
+}
+
+/// Every game pass of a universe, paged through up to `helpers.max_pages`.
+/// Streamed as NDJSON on request; see `helpers::Paged`.
+#[get("/helpers/games/<universe_id>/game-passes")]
+pub async fn game_passes<'r>(
+    universe_id: u64,
+    state: &'r State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Paged<'r>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "game_passes")]);
+
+    let url = format!(
+        "https://games.roblox.com/v1/games/{}/game-passes?limit=100&sortOrder=Asc",
+        universe_id
+    );
+    let ttl = Duration::from_secs(state.helpers.products_ttl_secs);
+    let pages = upstream.page_stream(&url, ttl);
+    let document = json!({ "universeId": universe_id });
+    helpers::paged(guard.request, pages, "gamePasses", document)
+        .await
+        .map_err(|err| ErrorResponse(helpers::rejection(err)))
+}
+
+// Developer products are paged by number rather than cursor, until
+// `FinalPage`.
+fn numbered_pages<'a>(upstream: &Upstream<'a>, universe_id: u64, ttl: Duration) -> BoxStream<'a, Result<Page>> {
+    let upstream = upstream.clone();
+    helpers::paginate(upstream.max_pages(), move |page, _| {
+        let upstream = upstream.clone();
+        async move {
+            let url = format!(
+                "https://apis.roblox.com/developer-products/v1/developer-products/list?universeId={}&page={}",
+                universe_id, page
+            );
+            let body = upstream.get(&url, ttl).await?;
+            let data = body["DeveloperProducts"].as_array().cloned().unwrap_or_default();
+            let last = data.is_empty() || body["FinalPage"].as_bool().unwrap_or(true);
+            Ok((data, (!last).then(String::new)))
+        }
+    })
+}
@@ -0,0 +1,52 @@
+use crate::ProxyResponse;
+use rocket::serde::json::Value;
+use std::collections::BTreeMap;
+
+/// Query parameter clients use to ask for a subset of a JSON response, e.g.
+/// `?_fields=data.id,data.name`. It's never forwarded upstream.
+pub const PARAM: &str = "_fields";
+
+/// A set of dotted field paths, merged into a tree. Arrays are projected
+/// element by element, so `data.id` keeps the `id` of every entry in `data`.
+#[derive(Default)]
+pub struct Projection(BTreeMap<String, Projection>);
+
+impl Projection {
+    pub fn parse<'a>(fields: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut root = Projection::default();
+        for field in fields {
+            let mut node = &mut root;
+            for part in field.trim().split('.').filter(|part| !part.is_empty()) {
+                node = node.0.entry(part.to_string()).or_default();
+            }
+        }
+        root
+    }
+
+    pub fn apply(&self, value: &Value) -> Value {
+        if self.0.is_empty() {
+            return value.clone();
+        }
+        match value {
+            Value::Array(items) => Value::Array(items.iter().map(|item| self.apply(item)).collect()),
+            Value::Object(object) => Value::Object(
+                self.0
+                    .iter()
+                    .filter_map(|(name, projection)| {
+                        Some((name.clone(), projection.apply(object.get(name)?)))
+                    })
+                    .collect(),
+            ),
+            _ => value.clone(),
+        }
+    }
+}
+
+/// Trims a successful JSON response down to `fields`. Anything else (errors,
+/// other content types, bodies that don't parse) passes through untouched.
+pub fn apply(mut response: ProxyResponse, fields: &str) -> ProxyResponse {
+    if let Some(value) = response.json() {
+        response.set_json(&Projection::parse(fields.split(',')).apply(&value));
+    }
+    response
+}
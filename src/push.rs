@@ -0,0 +1,207 @@
+use crate::{client_cert, config::PushConfig, tenants::same_secret, AppState, ErrorResponse, MyRequestGuard, Rejection};
+use anyhow::{Context as _, Result};
+use rocket::{
+    data::ToByteUnit,
+    http::Status,
+    serde::{
+        json::{self, json, Json, Value},
+        Serialize,
+    },
+    Data, Request, Route, State,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::watch;
+
+pub fn routes() -> Vec<Route> {
+    routes![publish, poll]
+}
+
+#[derive(Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PushMessage {
+    id: u64,
+    published_at_ms: u128,
+    body: Value,
+    #[serde(skip)]
+    stored: Instant,
+}
+
+struct Channel {
+    messages: VecDeque<PushMessage>,
+    last_id: u64,
+    // Carries the latest message ID so waiting pollers wake on publish.
+    latest: watch::Sender<u64>,
+    last_used: Instant,
+}
+
+/// Mailboxes for game servers, which can't accept pushes: external services
+/// publish to a channel and servers long-poll it for anything newer than the
+/// last message ID they saw.
+pub struct PushChannels {
+    channels: Mutex<HashMap<String, Channel>>,
+    config: PushConfig,
+}
+
+impl PushChannels {
+    pub fn new(config: PushConfig) -> Self {
+        PushChannels {
+            channels: Mutex::default(),
+            config,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    fn channel<'a>(
+        &self,
+        channels: &'a mut HashMap<String, Channel>,
+        name: &str,
+    ) -> Result<&'a mut Channel> {
+        let ttl = Duration::from_secs(self.config.message_ttl_secs);
+        if !channels.contains_key(name) && channels.len() >= self.config.max_channels {
+            channels.retain(|_, channel| {
+                channel.last_used.elapsed() < ttl || channel.latest.receiver_count() > 0
+            });
+            if channels.len() >= self.config.max_channels {
+                return Err(Rejection::new(Status::ServiceUnavailable, "Too many push channels").into());
+            }
+        }
+        let channel = channels.entry(name.to_string()).or_insert_with(|| Channel {
+            messages: VecDeque::new(),
+            last_id: 0,
+            latest: watch::channel(0).0,
+            last_used: Instant::now(),
+        });
+        channel.last_used = Instant::now();
+        channel.messages.retain(|message| message.stored.elapsed() < ttl);
+        Ok(channel)
+    }
+
+    pub fn publish(&self, name: &str, body: Value) -> Result<u64> {
+        let mut channels = self.channels.lock().unwrap();
+        let channel = self.channel(&mut channels, name)?;
+        channel.last_id += 1;
+        channel.messages.push_back(PushMessage {
+            id: channel.last_id,
+            published_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_millis()),
+            body,
+            stored: Instant::now(),
+        });
+        while channel.messages.len() > self.config.max_messages {
+            channel.messages.pop_front();
+        }
+        channel.latest.send_replace(channel.last_id);
+        Ok(channel.last_id)
+    }
+
+    /// Returns messages newer than `cursor`, waiting up to `hold` for one to
+    /// arrive. Without a cursor only messages published from now on count.
+    pub async fn poll(
+        &self,
+        name: &str,
+        cursor: Option<u64>,
+        hold: Duration,
+    ) -> Result<(u64, Vec<PushMessage>)> {
+        let (cursor, mut latest) = {
+            let mut channels = self.channels.lock().unwrap();
+            let channel = self.channel(&mut channels, name)?;
+            // A cursor from before a restart would otherwise never match.
+            let cursor = cursor.filter(|cursor| *cursor <= channel.last_id).unwrap_or(channel.last_id);
+            (cursor, channel.latest.subscribe())
+        };
+
+        if *latest.borrow_and_update() == cursor {
+            let _ = tokio::time::timeout(hold, latest.changed()).await;
+        }
+
+        let mut channels = self.channels.lock().unwrap();
+        let channel = self.channel(&mut channels, name)?;
+        let messages: Vec<_> = channel
+            .messages
+            .iter()
+            .filter(|message| message.id > cursor)
+            .cloned()
+            .collect();
+        let cursor = messages.last().map_or(cursor, |message| message.id);
+        Ok((cursor, messages))
+    }
+}
+
+// Channels belong to the caller's tenant when tenants are configured.
+fn channel_name(state: &AppState, req: &Request<'_>, channel: &str) -> Result<String> {
+    state.screen_client(req)?;
+    let api_key = client_cert::api_key(req);
+    Ok(match state.tenants.authenticate(api_key, &state.metrics)? {
+        Some(tenant) => format!("{}:{}", tenant.name, channel),
+        None => channel.to_string(),
+    })
+}
+
+#[post("/push/<channel>", data = "<data>")]
+async fn publish(
+    channel: &str,
+    data: Data<'_>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let req = guard.request;
+    let config = &state.push.config;
+    if let Some(token) = &config.publish_token {
+        let presented = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !presented.is_some_and(|presented| same_secret(presented, token)) {
+            return Err(ErrorResponse(
+                Rejection::new(Status::Unauthorized, "Missing or invalid publish token").into(),
+            ));
+        }
+    }
+    let name = channel_name(state, req, channel)?;
+
+    let body = data
+        .open(config.max_message_bytes.bytes())
+        .into_bytes()
+        .await
+        .context("Failed to read message body")?;
+    if !body.is_complete() {
+        return Err(ErrorResponse(
+            Rejection::new(Status::PayloadTooLarge, "Message is too large").into(),
+        ));
+    }
+    // JSON bodies are delivered as JSON, anything else as a string.
+    let body = json::from_slice(&body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+
+    let id = state.push.publish(&name, body)?;
+    state.metrics.incr("roproxy_push_published_total", &[]);
+    Ok(Json(json!({ "id": id })))
+}
+
+#[get("/poll/<channel>?<cursor>&<wait>")]
+async fn poll(
+    channel: &str,
+    cursor: Option<u64>,
+    wait: Option<u64>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let name = channel_name(state, guard.request, channel)?;
+    let config = &state.push.config;
+    let hold = Duration::from_secs(wait.unwrap_or(config.default_hold_secs).min(config.max_hold_secs));
+
+    let _waiting = state.metrics.hold_gauge("roproxy_push_pollers");
+    let (cursor, messages) = state.push.poll(&name, cursor, hold).await?;
+    state
+        .metrics
+        .add("roproxy_push_delivered_total", &[], messages.len() as u64);
+    Ok(Json(json!({ "cursor": cursor, "messages": messages })))
+}
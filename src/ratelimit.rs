@@ -0,0 +1,93 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+struct Bucket {
+    // May go negative while callers are queued for future tokens.
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket holding up to `limit` tokens, refilled continuously at
+/// `limit` per `window`.
+pub struct TokenBucket {
+    limit: f64,
+    per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        TokenBucket {
+            limit: limit as f64,
+            per_sec: limit as f64 / window.as_secs_f64().max(1.0),
+            bucket: Mutex::new(Bucket {
+                tokens: limit as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_sec).min(self.limit);
+        bucket.updated = now;
+    }
+
+    /// Reserves a token if one is available within `max_wait`, returning how
+    /// long the caller has to wait for it. Otherwise returns how long until a
+    /// token would be available, without reserving anything.
+    pub fn take(&self, max_wait: Duration) -> Result<Duration, Duration> {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket);
+        let wait = Duration::from_secs_f64(((1.0 - bucket.tokens) / self.per_sec).max(0.0));
+        if wait > max_wait {
+            return Err(wait);
+        }
+        bucket.tokens -= 1.0;
+        Ok(wait)
+    }
+
+    /// Empties the bucket so the next callers wait for a refill.
+    pub fn drain(&self) {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket);
+        bucket.tokens = bucket.tokens.min(0.0);
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit as u32
+    }
+
+    /// Tokens available right now and callers currently queued for one.
+    pub fn levels(&self) -> (u32, u32) {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket);
+        (
+            bucket.tokens.max(0.0).floor() as u32,
+            (-bucket.tokens).max(0.0).ceil() as u32,
+        )
+    }
+
+    /// How long a caller arriving now would wait for a token.
+    pub fn wait(&self) -> Duration {
+        self.wait_for(1.0)
+    }
+
+    /// How long until `tokens` have built up, for a caller that only takes
+    /// one when it can leave the rest to others.
+    pub fn wait_for(&self, tokens: f64) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket);
+        Duration::from_secs_f64(((tokens.min(self.limit) - bucket.tokens) / self.per_sec).max(0.0))
+    }
+
+    /// How long until the bucket is full again.
+    pub fn until_full(&self) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap();
+        self.refill(&mut bucket);
+        Duration::from_secs_f64((self.limit - bucket.tokens) / self.per_sec)
+    }
+}
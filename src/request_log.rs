@@ -0,0 +1,177 @@
+use crate::{config::LogSamplingConfig, trace};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    serde::{json, Serialize},
+    Data, Request, Response,
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+use tracing::info;
+
+/// One finished request, as streamed to `/admin/logs`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RequestLog {
+    pub time_ms: u128,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub elapsed_ms: u128,
+    pub client: Option<String>,
+    pub tenant: Option<String>,
+    pub key: Option<String>,
+    pub upstream: Option<String>,
+}
+
+impl RequestLog {
+    /// `req` as answered with `status`.
+    pub fn new(req: &Request<'_>, status: u16) -> Self {
+        let context = LogContext::cached(req).lock().unwrap();
+        let started = req.local_cache(|| Started(Instant::now()));
+        RequestLog {
+            time_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_millis()),
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            status,
+            elapsed_ms: started.0.elapsed().as_millis(),
+            client: req.client_ip().map(|ip| ip.to_string()),
+            tenant: context.tenant.clone(),
+            key: context.key.clone(),
+            upstream: context.upstream.clone(),
+        }
+    }
+}
+
+/// Details only the proxy handler knows, filled in as it resolves them.
+#[derive(Default)]
+pub struct LogContext {
+    tenant: Option<String>,
+    key: Option<String>,
+    upstream: Option<String>,
+}
+
+impl LogContext {
+    fn cached<'r>(req: &'r Request<'_>) -> &'r Mutex<LogContext> {
+        req.local_cache(|| Mutex::new(LogContext::default()))
+    }
+
+    pub fn set_tenant(req: &Request<'_>, tenant: &str, key: Option<&str>) {
+        let mut context = Self::cached(req).lock().unwrap();
+        context.tenant = Some(tenant.to_string());
+        context.key = key.map(str::to_string);
+    }
+
+    pub fn set_upstream(req: &Request<'_>, url: &str) {
+        Self::cached(req).lock().unwrap().upstream = Some(url.to_string());
+    }
+}
+
+struct Started(Instant);
+
+/// Publishes every finished request to whoever is tailing the logs. Sending
+/// is a no-op when nobody is subscribed.
+pub struct RequestLogger {
+    sender: broadcast::Sender<RequestLog>,
+}
+
+impl RequestLogger {
+    pub fn new(sender: broadcast::Sender<RequestLog>) -> Self {
+        RequestLogger { sender }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request log stream",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| Started(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        // Tailing the admin API would mostly show the tail itself.
+        if self.sender.receiver_count() == 0 || req.uri().path().starts_with("/admin") {
+            return;
+        }
+        let _ = self.sender.send(RequestLog::new(req, res.status().code));
+    }
+}
+
+/// Logs finished requests under the `access` target, a sampled share of
+/// them so busy deployments don't log every request. Sampling is by count
+/// rather than at random: a rate of 0.01 logs every hundredth request.
+pub struct AccessLog {
+    config: LogSamplingConfig,
+    successes: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl AccessLog {
+    pub fn new(config: &LogSamplingConfig) -> Self {
+        AccessLog {
+            config: config.clone(),
+            successes: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+}
+
+// Whether the `n`th request (counting from one) is in a sample of `rate`.
+fn sampled(n: u64, rate: f64) -> bool {
+    let rate = rate.clamp(0.0, 1.0);
+    (n as f64 * rate).floor() > ((n - 1) as f64 * rate).floor()
+}
+
+#[rocket::async_trait]
+impl Fairing for AccessLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Access log",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let status = res.status().code;
+        let (seen, rate) = if status >= 400 {
+            (&self.errors, self.config.error_rate)
+        } else {
+            (&self.successes, self.config.success_rate)
+        };
+        let n = seen.fetch_add(1, Ordering::Relaxed) + 1;
+        if !sampled(n, rate) && trace::id(req).is_none() {
+            return;
+        }
+        if let Ok(line) = json::to_string(&RequestLog::new(req, status)) {
+            info!(target: "access", "{}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_by_count() {
+        let count = |rate| (1..=1000).filter(|&n| sampled(n, rate)).count();
+        assert_eq!(count(1.0), 1000);
+        assert_eq!(count(0.01), 10);
+        assert_eq!(count(0.25), 250);
+        assert_eq!(count(0.0), 0);
+        assert!(sampled(1, 1.0) && !sampled(1, 0.5) && sampled(2, 0.5));
+    }
+}
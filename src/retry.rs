@@ -0,0 +1,71 @@
+use crate::{config::RetriesConfig, metrics::Metrics};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+struct Window {
+    started: Instant,
+    requests: u32,
+    retries: u32,
+}
+
+/// Caps retries at a share of recent traffic. When Roblox is down every
+/// request fails, and retrying each one would multiply the load on it just
+/// as it tries to recover; past the budget requests fail fast instead.
+pub struct RetryBudget {
+    config: RetriesConfig,
+    window: Mutex<Window>,
+}
+
+impl RetryBudget {
+    pub fn new(config: &RetriesConfig) -> Self {
+        RetryBudget {
+            config: config.clone(),
+            window: Mutex::new(Window {
+                started: Instant::now(),
+                requests: 0,
+                retries: 0,
+            }),
+        }
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.config.max_retries
+    }
+
+    fn current(&self) -> std::sync::MutexGuard<'_, Window> {
+        let mut window = self.window.lock().unwrap();
+        if window.started.elapsed() >= Duration::from_secs(self.config.window_secs) {
+            *window = Window {
+                started: Instant::now(),
+                requests: 0,
+                retries: 0,
+            };
+        }
+        window
+    }
+
+    /// Counts a request toward the traffic retries are budgeted against.
+    pub fn record_request(&self) {
+        self.current().requests += 1;
+    }
+
+    /// Takes a retry from the budget, or returns false if it's spent.
+    pub fn try_retry(&self, metrics: &Metrics) -> bool {
+        let mut window = self.current();
+        let allowed = (window.requests as f64 * self.config.budget_ratio) as u32 + self.config.min_retries;
+        if window.retries >= allowed {
+            metrics.incr("roproxy_retries_total", &[("result", "budget_exhausted")]);
+            return false;
+        }
+        window.retries += 1;
+        metrics.incr("roproxy_retries_total", &[("result", "retried")]);
+        true
+    }
+
+    /// How long to wait before retry number `attempt`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.config.backoff_ms) * attempt
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    config::{RewritesConfig, UpstreamTargetsConfig},
+    metrics::Metrics,
+    tenants::ApiKey,
+    Rejection,
+};
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use rocket::http::Status;
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Picks a named upstream for one request, e.g. `X-Proxy-Upstream: thumbnails`.
+pub const UPSTREAM_HEADER: &str = "X-Proxy-Upstream";
+
+struct Rule {
+    name: String,
+    pattern: Regex,
+    target: String,
+}
+
+/// Keeps old client code working after Roblox moves an endpoint: requests
+/// are rewritten to their current location before anything else looks at
+/// the upstream URL. The first matching rule wins.
+pub struct Rewrites {
+    rules: Vec<Rule>,
+}
+
+impl Rewrites {
+    pub fn new(config: &RewritesConfig) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    name: rule.name.clone(),
+                    pattern: Regex::new(&rule.pattern)
+                        .with_context(|| format!("Invalid pattern for rewrite {}", rule.name))?,
+                    target: rule.target.clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Rewrites { rules })
+    }
+
+    /// Returns the upstream URL for `target` (path and query, no leading
+    /// slash) if a rule rewrites it.
+    pub fn apply(&self, target: &str, metrics: &Metrics) -> Option<String> {
+        let rule = self.rules.iter().find(|rule| rule.pattern.is_match(target))?;
+        let rewritten = rule.pattern.replace(target, rule.target.as_str());
+        metrics.incr("roproxy_rewrites_total", &[("rule", &rule.name)]);
+        debug!("Rewrote {} to {} ({})", target, rewritten, rule.name);
+        Some(if rewritten.starts_with("https://") || rewritten.starts_with("http://") {
+            rewritten.into_owned()
+        } else {
+            format!("https://www.roblox.com/{}", rewritten.trim_start_matches('/'))
+        })
+    }
+}
+
+/// Named upstreams that trusted keys can send a request to directly.
+pub struct UpstreamTargets {
+    targets: HashMap<String, String>,
+}
+
+impl UpstreamTargets {
+    pub fn new(config: &UpstreamTargetsConfig) -> Result<Self> {
+        let mut targets = HashMap::new();
+        for target in &config.targets {
+            let url = reqwest::Url::parse(&target.url)
+                .with_context(|| format!("Invalid URL for upstream {}", target.name))?;
+            if !["https", "http"].contains(&url.scheme()) {
+                return Err(anyhow!("Upstream {} must be an http(s) URL", target.name));
+            }
+            targets.insert(target.name.clone(), target.url.trim_end_matches('/').to_string());
+        }
+        Ok(UpstreamTargets { targets })
+    }
+
+    /// The URL for `target` (path and query, no leading slash) on the
+    /// upstream named `name`, if `key` may use it.
+    pub fn resolve(&self, name: &str, key: Option<&ApiKey>, target: &str, metrics: &Metrics) -> Result<String> {
+        if !key.is_some_and(|key| key.may_target(name)) {
+            metrics.incr("roproxy_upstream_override_rejections_total", &[("reason", "forbidden")]);
+            return Err(Rejection::new(
+                Status::Forbidden,
+                format!("This proxy key is not allowed to choose upstream {}", name),
+            )
+            .into());
+        }
+        let Some(base) = self.targets.get(name) else {
+            metrics.incr("roproxy_upstream_override_rejections_total", &[("reason", "unknown")]);
+            let mut known: Vec<_> = self.targets.keys().cloned().collect();
+            known.sort();
+            return Err(Rejection::new(Status::BadRequest, format!("Unknown upstream {}", name))
+                .with_field("allowed", known)
+                .into());
+        };
+        metrics.incr("roproxy_upstream_overrides_total", &[("upstream", name)]);
+        debug!("Sending {} to upstream {}", target, name);
+        Ok(format!("{}/{}", base, target))
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{
+    config::{SchemaRuleConfig, SchemaValidationConfig},
+    metrics::Metrics,
+    ProxyResponse,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use jsonschema::Validator;
+use rocket::serde::json;
+use std::fs;
+use tracing::{info, warn};
+
+/// How many violations to quote when logging a drifted response.
+const LOGGED_ERRORS: usize = 3;
+
+struct Rule {
+    config: SchemaRuleConfig,
+    validator: Validator,
+}
+
+/// Checks upstream JSON against the shape clients were built for, so a
+/// silent Roblox API change shows up in logs and metrics before it shows up
+/// as broken games. Responses are flagged, never rejected.
+pub struct SchemaValidation {
+    rules: Vec<Rule>,
+    add_header: bool,
+}
+
+impl SchemaValidation {
+    pub fn new(config: &SchemaValidationConfig) -> Result<Self> {
+        let rules = if config.enabled {
+            config.rules.iter().map(compile).collect::<Result<_>>()?
+        } else {
+            Vec::new()
+        };
+        Ok(SchemaValidation {
+            rules,
+            add_header: config.add_header,
+        })
+    }
+
+    pub fn check(&self, url: &str, response: &mut ProxyResponse, metrics: &Metrics) {
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.config.prefixes.iter().any(|prefix| url.starts_with(prefix)))
+        else {
+            return;
+        };
+        let Some(body) = response.json() else {
+            return;
+        };
+        let name = rule.config.name.as_str();
+
+        let errors: Vec<_> = rule
+            .validator
+            .iter_errors(&body)
+            .map(|error| format!("{}: {}", error.instance_path(), error))
+            .collect();
+        let valid = errors.is_empty();
+        if !valid {
+            warn!(
+                "Response from {} doesn't match schema {} ({} violations): {}",
+                url,
+                name,
+                errors.len(),
+                errors[..errors.len().min(LOGGED_ERRORS)].join("; ")
+            );
+        }
+        let result = if valid { "valid" } else { "invalid" };
+        metrics.incr("roproxy_schema_validations_total", &[("schema", name), ("result", result)]);
+        if self.add_header {
+            response
+                .headers
+                .push(("X-Schema-Valid".to_string(), valid.to_string()));
+        }
+    }
+}
+
+fn compile(config: &SchemaRuleConfig) -> Result<Rule> {
+    let schema = match (&config.schema, &config.schema_file) {
+        (Some(schema), None) => schema.clone(),
+        (None, Some(path)) => {
+            let contents = fs::read(path).with_context(|| format!("Failed to read {}", path))?;
+            json::from_slice(&contents).with_context(|| format!("Failed to parse {}", path))?
+        }
+        _ => bail!("Schema {} needs exactly one of schema or schema_file", config.name),
+    };
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|err| anyhow!("Invalid schema {}: {}", config.name, err))?;
+    info!("Validating responses under {:?} against schema {}", config.prefixes, config.name);
+    Ok(Rule {
+        config: config.clone(),
+        validator,
+    })
+}
@@ -0,0 +1,265 @@
+use crate::{
+    config::{ScriptRuleConfig, ScriptsConfig},
+    engine::UpstreamRequest,
+    metrics::Metrics,
+    middleware::Middleware,
+    ProxyResponse, Rejection,
+};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, KeyInit, Mac};
+use mlua::{Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt, StdLib, Table, Value};
+use rocket::{
+    http::{Method, Status},
+    serde::json,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::{info, warn};
+
+/// Instructions between checks of the script's budget.
+const HOOK_INTERVAL: u32 = 1000;
+
+struct Script {
+    name: String,
+    prefixes: Vec<String>,
+    lua: Mutex<Lua>,
+    /// Hook intervals used by the current call.
+    used: Arc<AtomicU32>,
+}
+
+/// Operator-supplied Lua, run around upstream requests without rebuilding
+/// the proxy. A script defines `before(request)` and/or `after(response)`
+/// and edits the table it is given in place:
+///
+/// - `request`: `method`, `url`, `headers` and `body` (nil when there is
+///   none). Requests a script applies to are read whole rather than
+///   streamed upstream.
+/// - `response`: `url`, `status`, `content_type`, `headers` and `body`.
+///
+/// Header names are lowercase; a repeated header is a list of values. Scripts
+/// get Lua's string, table, math and utf8 libraries plus `proxy.json_decode`,
+/// `proxy.json_encode`, `proxy.sha256`, `proxy.hmac_sha256` (both hex),
+/// `proxy.base64` and `proxy.time`, and nothing that reaches the filesystem
+/// or network. Globals persist between calls. A script that errors fails the
+/// request with a 500.
+pub struct Scripts {
+    scripts: Vec<Script>,
+}
+
+impl Scripts {
+    pub fn new(config: &ScriptsConfig) -> Result<Self> {
+        let scripts = config
+            .rules
+            .iter()
+            .map(|rule| load(rule, config).with_context(|| format!("Failed to load script {}", rule.name)))
+            .collect::<Result<_>>()?;
+        Ok(Scripts { scripts })
+    }
+
+    fn find(&self, url: &str) -> Option<&Script> {
+        self.scripts
+            .iter()
+            .find(|script| script.prefixes.iter().any(|prefix| url.starts_with(prefix)))
+    }
+}
+
+fn load(rule: &ScriptRuleConfig, config: &ScriptsConfig) -> Result<Script> {
+    let source = fs::read_to_string(&rule.file).with_context(|| format!("Failed to read {}", rule.file))?;
+    let lua = Lua::new_with(
+        StdLib::STRING | StdLib::TABLE | StdLib::MATH | StdLib::UTF8,
+        LuaOptions::default(),
+    )?;
+    lua.set_memory_limit(config.max_memory_bytes)?;
+
+    let used = Arc::new(AtomicU32::new(0));
+    let counter = used.clone();
+    let limit = (config.max_instructions / HOOK_INTERVAL).max(1);
+    lua.set_hook(HookTriggers::new().every_nth_instruction(HOOK_INTERVAL), move |_, _| {
+        if counter.fetch_add(1, Ordering::Relaxed) >= limit {
+            return Err(mlua::Error::runtime("instruction limit exceeded"));
+        }
+        Ok(())
+    });
+
+    lua.globals().set("proxy", helpers(&lua)?)?;
+    lua.load(source).set_name(rule.file.as_str()).exec()?;
+    if !lua.globals().contains_key("before")? && !lua.globals().contains_key("after")? {
+        bail!("{} defines neither before nor after", rule.file);
+    }
+    info!("Running script {} for {:?}", rule.name, rule.prefixes);
+    Ok(Script {
+        name: rule.name.clone(),
+        prefixes: rule.prefixes.clone(),
+        lua: Mutex::new(lua),
+        used,
+    })
+}
+
+fn helpers(lua: &Lua) -> mlua::Result<Table<'_>> {
+    let proxy = lua.create_table()?;
+    proxy.set(
+        "json_decode",
+        lua.create_function(|lua, text: mlua::String| {
+            let value: json::Value = json::from_slice(text.as_bytes()).map_err(mlua::Error::external)?;
+            lua.to_value(&value)
+        })?,
+    )?;
+    proxy.set(
+        "json_encode",
+        lua.create_function(|lua, value: Value| {
+            let value: json::Value = lua.from_value(value)?;
+            Ok(value.to_string())
+        })?,
+    )?;
+    proxy.set(
+        "sha256",
+        lua.create_function(|_, data: mlua::String| Ok(hex::encode(Sha256::digest(data.as_bytes()))))?,
+    )?;
+    proxy.set(
+        "hmac_sha256",
+        lua.create_function(|_, (key, data): (mlua::String, mlua::String)| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).map_err(mlua::Error::external)?;
+            mac.update(data.as_bytes());
+            Ok(hex::encode(mac.finalize().into_bytes()))
+        })?,
+    )?;
+    proxy.set(
+        "base64",
+        lua.create_function(|_, data: mlua::String| Ok(STANDARD.encode(data.as_bytes())))?,
+    )?;
+    proxy.set(
+        "time",
+        lua.create_function(|_, ()| {
+            Ok(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+        })?,
+    )?;
+    Ok(proxy)
+}
+
+impl Script {
+    fn run<F>(&self, hook: &'static str, metrics: &Metrics, call: F) -> Result<()>
+    where
+        F: for<'lua> FnOnce(&'lua Lua, Function<'lua>) -> mlua::Result<()>,
+    {
+        let lua = self.lua.lock().unwrap();
+        let Some(function) = lua.globals().get::<_, Option<Function>>(hook)? else {
+            return Ok(());
+        };
+        self.used.store(0, Ordering::Relaxed);
+        metrics.incr("roproxy_script_runs_total", &[("script", &self.name), ("hook", hook)]);
+        call(&lua, function).map_err(|err| {
+            warn!("Script {} failed in {}: {}", self.name, hook, err);
+            metrics.incr("roproxy_script_errors_total", &[("script", &self.name), ("hook", hook)]);
+            Rejection::new(Status::InternalServerError, "Request script failed")
+                .with_field("script", self.name.clone())
+                .into()
+        })
+    }
+}
+
+fn headers_table<'lua>(lua: &'lua Lua, headers: &[(String, String)]) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    for (name, value) in headers {
+        let name = name.to_lowercase();
+        match table.get::<_, Value>(name.as_str())? {
+            Value::Nil => table.set(name, value.as_str())?,
+            Value::Table(values) => values.push(value.as_str())?,
+            previous => {
+                let values = lua.create_sequence_from([previous, Value::String(lua.create_string(value)?)])?;
+                table.set(name, values)?;
+            }
+        }
+    }
+    Ok(table)
+}
+
+fn headers_from<'lua>(lua: &'lua Lua, table: Table<'lua>) -> mlua::Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    for pair in table.pairs::<String, Value>() {
+        let (name, value) = pair?;
+        match value {
+            Value::Table(values) => {
+                for value in values.sequence_values::<String>() {
+                    headers.push((name.clone(), value?));
+                }
+            }
+            value => headers.push((name, lua.unpack(value)?)),
+        }
+    }
+    Ok(headers)
+}
+
+#[rocket::async_trait]
+impl Middleware for Scripts {
+    fn name(&self) -> &'static str {
+        "scripts"
+    }
+
+    fn reads_body(&self, url: &str) -> bool {
+        self.find(url).is_some()
+    }
+
+    async fn before_upstream(&self, request: &mut UpstreamRequest, metrics: &Metrics) -> Result<()> {
+        let Some(script) = self.find(&request.url) else {
+            return Ok(());
+        };
+        script.run("before", metrics, |lua, before| {
+            let table = lua.create_table()?;
+            table.set("method", request.method.as_str())?;
+            table.set("url", request.url.as_str())?;
+            table.set("headers", headers_table(lua, &request.headers)?)?;
+            let bytes = request.body.as_ref().map(|body| body.as_bytes());
+            if let Some(Some(bytes)) = bytes {
+                table.set("body", lua.create_string(bytes)?)?;
+            }
+            let streamed = matches!(bytes, Some(None));
+
+            before.call::<_, ()>(table.clone())?;
+
+            let method: String = table.get("method")?;
+            request.method = Method::from_str(&method)
+                .map_err(|_| mlua::Error::runtime(format!("unknown method {}", method)))?;
+            request.url = table.get("url")?;
+            request.headers = headers_from(lua, table.get("headers")?)?;
+            match table.get::<_, Option<mlua::String>>("body")? {
+                Some(body) => request.body = Some(body.as_bytes().to_vec().into()),
+                None if streamed => {}
+                None => request.body = None,
+            }
+            Ok(())
+        })
+    }
+
+    async fn after_upstream(&self, url: &str, response: &mut ProxyResponse, metrics: &Metrics) -> Result<()> {
+        let Some(script) = self.find(url) else {
+            return Ok(());
+        };
+        script.run("after", metrics, |lua, after| {
+            let table = lua.create_table()?;
+            table.set("url", url)?;
+            table.set("status", response.status.code)?;
+            table.set("content_type", response.content_type.as_str())?;
+            table.set("headers", headers_table(lua, &response.headers)?)?;
+            table.set("body", lua.create_string(&response.body)?)?;
+
+            after.call::<_, ()>(table.clone())?;
+
+            let status: u16 = table.get("status")?;
+            response.status = Status::from_code(status)
+                .ok_or_else(|| mlua::Error::runtime(format!("invalid status {}", status)))?;
+            response.content_type = table.get("content_type")?;
+            response.headers = headers_from(lua, table.get("headers")?)?;
+            response.body = table.get::<_, mlua::String>("body")?.as_bytes().to_vec();
+            Ok(())
+        })
+    }
+}
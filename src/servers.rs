@@ -0,0 +1,108 @@
+use crate::{
+    helpers::{self, Upstream},
+    request_log::LogContext,
+    tenants::ApiKey,
+    AppState, ErrorResponse, MyRequestGuard, Rejection,
+};
+use anyhow::Result;
+use rocket::{
+    http::Status,
+    serde::json::{json, Json, Value},
+    Request, State,
+};
+use std::sync::Arc;
+use tracing::info;
+
+/// Header a restart or shutdown has to carry, set to the ID of the universe
+/// or place it acts on, so a pipeline with the wrong ID in it fails instead
+/// of taking down the wrong game.
+pub const CONFIRM_HEADER: &str = "X-Proxy-Confirm";
+
+const SHUTDOWN: &str = "https://www.roblox.com/games/shutdown-all-instances";
+
+/// Whether `url` restarts or shuts down game servers.
+pub fn is_server_control(url: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+    let path = url.path().trim_end_matches('/').to_lowercase();
+    match url.host_str() {
+        Some("develop.roblox.com") => path.starts_with("/v1/universes/") && path.ends_with("/restart"),
+        Some("www.roblox.com") => path == "/games/shutdown-all-instances",
+        _ => false,
+    }
+}
+
+fn confirm(req: &Request<'_>, id: u64) -> Result<()> {
+    if req.headers().get_one(CONFIRM_HEADER).map(str::trim) == Some(id.to_string().as_str()) {
+        return Ok(());
+    }
+    Err(Rejection::new(
+        Status::PreconditionRequired,
+        format!("Set {} to {} to confirm", CONFIRM_HEADER, id),
+    )
+    .into())
+}
+
+/// Restarts the universe's servers that are running an older version of the
+/// game, the Develop page's "Restart Servers for Updates". Needs
+/// `X-Proxy-Confirm: <universe_id>`.
+#[post("/helpers/universes/<universe_id>/restart")]
+pub async fn restart_servers(
+    universe_id: u64,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    let url = format!("https://develop.roblox.com/v1/universes/{}/restart", universe_id);
+    LogContext::set_upstream(guard.request, &url);
+    confirm(guard.request, universe_id)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "server_restart")]);
+
+    upstream.post(&url, &json!({})).await.map_err(helpers::rejection)?;
+    info!(
+        "Servers of universe {} restarted by proxy key {}",
+        universe_id,
+        upstream.key().map_or("-", ApiKey::label)
+    );
+    Ok(Json(json!({ "universeId": universe_id, "restarted": true })))
+}
+
+/// Shuts down every server of a place, the Develop page's "Shut Down All
+/// Servers". Needs `X-Proxy-Confirm: <place_id>`.
+#[post("/helpers/places/<place_id>/shutdown")]
+pub async fn shutdown_servers(
+    place_id: u64,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    LogContext::set_upstream(guard.request, SHUTDOWN);
+    confirm(guard.request, place_id)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "server_shutdown")]);
+
+    upstream
+        .post(SHUTDOWN, &json!({ "placeId": place_id, "replaceInstances": false }))
+        .await
+        .map_err(helpers::rejection)?;
+    info!(
+        "Servers of place {} shut down by proxy key {}",
+        place_id,
+        upstream.key().map_or("-", ApiKey::label)
+    );
+    Ok(Json(json!({ "placeId": place_id, "shutDown": true })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_server_control() {
+        assert!(is_server_control("https://develop.roblox.com/v1/universes/1/restart"));
+        assert!(is_server_control("https://develop.roblox.com/v1/Universes/1/Restart/"));
+        assert!(is_server_control(SHUTDOWN));
+        assert!(!is_server_control("https://develop.roblox.com/v1/universes/1"));
+        assert!(!is_server_control("https://games.roblox.com/v1/universes/1/restart"));
+    }
+}
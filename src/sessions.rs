@@ -0,0 +1,107 @@
+use crate::{config::SessionsConfig, credentials::set_cookie};
+use reqwest::{
+    cookie::{CookieStore, Jar},
+    header::HeaderValue,
+    Url,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+struct Session {
+    jar: Arc<Jar>,
+    last_used: Instant,
+}
+
+/// Server-side cookie jars keyed by the client's `X-Proxy-Session` header, so
+/// multi-step authenticated flows keep their cookies between requests without
+/// the client ever seeing `Set-Cookie`.
+pub struct SessionJars {
+    sessions: Mutex<HashMap<String, Session>>,
+    enabled: bool,
+    idle_ttl: Duration,
+    max_sessions: usize,
+}
+
+impl SessionJars {
+    pub fn new(config: &SessionsConfig) -> Self {
+        SessionJars {
+            sessions: Mutex::default(),
+            enabled: config.enabled,
+            idle_ttl: Duration::from_secs(config.idle_ttl_secs),
+            max_sessions: config.max_sessions,
+        }
+    }
+
+    /// Returns the jar for `id`, creating it if needed, or `None` when
+    /// sessions are turned off.
+    pub fn jar(&self, id: &str) -> Option<Arc<Jar>> {
+        if !self.enabled {
+            return None;
+        }
+        let mut sessions = self.sessions.lock().unwrap();
+        if !sessions.contains_key(id) && sessions.len() >= self.max_sessions {
+            sessions.retain(|_, session| session.last_used.elapsed() < self.idle_ttl);
+            if sessions.len() >= self.max_sessions {
+                let oldest = sessions
+                    .iter()
+                    .min_by_key(|(_, session)| session.last_used)
+                    .map(|(id, _)| id.clone());
+                if let Some(oldest) = oldest {
+                    sessions.remove(&oldest);
+                }
+            }
+        }
+
+        let session = sessions.entry(id.to_string()).or_insert_with(|| Session {
+            jar: Arc::default(),
+            last_used: Instant::now(),
+        });
+        if session.last_used.elapsed() >= self.idle_ttl {
+            session.jar = Arc::default();
+        }
+        session.last_used = Instant::now();
+        Some(session.jar.clone())
+    }
+}
+
+/// Adds the jar's cookies for `url` to the outgoing headers. Cookies the
+/// client sent explicitly win over stored ones.
+pub fn attach_cookies(jar: &Jar, url: &str, headers: &mut Vec<(String, String)>) {
+    let Ok(url) = Url::parse(url) else { return };
+    let Some(stored) = jar.cookies(&url) else { return };
+    let Ok(stored) = stored.to_str() else { return };
+
+    let sent: Vec<String> = headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+        .flat_map(|(_, value)| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('=').map(|(name, _)| name.to_string()))
+        .collect();
+
+    for cookie in stored.split("; ") {
+        if let Some((name, value)) = cookie.split_once('=') {
+            if !sent.iter().any(|sent| sent == name) {
+                set_cookie(headers, name, value);
+            }
+        }
+    }
+}
+
+/// Moves `Set-Cookie` headers from an upstream response into the jar.
+pub fn store_cookies(jar: &Jar, url: &str, headers: &mut Vec<(String, String)>) {
+    let Ok(url) = Url::parse(url) else { return };
+    let mut set_cookies = Vec::new();
+    headers.retain(|(name, value)| {
+        if !name.eq_ignore_ascii_case("set-cookie") {
+            return true;
+        }
+        if let Ok(value) = HeaderValue::from_str(value) {
+            set_cookies.push(value);
+        }
+        false
+    });
+    jar.set_cookies(&mut set_cookies.iter(), &url);
+}
@@ -0,0 +1,183 @@
+use crate::{config::SharedStateConfig, metrics::Metrics};
+use anyhow::{Context, Result};
+use redis::aio::ConnectionManager;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
+
+/// How long Redis is left alone after a failed call, so an outage doesn't
+/// add a timeout to every request.
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// State the replicas of one deployment share through Redis when
+/// `shared_state.redis_url` is set: budget counters, budget holds after
+/// Roblox throttles any of them, and CSRF tokens. Without Redis, or while it
+/// can't be reached, each instance falls back to its own state, so an
+/// outage costs accuracy but never fails a request.
+pub struct SharedState {
+    client: Option<redis::Client>,
+    connection: OnceCell<ConnectionManager>,
+    prefix: String,
+    timeout: Duration,
+    metrics: Arc<Metrics>,
+    down_until: Mutex<Option<Instant>>,
+    /// Values also kept here, with their expiry, for when Redis is down.
+    local: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl SharedState {
+    pub fn new(config: &SharedStateConfig, metrics: Arc<Metrics>) -> Result<Self> {
+        let client = config
+            .redis_url
+            .as_deref()
+            .map(redis::Client::open)
+            .transpose()
+            .context("Invalid shared_state.redis_url")?;
+        Ok(SharedState {
+            client,
+            connection: OnceCell::new(),
+            prefix: config.key_prefix.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+            metrics,
+            down_until: Mutex::default(),
+            local: Mutex::default(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Runs `command` against Redis within the timeout, or gives up on it.
+    async fn run<T, F>(&self, command: impl FnOnce(ConnectionManager) -> F) -> Option<T>
+    where
+        F: Future<Output = redis::RedisResult<T>>,
+    {
+        let client = self.client.as_ref()?;
+        if self.down_until.lock().unwrap().is_some_and(|until| until > Instant::now()) {
+            return None;
+        }
+        let result = tokio::time::timeout(self.timeout, async {
+            let connection = self
+                .connection
+                .get_or_try_init(|| async {
+                    let connection = ConnectionManager::new(client.clone()).await?;
+                    info!("Connected to Redis for shared state");
+                    Ok::<_, redis::RedisError>(connection)
+                })
+                .await?;
+            command(connection.clone()).await
+        })
+        .await;
+        let error = match result {
+            Ok(Ok(value)) => return Some(value),
+            Ok(Err(err)) => {
+                warn!("Shared state unavailable, using local state: {}", err);
+                "redis"
+            }
+            Err(_) => {
+                warn!("Shared state timed out, using local state");
+                "timeout"
+            }
+        };
+        self.metrics.incr("roproxy_shared_state_errors_total", &[("error", error)]);
+        *self.down_until.lock().unwrap() = Some(Instant::now() + RETRY_AFTER);
+        None
+    }
+
+    /// Counts a request against the fixed window of `window` that `name` is
+    /// in, across every replica. Returns the count so far and how long
+    /// until the window ends, or `None` without Redis.
+    pub async fn count(&self, name: &str, window: Duration) -> Option<(u64, Duration)> {
+        let window_ms = window.as_millis().max(1) as u64;
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_millis() as u64;
+        let key = format!("{}{}:{}", self.prefix, name, now_ms / window_ms);
+        let (count, _): (u64, i64) = self
+            .run(|mut connection| async move {
+                redis::pipe()
+                    .atomic()
+                    .cmd("INCR")
+                    .arg(&key)
+                    .cmd("PEXPIRE")
+                    .arg(&key)
+                    .arg(window_ms * 2)
+                    .query_async(&mut connection)
+                    .await
+            })
+            .await?;
+        Some((count, Duration::from_millis(window_ms - now_ms % window_ms)))
+    }
+
+    /// Marks `name` as held for every replica for `duration`.
+    pub async fn hold(&self, name: &str, duration: Duration) {
+        let key = format!("{}{}", self.prefix, name);
+        let millis = duration.as_millis().max(1) as u64;
+        self.run(|mut connection| async move {
+            redis::cmd("SET")
+                .arg(&key)
+                .arg(1)
+                .arg("PX")
+                .arg(millis)
+                .query_async::<()>(&mut connection)
+                .await
+        })
+        .await;
+    }
+
+    /// How much longer `name` is held for, if it is.
+    pub async fn held(&self, name: &str) -> Option<Duration> {
+        let key = format!("{}{}", self.prefix, name);
+        let millis: i64 = self
+            .run(|mut connection| async move { redis::cmd("PTTL").arg(&key).query_async(&mut connection).await })
+            .await?;
+        // -2 for a missing key, -1 for one without an expiry.
+        (millis > 0).then(|| Duration::from_millis(millis as u64))
+    }
+
+    pub async fn get(&self, name: &str) -> Option<String> {
+        let key = format!("{}{}", self.prefix, name);
+        let shared = self
+            .run(|mut connection| {
+                let key = key.clone();
+                async move {
+                    redis::cmd("GET")
+                        .arg(&key)
+                        .query_async::<Option<String>>(&mut connection)
+                        .await
+                }
+            })
+            .await;
+        if let Some(value) = shared {
+            return value;
+        }
+        let mut local = self.local.lock().unwrap();
+        local.retain(|_, (_, expires)| *expires > Instant::now());
+        local.get(&key).map(|(value, _)| value.clone())
+    }
+
+    /// Stores `value` under `name` for `ttl`, in Redis if it's up and
+    /// locally either way.
+    pub async fn set(&self, name: &str, value: &str, ttl: Duration) {
+        let key = format!("{}{}", self.prefix, name);
+        self.local
+            .lock()
+            .unwrap()
+            .insert(key.clone(), (value.to_string(), Instant::now() + ttl));
+        let millis = ttl.as_millis().max(1) as u64;
+        self.run(|mut connection| async move {
+            redis::cmd("SET")
+                .arg(&key)
+                .arg(value)
+                .arg("PX")
+                .arg(millis)
+                .query_async::<()>(&mut connection)
+                .await
+        })
+        .await;
+    }
+}
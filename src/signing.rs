@@ -0,0 +1,101 @@
+use crate::{config::SignedUrlsConfig, Rejection};
+use anyhow::Result;
+use hmac::{Hmac, KeyInit, Mac};
+use rocket::http::{Method, Status};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Query parameters that belong to the signature rather than the upstream
+/// request.
+pub const EXPIRES: &str = "expires";
+pub const SIGNATURE: &str = "sig";
+pub const TENANT: &str = "tenant";
+
+/// Issues and checks time-limited URLs for one method and upstream path. The
+/// signature covers every other query parameter too, so a URL signed for one
+/// thumbnail batch can't be reused for another.
+pub struct UrlSigner {
+    secret: Vec<u8>,
+    max_ttl: Duration,
+}
+
+impl UrlSigner {
+    pub fn new(config: &SignedUrlsConfig) -> Option<Self> {
+        Some(UrlSigner {
+            secret: config.secret.as_ref()?.as_bytes().to_vec(),
+            max_ttl: Duration::from_secs(config.max_ttl_secs),
+        })
+    }
+
+    /// Returns the path and query of a URL granting `method` on `path` until
+    /// `ttl` from now, on behalf of `tenant` if given.
+    pub fn sign(
+        &self,
+        method: Method,
+        path: &str,
+        mut params: HashMap<String, String>,
+        ttl: Duration,
+        tenant: Option<&str>,
+    ) -> Result<(String, u64)> {
+        if ttl > self.max_ttl {
+            return Err(Rejection::new(
+                Status::BadRequest,
+                format!("ttl_secs may be at most {}", self.max_ttl.as_secs()),
+            )
+            .into());
+        }
+        let expires = (SystemTime::now() + ttl).duration_since(UNIX_EPOCH)?.as_secs();
+        params.remove(SIGNATURE);
+        params.insert(EXPIRES.to_string(), expires.to_string());
+        match tenant {
+            Some(tenant) => params.insert(TENANT.to_string(), tenant.to_string()),
+            None => params.remove(TENANT),
+        };
+
+        let path = path.trim_start_matches('/');
+        let signature = hex::encode(self.mac(method, path, &params).finalize().into_bytes());
+        let url = format!("/{}?{}&{}={}", path, canonical_query(&params), SIGNATURE, signature);
+        Ok((url, expires))
+    }
+
+    /// Checks the signature on a request and strips the signing parameters
+    /// from `params`. Returns the tenant the URL was issued for, if any.
+    pub fn verify(
+        &self,
+        method: Method,
+        path: &str,
+        params: &mut HashMap<String, String>,
+    ) -> Result<Option<String>> {
+        let signature = params.remove(SIGNATURE).unwrap_or_default();
+        let valid = hex::decode(&signature).is_ok_and(|signature| {
+            self.mac(method, path, params).verify_slice(&signature).is_ok()
+        });
+        if !valid {
+            return Err(Rejection::new(Status::Forbidden, "Invalid URL signature").into());
+        }
+
+        let expires = params.remove(EXPIRES).and_then(|expires| expires.parse::<u64>().ok());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        if expires.is_none_or(|expires| expires < now) {
+            return Err(Rejection::new(Status::Forbidden, "Signed URL has expired").into());
+        }
+        Ok(params.remove(TENANT))
+    }
+
+    fn mac(&self, method: Method, path: &str, params: &HashMap<String, String>) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC takes keys of any length");
+        mac.update(format!("{}\n/{}\n{}", method, path, canonical_query(params)).as_bytes());
+        mac
+    }
+}
+
+fn canonical_query(params: &HashMap<String, String>) -> String {
+    let mut sorted: Vec<_> = params.iter().collect();
+    sorted.sort();
+    form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(sorted)
+        .finish()
+}
@@ -0,0 +1,276 @@
+use crate::{
+    changes::{self, ChangeEvent},
+    client_cert,
+    config::{ChangeItemsConfig, SnapshotJobConfig, SnapshotsConfig},
+    AppState, ErrorResponse, MyRequestGuard, Rejection, UpstreamRequest,
+};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use rocket::{
+    http::Status,
+    serde::{
+        json::{self, json, Json, Value},
+        Deserialize, Serialize,
+    },
+    Route, State,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tracing::{info, warn};
+
+pub fn routes() -> Vec<Route> {
+    routes![get_snapshot]
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct Snapshot {
+    /// Unix seconds.
+    pub taken_at: i64,
+    pub data: Value,
+}
+
+#[derive(Default)]
+struct Job {
+    items: Option<ChangeItemsConfig>,
+    snapshots: VecDeque<Snapshot>,
+    // Lines in the job's file, which is only rewritten once it holds twice
+    // what's kept.
+    on_disk: usize,
+}
+
+/// JSON responses fetched on a schedule and kept with the time they were
+/// taken, so a dashboard can ask what a group or game looked like at an
+/// earlier point. With `dir` set each job appends to `<dir>/<job>.jsonl`
+/// and picks up where it left off after a restart.
+pub struct Snapshots {
+    jobs: HashMap<String, Mutex<Job>>,
+    dir: Option<PathBuf>,
+    max_per_job: usize,
+}
+
+impl Snapshots {
+    pub fn new(config: &SnapshotsConfig) -> Result<Self> {
+        let dir = config.dir.as_ref().map(PathBuf::from);
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut jobs = HashMap::new();
+        for job in &config.jobs {
+            // Names become file names.
+            let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if job.name.is_empty() || !job.name.chars().all(valid) {
+                bail!("Snapshot job name {:?} may only use letters, digits, - and _", job.name);
+            }
+            let mut loaded = Job {
+                items: job.items.clone(),
+                ..Job::default()
+            };
+            if let Some(dir) = &dir {
+                let path = dir.join(format!("{}.jsonl", job.name));
+                if let Ok(contents) = fs::read_to_string(&path) {
+                    for line in contents.lines() {
+                        loaded.on_disk += 1;
+                        match json::from_str(line) {
+                            Ok(snapshot) => loaded.snapshots.push_back(snapshot),
+                            Err(err) => warn!("Skipping unreadable snapshot in {}: {}", path.display(), err),
+                        }
+                    }
+                    while loaded.snapshots.len() > config.max_per_job {
+                        loaded.snapshots.pop_front();
+                    }
+                    info!("Loaded {} snapshots for {}", loaded.snapshots.len(), job.name);
+                }
+            }
+            jobs.insert(job.name.clone(), Mutex::new(loaded));
+        }
+        Ok(Snapshots {
+            jobs,
+            dir,
+            max_per_job: config.max_per_job,
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.jobs.is_empty()
+    }
+
+    pub fn record(&self, name: &str, snapshot: Snapshot) -> Result<()> {
+        let Some(job) = self.jobs.get(name) else {
+            bail!("Unknown snapshot job {}", name);
+        };
+        let mut job = job.lock().unwrap();
+        job.snapshots.push_back(snapshot.clone());
+        while job.snapshots.len() > self.max_per_job {
+            job.snapshots.pop_front();
+        }
+
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let path = dir.join(format!("{}.jsonl", name));
+        if job.on_disk + 1 > self.max_per_job.saturating_mul(2) {
+            let mut contents = String::new();
+            for snapshot in &job.snapshots {
+                contents.push_str(&json::to_string(snapshot)?);
+                contents.push('\n');
+            }
+            let tmp = path.with_extension("jsonl.tmp");
+            fs::write(&tmp, contents).with_context(|| format!("Failed to write {}", tmp.display()))?;
+            fs::rename(&tmp, &path).with_context(|| format!("Failed to replace {}", path.display()))?;
+            job.on_disk = job.snapshots.len();
+        } else {
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            writeln!(file, "{}", json::to_string(&snapshot)?)
+                .with_context(|| format!("Failed to append to {}", path.display()))?;
+            job.on_disk += 1;
+        }
+        Ok(())
+    }
+
+    /// The latest snapshot taken no later than `at`, or the latest of all.
+    /// `None` if the job is unknown or had nothing yet at that time.
+    pub fn at(&self, name: &str, at: Option<i64>) -> Option<Snapshot> {
+        let job = self.jobs.get(name)?.lock().unwrap();
+        let taken = match at {
+            Some(at) => job.snapshots.partition_point(|snapshot| snapshot.taken_at <= at),
+            None => job.snapshots.len(),
+        };
+        taken.checked_sub(1).map(|index| job.snapshots[index].clone())
+    }
+
+    /// What each snapshot taken after `since` and up to `until` changed from
+    /// the one before it, oldest first. `None` if the job is unknown.
+    pub fn changes(&self, name: &str, since: Option<i64>, until: Option<i64>) -> Option<Vec<ChangeEvent>> {
+        let job = self.jobs.get(name)?.lock().unwrap();
+        let taken_by = |at: i64| job.snapshots.partition_point(|snapshot| snapshot.taken_at <= at);
+        let first = since.map_or(0, taken_by).max(1);
+        let last = until.map_or(job.snapshots.len(), taken_by);
+        let mut events = Vec::new();
+        for index in first..last {
+            let (previous, current) = (&job.snapshots[index - 1], &job.snapshots[index]);
+            events.extend(
+                changes::diff(&previous.data, &current.data, job.items.as_ref())
+                    .into_iter()
+                    .map(|change| ChangeEvent {
+                        at: current.taken_at,
+                        change,
+                    }),
+            );
+        }
+        Some(events)
+    }
+}
+
+/// Starts one background task per configured snapshot job.
+pub fn spawn(state: Arc<AppState>, jobs: &[SnapshotJobConfig]) -> Result<()> {
+    for job in jobs {
+        let schedule = Schedule::from_str(&job.schedule)
+            .with_context(|| format!("Invalid schedule for snapshot job {}", job.name))?;
+        info!("Scheduling snapshot job {} ({})", job.name, job.schedule);
+        tokio::spawn(run(state.clone(), job.clone(), schedule));
+    }
+    Ok(())
+}
+
+async fn run(state: Arc<AppState>, job: SnapshotJobConfig, schedule: Schedule) {
+    for next in schedule.upcoming_owned(Utc) {
+        let delay = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(delay).await;
+
+        let request = UpstreamRequest::get(&job.url).with_credentials(&state.credentials);
+        let data = match state.engine.forward(request).await {
+            Ok(response) => match response.json() {
+                Some(data) => data,
+                None => {
+                    warn!("Snapshot job {}: {} returned a {} without JSON", job.name, job.url, response.status);
+                    state.metrics.incr("roproxy_snapshot_failures_total", &[("job", &job.name)]);
+                    continue;
+                }
+            },
+            Err(err) => {
+                warn!("Snapshot job {}: failed to fetch {}: {:?}", job.name, job.url, err);
+                state.metrics.incr("roproxy_snapshot_failures_total", &[("job", &job.name)]);
+                continue;
+            }
+        };
+        let snapshot = Snapshot {
+            taken_at: Utc::now().timestamp(),
+            data,
+        };
+        let previous = state.snapshots.at(&job.name, None);
+        if let Err(err) = state.snapshots.record(&job.name, snapshot.clone()) {
+            warn!("Snapshot job {}: {:?}", job.name, err);
+        }
+        state.metrics.incr("roproxy_snapshots_taken_total", &[("job", &job.name)]);
+
+        if let (Some(webhook), Some(previous)) = (&job.webhook, previous) {
+            let changes = changes::diff(&previous.data, &snapshot.data, job.items.as_ref());
+            if !changes.is_empty() {
+                notify(&state, &job.name, webhook, snapshot.taken_at, changes).await;
+            }
+        }
+    }
+}
+
+async fn notify(state: &AppState, name: &str, webhook: &str, at: i64, changes: Vec<changes::Change>) {
+    // `content` makes the payload readable as-is by Discord-style webhooks.
+    let payload = json!({
+        "content": format!("{} changed: {}", name, changes::summary(&changes)),
+        "name": name,
+        "at": at,
+        "changes": changes,
+    });
+    match state.engine.client.post(webhook).json(&payload).send().await {
+        Ok(response) if response.status().is_success() => {
+            state.metrics.incr("roproxy_change_webhooks_total", &[("job", name)]);
+        }
+        Ok(response) => warn!("Change webhook for {} returned {}", name, response.status()),
+        Err(err) => warn!("Failed to send changes for {}: {:?}", name, err),
+    }
+}
+
+/// `at` is Unix seconds or an RFC 3339 timestamp.
+pub fn parse_time(at: &str) -> Result<i64> {
+    if let Ok(seconds) = at.parse() {
+        return Ok(seconds);
+    }
+    DateTime::parse_from_rfc3339(at)
+        .map(|time| time.timestamp())
+        .map_err(|_| {
+            Rejection::new(Status::BadRequest, format!("Invalid time {}", at))
+                .with_field("expected", "Unix seconds or RFC 3339")
+                .into()
+        })
+}
+
+#[get("/snapshots/<name>?<at>")]
+fn get_snapshot(
+    name: &str,
+    at: Option<&str>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let req = guard.request;
+    state.screen_client(req)?;
+    state.tenants.authenticate(client_cert::api_key(req), &state.metrics)?;
+    let at = at.map(parse_time).transpose()?;
+
+    let Some(snapshot) = state.snapshots.at(name, at) else {
+        return Err(ErrorResponse(
+            Rejection::new(Status::NotFound, format!("No snapshot of {} at that time", name)).into(),
+        ));
+    };
+    Ok(Json(json!({ "name": name, "taken_at": snapshot.taken_at, "data": snapshot.data })))
+}
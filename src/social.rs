@@ -0,0 +1,136 @@
+use crate::{
+    helpers::{self, Paged, Upstream},
+    AppState, ErrorResponse, MyRequestGuard, Rejection,
+};
+use rocket::{
+    futures::{
+        future::join_all,
+        stream::{self, StreamExt},
+    },
+    http::Status,
+    serde::json::{json, Value},
+    State,
+};
+use std::{sync::Arc, time::Duration};
+
+const LISTS: [&str; 3] = ["friends", "followers", "followings"];
+
+/// A user's friend, follower and following counts in one document, plus
+/// any of the full lists named in `lists` (e.g. `?lists=friends,followers`),
+/// paged through up to `helpers.max_pages`. Lists cut short are named under
+/// `truncated`. A section that fails is reported under `errors` and left
+/// null without failing the rest. Streamed as NDJSON, each line is
+/// `{"list": ..., "item": ...}`, and the last the document without the
+/// lists.
+#[get("/helpers/users/<user_id>/social?<lists>")]
+pub async fn social<'r>(
+    user_id: u64,
+    lists: Option<&str>,
+    state: &'r State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Paged<'r>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    let lists: Vec<&str> = lists
+        .unwrap_or_default()
+        .split(',')
+        .filter(|list| !list.is_empty())
+        .collect();
+    if let Some(unknown) = lists.iter().find(|list| !LISTS.contains(list)) {
+        return Err(ErrorResponse(
+            Rejection::new(Status::BadRequest, format!("Unknown list {}", unknown))
+                .with_field("supported", LISTS.to_vec())
+                .into(),
+        ));
+    }
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "social")]);
+
+    let counts_ttl = Duration::from_secs(state.helpers.social_counts_ttl_secs);
+    let lists_ttl = Duration::from_secs(state.helpers.social_lists_ttl_secs);
+    let base = format!("https://friends.roblox.com/v1/users/{}", user_id);
+    let list_url = |list: &str| match list {
+        // Friends come in one page; the others are paged.
+        "friends" => format!("{}/friends", base),
+        _ => format!("{}/{}?limit=100&sortOrder=Asc", base, list),
+    };
+    let streamed = helpers::wants_ndjson(guard.request);
+    let upstream = &upstream;
+    let (counts, fetched) = tokio::join!(
+        join_all(LISTS.iter().map(|list| {
+            let url = format!("{}/{}/count", base, list);
+            async move { (format!("{}Count", list), upstream.get(&url, counts_ttl).await) }
+        })),
+        join_all(lists.iter().filter(|_| !streamed).map(|list| {
+            let url = list_url(list);
+            async move { (list.to_string(), upstream.pages(&url, lists_ttl).await) }
+        })),
+    );
+
+    let mut document = json!({ "userId": user_id });
+    let mut errors = Vec::new();
+    let mut truncated = Vec::new();
+    for (section, count) in counts {
+        match count {
+            Ok(count) => document[&section] = count["count"].clone(),
+            Err(err) => {
+                document[&section] = Value::Null;
+                errors.push(json!({ "section": section, "error": format!("{:#}", err) }));
+            }
+        }
+    }
+    for (section, list) in fetched {
+        match list {
+            Ok((items, complete)) => {
+                if !complete {
+                    truncated.push(section.clone());
+                }
+                document[&section] = Value::Array(items);
+            }
+            Err(err) => {
+                document[&section] = Value::Null;
+                errors.push(json!({ "section": section, "error": format!("{:#}", err) }));
+            }
+        }
+    }
+    if !streamed {
+        document["truncated"] = json!(truncated);
+        document["errors"] = json!(errors);
+        return Ok(Paged::Json(document));
+    }
+
+    // The lists are fetched side by side, their lines interleaved as pages
+    // arrive.
+    let pages = stream::select_all(lists.iter().map(|list| {
+        let list = list.to_string();
+        upstream
+            .page_stream(&list_url(&list), lists_ttl)
+            .map(move |page| (list.clone(), page))
+            .boxed()
+    }));
+    let lines = stream::unfold(Some((pages, document, truncated, errors)), |state| async move {
+        let (mut pages, mut document, mut truncated, mut errors) = state?;
+        let lines = match pages.next().await {
+            Some((list, Ok(page))) => {
+                if page.complete == Some(false) {
+                    truncated.push(list.clone());
+                }
+                page.items
+                    .into_iter()
+                    .map(|item| json!({ "list": list, "item": item }))
+                    .collect()
+            }
+            Some((list, Err(err))) => {
+                errors.push(json!({ "section": list, "error": format!("{:#}", err) }));
+                Vec::new()
+            }
+            None => {
+                document["truncated"] = json!(truncated);
+                document["errors"] = json!(errors);
+                return Some((vec![document], None));
+            }
+        };
+        Some((lines, Some((pages, document, truncated, errors))))
+    })
+    .flat_map(stream::iter)
+    .boxed();
+    Ok(Paged::Lines(lines))
+}
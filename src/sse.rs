@@ -0,0 +1,73 @@
+use crate::config::SseConfig;
+use rocket::{
+    futures::stream::{self, StreamExt},
+    response::stream::ReaderStream,
+    tokio::io::AsyncRead,
+};
+use std::{
+    io::Cursor,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// An upstream `text/event-stream` body, relayed to the client as it arrives
+/// instead of being buffered until the stream ends.
+#[derive(Clone)]
+pub struct EventStreamBody {
+    // Shared so `ProxyResponse` stays cloneable; only the first reader gets
+    // the stream.
+    response: Arc<Mutex<Option<reqwest::Response>>>,
+    heartbeat: Duration,
+    idle_timeout: Duration,
+}
+
+impl EventStreamBody {
+    pub fn new(response: reqwest::Response, config: &SseConfig) -> Self {
+        EventStreamBody {
+            response: Arc::new(Mutex::new(Some(response))),
+            heartbeat: Duration::from_secs(config.heartbeat_secs),
+            idle_timeout: Duration::from_secs(config.idle_timeout_secs),
+        }
+    }
+
+    pub fn into_reader(self) -> impl AsyncRead + Send + 'static {
+        let response = self.response.lock().unwrap().take();
+        let (heartbeat, idle_timeout) = (self.heartbeat, self.idle_timeout);
+
+        // State: the upstream response, whether the last bytes sent ended an
+        // event, and when upstream last sent anything.
+        let events = stream::unfold(
+            (response, true, Instant::now()),
+            move |(mut response, at_boundary, last_data)| async move {
+                loop {
+                    let upstream = response.as_mut()?;
+                    let chunk = tokio::time::timeout(heartbeat, upstream.chunk()).await;
+                    match chunk {
+                        Ok(Ok(Some(chunk))) => {
+                            let boundary = chunk.ends_with(b"\n\n") || chunk.ends_with(b"\r\n\r\n");
+                            return Some((chunk.to_vec(), (response, boundary, Instant::now())));
+                        }
+                        Ok(Ok(None)) => return None,
+                        Ok(Err(err)) => {
+                            warn!("Upstream event stream failed: {:?}", err);
+                            return None;
+                        }
+                        Err(_) if last_data.elapsed() >= idle_timeout => {
+                            debug!("Closing event stream idle for {:?}", last_data.elapsed());
+                            return None;
+                        }
+                        // A comment line keeps idle connections from being
+                        // dropped by intermediaries. It can only go between
+                        // events, never inside a partially relayed one.
+                        Err(_) if at_boundary => {
+                            return Some((b":\n\n".to_vec(), (response, true, last_data)));
+                        }
+                        Err(_) => {}
+                    }
+                }
+            },
+        );
+        ReaderStream::from(events.map(Cursor::new))
+    }
+}
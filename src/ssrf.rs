@@ -0,0 +1,204 @@
+use crate::{config::UpstreamConfig, metrics::Metrics, tenants::host_matches, Rejection};
+use anyhow::Result;
+use reqwest::Url;
+use rocket::http::Status;
+
+/// Why an upstream URL was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unsafe {
+    ControlCharacter,
+    Backslash,
+    DotSegment,
+    Unparseable,
+    Scheme,
+    Userinfo,
+    Host,
+}
+
+impl Unsafe {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Unsafe::ControlCharacter => "control_character",
+            Unsafe::Backslash => "backslash",
+            Unsafe::DotSegment => "dot_segment",
+            Unsafe::Unparseable => "unparseable",
+            Unsafe::Scheme => "scheme",
+            Unsafe::Userinfo => "userinfo",
+            Unsafe::Host => "host",
+        }
+    }
+}
+
+/// The last check before anything is sent upstream, whatever assembled the
+/// URL: client paths, rewrites, named upstreams, envelopes, background jobs
+/// and WebSocket bridges all pass through it, and so does every redirect.
+/// WebSockets count as their HTTP counterparts. URL parsers disagree
+/// on the edge cases, so anything ambiguous is refused rather than guessed at.
+#[derive(Clone)]
+pub struct UpstreamGuard {
+    hosts: Vec<String>,
+    allow_http: bool,
+}
+
+impl UpstreamGuard {
+    pub fn new(config: &UpstreamConfig) -> Self {
+        UpstreamGuard {
+            hosts: config.allowed_hosts.iter().map(|host| host.to_lowercase()).collect(),
+            allow_http: config.allow_http,
+        }
+    }
+
+    /// Returns `url` in normalized form, or why it may not be sent.
+    pub fn normalize(&self, url: &str) -> Result<String, Unsafe> {
+        // The WHATWG parser silently drops tabs and newlines, so
+        // `www.rob\nlox.com` would otherwise come out as a different host than
+        // anything looking at the raw string saw.
+        if url.chars().any(|c| c.is_ascii_control()) {
+            return Err(Unsafe::ControlCharacter);
+        }
+        let before_query = url.split(['?', '#']).next().unwrap_or_default();
+        // Some parsers read `\` as `/` and others don't, which is how
+        // `https://evil.com\@www.roblox.com` gets two different hosts.
+        if before_query.contains('\\') {
+            return Err(Unsafe::Backslash);
+        }
+        // Dot segments would be resolved away below, but a path that climbs
+        // can slip past URL-prefix rules (budgets, scopes, identities), and
+        // servers that decode `%2f` may climb further than the parser did.
+        let path = before_query.to_lowercase().replace("%2f", "/").replace("%5c", "/");
+        if path
+            .split('/')
+            .any(|segment| matches!(segment.replace("%2e", ".").as_str(), "." | ".."))
+        {
+            return Err(Unsafe::DotSegment);
+        }
+
+        let mut parsed = Url::parse(url).map_err(|_| Unsafe::Unparseable)?;
+        match parsed.scheme() {
+            "https" | "wss" => {}
+            "http" | "ws" if self.allow_http => {}
+            _ => return Err(Unsafe::Scheme),
+        }
+        if !parsed.username().is_empty() || parsed.password().is_some() {
+            return Err(Unsafe::Userinfo);
+        }
+        if !self.allows(&parsed) {
+            return Err(Unsafe::Host);
+        }
+        parsed.set_fragment(None);
+        Ok(parsed.into())
+    }
+
+    /// Whether `url`'s host (with its port, if not the default) is
+    /// allowlisted. IP literals only match when listed as such.
+    pub fn allows(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        self.hosts.iter().any(|pattern| host_matches(pattern, &host))
+    }
+
+    /// [`normalize`](Self::normalize), as a client-facing rejection.
+    pub fn check(&self, url: &str, metrics: &Metrics) -> Result<String> {
+        self.normalize(url).map_err(|reason| {
+            metrics.incr("roproxy_upstream_url_rejections_total", &[("reason", reason.as_str())]);
+            let rejection = match reason {
+                Unsafe::Host | Unsafe::Scheme => {
+                    Rejection::new(Status::Forbidden, "Upstream URL is not allowed").with_field("allowed", self.hosts.clone())
+                }
+                _ => Rejection::new(Status::BadRequest, "Invalid upstream URL"),
+            };
+            rejection.with_field("reason", reason.as_str()).into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> UpstreamGuard {
+        UpstreamGuard::new(&UpstreamConfig::default())
+    }
+
+    #[test]
+    fn roblox_urls_pass_normalized() {
+        let guard = guard();
+        for (url, expected) in [
+            ("https://users.roblox.com/v1/users/1", "https://users.roblox.com/v1/users/1"),
+            ("https://roblox.com", "https://roblox.com/"),
+            ("HTTPS://Users.ROBLOX.com:443/v1/x", "https://users.roblox.com/v1/x"),
+            ("https://www.roblox.com/search?q=a b", "https://www.roblox.com/search?q=a%20b"),
+            ("https://games.roblox.com/v1/games#frag", "https://games.roblox.com/v1/games"),
+            ("https://www.roblox.com/@evil.com/x", "https://www.roblox.com/@evil.com/x"),
+            ("https://www.roblox.com/x?next=../../admin", "https://www.roblox.com/x?next=../../admin"),
+            ("https://www.roblox.com/x?q=a\\b", "https://www.roblox.com/x?q=a\\b"),
+            ("wss://realtime.roblox.com/notifications", "wss://realtime.roblox.com/notifications"),
+        ] {
+            assert_eq!(guard.normalize(url).as_deref(), Ok(expected), "{}", url);
+        }
+    }
+
+    #[test]
+    fn ssrf_payloads_are_refused() {
+        let guard = guard();
+        for (url, reason) in [
+            ("https://www.roblox.com@evil.com/", Unsafe::Userinfo),
+            ("https://roblox.com:pw@evil.com/", Unsafe::Userinfo),
+            ("https://user@www.roblox.com/", Unsafe::Userinfo),
+            ("https://evil.com\\@www.roblox.com/", Unsafe::Backslash),
+            ("https://www.roblox.com\\.evil.com/", Unsafe::Backslash),
+            ("https://www.rob\nlox.com/", Unsafe::ControlCharacter),
+            ("https://www.roblox.com/x\r\nHost: evil.com", Unsafe::ControlCharacter),
+            ("https://www.roblox.com/\tx", Unsafe::ControlCharacter),
+            ("https://www.roblox.com/x\0", Unsafe::ControlCharacter),
+            ("//evil.com/x", Unsafe::Unparseable),
+            ("/v1/users", Unsafe::Unparseable),
+            ("https:evil.com/x", Unsafe::Host),
+            ("https:///evil.com/x", Unsafe::Host),
+            ("https://evil.com/x", Unsafe::Host),
+            ("https://roblox.com.evil.com/", Unsafe::Host),
+            ("https://evilroblox.com/", Unsafe::Host),
+            ("https://www.roblox.com./", Unsafe::Host),
+            ("https://www.roblox.com:8443/", Unsafe::Host),
+            ("https://127.0.0.1/", Unsafe::Host),
+            ("https://2130706433/", Unsafe::Host),
+            ("https://0x7f.1/", Unsafe::Host),
+            ("https://[::1]/", Unsafe::Host),
+            ("https://169.254.169.254/latest/meta-data/", Unsafe::Host),
+            ("https://localhost/", Unsafe::Host),
+            ("http://www.roblox.com/", Unsafe::Scheme),
+            ("ws://realtime.roblox.com/", Unsafe::Scheme),
+            ("wss://realtime.roblox.com@evil.com/", Unsafe::Userinfo),
+            ("file:///etc/passwd", Unsafe::Scheme),
+            ("gopher://www.roblox.com/", Unsafe::Scheme),
+            ("https://www.roblox.com/v1/../../admin", Unsafe::DotSegment),
+            ("https://www.roblox.com/v1/./x", Unsafe::DotSegment),
+            ("https://www.roblox.com/v1/%2e%2e/admin", Unsafe::DotSegment),
+            ("https://www.roblox.com/v1/.%2E/admin", Unsafe::DotSegment),
+            ("https://www.roblox.com/v1%2f..%2fadmin", Unsafe::DotSegment),
+            ("https://www.roblox.com/v1%5C..%5Cadmin", Unsafe::DotSegment),
+            ("https://www.roblox.com/..", Unsafe::DotSegment),
+        ] {
+            assert_eq!(guard.normalize(url), Err(reason), "{:?}", url);
+        }
+    }
+
+    #[test]
+    fn listed_hosts_and_ports_are_allowed() {
+        let guard = UpstreamGuard::new(&UpstreamConfig {
+            allowed_hosts: vec!["127.0.0.1:18080".to_string(), "*.example.com".to_string()],
+            allow_http: true,
+            ..UpstreamConfig::default()
+        });
+        assert_eq!(guard.normalize("http://127.0.0.1:18080/x").as_deref(), Ok("http://127.0.0.1:18080/x"));
+        assert_eq!(guard.normalize("http://127.0.0.1/x"), Err(Unsafe::Host));
+        assert_eq!(guard.normalize("https://a.example.com/").as_deref(), Ok("https://a.example.com/"));
+        assert_eq!(guard.normalize("https://example.com/"), Err(Unsafe::Host));
+        assert_eq!(guard.normalize("https://users.roblox.com/"), Err(Unsafe::Host));
+    }
+}
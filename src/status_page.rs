@@ -0,0 +1,106 @@
+use crate::AppState;
+use rocket::{response::content::RawHtml, State};
+use std::{fmt::Write, sync::Arc, time::Duration};
+
+/// At-a-glance health for self-hosters who don't run a metrics stack. Reads
+/// the same numbers `/metrics` exports.
+#[get("/status")]
+pub fn get_status(state: &State<Arc<AppState>>) -> RawHtml<String> {
+    let metrics = &state.metrics;
+    let requests = metrics.window_count("roproxy_upstream_requests_last_minute", &[]);
+    let errors = metrics.window_count("roproxy_upstream_errors_last_minute", &[]);
+    let hits = metrics.counter("roproxy_cache_requests_total", &[("result", "hit")]);
+    let misses = metrics.counter("roproxy_cache_requests_total", &[("result", "miss")]);
+
+    let mut html = String::new();
+    html.push_str(concat!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">",
+        "<meta http-equiv=\"refresh\" content=\"10\"><title>rusty-roproxy status</title>",
+        "<style>body{font-family:sans-serif;margin:2em;color:#222}",
+        "table{border-collapse:collapse;margin-bottom:1.5em}",
+        "td,th{border:1px solid #ccc;padding:.3em .8em;text-align:left}",
+        ".bad{color:#b00}.ok{color:#080}</style></head><body>",
+        "<h1>rusty-roproxy</h1>"
+    ));
+
+    html.push_str("<table>");
+    row(&mut html, "Uptime", &format_uptime(state.started.elapsed()));
+    row(&mut html, "Upstream requests (last minute)", &format!("{} ({:.2}/s)", requests, requests as f64 / 60.0));
+    row(&mut html, "Upstream errors (last minute)", &format!("{} ({})", errors, percent(errors as u64, requests as u64)));
+    row(&mut html, "Cache hit ratio", &format!("{} ({} hits, {} misses)", percent(hits, hits + misses), hits, misses));
+    row(&mut html, "Requests in flight", &state.inflight.list().len().to_string());
+    // Counts only: the page needs no token, so account and tenant names stay
+    // behind the admin API.
+    let (mut healthy, mut throttled, mut unhealthy) = (0, 0, 0);
+    let pools = std::iter::once(&state.credentials).chain(state.tenants.iter().map(|tenant| &tenant.credentials));
+    for credential in pools.flat_map(|pool| pool.status()) {
+        match (credential.reason.is_some(), credential.throttled) {
+            (true, _) => unhealthy += 1,
+            (false, true) => throttled += 1,
+            (false, false) => healthy += 1,
+        }
+    }
+    row(
+        &mut html,
+        "Credentials",
+        &format!("{} healthy, {} throttled, {} unhealthy", healthy, throttled, unhealthy),
+    );
+    html.push_str("</table>");
+
+    // The proxy has no circuit breaker: budgets are what stop traffic to an
+    // endpoint family, so their state is shown instead.
+    html.push_str("<h2>Rate limit budgets</h2><table><tr><th>Family</th><th>Remaining</th><th>Queued</th><th>State</th></tr>");
+    for budget in state.engine.budgets.status() {
+        let (class, label) = if budget.remaining == 0 {
+            ("bad", "exhausted")
+        } else {
+            ("ok", "open")
+        };
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{} / {} per {}s</td><td>{}</td><td class=\"{}\">{}</td></tr>",
+            escape(&budget.name),
+            budget.remaining,
+            budget.limit,
+            budget.window_secs,
+            budget.queued,
+            class,
+            label
+        );
+    }
+    html.push_str("</table>");
+
+    html.push_str("</table></body></html>");
+
+    RawHtml(html)
+}
+
+fn row(html: &mut String, name: &str, value: &str) {
+    let _ = write!(html, "<tr><th>{}</th><td>{}</td></tr>", name, escape(value));
+}
+
+fn percent(part: u64, whole: u64) -> String {
+    if whole == 0 {
+        return "n/a".to_string();
+    }
+    format!("{:.1}%", part as f64 * 100.0 / whole as f64)
+}
+
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (days, hours, minutes) = (secs / 86_400, secs % 86_400 / 3_600, secs % 3_600 / 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m {}s", minutes, secs % 60)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,114 @@
+use crate::{client_cert, config::TagsConfig, AppState, Rejection};
+use anyhow::Result;
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::Status,
+    Request, Response,
+};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+/// Names the part of a game a request comes from, e.g.
+/// `X-Proxy-Tag: matchmaking`, so its share of the traffic shows up in the
+/// metrics and can be given its own rate limit.
+pub const HEADER: &str = "X-Proxy-Tag";
+
+const MAX_LENGTH: usize = 32;
+/// Label for tags seen after `tags.max_tags` others.
+const OTHER: &str = "other";
+
+/// The tag `req` carries: lowercase letters, digits, `-` and `_`, at most 32
+/// of them. Anything else is a 400, so a typo doesn't quietly go untracked.
+pub fn tag<'r>(req: &'r Request<'_>) -> Result<Option<&'r str>> {
+    parse(req.headers().get_one(HEADER))
+}
+
+/// Checks a tag as sent in the header, for front-ends without a Rocket
+/// request.
+pub fn parse(tag: Option<&str>) -> Result<Option<&str>> {
+    let Some(tag) = tag else {
+        return Ok(None);
+    };
+    let valid = !tag.is_empty()
+        && tag.len() <= MAX_LENGTH
+        && tag
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-' || byte == b'_');
+    if !valid {
+        return Err(Rejection::new(
+            Status::BadRequest,
+            format!("{} must be 1 to {} lowercase letters, digits, - or _", HEADER, MAX_LENGTH),
+        )
+        .into());
+    }
+    Ok(Some(tag))
+}
+
+/// The tags the metrics are labelled with. Past `max_tags` distinct ones,
+/// new tags are counted as `other` so clients can't blow up the series.
+pub struct Tags {
+    max_tags: usize,
+    seen: Mutex<HashSet<String>>,
+}
+
+impl Tags {
+    pub fn new(config: &TagsConfig) -> Self {
+        Tags {
+            max_tags: config.max_tags,
+            seen: Mutex::default(),
+        }
+    }
+
+    pub fn label(&self, tag: &str) -> String {
+        let mut seen = self.seen.lock().unwrap();
+        if seen.contains(tag) {
+            return tag.to_string();
+        }
+        if seen.len() >= self.max_tags {
+            return OTHER.to_string();
+        }
+        seen.insert(tag.to_string());
+        tag.to_string()
+    }
+}
+
+/// Counts tagged requests and their bytes per proxy key and tag.
+pub struct TagMetrics;
+
+#[rocket::async_trait]
+impl Fairing for TagMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Per-tag metrics",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let Ok(Some(tag)) = tag(req) else {
+            return;
+        };
+        let Some(state) = req.rocket().state::<Arc<AppState>>() else {
+            return;
+        };
+        let tag = state.tags.label(tag);
+        let key = client_cert::api_key(req)
+            .and_then(|api_key| state.tenants.iter().find_map(|tenant| tenant.key(api_key)))
+            .map_or("-", |key| key.label());
+        let received = req
+            .headers()
+            .get_one("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .unwrap_or(0);
+        let sent = res.body().preset_size().unwrap_or(0) as u64;
+        let status = res.status().code.to_string();
+        state.metrics.incr(
+            "roproxy_tag_requests_total",
+            &[("key", key), ("tag", &tag), ("status", status.as_str())],
+        );
+        state.metrics.add("roproxy_tag_bytes_total", &[("key", key), ("tag", &tag), ("direction", "in")], received);
+        state.metrics.add("roproxy_tag_bytes_total", &[("key", key), ("tag", &tag), ("direction", "out")], sent);
+    }
+}
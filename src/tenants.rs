@@ -0,0 +1,359 @@
+use crate::{
+    bandwidth::Bandwidth,
+    config::{AccountConfig, ApiKeyConfig, CredentialsConfig, TenantConfig},
+    credentials::CredentialPool,
+    metrics::Metrics,
+    ratelimit::TokenBucket,
+    Rejection,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use rocket::{
+    http::{Method, Status},
+    serde::json::{json, Value},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::warn;
+
+/// A proxy key and what it may reach upstream.
+pub struct ApiKey {
+    key: String,
+    label: String,
+    hosts: Vec<String>,
+    methods: Vec<String>,
+    upstreams: Vec<String>,
+    client_certs: Vec<String>,
+    cache_ttl: bool,
+    permissions: Vec<String>,
+    bandwidth: Bandwidth,
+    expires: Option<DateTime<Utc>>,
+    revoked: bool,
+    tag_limits: HashMap<String, TokenBucket>,
+}
+
+/// Compares secrets in time that doesn't depend on where they first differ,
+/// so response times can't be used to guess one byte by byte. Hashing first
+/// evens out their lengths too.
+pub fn same_secret(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter().zip(b.iter()).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn parse_expiry(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc());
+    }
+    Ok(DateTime::parse_from_rfc3339(value)
+        .with_context(|| format!("Invalid proxy key expiry {}", value))?
+        .with_timezone(&Utc))
+}
+
+impl ApiKey {
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn new(config: &ApiKeyConfig) -> Result<Self> {
+        let config = config.scoped();
+        Ok(ApiKey {
+            label: config
+                .name
+                .unwrap_or_else(|| hex::encode(&Sha256::digest(&config.key)[..4])),
+            hosts: config.hosts.iter().map(|host| host.to_lowercase()).collect(),
+            methods: config.methods.iter().map(|method| method.to_uppercase()).collect(),
+            upstreams: config.upstreams,
+            client_certs: config
+                .client_certs
+                .iter()
+                .map(|cert| match cert.strip_prefix("sha256:") {
+                    Some(fingerprint) => format!("sha256:{}", fingerprint.replace(':', "").to_lowercase()),
+                    None => cert.clone(),
+                })
+                .collect(),
+            cache_ttl: config.cache_ttl,
+            permissions: config.permissions,
+            bandwidth: Bandwidth::new(config.daily_bytes, config.monthly_bytes),
+            expires: config.expires.as_deref().map(parse_expiry).transpose()?,
+            revoked: config.revoked,
+            tag_limits: config
+                .tag_rate_limits
+                .iter()
+                .map(|(tag, limit)| (tag.clone(), TokenBucket::new(limit.limit, Duration::from_secs(limit.window_secs))))
+                .collect(),
+            key: config.key,
+        })
+    }
+
+    pub fn secret(&self) -> &str {
+        &self.key
+    }
+
+    /// Whether a client certificate with this SHA-256 `fingerprint` (lowercase
+    /// hex) and these SANs stands in for the key.
+    pub fn matches_certificate(&self, fingerprint: &str, sans: &[&str]) -> bool {
+        self.client_certs.iter().any(|cert| match cert.split_once(':') {
+            Some(("sha256", expected)) => expected == fingerprint,
+            Some(("san", expected)) => sans.contains(&expected),
+            _ => false,
+        })
+    }
+
+    /// Whether the key may choose how fresh cached responses must be.
+    pub fn may_set_cache_ttl(&self) -> bool {
+        self.cache_ttl
+    }
+
+    /// Whether the key was granted `permission`, e.g. `trades`.
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|granted| granted == permission)
+    }
+
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        self.expires
+    }
+
+    /// Refuses a key that's been revoked or has expired, with a `code`
+    /// saying which, and counts the attempt so an operator can tell whether
+    /// anything still uses it.
+    pub fn check_active(&self, metrics: &Metrics) -> Result<()> {
+        let (reason, rejection) = if self.revoked {
+            (
+                "revoked",
+                Rejection::new(Status::Unauthorized, format!("Proxy key {} has been revoked", self.label))
+                    .with_field("code", "key_revoked"),
+            )
+        } else if let Some(expires) = self.expires.filter(|expires| *expires <= Utc::now()) {
+            (
+                "expired",
+                Rejection::new(Status::Unauthorized, format!("Proxy key {} has expired", self.label))
+                    .with_field("code", "key_expired")
+                    .with_field("expired", expires.to_rfc3339()),
+            )
+        } else {
+            return Ok(());
+        };
+        metrics.incr("roproxy_inactive_key_requests_total", &[("key", &self.label), ("reason", reason)]);
+        warn!("Refused {} proxy key {}", reason, self.label);
+        Err(rejection.into())
+    }
+
+    /// Applies the rate limit the key has for `tag`, if any.
+    pub fn check_tag(&self, tag: Option<&str>, metrics: &Metrics) -> Result<()> {
+        let Some((tag, limiter)) = tag.and_then(|tag| Some((tag, self.tag_limits.get(tag)?))) else {
+            return Ok(());
+        };
+        if let Err(retry_after) = limiter.take(Duration::ZERO) {
+            metrics.incr("roproxy_tag_rate_limited_total", &[("key", &self.label), ("tag", tag)]);
+            return Err(Rejection::new(Status::TooManyRequests, format!("Rate limit exceeded for tag {}", tag))
+                .with_header("Retry-After", retry_after.as_secs_f64().ceil() as u64)
+                .into());
+        }
+        Ok(())
+    }
+
+    /// What the key has moved against its bandwidth quotas.
+    pub fn bandwidth(&self) -> &Bandwidth {
+        &self.bandwidth
+    }
+
+    /// What the key may reach, as configured. Empty `hosts` and `methods`
+    /// allow any.
+    pub fn scopes(&self) -> Value {
+        json!({
+            "hosts": self.hosts,
+            "methods": self.methods,
+            "upstreams": self.upstreams,
+            "permissions": self.permissions,
+            "cache_ttl": self.cache_ttl,
+        })
+    }
+
+    /// Whether the key may send requests to the named upstream.
+    pub fn may_target(&self, upstream: &str) -> bool {
+        self.upstreams.iter().any(|name| name == upstream)
+    }
+
+    /// Rejects requests for a method or upstream host outside the key's
+    /// scope, saying which one was missing.
+    pub fn check_scope(&self, method: Method, url: &str, metrics: &Metrics) -> Result<()> {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .unwrap_or_default();
+        let missing = if !self.methods.is_empty() && !self.methods.iter().any(|m| m == method.as_str()) {
+            Some(("method", method.as_str().to_string(), &self.methods))
+        } else if !self.hosts.is_empty() && !self.hosts.iter().any(|pattern| host_matches(pattern, &host)) {
+            Some(("host", host, &self.hosts))
+        } else {
+            None
+        };
+
+        let Some((scope, requested, allowed)) = missing else {
+            return Ok(());
+        };
+        metrics.incr(
+            "roproxy_scope_violations_total",
+            &[("key", &self.label), ("scope", scope)],
+        );
+        Err(Rejection::new(
+            Status::Forbidden,
+            format!("Proxy key {} is not allowed to use {} {}", self.label, scope, requested),
+        )
+        .with_field("missing_scope", scope)
+        .with_field("requested", requested.as_str())
+        .with_field("allowed", allowed.clone())
+        .into())
+    }
+}
+
+/// Matches `host` against `example.com` or `*.example.com` (subdomains only).
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(suffix) => host
+            .strip_suffix(suffix)
+            .is_some_and(|rest| rest.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+pub struct Tenant {
+    pub name: String,
+    api_keys: Vec<ApiKey>,
+    path_prefix: Option<String>,
+    pub credentials: CredentialPool,
+    limiter: Option<TokenBucket>,
+}
+
+impl Tenant {
+    pub fn api_keys(&self) -> &[ApiKey] {
+        &self.api_keys
+    }
+
+    pub fn key(&self, api_key: &str) -> Option<&ApiKey> {
+        self.api_keys.iter().find(|key| same_secret(&key.key, api_key))
+    }
+
+    /// The tenant's rate limit and what's left of it, if it has one.
+    pub fn rate_limit_status(&self) -> Option<Value> {
+        let limiter = self.limiter.as_ref()?;
+        let (available, _) = limiter.levels();
+        Some(json!({
+            "limit": limiter.limit(),
+            "remaining": available,
+            "retry_after_secs": limiter.wait().as_secs_f64().ceil() as u64,
+        }))
+    }
+
+    pub fn check_rate_limit(&self, metrics: &Metrics) -> Result<()> {
+        let Some(limiter) = &self.limiter else {
+            return Ok(());
+        };
+        if let Err(retry_after) = limiter.take(Duration::ZERO) {
+            metrics.incr("roproxy_tenant_rate_limited_total", &[("tenant", &self.name)]);
+            return Err(Rejection::new(Status::TooManyRequests, "Tenant rate limit exceeded")
+                .with_header("Retry-After", retry_after.as_secs_f64().ceil() as u64)
+                .into());
+        }
+        Ok(())
+    }
+}
+
+/// Tenants sharing one deployment, each with its own credentials, rate limit,
+/// cache namespace and metrics. With none configured the proxy runs in its
+/// original open, single-tenant mode.
+pub struct Tenants {
+    tenants: Vec<Arc<Tenant>>,
+}
+
+impl Tenants {
+    pub fn new(configs: &[TenantConfig], rotation: &CredentialsConfig) -> Result<Self> {
+        let tenants = configs
+            .iter()
+            .map(|config| {
+                let mut accounts = config.accounts.clone();
+                if config.roblosecurity.is_some() || config.open_cloud_key.is_some() {
+                    accounts.insert(
+                        0,
+                        AccountConfig {
+                            name: config.name.clone(),
+                            roblosecurity: config.roblosecurity.clone(),
+                            open_cloud_key: config.open_cloud_key.clone(),
+                        },
+                    );
+                }
+                Ok(Arc::new(Tenant {
+                    name: config.name.clone(),
+                    api_keys: config.api_keys.iter().map(ApiKey::new).collect::<Result<_>>()?,
+                    path_prefix: config.path_prefix.clone(),
+                    credentials: CredentialPool::new(
+                        &accounts,
+                        rotation.strategy,
+                        rotation.affinity,
+                        Duration::from_secs(rotation.throttle_cooldown_secs),
+                    ),
+                    limiter: config.rate_limit.map(|limit| {
+                        TokenBucket::new(limit.limit, Duration::from_secs(limit.window_secs))
+                    }),
+                }))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Tenants { tenants })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tenants.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Tenant>> {
+        self.tenants.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Tenant>> {
+        self.tenants.iter().find(|tenant| tenant.name == name).cloned()
+    }
+
+    /// Identifies the tenant for endpoints that only accept `X-Proxy-Key`, and
+    /// applies its rate limit. `None` when no tenants are configured.
+    pub fn authenticate(&self, api_key: Option<&str>, metrics: &Metrics) -> Result<Option<Arc<Tenant>>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let Some((tenant, _)) = api_key.and_then(|key| self.resolve(Some(key), Path::new(""))) else {
+            return Err(Rejection::new(Status::Unauthorized, "Unknown or missing proxy key").into());
+        };
+        metrics.incr("roproxy_tenant_requests_total", &[("tenant", &tenant.name)]);
+        let key = api_key.and_then(|api_key| tenant.key(api_key));
+        if let Some(key) = key {
+            key.check_active(metrics)?;
+        }
+        tenant.check_rate_limit(metrics)?;
+        if let Some(key) = key {
+            key.bandwidth.check(&key.label, metrics)?;
+        }
+        Ok(Some(tenant))
+    }
+
+    /// Identifies the tenant by its proxy key, or failing that by the first
+    /// path segment. Returns the path with the tenant prefix removed.
+    pub fn resolve(&self, api_key: Option<&str>, path: &Path) -> Option<(Arc<Tenant>, PathBuf)> {
+        if let Some(api_key) = api_key {
+            let tenant = self
+                .tenants
+                .iter()
+                .find(|tenant| tenant.key(api_key).is_some())?;
+            return Some((tenant.clone(), path.to_path_buf()));
+        }
+
+        self.tenants.iter().find_map(|tenant| {
+            let prefix = tenant.path_prefix.as_deref()?;
+            let rest = path.strip_prefix(prefix).ok()?;
+            Some((tenant.clone(), rest.to_path_buf()))
+        })
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+tokio::task_local! {
+    static CURRENT: Arc<Mutex<Timings>>;
+}
+
+/// Where one upstream call spent its time. DNS and connect are zero when a
+/// pooled connection was reused.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Timings {
+    /// Held back by a local budget.
+    pub queue: Duration,
+    pub dns: Duration,
+    /// TCP and TLS, excluding DNS.
+    pub connect: Duration,
+    /// From sending to the response headers, including DNS and connect.
+    pub ttfb: Duration,
+    pub body: Duration,
+}
+
+impl Timings {
+    /// The `Server-Timing` header value, in milliseconds.
+    pub fn header_value(&self) -> String {
+        [
+            ("queue", self.queue),
+            ("dns", self.dns),
+            ("connect", self.connect),
+            ("ttfb", self.ttfb),
+            ("body", self.body),
+        ]
+        .iter()
+        .map(|(name, duration)| format!("{};dur={:.1}", name, duration.as_secs_f64() * 1000.0))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// Runs `future`, collecting whatever it records with [`record`].
+pub async fn collect<F: Future>(future: F) -> (F::Output, Timings) {
+    let timings = Arc::new(Mutex::new(Timings::default()));
+    let output = CURRENT.scope(timings.clone(), future).await;
+    let timings = *timings.lock().unwrap();
+    (output, timings)
+}
+
+/// Updates the timings being collected for the current task, if any. The
+/// connector records into them too, as connections are made while the
+/// request's own future is polled.
+pub fn record(update: impl FnOnce(&mut Timings)) {
+    let _ = CURRENT.try_with(|timings| update(&mut timings.lock().unwrap()));
+}
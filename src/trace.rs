@@ -0,0 +1,94 @@
+use rocket::{
+    data::Data,
+    fairing::{Fairing, Info, Kind},
+    Request, Response,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::SystemTime,
+};
+use tracing::info;
+
+/// Request header asking for one request to be traced: `X-Proxy-Trace: 1`.
+pub const HEADER: &str = "X-Proxy-Trace";
+/// Response header with the ID a traced request's logs are tagged with.
+pub const ID_HEADER: &str = "X-Proxy-Trace-Id";
+
+/// Headers whose values never make it into a trace, or onto disk.
+pub const SECRET_HEADERS: [&str; 5] = ["cookie", "x-api-key", "authorization", "x-csrf-token", "proxy-authorization"];
+
+tokio::task_local! {
+    static CURRENT: String;
+}
+
+struct TraceId(Option<String>);
+
+fn new_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let seed = format!("{:?}{}", SystemTime::now(), COUNTER.fetch_add(1, Ordering::Relaxed));
+    hex::encode(&Sha256::digest(seed)[..8])
+}
+
+/// The trace ID of `req`, if it asked to be traced.
+pub fn id<'r>(req: &'r Request<'_>) -> Option<&'r str> {
+    req.local_cache(|| TraceId(None)).0.as_deref()
+}
+
+/// Runs `future` traced as `id`, if given, so what it logs with [`event`]
+/// shows up at info level for just this request.
+pub async fn scope<F: Future>(id: Option<&str>, future: F) -> F::Output {
+    match id {
+        Some(id) => CURRENT.scope(id.to_string(), future).await,
+        None => future.await,
+    }
+}
+
+/// Logs `message` under the `trace` target if the current task is traced.
+/// That target can be let through on its own, e.g. `RUST_LOG=warn,trace=info`.
+/// Built lazily, since it's skipped for nearly every request.
+pub fn event(message: impl FnOnce() -> String) {
+    let _ = CURRENT.try_with(|id| info!(target: "trace", trace_id = %id, "{}", message()));
+}
+
+/// `headers` for a trace, with credentials blanked out.
+pub fn headers<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    headers
+        .into_iter()
+        .map(|(name, value)| {
+            let value = if SECRET_HEADERS.contains(&name.to_lowercase().as_str()) {
+                "<redacted>"
+            } else {
+                value
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Assigns a trace ID to requests with `X-Proxy-Trace: 1` and hands it back
+/// in `X-Proxy-Trace-Id`.
+pub struct Tracing;
+
+#[rocket::async_trait]
+impl Fairing for Tracing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Per-request tracing",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        let wanted = matches!(req.headers().get_one(HEADER).map(str::trim), Some("1" | "true"));
+        req.local_cache(|| TraceId(wanted.then(new_id)));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if let Some(id) = id(req) {
+            res.set_raw_header(ID_HEADER, id.to_string());
+        }
+    }
+}
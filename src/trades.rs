@@ -0,0 +1,166 @@
+use crate::{
+    config::TradesConfig,
+    helpers::{self, Upstream},
+    metrics::Metrics,
+    tenants::ApiKey,
+    AppState, ErrorResponse, MyRequestGuard, Rejection,
+};
+use anyhow::Result;
+use rocket::{
+    http::{Method, Status},
+    serde::json::{json, Json, Value},
+    State,
+};
+use std::sync::Arc;
+use tracing::info;
+
+/// What a proxy key needs to be granted to send or accept trades.
+pub const PERMISSION: &str = "trades";
+
+const TRADES: &str = "https://trades.roblox.com/v1/trades";
+const STATUSES: [&str; 4] = ["Inbound", "Outbound", "Completed", "Inactive"];
+
+/// Whether `method` on `url` sends, counters or accepts a trade.
+pub fn is_write(method: Method, url: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+    if method != Method::Post || url.host_str() != Some("trades.roblox.com") {
+        return false;
+    }
+    let segments: Vec<String> = url
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    matches!(segments[..], [_, "trades", "send"] | [_, "trades", _, "accept" | "counter"])
+}
+
+/// Turns away trade writes unless the deployment allows them and `key` has
+/// the `trades` permission.
+pub fn check(config: &TradesConfig, key: Option<&ApiKey>, method: Method, url: &str, metrics: &Metrics) -> Result<()> {
+    if !is_write(method, url) {
+        return Ok(());
+    }
+    authorize(config, key, metrics)?;
+    info!("Trade write {} by proxy key {}", url, key.map_or("-", ApiKey::label));
+    Ok(())
+}
+
+fn authorize(config: &TradesConfig, key: Option<&ApiKey>, metrics: &Metrics) -> Result<()> {
+    if !config.allow_writes {
+        metrics.incr("roproxy_trade_writes_rejected_total", &[("reason", "disabled")]);
+        return Err(Rejection::new(Status::Forbidden, "Sending and accepting trades is disabled on this proxy").into());
+    }
+    if !key.is_some_and(|key| key.has_permission(PERMISSION)) {
+        metrics.incr("roproxy_trade_writes_rejected_total", &[("reason", "permission")]);
+        return Err(Rejection::new(Status::Forbidden, "This proxy key may not send or accept trades")
+            .with_field("missing_scope", PERMISSION)
+            .into());
+    }
+    Ok(())
+}
+
+/// A page of the account's trades with the given `status` (Inbound by
+/// default), newest first. Pass `nextPageCursor` back as `cursor`.
+#[get("/helpers/trades?<status>&<cursor>&<limit>")]
+pub async fn list_trades(
+    status: Option<&str>,
+    cursor: Option<&str>,
+    limit: Option<u32>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    let status = status.unwrap_or("Inbound");
+    if !STATUSES.contains(&status) {
+        return Err(ErrorResponse(
+            Rejection::new(Status::BadRequest, format!("Unknown trade status {}", status))
+                .with_field("supported", STATUSES.to_vec())
+                .into(),
+        ));
+    }
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "trades")]);
+
+    let url = format!("{}/{}?limit={}&sortOrder=Desc", TRADES, status, limit.unwrap_or(25));
+    let page = upstream
+        .get_fresh(&helpers::page_url(&url, cursor)?)
+        .await
+        .map_err(helpers::rejection)?;
+    Ok(Json(page))
+}
+
+#[get("/helpers/trades/<trade_id>")]
+pub async fn get_trade(
+    trade_id: u64,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "trades")]);
+
+    let trade = upstream
+        .get_fresh(&format!("{}/{}", TRADES, trade_id))
+        .await
+        .map_err(helpers::rejection)?;
+    Ok(Json(trade))
+}
+
+/// Sends a trade, with the body Roblox's `/v1/trades/send` takes. Needs
+/// `trades.allow_writes` and a key with the `trades` permission.
+#[post("/helpers/trades", data = "<offer>")]
+pub async fn send_trade(
+    offer: Json<Value>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    authorize(&state.trades, upstream.key(), &state.metrics)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "trades")]);
+
+    let sent = upstream
+        .post(&format!("{}/send", TRADES), &offer)
+        .await
+        .map_err(helpers::rejection)?;
+    info!("Trade {} sent by proxy key {}", sent["id"], upstream.key().map_or("-", ApiKey::label));
+    Ok(Json(sent))
+}
+
+/// Accepts an inbound trade. Needs `trades.allow_writes` and a key with the
+/// `trades` permission.
+#[post("/helpers/trades/<trade_id>/accept")]
+pub async fn accept_trade(
+    trade_id: u64,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<Json<Value>, ErrorResponse> {
+    let upstream = Upstream::authenticate(state, guard.request)?;
+    authorize(&state.trades, upstream.key(), &state.metrics)?;
+    state.metrics.incr("roproxy_helper_requests_total", &[("helper", "trades")]);
+
+    upstream
+        .post(&format!("{}/{}/accept", TRADES, trade_id), &json!({}))
+        .await
+        .map_err(helpers::rejection)?;
+    info!("Trade {} accepted by proxy key {}", trade_id, upstream.key().map_or("-", ApiKey::label));
+    Ok(Json(json!({ "tradeId": trade_id, "accepted": true })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_trade_writes() {
+        assert!(is_write(Method::Post, "https://trades.roblox.com/v1/trades/send"));
+        assert!(is_write(Method::Post, "https://trades.roblox.com/v2/trades/send"));
+        assert!(is_write(Method::Post, "https://trades.roblox.com/v1/trades/123/accept"));
+        assert!(is_write(Method::Post, "https://trades.roblox.com/v1/Trades/123/Counter/"));
+        assert!(!is_write(Method::Get, "https://trades.roblox.com/v1/trades/123"));
+        assert!(!is_write(Method::Post, "https://trades.roblox.com/v1/trades/123/decline"));
+        assert!(!is_write(Method::Post, "https://www.roblox.com/v1/trades/send"));
+    }
+}
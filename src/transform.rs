@@ -0,0 +1,691 @@
+use crate::{
+    config::{TransformRuleConfig, TransformsConfig},
+    metrics::Metrics,
+    ProxyResponse,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use rocket::serde::json::{self, serde_json::Map, Value};
+use std::cmp::Ordering;
+use tracing::warn;
+
+struct Rule {
+    config: TransformRuleConfig,
+    expr: Expr,
+}
+
+/// Operator-defined reshaping of upstream JSON, so verbose Roblox responses
+/// can be trimmed once here instead of in every client. Expressions are a
+/// small subset of jq; see `Expr` for what's understood.
+pub struct Transforms {
+    rules: Vec<Rule>,
+}
+
+impl Transforms {
+    pub fn new(config: &TransformsConfig) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                let expr = parse(&rule.expression)
+                    .with_context(|| format!("Invalid expression for transform {}", rule.name))?;
+                Ok(Rule {
+                    config: rule.clone(),
+                    expr,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Transforms { rules })
+    }
+
+    /// Runs the first rule matching `url` over a successful JSON response. A
+    /// rule that fails on a particular body leaves the response untouched.
+    pub fn apply(&self, url: &str, mut response: ProxyResponse, metrics: &Metrics) -> ProxyResponse {
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.config.prefixes.iter().any(|prefix| url.starts_with(prefix)))
+        else {
+            return response;
+        };
+        let Some(value) = response.json() else {
+            return response;
+        };
+        let name = rule.config.name.as_str();
+        match eval(&rule.expr, &value) {
+            Ok(mut outputs) => {
+                let value = match outputs.len() {
+                    1 => outputs.remove(0),
+                    _ => Value::Array(outputs),
+                };
+                metrics.incr("roproxy_transforms_total", &[("rule", name), ("result", "applied")]);
+                response.set_json(&value);
+            }
+            Err(err) => {
+                warn!("Transform {} failed on {}: {:#}", name, url, err);
+                metrics.incr("roproxy_transforms_total", &[("rule", name), ("result", "failed")]);
+            }
+        }
+        response
+    }
+}
+
+/// Supported: `.`, `.a.b`, `."a b"`, `.[n]`, `.[]`, `[...]`, `{a, b: .c}`,
+/// `|`, `,`, comparisons, `and`/`or`, literals, parentheses and the
+/// builtins `select`, `map`, `del`, `length`, `keys` and `not`.
+#[derive(Debug)]
+enum Expr {
+    Identity,
+    Field(Box<Expr>, String),
+    Index(Box<Expr>, i64),
+    Iterate(Box<Expr>),
+    Literal(Value),
+    Array(Option<Box<Expr>>),
+    Object(Vec<(String, Expr)>),
+    Pipe(Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Select(Box<Expr>),
+    Map(Box<Expr>),
+    Del(Box<Expr>),
+    Length,
+    Keys,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Punct(&'static str),
+}
+
+// Longer operators first so `<=` isn't read as `<` followed by `=`.
+const PUNCTS: [&str; 15] = [
+    "==", "!=", "<=", ">=", "<", ">", "|", ",", "[", "]", "{", "}", "(", ")", ":",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == '.' {
+            tokens.push(Token::Dot);
+            rest = &rest[1..];
+        } else if c == '"' {
+            let mut end = 1;
+            let bytes = rest.as_bytes();
+            while end < bytes.len() && bytes[end] != b'"' {
+                end += if bytes[end] == b'\\' { 2 } else { 1 };
+            }
+            if end >= bytes.len() {
+                bail!("Unterminated string");
+            }
+            tokens.push(Token::Str(json::from_str(&rest[..=end])?));
+            rest = &rest[end + 1..];
+        } else if c.is_ascii_digit() || (c == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let end = rest[1..]
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .map_or(rest.len(), |end| end + 1);
+            tokens.push(Token::Num(rest[..end].parse()?));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if let Some(punct) = PUNCTS.iter().find(|punct| rest.starts_with(**punct)) {
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
+        } else {
+            bail!("Unexpected character {:?}", c);
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse(source: &str) -> Result<Expr> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+    };
+    let expr = parser.pipe()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => bail!("Unexpected {:?}", token),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, punct: &'static str) -> bool {
+        if self.peek() == Some(&Token::Punct(punct)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &'static str) -> Result<()> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            bail!("Expected {:?}, found {:?}", punct, self.peek())
+        }
+    }
+
+    fn pipe(&mut self) -> Result<Expr> {
+        let mut expr = self.comma()?;
+        while self.eat("|") {
+            expr = Expr::Pipe(Box::new(expr), Box::new(self.comma()?));
+        }
+        Ok(expr)
+    }
+
+    fn comma(&mut self) -> Result<Expr> {
+        let mut expr = self.or()?;
+        while self.eat(",") {
+            expr = Expr::Comma(Box::new(expr), Box::new(self.or()?));
+        }
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Ident("or".to_string())) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.compare()?;
+        while self.peek() == Some(&Token::Ident("and".to_string())) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.compare()?));
+        }
+        Ok(expr)
+    }
+
+    fn compare(&mut self) -> Result<Expr> {
+        let left = self.postfix()?;
+        let op = match self.peek() {
+            Some(Token::Punct("==")) => CompareOp::Eq,
+            Some(Token::Punct("!=")) => CompareOp::Ne,
+            Some(Token::Punct("<")) => CompareOp::Lt,
+            Some(Token::Punct("<=")) => CompareOp::Le,
+            Some(Token::Punct(">")) => CompareOp::Gt,
+            Some(Token::Punct(">=")) => CompareOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Compare(Box::new(left), op, Box::new(self.postfix()?)))
+    }
+
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.peek() == Some(&Token::Dot) {
+                match self.tokens.get(self.pos + 1) {
+                    Some(Token::Ident(name) | Token::Str(name)) => {
+                        expr = Expr::Field(Box::new(expr), name.clone());
+                        self.pos += 2;
+                    }
+                    Some(Token::Punct("[")) => self.pos += 1,
+                    _ => bail!("Expected a field name after '.'"),
+                }
+            } else if self.eat("[") {
+                if self.eat("]") {
+                    expr = Expr::Iterate(Box::new(expr));
+                    continue;
+                }
+                expr = match self.next() {
+                    Some(Token::Num(index)) if index.fract() == 0.0 => Expr::Index(Box::new(expr), index as i64),
+                    Some(Token::Str(name)) => Expr::Field(Box::new(expr), name),
+                    other => bail!("Unsupported index {:?}", other),
+                };
+                self.expect("]")?;
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Dot) => match self.peek() {
+                Some(Token::Ident(name) | Token::Str(name)) => {
+                    let name = name.clone();
+                    self.pos += 1;
+                    Ok(Expr::Field(Box::new(Expr::Identity), name))
+                }
+                _ => Ok(Expr::Identity),
+            },
+            Some(Token::Str(text)) => Ok(Expr::Literal(Value::String(text))),
+            Some(Token::Num(number)) => Ok(Expr::Literal(json::json!(number))),
+            Some(Token::Punct("(")) => {
+                let expr = self.pipe()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Punct("[")) => {
+                if self.eat("]") {
+                    return Ok(Expr::Array(None));
+                }
+                let expr = self.pipe()?;
+                self.expect("]")?;
+                Ok(Expr::Array(Some(Box::new(expr))))
+            }
+            Some(Token::Punct("{")) => self.object(),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                "length" => Ok(Expr::Length),
+                "keys" => Ok(Expr::Keys),
+                "not" => Ok(Expr::Not),
+                "select" | "map" | "del" => {
+                    self.expect("(")?;
+                    let arg = Box::new(self.pipe()?);
+                    self.expect(")")?;
+                    Ok(match name.as_str() {
+                        "select" => Expr::Select(arg),
+                        "map" => Expr::Map(arg),
+                        _ => Expr::Del(arg),
+                    })
+                }
+                _ => bail!("Unknown function {}", name),
+            },
+            other => bail!("Unexpected {:?}", other),
+        }
+    }
+
+    fn object(&mut self) -> Result<Expr> {
+        let mut entries = Vec::new();
+        if self.eat("}") {
+            return Ok(Expr::Object(entries));
+        }
+        loop {
+            let key = match self.next() {
+                Some(Token::Ident(key) | Token::Str(key)) => key,
+                other => bail!("Expected an object key, found {:?}", other),
+            };
+            let value = if self.eat(":") {
+                self.or()?
+            } else {
+                Expr::Field(Box::new(Expr::Identity), key.clone())
+            };
+            entries.push((key, value));
+            if self.eat("}") {
+                return Ok(Expr::Object(entries));
+            }
+            self.expect(",")?;
+        }
+    }
+}
+
+fn eval(expr: &Expr, input: &Value) -> Result<Vec<Value>> {
+    Ok(match expr {
+        Expr::Identity => vec![input.clone()],
+        Expr::Literal(value) => vec![value.clone()],
+        Expr::Field(base, name) => eval(base, input)?
+            .iter()
+            .map(|value| match value {
+                Value::Object(object) => Ok(object.get(name).cloned().unwrap_or(Value::Null)),
+                Value::Null => Ok(Value::Null),
+                other => Err(anyhow!("Cannot index {} with {:?}", type_name(other), name)),
+            })
+            .collect::<Result<_>>()?,
+        Expr::Index(base, index) => eval(base, input)?
+            .iter()
+            .map(|value| match value {
+                Value::Array(items) => {
+                    let index = if *index < 0 { items.len() as i64 + index } else { *index };
+                    Ok(usize::try_from(index)
+                        .ok()
+                        .and_then(|index| items.get(index).cloned())
+                        .unwrap_or(Value::Null))
+                }
+                Value::Null => Ok(Value::Null),
+                other => Err(anyhow!("Cannot index {} with a number", type_name(other))),
+            })
+            .collect::<Result<_>>()?,
+        Expr::Iterate(base) => {
+            let mut outputs = Vec::new();
+            for value in eval(base, input)? {
+                match value {
+                    Value::Array(items) => outputs.extend(items),
+                    Value::Object(object) => outputs.extend(object.into_iter().map(|(_, value)| value)),
+                    other => bail!("Cannot iterate over {}", type_name(&other)),
+                }
+            }
+            outputs
+        }
+        Expr::Array(None) => vec![Value::Array(Vec::new())],
+        Expr::Array(Some(inner)) => vec![Value::Array(eval(inner, input)?)],
+        Expr::Object(entries) => {
+            let mut objects = vec![Map::new()];
+            for (key, value) in entries {
+                let values = eval(value, input)?;
+                objects = objects
+                    .iter()
+                    .flat_map(|object| {
+                        values.iter().map(move |value| {
+                            let mut object = object.clone();
+                            object.insert(key.clone(), value.clone());
+                            object
+                        })
+                    })
+                    .collect();
+            }
+            objects.into_iter().map(Value::Object).collect()
+        }
+        Expr::Pipe(left, right) => {
+            let mut outputs = Vec::new();
+            for value in eval(left, input)? {
+                outputs.extend(eval(right, &value)?);
+            }
+            outputs
+        }
+        Expr::Comma(left, right) => {
+            let mut outputs = eval(left, input)?;
+            outputs.extend(eval(right, input)?);
+            outputs
+        }
+        Expr::Compare(left, op, right) => {
+            let rights = eval(right, input)?;
+            let mut outputs = Vec::new();
+            for left in eval(left, input)? {
+                for right in &rights {
+                    let ordering = compare(&left, right);
+                    outputs.push(Value::Bool(match op {
+                        CompareOp::Eq => equal(&left, right),
+                        CompareOp::Ne => !equal(&left, right),
+                        CompareOp::Lt => ordering == Ordering::Less,
+                        CompareOp::Le => ordering != Ordering::Greater,
+                        CompareOp::Gt => ordering == Ordering::Greater,
+                        CompareOp::Ge => ordering != Ordering::Less,
+                    }));
+                }
+            }
+            outputs
+        }
+        Expr::And(left, right) => {
+            let mut outputs = Vec::new();
+            for left in eval(left, input)? {
+                if !truthy(&left) {
+                    outputs.push(Value::Bool(false));
+                    continue;
+                }
+                outputs.extend(eval(right, input)?.iter().map(|right| Value::Bool(truthy(right))));
+            }
+            outputs
+        }
+        Expr::Or(left, right) => {
+            let mut outputs = Vec::new();
+            for left in eval(left, input)? {
+                if truthy(&left) {
+                    outputs.push(Value::Bool(true));
+                    continue;
+                }
+                outputs.extend(eval(right, input)?.iter().map(|right| Value::Bool(truthy(right))));
+            }
+            outputs
+        }
+        Expr::Select(condition) => eval(condition, input)?
+            .iter()
+            .filter(|value| truthy(value))
+            .map(|_| input.clone())
+            .collect(),
+        Expr::Map(inner) => {
+            let items = eval(&Expr::Iterate(Box::new(Expr::Identity)), input)?;
+            let mut outputs = Vec::new();
+            for item in &items {
+                outputs.extend(eval(inner, item)?);
+            }
+            vec![Value::Array(outputs)]
+        }
+        Expr::Del(path) => {
+            let mut output = input.clone();
+            delete(path, &mut output)?;
+            vec![output]
+        }
+        Expr::Length => vec![match input {
+            Value::Null => 0.into(),
+            Value::Bool(_) => bail!("boolean has no length"),
+            Value::Number(number) => json::json!(number.as_f64().unwrap_or(0.0).abs()),
+            Value::String(text) => text.chars().count().into(),
+            Value::Array(items) => items.len().into(),
+            Value::Object(object) => object.len().into(),
+        }],
+        Expr::Keys => match input {
+            Value::Object(object) => {
+                let mut keys: Vec<_> = object.keys().cloned().map(Value::String).collect();
+                keys.sort_by(compare);
+                vec![Value::Array(keys)]
+            }
+            Value::Array(items) => vec![Value::Array((0..items.len()).map(Value::from).collect())],
+            other => bail!("{} has no keys", type_name(other)),
+        },
+        Expr::Not => vec![Value::Bool(!truthy(input))],
+    })
+}
+
+// Removes whatever `path` points at. Only plain paths (fields, indices,
+// iteration and commas between them) can be deleted.
+fn delete(path: &Expr, value: &mut Value) -> Result<()> {
+    match path {
+        Expr::Field(base, name) => each_mut(base, value, &mut |target| {
+            if let Value::Object(object) = target {
+                object.remove(name);
+            }
+        }),
+        Expr::Index(base, index) => each_mut(base, value, &mut |target| {
+            if let Value::Array(items) = target {
+                let index = if *index < 0 { items.len() as i64 + index } else { *index };
+                if let Ok(index) = usize::try_from(index) {
+                    if index < items.len() {
+                        items.remove(index);
+                    }
+                }
+            }
+        }),
+        Expr::Iterate(base) => each_mut(base, value, &mut |target| match target {
+            Value::Array(items) => items.clear(),
+            Value::Object(object) => object.clear(),
+            _ => {}
+        }),
+        Expr::Comma(left, right) => {
+            delete(left, value)?;
+            delete(right, value)
+        }
+        other => bail!("Cannot delete {:?}", other),
+    }
+}
+
+fn each_mut(path: &Expr, value: &mut Value, f: &mut dyn FnMut(&mut Value)) -> Result<()> {
+    match path {
+        Expr::Identity => f(value),
+        Expr::Field(base, name) => each_mut(base, value, &mut |target| {
+            if let Some(field) = target.as_object_mut().and_then(|object| object.get_mut(name)) {
+                f(field);
+            }
+        })?,
+        Expr::Index(base, index) => each_mut(base, value, &mut |target| {
+            if let Value::Array(items) = target {
+                let index = if *index < 0 { items.len() as i64 + index } else { *index };
+                if let Some(item) = usize::try_from(index).ok().and_then(|index| items.get_mut(index)) {
+                    f(item);
+                }
+            }
+        })?,
+        Expr::Iterate(base) => each_mut(base, value, &mut |target| match target {
+            Value::Array(items) => items.iter_mut().for_each(&mut *f),
+            Value::Object(object) => object.values_mut().for_each(&mut *f),
+            _ => {}
+        })?,
+        other => bail!("Cannot delete {:?}", other),
+    }
+    Ok(())
+}
+
+fn truthy(value: &Value) -> bool {
+    !matches!(value, Value::Null | Value::Bool(false))
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// Numbers are equal by value, so a literal `1` matches an upstream `1.0`.
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(_), Value::Number(_)) => compare(left, right) == Ordering::Equal,
+        (Value::Array(left), Value::Array(right)) => {
+            left.len() == right.len() && left.iter().zip(right).all(|(left, right)| equal(left, right))
+        }
+        (Value::Object(left), Value::Object(right)) => {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .all(|(key, left)| right.get(key).is_some_and(|right| equal(left, right)))
+        }
+        _ => left == right,
+    }
+}
+
+// jq's total order: null < false < true < numbers < strings < arrays < objects.
+fn compare(left: &Value, right: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(false) => 1,
+            Value::Bool(true) => 2,
+            Value::Number(_) => 3,
+            Value::String(_) => 4,
+            Value::Array(_) => 5,
+            Value::Object(_) => 6,
+        }
+    }
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left
+            .as_f64()
+            .partial_cmp(&right.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Value::String(left), Value::String(right)) => left.cmp(right),
+        (Value::Array(left), Value::Array(right)) => left
+            .iter()
+            .zip(right)
+            .map(|(left, right)| compare(left, right))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| left.len().cmp(&right.len())),
+        _ => rank(left).cmp(&rank(right)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::serde::json::json;
+
+    fn run(expression: &str, input: Value) -> Vec<Value> {
+        eval(&parse(expression).unwrap(), &input).unwrap()
+    }
+
+    #[test]
+    fn object_shorthand_picks_fields() {
+        let input = json!({"id": 1, "name": "a", "extra": true});
+        assert_eq!(run("{id, name}", input.clone()), [json!({"id": 1, "name": "a"})]);
+        assert_eq!(run("{id, label: .name}", input), [json!({"id": 1, "label": "a"})]);
+    }
+
+    #[test]
+    fn map_reshapes_each_item() {
+        let input = json!({"data": [{"id": 1, "name": "a", "x": 0}, {"id": 2, "name": "b"}]});
+        assert_eq!(
+            run(".data | map({id, name})", input),
+            [json!([{"id": 1, "name": "a"}, {"id": 2, "name": "b"}])]
+        );
+    }
+
+    #[test]
+    fn select_keeps_matching_items() {
+        let input = json!([{"x": 1}, {"x": 2}, {"x": 1.0}, {"y": 1}]);
+        assert_eq!(run(".[] | select(.x == 1)", input.clone()), [json!({"x": 1}), json!({"x": 1.0})]);
+        assert_eq!(run("map(select(.x != 1 and .x))", input), [json!([{"x": 2}])]);
+    }
+
+    #[test]
+    fn del_removes_paths() {
+        let input = json!({"a": 1, "b": {"c": 2, "d": 3}, "e": [1, 2, 3]});
+        assert_eq!(run("del(.a)", input.clone()), [json!({"b": {"c": 2, "d": 3}, "e": [1, 2, 3]})]);
+        assert_eq!(run("del(.a, .b.c, .e[0])", input), [json!({"b": {"d": 3}, "e": [2, 3]})]);
+    }
+
+    #[test]
+    fn indices_and_iteration() {
+        let input = json!({"items": [10, 20, 30]});
+        assert_eq!(run(".items[1]", input.clone()), [json!(20)]);
+        assert_eq!(run(".items.[-1]", input.clone()), [json!(30)]);
+        assert_eq!(run(".items[5]", input.clone()), [Value::Null]);
+        assert_eq!(run(".items[]", input.clone()), [json!(10), json!(20), json!(30)]);
+        assert_eq!(run("[.items[] | select(. > 15)]", input), [json!([20, 30])]);
+    }
+
+    #[test]
+    fn comma_binds_tighter_than_pipe() {
+        let input = json!({"a": [1, 2], "b": "xyz"});
+        assert_eq!(run(".a, .b | length", input.clone()), [json!(2), json!(3)]);
+        assert_eq!(run(".a | length, .[0]", input.clone()), [json!(2), json!(1)]);
+        assert_eq!(run("[.a[] | . , .]", input), [json!([1, 1, 2, 2])]);
+    }
+
+    #[test]
+    fn invalid_expressions_fail_at_startup() {
+        let error = |expression| format!("{:#}", parse(expression).unwrap_err());
+        assert_eq!(error(".name == \"abc"), "Unterminated string");
+        assert!(error(".a )").starts_with("Unexpected Punct(\")\")"));
+        assert!(error("{id,}").starts_with("Expected an object key"));
+        assert!(error(".a ; .b").starts_with("Unexpected character ';'"));
+        assert!(error("first(.a)").starts_with("Unknown function first"));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::Rejection;
+use anyhow::{Context, Result};
+use rocket::{
+    data::{ByteUnit, Data},
+    futures::stream,
+    http::Status,
+};
+use std::{future::Future, io, time::Duration};
+use tokio::{io::AsyncReadExt, sync::mpsc};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks read ahead of the upstream connection before reading pauses.
+const CHUNKS_BUFFERED: usize = 4;
+
+/// Streams a request body upstream as it arrives, so memory stays flat for
+/// large uploads and Roblox starts receiving before the client has finished
+/// sending. Returns the body to send and the future that feeds it, which has
+/// to be polled alongside the upstream request. That future fails with a 413
+/// once `limit` is passed and a 408 if the body takes longer than `timeout`,
+/// and returns the number of bytes read otherwise.
+pub fn stream_body<'r>(
+    data: Data<'r>,
+    limit: ByteUnit,
+    timeout: Duration,
+) -> (reqwest::Body, impl Future<Output = Result<u64>> + 'r) {
+    let (sender, receiver) = mpsc::channel::<io::Result<Vec<u8>>>(CHUNKS_BUFFERED);
+    let chunks = stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+
+    let feed = async move {
+        // One byte past the limit, to tell a body that fits exactly from one
+        // that doesn't.
+        let mut reader = data.open(limit + 1);
+        let mut total = 0;
+        let read_all = async {
+            loop {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let read = reader.read(&mut chunk).await.context("Failed to read request body")?;
+                if read == 0 {
+                    return Ok(());
+                }
+                total += read as u64;
+                if total > limit.as_u64() {
+                    let _ = sender
+                        .send(Err(io::Error::other("request body is too large")))
+                        .await;
+                    return Err(Rejection::new(Status::PayloadTooLarge, "Request body is too large")
+                        .with_field("max_bytes", limit.as_u64())
+                        .into());
+                }
+                chunk.truncate(read);
+                // Upstream stopped reading (it answered early or failed), so
+                // the rest of the body isn't needed.
+                if sender.send(Ok(chunk)).await.is_err() {
+                    return Ok(());
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, read_all).await {
+            Ok(result) => result.map(|()| total),
+            Err(_) => {
+                let _ = sender
+                    .send(Err(io::Error::other("timed out reading the request body")))
+                    .await;
+                Err(Rejection::new(Status::RequestTimeout, "Timed out reading the request body").into())
+            }
+        }
+    };
+    (reqwest::Body::wrap_stream(chunks), feed)
+}
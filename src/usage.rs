@@ -0,0 +1,125 @@
+use crate::{config::UsageConfig, AppState};
+use anyhow::{Context, Result};
+use chrono::{Duration as ChronoDuration, NaiveDate, Utc};
+use rocket::serde::{json, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::warn;
+
+/// Date format of usage rows and the export's `from` and `to`.
+pub const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// One proxy key's traffic on one UTC day.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct UsageRow {
+    pub date: String,
+    pub tenant: String,
+    pub key: String,
+    pub requests: u64,
+    /// Requests answered with a 4xx or 5xx.
+    pub errors: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+/// Per-key, per-day request and byte counts, for billing and capacity
+/// planning. Saved to `usage.path` every `usage.flush_secs` if set, and
+/// loaded back on start.
+pub struct UsageStats {
+    rows: Mutex<BTreeMap<(String, String, String), UsageRow>>,
+    path: Option<PathBuf>,
+    retention_days: i64,
+}
+
+impl UsageStats {
+    pub fn new(config: &UsageConfig) -> Result<Self> {
+        let path = config.path.as_ref().map(PathBuf::from);
+        let rows = match &path {
+            Some(path) if path.exists() => {
+                let contents = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+                json::from_slice::<Vec<UsageRow>>(&contents)
+                    .with_context(|| format!("{} isn't a usage file", path.display()))?
+            }
+            _ => Vec::new(),
+        };
+        Ok(UsageStats {
+            rows: Mutex::new(
+                rows.into_iter()
+                    .map(|row| ((row.date.clone(), row.tenant.clone(), row.key.clone()), row))
+                    .collect(),
+            ),
+            path,
+            retention_days: config.retention_days as i64,
+        })
+    }
+
+    pub fn record(&self, tenant: &str, key: &str, status: u16, bytes_in: u64, bytes_out: u64) {
+        let date = Utc::now().format(DATE_FORMAT).to_string();
+        let mut rows = self.rows.lock().unwrap();
+        let row = rows
+            .entry((date.clone(), tenant.to_string(), key.to_string()))
+            .or_insert_with(|| UsageRow {
+                date,
+                tenant: tenant.to_string(),
+                key: key.to_string(),
+                ..UsageRow::default()
+            });
+        row.requests += 1;
+        if status >= 400 {
+            row.errors += 1;
+        }
+        row.bytes_in += bytes_in;
+        row.bytes_out += bytes_out;
+    }
+
+    /// Rows from `from` to `to`, both inclusive, by date, tenant and key.
+    pub fn rows(&self, from: NaiveDate, to: NaiveDate) -> Vec<UsageRow> {
+        let (from, to) = (from.format(DATE_FORMAT).to_string(), to.format(DATE_FORMAT).to_string());
+        self.rows
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|row| row.date >= from && row.date <= to)
+            .cloned()
+            .collect()
+    }
+
+    // Drops days past retention and writes what's left.
+    fn save(&self) -> Result<()> {
+        let cutoff = (Utc::now().date_naive() - ChronoDuration::days(self.retention_days))
+            .format(DATE_FORMAT)
+            .to_string();
+        let rows: Vec<UsageRow> = {
+            let mut rows = self.rows.lock().unwrap();
+            rows.retain(|(date, _, _), _| *date >= cutoff);
+            rows.values().cloned().collect()
+        };
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, json::to_string(&rows)?).with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Prunes and saves usage every `usage.flush_secs`.
+pub fn spawn(state: Arc<AppState>, config: &UsageConfig) {
+    let period = Duration::from_secs(config.flush_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            if let Err(err) = state.usage.save() {
+                warn!("Failed to save usage: {:?}", err);
+            }
+        }
+    });
+}
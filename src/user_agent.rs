@@ -0,0 +1,71 @@
+use crate::{
+    config::{UserAgentAction, UserAgentsConfig},
+    metrics::Metrics,
+    ratelimit::TokenBucket,
+    Rejection,
+};
+use anyhow::{Context, Result};
+use regex::Regex;
+use rocket::http::Status;
+use std::time::Duration;
+
+struct Rule {
+    name: String,
+    pattern: Regex,
+    action: UserAgentAction,
+    limiter: Option<TokenBucket>,
+}
+
+/// Allows, denies or throttles clients by `User-Agent`, so a public
+/// deployment can be reserved for Roblox game servers and Studio rather than
+/// whoever finds the URL. Trivially spoofable, but it stops casual scrapers.
+pub struct UserAgentPolicy {
+    rules: Vec<Rule>,
+    default_action: UserAgentAction,
+}
+
+impl UserAgentPolicy {
+    pub fn new(config: &UserAgentsConfig) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    name: rule.name.clone(),
+                    pattern: Regex::new(&rule.pattern)
+                        .with_context(|| format!("Invalid pattern for user agent rule {}", rule.name))?,
+                    action: rule.action,
+                    limiter: rule
+                        .rate_limit
+                        .as_ref()
+                        .map(|limit| TokenBucket::new(limit.limit, Duration::from_secs(limit.window_secs))),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(UserAgentPolicy {
+            rules,
+            default_action: config.default_action,
+        })
+    }
+
+    pub fn check(&self, user_agent: Option<&str>, metrics: &Metrics) -> Result<()> {
+        let user_agent = user_agent.unwrap_or_default();
+        let rule = self.rules.iter().find(|rule| rule.pattern.is_match(user_agent));
+        let name = rule.map_or("default", |rule| rule.name.as_str());
+        let action = rule.map_or(self.default_action, |rule| rule.action);
+
+        if action == UserAgentAction::Deny {
+            metrics.incr("roproxy_user_agent_rejections_total", &[("rule", name), ("reason", "denied")]);
+            return Err(Rejection::new(Status::Forbidden, "This client is not allowed to use the proxy").into());
+        }
+        if let Some(limiter) = rule.and_then(|rule| rule.limiter.as_ref()) {
+            if let Err(retry_after) = limiter.take(Duration::ZERO) {
+                metrics.incr("roproxy_user_agent_rejections_total", &[("rule", name), ("reason", "rate_limited")]);
+                return Err(Rejection::new(Status::TooManyRequests, "Rate limit exceeded for this client")
+                    .with_header("Retry-After", retry_after.as_secs_f64().ceil() as u64)
+                    .into());
+            }
+        }
+        Ok(())
+    }
+}
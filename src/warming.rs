@@ -0,0 +1,106 @@
+use crate::{
+    cache::CacheKey,
+    config::{CacheConfig, WarmingJobConfig},
+    AppState, UpstreamRequest,
+};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use cron::Schedule;
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tracing::{debug, info, warn};
+
+/// Starts one background task per configured warming job.
+pub fn spawn(state: Arc<AppState>, jobs: &[WarmingJobConfig]) -> Result<()> {
+    for job in jobs {
+        let schedule = Schedule::from_str(&job.schedule)
+            .with_context(|| format!("Invalid schedule for warming job {}", job.name))?;
+        info!("Scheduling cache warming job {} ({})", job.name, job.schedule);
+        tokio::spawn(run(state.clone(), job.clone(), schedule));
+    }
+    Ok(())
+}
+
+async fn run(state: Arc<AppState>, job: WarmingJobConfig, schedule: Schedule) {
+    let ttl = job.ttl_secs.map(Duration::from_secs);
+
+    for next in schedule.upcoming_owned(Utc) {
+        let delay = (next - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(delay).await;
+
+        info!("Running cache warming job {}", job.name);
+        let mut warmed = 0;
+        for url in &job.urls {
+            let credential = state.credentials.pick();
+            let key = CacheKey::new(None, url).with_account(credential.as_ref().map(|credential| credential.name.as_str()));
+            match state.engine.forward(UpstreamRequest::get(url).with_credential(credential)).await {
+                Ok(response) => {
+                    if state.engine.cache.insert(&key, &response, ttl) {
+                        warmed += 1;
+                    } else {
+                        warn!("Warming job {}: {} returned an uncacheable {}", job.name, url, response.status);
+                    }
+                }
+                Err(err) => warn!("Warming job {}: failed to fetch {}: {:?}", job.name, url, err),
+            }
+        }
+        state
+            .metrics
+            .add("roproxy_cache_warmed_total", &[("job", &job.name)], warmed);
+        info!("Warming job {} cached {}/{} URLs", job.name, warmed, job.urls.len());
+    }
+}
+
+/// Starts the task that refetches the hottest cache entries shortly before
+/// they expire, so popular keys don't all go cold at once.
+pub fn spawn_refresher(state: Arc<AppState>, config: &CacheConfig) {
+    if !config.enabled || config.refresh_top_n == 0 {
+        return;
+    }
+    let top_n = config.refresh_top_n;
+    let ahead = Duration::from_secs(config.refresh_ahead_secs);
+    // Check often enough that nothing slips from "about to expire" to expired
+    // between two passes.
+    let period = (ahead / 2).max(Duration::from_secs(1));
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            for (key, ttl) in state.engine.cache.refresh_candidates(top_n, ahead) {
+                debug!("Refreshing hot cache entry {}", key.url);
+                // Namespaced entries are refetched with their tenant's credentials.
+                let tenant = match &key.namespace {
+                    Some(name) => match state.tenants.get(name) {
+                        Some(tenant) => Some(tenant),
+                        None => continue,
+                    },
+                    None => None,
+                };
+                let credentials = tenant.as_ref().map_or(&state.credentials, |tenant| &tenant.credentials);
+                // Refetched as the account the entry is keyed by, or not at
+                // all once it has left the pool.
+                let credential = match &key.account {
+                    Some(name) => match credentials.member(name) {
+                        Some(credential) => Some(credential),
+                        None => continue,
+                    },
+                    None => None,
+                };
+                let request = UpstreamRequest::get(&key.url).with_credential(credential);
+                match state.engine.forward(request).await {
+                    Ok(response) if state.engine.cache.insert(&key, &response, Some(ttl)) => {
+                        state.metrics.incr("roproxy_cache_refreshed_total", &[]);
+                    }
+                    Ok(response) => {
+                        debug!("Refresh of {} returned an uncacheable {}", key.url, response.status);
+                        state.engine.cache.refresh_failed(&key);
+                    }
+                    Err(err) => {
+                        warn!("Failed to refresh {}: {:?}", key.url, err);
+                        state.engine.cache.refresh_failed(&key);
+                    }
+                }
+            }
+        }
+    });
+}
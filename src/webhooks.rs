@@ -0,0 +1,105 @@
+use crate::{
+    config::{ResponseWebhookRuleConfig, ResponseWebhooksConfig},
+    metrics::Metrics,
+    middleware::Middleware,
+    ProxyResponse,
+};
+use anyhow::Result;
+use reqwest::Client;
+use rocket::serde::json::{json, Value};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+use tracing::{info, warn};
+
+/// POSTs a summary of matching responses to the rule's webhook, e.g. to tell
+/// a Discord channel a watched user came online. Delivery happens in the
+/// background and never holds up or fails the response.
+pub struct ResponseWebhooks {
+    rules: Vec<ResponseWebhookRuleConfig>,
+    client: Client,
+    metrics: Arc<Metrics>,
+    /// Rule and URL of every `on_change` response that matched last time.
+    matching: Mutex<HashSet<(String, String)>>,
+}
+
+impl ResponseWebhooks {
+    pub fn new(config: &ResponseWebhooksConfig, client: Client, metrics: Arc<Metrics>) -> Self {
+        ResponseWebhooks {
+            rules: config.rules.clone(),
+            client,
+            metrics,
+            matching: Mutex::default(),
+        }
+    }
+
+    // Whether `rule` should fire for this response, remembering the outcome
+    // for `on_change` rules.
+    fn fires(&self, rule: &ResponseWebhookRuleConfig, url: &str, matched: bool) -> bool {
+        if !rule.on_change {
+            return matched;
+        }
+        let key = (rule.name.clone(), url.to_string());
+        let mut matching = self.matching.lock().unwrap();
+        if matched {
+            matching.insert(key)
+        } else {
+            matching.remove(&key);
+            false
+        }
+    }
+}
+
+fn matches(rule: &ResponseWebhookRuleConfig, body: &Value) -> Option<Value> {
+    let value = body.pointer(&rule.pointer)?;
+    match &rule.equals {
+        Some(expected) if expected != value => None,
+        _ => Some(value.clone()),
+    }
+}
+
+#[rocket::async_trait]
+impl Middleware for ResponseWebhooks {
+    fn name(&self) -> &'static str {
+        "webhooks"
+    }
+
+    async fn after_upstream(&self, url: &str, response: &mut ProxyResponse, _metrics: &Metrics) -> Result<()> {
+        let mut body = None;
+        for rule in &self.rules {
+            if !rule.prefixes.iter().any(|prefix| url.starts_with(prefix)) {
+                continue;
+            }
+            let Some(body) = body.get_or_insert_with(|| response.json()) else {
+                return Ok(());
+            };
+            let value = matches(rule, body);
+            if !self.fires(rule, url, value.is_some()) {
+                continue;
+            }
+            info!("Response from {} matched webhook rule {}", url, rule.name);
+            // `content` makes the payload readable as-is by Discord-style webhooks.
+            let payload = json!({
+                "content": format!("{}: {} is {}", rule.name, rule.pointer, value.as_ref().unwrap_or(&Value::Null)),
+                "rule": rule.name,
+                "url": url,
+                "status": response.status.code,
+                "value": value,
+            });
+            let request = self.client.post(&rule.webhook).json(&payload);
+            let metrics = self.metrics.clone();
+            let name = rule.name.clone();
+            tokio::spawn(async move {
+                match request.send().await {
+                    Ok(response) if response.status().is_success() => {
+                        metrics.incr("roproxy_response_webhooks_total", &[("rule", &name)]);
+                    }
+                    Ok(response) => warn!("Webhook for rule {} returned {}", name, response.status()),
+                    Err(err) => warn!("Failed to send webhook for rule {}: {:?}", name, err),
+                }
+            });
+        }
+        Ok(())
+    }
+}
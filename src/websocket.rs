@@ -0,0 +1,232 @@
+use crate::{client_cert, credentials, metrics::Metrics, AppState, ErrorResponse, MyRequestGuard, Rejection};
+use anyhow::Context as _;
+use reqwest::Url;
+use rocket::{
+    data::{IoHandler, IoStream},
+    futures::{SinkExt, StreamExt},
+    http::{Method, Status},
+    response::{self, Responder, Response},
+    Request, State,
+};
+use std::{collections::HashMap, io, path::PathBuf, pin::Pin, sync::Arc, time::Duration};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async_with_config,
+    tungstenite::{
+        client::IntoClientRequest,
+        handshake::derive_accept_key,
+        http::HeaderValue,
+        protocol::{Role, WebSocketConfig as FrameLimits},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+use tracing::{debug, info, warn};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Opens a WebSocket to `wss://<host>/<path>` and bridges it to the client's
+/// upgraded connection.
+#[get("/ws/<host>/<path..>?<params..>")]
+pub async fn websocket(
+    host: &str,
+    path: PathBuf,
+    params: HashMap<String, String>,
+    state: &State<Arc<AppState>>,
+    guard: MyRequestGuard<'_>,
+) -> Result<WebSocketBridge, ErrorResponse> {
+    let req = guard.request;
+    let config = &state.websocket;
+    if !config.enabled {
+        return Err(ErrorResponse(Rejection::new(Status::NotFound, "WebSocket proxying is disabled").into()));
+    }
+    let is_upgrade = req
+        .headers()
+        .get_one("Upgrade")
+        .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    let Some(client_key) = req.headers().get_one("Sec-WebSocket-Key").filter(|_| is_upgrade) else {
+        return Err(ErrorResponse(Rejection::new(Status::BadRequest, "Expected a WebSocket upgrade").into()));
+    };
+
+    let mut url = format!("wss://{}/{}", host, path.to_string_lossy());
+    if !params.is_empty() {
+        let query = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params.iter())
+            .finish();
+        url.push('?');
+        url.push_str(&query);
+    }
+    let url = state.engine.check_url(&url)?;
+    if !Url::parse(&url).is_ok_and(|parsed| allowed(&config.upstreams, &parsed)) {
+        return Err(ErrorResponse(
+            Rejection::new(Status::Forbidden, format!("{} is not an allowed WebSocket upstream", host)).into(),
+        ));
+    }
+
+    state.screen_client(req)?;
+    let api_key = client_cert::api_key(req);
+    let tenant = state.tenants.authenticate(api_key, &state.metrics)?;
+    if let Some(key) = tenant.as_ref().zip(api_key).and_then(|(tenant, key)| tenant.key(key)) {
+        key.check_scope(Method::Get, &url, &state.metrics)?;
+    }
+
+    let mut headers = vec![("Origin".to_string(), "https://www.roblox.com".to_string())];
+    if let Some(protocol) = req.headers().get_one("Sec-WebSocket-Protocol") {
+        headers.push(("Sec-WebSocket-Protocol".to_string(), protocol.to_string()));
+    }
+    let pool = tenant.as_ref().map_or(&state.credentials, |tenant| &tenant.credentials);
+    if let Some(credential) = pool.pick_for(credentials::affinity(req).as_deref()) {
+        credential.credentials.apply(&mut headers);
+    }
+
+    let mut request = url.as_str().into_client_request().context("Invalid WebSocket URL")?;
+    for (name, value) in headers {
+        let name: tokio_tungstenite::tungstenite::http::HeaderName =
+            name.parse().context("Invalid header name")?;
+        request
+            .headers_mut()
+            .insert(name, HeaderValue::from_str(&value).context("Invalid header value")?);
+    }
+
+    let limits = FrameLimits::default()
+        .max_message_size(Some(config.max_message_bytes))
+        .max_frame_size(Some(config.max_message_bytes));
+    let connected = tokio::time::timeout(CONNECT_TIMEOUT, connect_async_with_config(request, Some(limits), true)).await;
+    let (upstream, response) = match connected {
+        Ok(Ok(connected)) => connected,
+        Ok(Err(err)) => {
+            warn!("WebSocket connection to {} failed: {}", url, err);
+            return Err(ErrorResponse(Rejection::new(Status::BadGateway, "Upstream WebSocket connection failed").into()));
+        }
+        Err(_) => {
+            return Err(ErrorResponse(Rejection::new(Status::GatewayTimeout, "Upstream WebSocket connection timed out").into()));
+        }
+    };
+    info!("Opened WebSocket bridge to {}", url);
+
+    Ok(WebSocketBridge {
+        accept_key: derive_accept_key(client_key.as_bytes()),
+        protocol: response
+            .headers()
+            .get("Sec-WebSocket-Protocol")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        upstream,
+        limits,
+        idle_timeout: Duration::from_secs(config.idle_timeout_secs),
+        metrics: state.metrics.clone(),
+    })
+}
+
+// Matched on the parsed URL rather than its text, so neither
+// `realtime.roblox.com@evil.com` nor `realtime.roblox.com.evil.com` can pass
+// for a listed host.
+fn allowed(upstreams: &[String], url: &Url) -> bool {
+    if !url.username().is_empty() || url.password().is_some() {
+        return false;
+    }
+    upstreams
+        .iter()
+        .filter_map(|upstream| Url::parse(upstream).ok())
+        .any(|upstream| {
+            let prefix = upstream.path().trim_end_matches('/');
+            upstream.scheme() == url.scheme()
+                && upstream.host() == url.host()
+                && upstream.port_or_known_default() == url.port_or_known_default()
+                && (url.path() == prefix || url.path().starts_with(&format!("{}/", prefix)))
+        })
+}
+
+/// Completes the client's handshake once the upstream socket is open, then
+/// relays messages both ways until either side closes or goes idle.
+pub struct WebSocketBridge {
+    accept_key: String,
+    protocol: Option<String>,
+    upstream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    limits: FrameLimits,
+    idle_timeout: Duration,
+    metrics: Arc<Metrics>,
+}
+
+impl<'r> Responder<'r, 'static> for WebSocketBridge {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response.raw_header("Sec-WebSocket-Accept", self.accept_key.clone());
+        if let Some(protocol) = &self.protocol {
+            response.raw_header("Sec-WebSocket-Protocol", protocol.clone());
+        }
+        response.upgrade("websocket", self);
+        response.ok()
+    }
+}
+
+#[rocket::async_trait]
+impl IoHandler for WebSocketBridge {
+    async fn io(self: Pin<Box<Self>>, io: IoStream) -> io::Result<()> {
+        let WebSocketBridge {
+            mut upstream,
+            limits,
+            idle_timeout,
+            metrics,
+            ..
+        } = *Pin::into_inner(self);
+        let mut client = WebSocketStream::from_raw_socket(io, Role::Server, Some(limits)).await;
+        let _open = metrics.hold_gauge("roproxy_websocket_connections");
+
+        loop {
+            let next = tokio::select! {
+                message = client.next() => message.map(|message| ("upstream", message)),
+                message = upstream.next() => message.map(|message| ("client", message)),
+                _ = tokio::time::sleep(idle_timeout) => {
+                    debug!("Closing idle WebSocket bridge");
+                    break;
+                }
+            };
+            let Some((direction, message)) = next else { break };
+            let message = match message {
+                Ok(message) => message,
+                Err(err) => {
+                    debug!("WebSocket bridge ended: {}", err);
+                    break;
+                }
+            };
+            // Each hop answers its own pings.
+            if matches!(message, Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) {
+                continue;
+            }
+            let closing = matches!(message, Message::Close(_));
+            metrics.incr("roproxy_websocket_messages_total", &[("direction", direction)]);
+            let sent = match direction {
+                "upstream" => upstream.send(message).await,
+                _ => client.send(message).await,
+            };
+            if sent.is_err() || closing {
+                break;
+            }
+        }
+
+        let _ = client.close(None).await;
+        let _ = upstream.close(None).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upstreams_match_on_the_parsed_host() {
+        let upstreams = ["wss://realtime.roblox.com".to_string(), "wss://chat.roblox.com/v2/".to_string()];
+        let allowed = |url: &str| allowed(&upstreams, &Url::parse(url).unwrap());
+        assert!(allowed("wss://realtime.roblox.com/notifications?x=1"));
+        assert!(allowed("wss://REALTIME.roblox.com:443/"));
+        assert!(allowed("wss://chat.roblox.com/v2/stream"));
+        assert!(!allowed("wss://realtime.roblox.com@evil.com/x"));
+        assert!(!allowed("wss://realtime.roblox.com.evil.com/x"));
+        assert!(!allowed("wss://realtime.roblox.com:8443/"));
+        assert!(!allowed("ws://realtime.roblox.com/"));
+        assert!(!allowed("wss://chat.roblox.com/v2x"));
+        assert!(!allowed("wss://chat.roblox.com/"));
+    }
+}